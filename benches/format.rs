@@ -0,0 +1,101 @@
+//! Cost of formatting a text line, run with `cargo bench --bench format`.
+//!
+//! Measures the time per event of a [`TaosLayer`] writing to `io::sink`, so
+//! the formatting is all that is measured, and checks that it allocates
+//! nothing on the heap.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use taoslog::{layer::TaosLayer, QidManager};
+use tracing_subscriber::layer::SubscriberExt;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Clone)]
+struct Qid(u64);
+
+impl QidManager for Qid {
+    fn init() -> Self {
+        Self(0x1234)
+    }
+
+    fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Qid {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/// Heap allocations per event, out of the measured loop.
+fn allocations(event: &impl Fn()) -> f64 {
+    const EVENTS: u64 = 10_000;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..EVENTS {
+        event();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / EVENTS as f64
+}
+
+fn bench(c: &mut Criterion, name: &str, event: impl Fn()) {
+    c.bench_function(name, |b| b.iter(&event));
+    let allocations = allocations(&event);
+    println!("{name}: {allocations:.2} allocations/event");
+    // lines are formatted in reusable per-thread buffers
+    assert_eq!(allocations, 0.0, "{name} allocates");
+}
+
+fn events(c: &mut Criterion, group: &str) {
+    let span = tracing::info_span!("request");
+    let _entered = span.enter();
+    bench(c, &format!("{group}/message"), || {
+        tracing::info!("query finished")
+    });
+    bench(c, &format!("{group}/fields"), || {
+        tracing::info!(
+            rows = 100,
+            table = "meters",
+            sql = "select * from meters",
+            "query finished"
+        )
+    });
+    bench(c, &format!("{group}/debug"), || {
+        tracing::debug!(rows = 100, "query finished")
+    });
+}
+
+fn format(c: &mut Criterion) {
+    let subscriber =
+        tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(std::io::sink));
+    tracing::subscriber::with_default(subscriber, || events(c, "text"));
+
+    let subscriber =
+        tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(std::io::sink).with_ansi());
+    tracing::subscriber::with_default(subscriber, || events(c, "ansi"));
+}
+
+criterion_group!(benches, format);
+criterion_main!(benches);
@@ -0,0 +1,179 @@
+//! Requests left out of the access log, like health checks and metrics.
+//!
+//! [`AccessFilter`] matches the path of each request against its rules, the
+//! first matching rule excludes the request or keeps one in `every` requests,
+//! and [`TaosRootSpanBuilder`](crate::TaosRootSpanBuilder) creates no span
+//! and logs no lines for the others. Wrap it outside of the `TracingLogger`:
+//!
+//! ```ignore
+//! App::new()
+//!     .wrap(TracingLogger::<TaosRootSpanBuilder<Qid>>::new())
+//!     .wrap(
+//!         AccessFilter::new()
+//!             .exclude_prefix("/health")
+//!             .exclude_prefix("/metrics")
+//!             .exclude_prefix("/static/")
+//!             .sample_prefix("/rest/sql", 100),
+//!     )
+//! ```
+//!
+//! Samples are counted by worker.
+
+use std::{
+    cell::Cell,
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, Transform},
+    HttpMessage,
+};
+
+/// Marks a request without span or access lines, in its extensions.
+pub(crate) struct Skipped;
+
+enum Matcher {
+    Prefix(String),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl Matcher {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Matcher::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            #[cfg(feature = "regex")]
+            Matcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+struct Rule {
+    matcher: Matcher,
+    /// Keep one in `every` requests, none with 0.
+    every: u64,
+    seen: Cell<u64>,
+}
+
+impl Rule {
+    fn skip(&self) -> bool {
+        if self.every == 0 {
+            return true;
+        }
+        let seen = self.seen.get();
+        self.seen.set(seen.wrapping_add(1));
+        !seen.is_multiple_of(self.every)
+    }
+}
+
+/// Middleware excluding or sampling requests by path, see the [module
+/// docs](self).
+#[derive(Clone, Default)]
+pub struct AccessFilter {
+    rules: Vec<Rc<Rule>>,
+}
+
+impl AccessFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn rule(mut self, matcher: Matcher, every: u64) -> Self {
+        self.rules.push(Rc::new(Rule {
+            matcher,
+            every,
+            seen: Cell::new(0),
+        }));
+        self
+    }
+
+    /// Skip requests whose path starts with `prefix`.
+    pub fn exclude_prefix(self, prefix: impl Into<String>) -> Self {
+        self.rule(Matcher::Prefix(prefix.into()), 0)
+    }
+
+    /// Skip requests whose path matches `regex`.
+    #[cfg(feature = "regex")]
+    pub fn exclude_regex(self, regex: regex::Regex) -> Self {
+        self.rule(Matcher::Regex(regex), 0)
+    }
+
+    /// Keep one in `every` requests whose path starts with `prefix`, the first
+    /// one included.
+    pub fn sample_prefix(self, prefix: impl Into<String>, every: u64) -> Self {
+        self.rule(Matcher::Prefix(prefix.into()), every)
+    }
+
+    /// Keep one in `every` requests whose path matches `regex`.
+    #[cfg(feature = "regex")]
+    pub fn sample_regex(self, regex: regex::Regex, every: u64) -> Self {
+        self.rule(Matcher::Regex(regex), every)
+    }
+
+    fn skip(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(path))
+            .is_some_and(|rule| rule.skip())
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for AccessFilter
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Transform = AccessFilterService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessFilterService {
+            service,
+            filter: self.clone(),
+        }))
+    }
+}
+
+pub struct AccessFilterService<S> {
+    service: S,
+    filter: AccessFilter,
+}
+
+impl<S> Service<ServiceRequest> for AccessFilterService<S>
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        if self.filter.skip(request.path()) {
+            request.extensions_mut().insert(Skipped);
+        }
+        self.service.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_test() {
+        let filter = AccessFilter::new()
+            .exclude_prefix("/health")
+            .sample_prefix("/rest/sql", 3)
+            .exclude_prefix("/rest");
+        assert!(filter.skip("/health/live"));
+        assert!(!filter.skip("/metricsx"));
+        let sampled: Vec<_> = (0..6).map(|_| filter.skip("/rest/sql")).collect();
+        assert_eq!(sampled, [false, true, true, false, true, true]);
+        assert!(filter.skip("/rest/schemaless"));
+    }
+}
@@ -0,0 +1,249 @@
+//! HTTP endpoints to control the logging of a running node.
+//!
+//! [`service`] is an actix-web scope mounted at `/log`:
+//!
+//! | Endpoint | |
+//! |---|---|
+//! | `GET /log/level` | the directives, e.g. `info,hyper=warn` |
+//! | `PUT /log/level` | a level like `debug` sets the default level, directives replace all of them |
+//! | `POST /log/rotate` | switch the appender to a new file |
+//! | `GET /log/metrics` | the appender metrics in the Prometheus text format |
+//! | `GET /log/disk` | the disk pressure stage of the appender, since when, its thresholds and dropped events, in JSON |
+//! | `GET`, `PUT /log/ansi` | `true` or `false`, colors of text lines |
+//! | `GET`, `PUT /log/location` | `true` or `false`, `file:line` of text lines |
+//!
+//! ```ignore
+//! let handle = Handle::new(appender);
+//! let layer = TaosLayer::<Qid, _, _>::new(handle.clone());
+//! let admin = Admin::new(layer.reload_handle())
+//!     .display(layer.display_handle())
+//!     .appender(handle);
+//! HttpServer::new(move || App::new().service(admin::service(admin.clone())))
+//! ```
+//!
+//! The endpoints are not authenticated, mount them on an admin listener.
+
+use actix_web::{web, HttpResponse, Scope};
+use taoslog_core::{
+    filter::ReloadHandle,
+    layer::DisplayHandle,
+    writer::{Handle, RotationReason},
+};
+use tracing::level_filters::LevelFilter;
+
+/// What the endpoints of [`service`] control.
+#[derive(Clone)]
+pub struct Admin {
+    reload: ReloadHandle,
+    display: Option<DisplayHandle>,
+    appender: Option<Handle>,
+}
+
+impl Admin {
+    pub fn new(reload: impl Into<ReloadHandle>) -> Self {
+        Self {
+            reload: reload.into(),
+            display: None,
+            appender: None,
+        }
+    }
+
+    /// Enable the `ansi` and `location` endpoints.
+    pub fn display(self, display: DisplayHandle) -> Self {
+        Self {
+            display: Some(display),
+            ..self
+        }
+    }
+
+    /// Enable the `rotate`, `metrics` and `disk` endpoints.
+    pub fn appender(self, appender: impl Into<Handle>) -> Self {
+        Self {
+            appender: Some(appender.into()),
+            ..self
+        }
+    }
+}
+
+/// The `/log` scope of the endpoints.
+pub fn service(admin: Admin) -> Scope {
+    web::scope("/log")
+        .app_data(web::Data::new(admin))
+        .route("/level", web::get().to(get_level))
+        .route("/level", web::put().to(put_level))
+        .route("/rotate", web::post().to(rotate))
+        .route("/metrics", web::get().to(metrics))
+        .route("/disk", web::get().to(disk))
+        .route("/ansi", web::get().to(get_ansi))
+        .route("/ansi", web::put().to(put_ansi))
+        .route("/location", web::get().to(get_location))
+        .route("/location", web::put().to(put_location))
+}
+
+async fn get_level(admin: web::Data<Admin>) -> HttpResponse {
+    HttpResponse::Ok().body(admin.reload.directives().to_string())
+}
+
+async fn put_level(admin: web::Data<Admin>, body: String) -> HttpResponse {
+    let body = body.trim();
+    if let Ok(level) = body.parse::<LevelFilter>() {
+        admin.reload.set_level(level);
+    } else if let Err(e) = admin.reload.reload(body) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    tracing::warn!("log level changed to {body} through the admin endpoint");
+    get_level(admin).await
+}
+
+async fn rotate(admin: web::Data<Admin>) -> HttpResponse {
+    let Some(appender) = &admin.appender else {
+        return HttpResponse::NotFound().body("no appender");
+    };
+    match appender.rotate_now(RotationReason::Manual) {
+        Ok(()) => HttpResponse::Ok().body(appender.current_file_path().display().to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn metrics(admin: web::Data<Admin>) -> HttpResponse {
+    match &admin.appender {
+        Some(appender) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(appender.metrics().prometheus()),
+        None => HttpResponse::NotFound().body("no appender"),
+    }
+}
+
+async fn disk(admin: web::Data<Admin>) -> HttpResponse {
+    let Some(appender) = &admin.appender else {
+        return HttpResponse::NotFound().body("no appender");
+    };
+    let disk = appender.stats().disk;
+    HttpResponse::Ok().json(serde_json::json!({
+        "stage": format!("{:?}", disk.stage),
+        "since": disk.since.to_rfc3339(),
+        "available_space": disk.available_space,
+        "downgrade_threshold": disk.downgrade_threshold,
+        "stop_threshold": disk.stop_threshold,
+        "dropped_downgrade": disk.dropped_downgrade,
+        "dropped_stopped": disk.dropped_stopped,
+    }))
+}
+
+async fn get_ansi(admin: web::Data<Admin>) -> HttpResponse {
+    toggle(&admin, None, DisplayHandle::ansi, DisplayHandle::set_ansi)
+}
+
+async fn put_ansi(admin: web::Data<Admin>, body: String) -> HttpResponse {
+    toggle(
+        &admin,
+        Some(&body),
+        DisplayHandle::ansi,
+        DisplayHandle::set_ansi,
+    )
+}
+
+async fn get_location(admin: web::Data<Admin>) -> HttpResponse {
+    toggle(
+        &admin,
+        None,
+        DisplayHandle::location,
+        DisplayHandle::set_location,
+    )
+}
+
+async fn put_location(admin: web::Data<Admin>, body: String) -> HttpResponse {
+    toggle(
+        &admin,
+        Some(&body),
+        DisplayHandle::location,
+        DisplayHandle::set_location,
+    )
+}
+
+/// Read a display option, after setting it to `body` if any.
+fn toggle(
+    admin: &Admin,
+    body: Option<&str>,
+    get: fn(&DisplayHandle) -> bool,
+    set: fn(&DisplayHandle, bool),
+) -> HttpResponse {
+    let Some(display) = &admin.display else {
+        return HttpResponse::NotFound().body("no display handle");
+    };
+    if let Some(body) = body {
+        match body.trim().parse() {
+            Ok(value) => set(display, value),
+            Err(_) => return HttpResponse::BadRequest().body("expected true or false"),
+        }
+    }
+    HttpResponse::Ok().body(get(display).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::MessageBody,
+        http::{Method, StatusCode},
+        test, App,
+    };
+    use taoslog_core::filter::Directives;
+
+    use super::*;
+
+    #[test]
+    fn admin_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = taoslog_core::writer::RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .build()
+            .unwrap();
+        let directives = Directives::parse("info,hyper=warn").unwrap();
+        let display = DisplayHandle::default();
+        let admin = Admin::new(directives.clone())
+            .display(display.clone())
+            .appender(appender);
+        let handle = admin.appender.clone().unwrap();
+
+        actix_web::rt::System::new().block_on(async move {
+            let app = test::init_service(App::new().service(service(admin))).await;
+            let call = |method: Method, uri: &str, body: &str| {
+                let request = test::TestRequest::default()
+                    .method(method)
+                    .uri(uri)
+                    .set_payload(body.to_string())
+                    .to_request();
+                test::call_service(&app, request)
+            };
+            let text = |body: actix_web::body::BoxBody| {
+                String::from_utf8(body.try_into_bytes().unwrap().to_vec()).unwrap()
+            };
+
+            let response = call(Method::GET, "/log/level", "").await;
+            assert_eq!(text(response.into_body().boxed()), "info,hyper=warn");
+            let response = call(Method::PUT, "/log/level", "debug").await;
+            assert_eq!(text(response.into_body().boxed()), "debug,hyper=warn");
+            let response = call(Method::PUT, "/log/level", "warn,h2=oops").await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(directives.default_level(), Some(LevelFilter::DEBUG));
+
+            let first = handle.current_file_path();
+            let response = call(Method::POST, "/log/rotate", "").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_ne!(handle.current_file_path(), first);
+            let response = call(Method::GET, "/log/metrics", "").await;
+            let metrics = text(response.into_body().boxed());
+            assert!(metrics.contains("reason=\"manual\"} 1"), "{metrics}");
+            let response = call(Method::GET, "/log/disk", "").await;
+            let disk: serde_json::Value =
+                serde_json::from_str(&text(response.into_body().boxed())).unwrap();
+            assert_eq!(disk["stage"], "Normal");
+            assert_eq!(disk["dropped_downgrade"], 0);
+
+            let response = call(Method::PUT, "/log/location", "true").await;
+            assert_eq!(text(response.into_body().boxed()), "true");
+            assert!(display.location() && !display.ansi());
+            let response = call(Method::PUT, "/log/ansi", "yes").await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        });
+    }
+}
@@ -0,0 +1,329 @@
+//! actix-web integration of taoslog.
+
+use std::{borrow::Cow, marker::PhantomData, time::Instant};
+
+pub mod access;
+pub mod admin;
+mod qid;
+
+pub use qid::{QidExtractor, QidHeader, QidHeaderService};
+
+use actix_web::{http::StatusCode, HttpMessage};
+use tracing_actix_web::{root_span, RootSpanBuilder};
+use tracing_subscriber::{registry::LookupSpan, Registry};
+
+use taoslog_core::{
+    split::ACCESS_TARGET,
+    utils::{QidMetadataGetter, QidMetadataSetter},
+    QidManager,
+};
+
+/// Root span of each request, with its QID from the upstream header.
+///
+/// The request and response lines have the
+/// [`ACCESS_TARGET`](taoslog_core::split::ACCESS_TARGET) target, routed to an
+/// access log with [`TargetSplit`](taoslog_core::split::TargetSplit).
+///
+/// The response line has the time spent in milliseconds as `duration_elapsed`,
+/// at INFO, WARN for a 4xx status and ERROR for a 5xx status or an error. The
+/// time is kept in the span, with a subscriber built on a
+/// [`Registry`](tracing_subscriber::Registry).
+///
+/// Requests skipped by an [`AccessFilter`](access::AccessFilter) have no span.
+pub struct TaosRootSpanBuilder<Q>(PhantomData<Q>);
+
+/// Start and `"METHOD target"` of a request, in the extensions of its root
+/// span, for the response line of an error without request.
+struct RequestStart {
+    at: Instant,
+    line: String,
+}
+
+fn start(span: &tracing::Span, start: RequestStart) {
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(span) = dispatch.downcast_ref::<Registry>().and_then(|r| r.span(id)) {
+            span.extensions_mut().insert(start);
+        }
+    });
+}
+
+/// Take the elapsed milliseconds and request line of the request of `span`.
+fn finish(span: &tracing::Span) -> Option<(u64, String)> {
+    let start = span
+        .with_subscriber(|(id, dispatch)| {
+            let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+            let start = span.extensions_mut().remove::<RequestStart>();
+            start
+        })
+        .flatten()?;
+    Some((start.at.elapsed().as_millis() as u64, start.line))
+}
+
+/// Log an access line at the level of `status`.
+macro_rules! access {
+    ($status:expr, $($arg:tt)+) => {
+        let status: StatusCode = $status;
+        if status.is_server_error() {
+            tracing::error!(target: ACCESS_TARGET, $($arg)+);
+        } else if status.is_client_error() {
+            tracing::warn!(target: ACCESS_TARGET, $($arg)+);
+        } else {
+            tracing::info!(target: ACCESS_TARGET, $($arg)+);
+        }
+    };
+}
+
+impl<Q> RootSpanBuilder for TaosRootSpanBuilder<Q>
+where
+    Q: QidManager,
+{
+    fn on_request_start(request: &actix_web::dev::ServiceRequest) -> tracing::Span {
+        if request.extensions().contains::<access::Skipped>() {
+            return tracing::Span::none();
+        }
+        let connection_info = request.connection_info();
+        let schema = connection_info.scheme();
+        let flavor = http_flavor(request.version());
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .map(|h| h.to_str().unwrap_or(""))
+            .unwrap_or("");
+        let client_ip = connection_info.realip_remote_addr().unwrap_or("");
+        let method = request.method().as_str();
+        let target = request
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("");
+
+        let mut span = root_span!(level = tracing::Level::INFO, request);
+        // get qid from upstream header
+        if let Some(qid) = request.headers().get_qid::<Q>() {
+            span.set_qid(&qid);
+        }
+        span.in_scope(|| {
+            tracing::info!(target: ACCESS_TARGET, "{client_ip} \"{method} {target} {schema}/{flavor}\" {user_agent}");
+        });
+        let line = format!("{method} {target}");
+        start(
+            &span,
+            RequestStart {
+                at: Instant::now(),
+                line,
+            },
+        );
+
+        span
+    }
+
+    fn on_request_end<B: actix_web::body::MessageBody>(
+        span: tracing::Span,
+        outcome: &Result<actix_web::dev::ServiceResponse<B>, actix_web::error::Error>,
+    ) {
+        if span.is_none() {
+            return;
+        }
+        let (elapsed, line) = finish(&span).unzip();
+        match outcome {
+            Ok(response) => {
+                let status = response.response().status();
+                let code = status.as_u16();
+                let size = response.response().body().size();
+                let request = response.request();
+                let method = request.method().as_str();
+                let target = request
+                    .uri()
+                    .path_and_query()
+                    .map(|p| p.as_str())
+                    .unwrap_or("");
+                span.in_scope(|| {
+                    access!(
+                        status,
+                        duration_elapsed = elapsed,
+                        "\"{method} {target}\" status code: {code}, body: {size:?}"
+                    );
+                });
+            }
+            Err(error) => {
+                let status = error.as_response_error().status_code();
+                let code = status.as_u16();
+                let line = line.unwrap_or_default();
+                span.in_scope(|| {
+                    access!(
+                        status,
+                        duration_elapsed = elapsed,
+                        "\"{line}\" status code: {code}, error: {error}"
+                    );
+                });
+            }
+        }
+    }
+}
+
+pub fn http_flavor(version: actix_web::http::Version) -> Cow<'static, str> {
+    match version {
+        actix_web::http::Version::HTTP_09 => "0.9".into(),
+        actix_web::http::Version::HTTP_10 => "1.0".into(),
+        actix_web::http::Version::HTTP_11 => "1.1".into(),
+        actix_web::http::Version::HTTP_2 => "2.0".into(),
+        actix_web::http::Version::HTTP_3 => "3.0".into(),
+        other => format!("{other:?}").into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, error, test, web, App, HttpResponse};
+    use taoslog_core::{testing::CaptureWriter, utils::QID_HEADER_KEY};
+    use tracing::Level;
+    use tracing_actix_web::TracingLogger;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Qid(u64);
+
+    impl QidManager for Qid {
+        fn init() -> Self {
+            Self(0x1234)
+        }
+
+        fn get(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<u64> for Qid {
+        fn from(value: u64) -> Self {
+            Self(value)
+        }
+    }
+
+    #[test]
+    fn response_level_test() {
+        let capture = CaptureWriter::new();
+        let subscriber = tracing_subscriber::registry().with(capture.layer::<Qid, _>());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        actix_web::rt::System::new().block_on(async {
+            let app = test::init_service(
+                App::new()
+                    .route("/ok", web::get().to(|| async { "ok" }))
+                    .route(
+                        "/missing",
+                        web::get().to(|| async { HttpResponse::NotFound().finish() }),
+                    )
+                    .route(
+                        "/broken",
+                        web::get().to(|| async { HttpResponse::InternalServerError().finish() }),
+                    )
+                    .wrap_fn(|request, service| {
+                        let fail = request.path() == "/fail";
+                        let future = service.call(request);
+                        async move {
+                            if fail {
+                                return Err(error::ErrorBadGateway("upstream down"));
+                            }
+                            future.await
+                        }
+                    })
+                    .wrap(TracingLogger::<TaosRootSpanBuilder<Qid>>::new()),
+            )
+            .await;
+            for uri in ["/ok", "/missing", "/broken", "/fail"] {
+                let request = test::TestRequest::get().uri(uri).to_request();
+                let _ = test::try_call_service(&app, request).await;
+            }
+        });
+
+        let ok = capture.assert_event(Level::INFO, "\"GET /ok\" status code: 200");
+        assert_eq!(ok.0["target"], ACCESS_TARGET);
+        assert!(ok.field("duration_elapsed").is_some(), "{}", ok.0);
+        capture.assert_event(Level::WARN, "\"GET /missing\" status code: 404");
+        capture.assert_event(Level::ERROR, "\"GET /broken\" status code: 500");
+        let fail = capture.assert_event(
+            Level::ERROR,
+            "\"GET /fail\" status code: 502, error: upstream down",
+        );
+        assert!(fail.field("duration_elapsed").is_some(), "{}", fail.0);
+    }
+
+    #[test]
+    fn access_filter_test() {
+        let capture = CaptureWriter::new();
+        let subscriber = tracing_subscriber::registry().with(capture.layer::<Qid, _>());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        actix_web::rt::System::new().block_on(async {
+            let app = test::init_service(
+                App::new()
+                    .default_service(web::to(|| async {
+                        tracing::info!("handled");
+                        "ok"
+                    }))
+                    .wrap(TracingLogger::<TaosRootSpanBuilder<Qid>>::new())
+                    .wrap(access::AccessFilter::new().exclude_prefix("/health")),
+            )
+            .await;
+            for uri in ["/health", "/sql"] {
+                let request = test::TestRequest::get().uri(uri).to_request();
+                test::call_service(&app, request).await;
+            }
+        });
+
+        let events = capture.events();
+        assert_eq!(events.len(), 4, "{events:#?}");
+        assert!(events[0].contains("handled") && !events[0].contains("qid"));
+        assert!(events[1..].iter().all(|event| event.contains("/sql")));
+    }
+
+    #[test]
+    fn qid_header_and_extractor_test() {
+        let capture = CaptureWriter::new();
+        let subscriber = tracing_subscriber::registry().with(capture.layer::<Qid, _>());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        actix_web::rt::System::new().block_on(async {
+            let app = test::init_service(
+                App::new()
+                    .default_service(web::to(|qid: QidExtractor<Qid>| async move {
+                        tracing::info!("handled");
+                        format!("{:x}", qid.get())
+                    }))
+                    .wrap(QidHeader::<Qid>::new())
+                    .wrap(TracingLogger::<TaosRootSpanBuilder<Qid>>::new()),
+            )
+            .await;
+
+            // the upstream qid is kept
+            let request = test::TestRequest::get()
+                .uri("/sql")
+                .insert_header((QID_HEADER_KEY, "0x0000000000000abc"))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(
+                response.headers().get(QID_HEADER_KEY).unwrap(),
+                "0x0000000000000abc"
+            );
+            assert_eq!(test::read_body(response).await, "abc");
+
+            // a new qid is returned
+            let request = test::TestRequest::get().uri("/sql").to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(
+                response.headers().get(QID_HEADER_KEY).unwrap(),
+                "0x0000000000001234"
+            );
+        });
+
+        let handled: Vec<_> = capture
+            .captured()
+            .into_iter()
+            .filter(|event| event.message() == "handled")
+            .map(|event| event.qid())
+            .collect();
+        assert_eq!(handled, [Some(0xabc), Some(0x1234)]);
+    }
+}
@@ -0,0 +1,161 @@
+//! QID of the request in the `x-qid` response header and in handlers.
+
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    marker::PhantomData,
+    ops::Deref,
+    pin::Pin,
+};
+
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    FromRequest, HttpMessage, HttpRequest,
+};
+use tracing_actix_web::RootSpan;
+
+use taoslog_core::{
+    utils::{QidMetadataGetter, QidMetadataSetter},
+    QidManager,
+};
+
+/// Middleware returning the QID of each request in the `x-qid` response
+/// header, so clients can quote it when reporting a failure.
+///
+/// The QID is the one of the root span of the `TracingLogger`, from the
+/// upstream header or a new one, so wrap it inside of the `TracingLogger`:
+///
+/// ```ignore
+/// App::new()
+///     .wrap(QidHeader::<Qid>::new())
+///     .wrap(TracingLogger::<TaosRootSpanBuilder<Qid>>::new())
+/// ```
+///
+/// Without root span, the QID of the upstream header is returned, or a new
+/// one from [`QidManager::init`]. Errors of the service have no response yet
+/// and are passed through without header.
+pub struct QidHeader<Q>(PhantomData<fn(Q)>);
+
+impl<Q> QidHeader<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for QidHeader<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> Clone for QidHeader<Q> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<S, Q, B> Transform<S, ServiceRequest> for QidHeader<Q>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    Q: QidManager,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = QidHeaderService<S, Q>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(QidHeaderService {
+            service,
+            _q: PhantomData,
+        }))
+    }
+}
+
+pub struct QidHeaderService<S, Q> {
+    service: S,
+    _q: PhantomData<fn(Q)>,
+}
+
+impl<S, Q, B> Service<ServiceRequest> for QidHeaderService<S, Q>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    Q: QidManager,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let qid = request_qid::<Q>(request.request());
+        let future = self.service.call(request);
+        Box::pin(async move {
+            let mut response = future.await?;
+            response.headers_mut().set_qid(&qid);
+            Ok(response)
+        })
+    }
+}
+
+/// Extractor of the QID of the request, for handlers:
+///
+/// ```ignore
+/// async fn query(qid: QidExtractor<Qid>, sql: String) -> HttpResponse {
+///     let rows = taos.query_with_req_id(&sql, qid.get()).await;
+///     // ...
+/// }
+/// ```
+///
+/// It is the QID of the root span of the `TracingLogger`, or else of the
+/// current span, the `x-qid` header of the request, or a new one from
+/// [`QidManager::init`], set on the span.
+#[derive(Debug, Clone)]
+pub struct QidExtractor<Q>(pub Q);
+
+impl<Q> QidExtractor<Q> {
+    pub fn into_inner(self) -> Q {
+        self.0
+    }
+}
+
+impl<Q> Deref for QidExtractor<Q> {
+    type Target = Q;
+
+    fn deref(&self) -> &Q {
+        &self.0
+    }
+}
+
+impl<Q: QidManager> FromRequest for QidExtractor<Q> {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self(request_qid(request))))
+    }
+}
+
+/// The QID of the root span of `request` or of the current span, set on it
+/// when missing, or of its `x-qid` header, or a new one.
+fn request_qid<Q: QidManager>(request: &HttpRequest) -> Q {
+    let mut span = request
+        .extensions()
+        .get::<RootSpan>()
+        .map(|span| tracing::Span::clone(span))
+        .unwrap_or_else(tracing::Span::current);
+    if span.id().is_some() {
+        if let Some(qid) = span.get_qid::<Q>() {
+            return qid;
+        }
+    }
+    let qid = request.headers().get_qid::<Q>().unwrap_or_else(Q::init);
+    if span.id().is_some() {
+        span.set_qid(&qid);
+    }
+    qid
+}
@@ -0,0 +1,281 @@
+//! QID propagation for Arrow Flight streams, like the HTTP middleware does
+//! for requests.
+//!
+//! ```ignore
+//! // client: stamp the QID of the current span on each outgoing message
+//! let response = client.do_exchange(QidFlightStream::new::<Qid>(input)).await?;
+//!
+//! // server: a span with the QID of the caller, and the answer stamped with it
+//! async fn do_exchange(
+//!     &self,
+//!     request: Request<Streaming<FlightData>>,
+//! ) -> Result<Response<Self::DoExchangeStream>, Status> {
+//!     let mut input = request.into_inner();
+//!     let first = input.message().await?;
+//!     let span = taoslog::flight::span::<Qid>(first.as_ref());
+//!     let output = span.in_scope(|| QidFlightStream::new::<Qid>(exchange(first, input)));
+//!     Ok(Response::new(Box::pin(output)))
+//! }
+//! ```
+//!
+//! The QID is written in `app_metadata` in the hex form of the `x-qid`
+//! header, only on messages without app metadata of their own.
+//!
+//! Calls taking a `FlightDescriptor` or a `Ticket`, like `get_flight_info` and
+//! `do_get`, carry the QID in the `x-qid` metadata of their request:
+//!
+//! ```ignore
+//! // client
+//! let stream = client.do_get(taoslog::flight::request::<Qid, _>(ticket)).await?;
+//!
+//! // server
+//! async fn do_get(&self, request: Request<Ticket>) -> Result<Response<Self::DoGetStream>, Status> {
+//!     let span = taoslog::flight::request_span::<Qid, _>(&request);
+//!     let output = span.in_scope(|| QidFlightStream::new::<Qid>(get(request.into_inner())));
+//!     Ok(Response::new(Box::pin(output)))
+//! }
+//! ```
+
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use arrow_flight::FlightData;
+use bytes::Bytes;
+use futures_core::Stream;
+
+use taoslog_core::{
+    utils::{qid_from_hex, qid_to_hex, QidMetadataGetter, QidMetadataSetter, Span},
+    QidManager,
+};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Items of a stream wrapped by [`QidFlightStream`]: `FlightData` on the
+/// client side, `Result<FlightData, Status>` on the server side.
+pub trait FlightItem: private::Sealed {
+    fn app_metadata(&mut self) -> Option<&mut Bytes>;
+}
+
+impl FlightItem for FlightData {
+    fn app_metadata(&mut self) -> Option<&mut Bytes> {
+        Some(&mut self.app_metadata)
+    }
+}
+
+impl private::Sealed for FlightData {}
+
+impl<E> FlightItem for Result<FlightData, E> {
+    fn app_metadata(&mut self) -> Option<&mut Bytes> {
+        self.as_mut().ok().map(|data| &mut data.app_metadata)
+    }
+}
+
+impl<E> private::Sealed for Result<FlightData, E> {}
+
+pin_project_lite::pin_project! {
+    /// An outgoing Flight stream in a child span of the current span, with
+    /// the QID of the span in the `app_metadata` of its messages.
+    pub struct QidFlightStream<S> {
+        #[pin]
+        inner: S,
+        span: tracing::Span,
+        qid: Bytes,
+    }
+}
+
+impl<S> QidFlightStream<S> {
+    pub fn new<Q: QidManager>(inner: S) -> Self {
+        let mut span = tracing::info_span!("flight stream");
+        let qid = span
+            .get_qid::<Q>()
+            .or_else(|| Span.get_qid())
+            .unwrap_or_else(Q::init);
+        span.set_qid(&qid);
+        Self {
+            inner,
+            span,
+            qid: qid_to_hex(qid.get()).into(),
+        }
+    }
+
+    /// The span of the stream.
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Stream for QidFlightStream<S>
+where
+    S: Stream,
+    S::Item: FlightItem,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        let mut item = ready!(this.inner.poll_next(cx));
+        if let Some(metadata) = item.as_mut().and_then(FlightItem::app_metadata) {
+            if metadata.is_empty() {
+                *metadata = this.qid.clone();
+            }
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// The QID stamped on a message by [`QidFlightStream`].
+pub fn qid<Q: QidManager>(data: &FlightData) -> Option<Q> {
+    std::str::from_utf8(&data.app_metadata)
+        .ok()
+        .and_then(qid_from_hex)
+        .map(Q::from)
+}
+
+/// A root span for an incoming stream, carrying the QID of its first
+/// message, or a new one.
+pub fn span<Q: QidManager>(first: Option<&FlightData>) -> tracing::Span {
+    let mut span = tracing::info_span!(parent: None, "flight stream");
+    let qid = first.and_then(qid::<Q>).unwrap_or_else(Q::init);
+    span.set_qid(&qid);
+    span
+}
+
+/// A request of `message` with the QID of the current span, if any.
+pub fn request<Q, T>(message: T) -> tonic::Request<T>
+where
+    Q: QidManager,
+    tonic::Request<T>: QidMetadataSetter,
+{
+    let mut request = tonic::Request::new(message);
+    if let Some(qid) = Span.get_qid::<Q>() {
+        request.set_qid(&qid);
+    }
+    request
+}
+
+/// A root span for an incoming request, carrying its QID, or a new one.
+pub fn request_span<Q, T>(request: &tonic::Request<T>) -> tracing::Span
+where
+    Q: QidManager,
+    tonic::Request<T>: QidMetadataGetter,
+{
+    let mut span = tracing::info_span!(parent: None, "flight request");
+    let qid = request.get_qid::<Q>().unwrap_or_else(Q::init);
+    span.set_qid(&qid);
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use taoslog_core::layer::TaosLayer;
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    use super::*;
+    use crate::fake::Qid;
+
+    fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut items = Vec::new();
+            while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                items.push(item);
+            }
+            items
+        })
+    }
+
+    struct Iter<I>(I);
+
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    #[test]
+    fn stream_test() {
+        let _guard = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(std::sync::Mutex::new(
+                std::io::empty(),
+            )))
+            .set_default();
+
+        let messages = vec![
+            FlightData::new(),
+            FlightData::new().with_app_metadata("offset=10"),
+        ];
+        let stream = tracing::info_span!("client").in_scope(|| {
+            Span.set_qid(&Qid::from(0xabc));
+            QidFlightStream::new::<Qid>(Iter(messages.into_iter()))
+        });
+        assert_eq!(stream.span().get_qid::<Qid>().unwrap().get(), 0xabc);
+        let sent = collect(stream);
+        assert_eq!(&sent[0].app_metadata[..], b"0x0000000000000abc");
+        assert_eq!(&sent[1].app_metadata[..], b"offset=10");
+
+        let server = span::<Qid>(sent.first());
+        assert_eq!(server.get_qid::<Qid>().unwrap().get(), 0xabc);
+        let answer = vec![Ok(FlightData::new()), Err("failed")];
+        let stream = server.in_scope(|| QidFlightStream::new::<Qid>(Iter(answer.into_iter())));
+        let answer = collect(stream);
+        assert_eq!(
+            qid::<Qid>(answer[0].as_ref().unwrap()).unwrap().get(),
+            0xabc
+        );
+        assert_eq!(answer[1], Err("failed"));
+
+        // a new qid without upstream
+        assert_eq!(
+            span::<Qid>(None).get_qid::<Qid>().unwrap().get(),
+            Qid::init().get()
+        );
+    }
+
+    #[test]
+    fn request_test() {
+        use arrow_flight::{FlightDescriptor, Ticket};
+
+        let _guard = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(std::sync::Mutex::new(
+                std::io::empty(),
+            )))
+            .set_default();
+
+        let ticket = tracing::info_span!("client").in_scope(|| {
+            Span.set_qid(&Qid::from(0xabc));
+            request::<Qid, _>(Ticket::new("q1"))
+        });
+        let server = request_span::<Qid, _>(&ticket);
+        assert_eq!(server.get_qid::<Qid>().unwrap().get(), 0xabc);
+        assert_eq!(&ticket.into_inner().ticket[..], b"q1");
+
+        // a new qid without upstream
+        let descriptor = tonic::Request::new(FlightDescriptor::new_path(vec!["meters".into()]));
+        assert_eq!(
+            request_span::<Qid, _>(&descriptor)
+                .get_qid::<Qid>()
+                .unwrap()
+                .get(),
+            Qid::init().get()
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! QID propagation for Arrow Flight, see [`flight`].
+//!
+//! The QID carriers of `arrow_schema::Schema` and of the Flight requests are
+//! in `taoslog-core`, with its `arrow` and `flight` features.
+
+pub mod flight;
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use taoslog_core::QidManager;
+
+    #[derive(Clone)]
+    pub(crate) struct Qid(u64);
+
+    impl QidManager for Qid {
+        fn init() -> Self {
+            Self(9223372036854775807)
+        }
+
+        fn get(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<u64> for Qid {
+        fn from(value: u64) -> Self {
+            Self(value)
+        }
+    }
+}
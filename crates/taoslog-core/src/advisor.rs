@@ -0,0 +1,198 @@
+//! Usage of the rotation, compression and cleanup settings of an appender,
+//! with suggestions when they do not fit the log volume.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::humanize;
+
+/// Size rotations per day above which `rotation_size` is too small.
+const MAX_ROTATIONS_PER_DAY: f64 = 48.;
+/// Compression saving less than this share of the size is not worth it.
+const MIN_COMPRESSION_SAVING: f64 = 0.1;
+/// Files deleted younger than this leave too little history.
+const MIN_RETENTION: Duration = Duration::from_secs(24 * 3600);
+/// Rates are not extrapolated from a shorter uptime.
+const MIN_UPTIME: Duration = Duration::from_secs(3600);
+
+/// Counters updated by the appender and its maintenance thread.
+pub(crate) struct Usage {
+    started: Instant,
+    rotations: AtomicU64,
+    size_rotations: AtomicU64,
+    compressed_files: AtomicU64,
+    bytes_before_compression: AtomicU64,
+    bytes_after_compression: AtomicU64,
+    deleted_files: AtomicU64,
+    /// in seconds, `u64::MAX` until a file is deleted
+    min_deleted_age: AtomicU64,
+}
+
+impl Usage {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            rotations: AtomicU64::new(0),
+            size_rotations: AtomicU64::new(0),
+            compressed_files: AtomicU64::new(0),
+            bytes_before_compression: AtomicU64::new(0),
+            bytes_after_compression: AtomicU64::new(0),
+            deleted_files: AtomicU64::new(0),
+            min_deleted_age: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub(crate) fn rotated(&self, by_size: bool) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+        if by_size {
+            self.size_rotations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn compressed(&self, before: u64, after: u64) {
+        self.compressed_files.fetch_add(1, Ordering::Relaxed);
+        self.bytes_before_compression
+            .fetch_add(before, Ordering::Relaxed);
+        self.bytes_after_compression
+            .fetch_add(after, Ordering::Relaxed);
+    }
+
+    /// A file last written `age` ago was deleted.
+    pub(crate) fn deleted(&self, age: Duration) {
+        self.deleted_files.fetch_add(1, Ordering::Relaxed);
+        self.min_deleted_age
+            .fetch_min(age.as_secs(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self, settings: Settings) -> UsageStats {
+        let min_deleted_age = self.min_deleted_age.load(Ordering::Relaxed);
+        UsageStats {
+            uptime: self.started.elapsed(),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            size_rotations: self.size_rotations.load(Ordering::Relaxed),
+            compressed_files: self.compressed_files.load(Ordering::Relaxed),
+            bytes_before_compression: self.bytes_before_compression.load(Ordering::Relaxed),
+            bytes_after_compression: self.bytes_after_compression.load(Ordering::Relaxed),
+            deleted_files: self.deleted_files.load(Ordering::Relaxed),
+            min_deleted_age: (min_deleted_age != u64::MAX)
+                .then(|| Duration::from_secs(min_deleted_age)),
+            settings,
+        }
+    }
+}
+
+/// The settings the suggestions are about.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Settings {
+    pub rotation_size: u64,
+    pub rotation_count: usize,
+}
+
+/// Snapshot of the rotation, compression and deletion usage of an appender
+/// since it was built.
+#[derive(Debug, Clone, Default)]
+pub struct UsageStats {
+    pub uptime: Duration,
+    pub rotations: u64,
+    /// Rotations because the file reached `rotation_size`.
+    pub size_rotations: u64,
+    pub compressed_files: u64,
+    pub bytes_before_compression: u64,
+    pub bytes_after_compression: u64,
+    pub deleted_files: u64,
+    /// Shortest time between the last write to a deleted file and its deletion.
+    pub min_deleted_age: Option<Duration>,
+    pub settings: Settings,
+}
+
+impl UsageStats {
+    /// Compressed size over the original size of the compressed files.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.bytes_before_compression > 0)
+            .then(|| self.bytes_after_compression as f64 / self.bytes_before_compression as f64)
+    }
+
+    /// Size rotations per day, `None` within the first hour.
+    pub fn size_rotations_per_day(&self) -> Option<f64> {
+        (self.uptime >= MIN_UPTIME)
+            .then(|| self.size_rotations as f64 * 86400. / self.uptime.as_secs_f64())
+    }
+
+    /// Suggestions on the settings, empty when they fit.
+    pub fn advisor(&self) -> Vec<String> {
+        let mut advice = Vec::new();
+        if let Some(rate) = self
+            .size_rotations_per_day()
+            .filter(|rate| *rate > MAX_ROTATIONS_PER_DAY)
+        {
+            advice.push(format!(
+                "rotation_size too small: {rate:.0} rotations/day of {}, consider {}",
+                humanize::bytes(self.settings.rotation_size),
+                humanize::bytes((self.settings.rotation_size as f64 * rate / 24.).ceil() as u64),
+            ));
+        }
+        if let Some(ratio) = self
+            .compression_ratio()
+            .filter(|ratio| 1. - ratio < MIN_COMPRESSION_SAVING)
+        {
+            advice.push(format!(
+                "compression saves <10% ({:.1}% over {} files), consider disabling",
+                (1. - ratio) * 100.,
+                self.compressed_files
+            ));
+        }
+        if let Some(age) = self.min_deleted_age.filter(|age| *age < MIN_RETENTION) {
+            advice.push(format!(
+                "files deleted {} after their last write: rotation_count {} keeps less than a day of logs",
+                humanize::duration(age),
+                self.settings.rotation_count
+            ));
+        }
+        advice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advisor_test() {
+        let settings = Settings {
+            rotation_size: 1024 * 1024,
+            rotation_count: 3,
+        };
+        assert!(UsageStats::default().advisor().is_empty());
+
+        let usage = Usage::new();
+        usage.rotated(true);
+        usage.rotated(false);
+        usage.compressed(1000, 950);
+        usage.compressed(1000, 940);
+        usage.deleted(Duration::from_secs(7200));
+        usage.deleted(Duration::from_secs(600));
+        let stats = usage.stats(settings);
+        assert_eq!(stats.rotations, 2);
+        assert_eq!(stats.size_rotations, 1);
+        assert_eq!(stats.compression_ratio(), Some(0.945));
+        assert_eq!(stats.min_deleted_age, Some(Duration::from_secs(600)));
+        // too early for a rate
+        assert_eq!(stats.size_rotations_per_day(), None);
+
+        let stats = UsageStats {
+            uptime: Duration::from_secs(8 * 3600),
+            size_rotations: 25,
+            ..stats
+        };
+        let advice = stats.advisor();
+        assert_eq!(advice.len(), 3, "{advice:?}");
+        assert_eq!(
+            advice[0],
+            "rotation_size too small: 75 rotations/day of 1.0MiB, consider 3.1MiB"
+        );
+        assert!(advice[1].starts_with("compression saves <10% (5.5% over 2 files)"));
+        assert!(advice[2].contains("rotation_count 3"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Operator-facing marker lines in the language of the deployment.
+//!
+//! Only the human text of marker lines, like the disk pressure banners and the
+//! suppressed events summaries, is translated. Levels, field names and values
+//! stay the same in every language, for log parsers and alert rules.
+
+/// Language of the marker lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Chinese,
+}
+
+impl Language {
+    /// The language of `LC_ALL`, `LC_MESSAGES` or `LANG`, like `zh_CN.UTF-8`,
+    /// English if unset or unknown.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| std::env::var(key).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Self::English, |locale| Self::from_locale(&locale))
+    }
+
+    /// The language of a locale name like `zh_CN.UTF-8` or `en-US`.
+    pub fn from_locale(locale: &str) -> Self {
+        if locale.to_ascii_lowercase().starts_with("zh") {
+            Self::Chinese
+        } else {
+            Self::English
+        }
+    }
+
+    /// Written when the disk space falls below the reserved size.
+    pub(crate) fn level_downgrade(self) -> &'static str {
+        match self {
+            Self::English => "=======level downgrade=====\n",
+            Self::Chinese => "=======日志级别降级=====\n",
+        }
+    }
+
+    /// Written when the disk space is back above the reserved size.
+    pub(crate) fn level_upgrade(self) -> &'static str {
+        match self {
+            Self::English => "=======level upgrade=====\n",
+            Self::Chinese => "=======日志级别恢复=====\n",
+        }
+    }
+
+    /// Written after the level upgrade line, with the counts of the events
+    /// dropped under disk pressure, like `1243 INFO, 15 WARN`.
+    pub(crate) fn suppressed_during_downgrade(self, counts: &str) -> String {
+        match self {
+            Self::English => format!("suppressed {counts} events during downgrade\n"),
+            Self::Chinese => format!("日志级别降级期间已抑制 {counts} 条日志\n"),
+        }
+    }
+
+    /// Written when SIGUSR1 raises the level to DEBUG for `duration`.
+    #[cfg(unix)]
+    pub(crate) fn level_debug(self, duration: &str) -> String {
+        match self {
+            Self::English => format!("=======level debug for {duration}=====\n"),
+            Self::Chinese => format!("=======日志级别临时调整为 DEBUG，持续 {duration}=====\n"),
+        }
+    }
+
+    /// Written when the level raised by SIGUSR1 is back to `directives`.
+    #[cfg(unix)]
+    pub(crate) fn level_restore(self, directives: &str) -> String {
+        match self {
+            Self::English => format!("=======level restored to {directives}=====\n"),
+            Self::Chinese => format!("=======日志级别已恢复为 {directives}=====\n"),
+        }
+    }
+
+    /// Message of the summary of the events dropped by a rate limit.
+    pub(crate) fn suppressed(self, count: u64, callsite: &str) -> String {
+        match self {
+            Self::English => format!("suppressed {count} events from {callsite}"),
+            Self::Chinese => format!("已抑制来自 {callsite} 的 {count} 条日志"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_test() {
+        assert_eq!(Language::from_locale("zh_CN.UTF-8"), Language::Chinese);
+        assert_eq!(Language::from_locale("zh-Hans"), Language::Chinese);
+        assert_eq!(Language::from_locale("en_US.UTF-8"), Language::English);
+        assert_eq!(Language::from_locale("C"), Language::English);
+    }
+}
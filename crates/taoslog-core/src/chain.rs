@@ -0,0 +1,236 @@
+//! Tamper-evident hash chain of the lines of log files, for audit deployments.
+//!
+//! With [`RollingFileAppenderBuilder::hash_chain`], each line ends with the
+//! first 8 bytes of `sha256(previous || line)`, in hex, where `previous` is the
+//! full hash of the previous line of the file, zeros for the first one:
+//!
+//! ```text
+//! 01/02 15:04:05.000000 1234 INFO  login ok chain:3f2a9c01d4e5b6a7
+//! {"timestamp":"...","message":"login ok","chain":"3f2a9c01d4e5b6a7"}
+//! ```
+//!
+//! Each `write` to the appender is sealed as one line, as
+//! [`TaosLayer`](crate::layer::TaosLayer) writes each event at once.
+//!
+//! A modified, inserted or deleted line breaks the chain from there on, see
+//! [`verify`]. Each rotated file also gets a `<name>.sha256` digest, in the
+//! `sha256sum` format, so a truncated tail is detected too.
+//!
+//! [`RollingFileAppenderBuilder::hash_chain`]: crate::writer::RollingFileAppenderBuilder::hash_chain
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use parking_lot::{Mutex, MutexGuard};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+
+use crate::{HashChainBrokenSnafu, HashChainSnafu, Result};
+
+type Hash = [u8; 32];
+
+const TAG_LEN: usize = 16;
+const TEXT_SUFFIX: &[u8] = b" chain:";
+const JSON_SUFFIX: &[u8] = b",\"chain\":\"";
+
+/// Chain state of the active file, reset on each new file.
+pub(crate) struct HashChain {
+    last: Mutex<Hash>,
+}
+
+impl HashChain {
+    pub(crate) fn new() -> Self {
+        Self {
+            last: Mutex::new([0; 32]),
+        }
+    }
+
+    /// Held while sealing and writing a line, so lines land in chain order.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Hash> {
+        self.last.lock()
+    }
+
+    /// Restart the chain, locked until the new file is in place.
+    pub(crate) fn reset(&self) -> MutexGuard<'_, Hash> {
+        let mut last = self.last.lock();
+        *last = [0; 32];
+        last
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn next(last: &Hash, line: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(last);
+    hasher.update(line);
+    hasher.finalize().into()
+}
+
+/// `buf` with the chain value appended to its line, advancing `last`.
+pub(crate) fn seal(last: &mut Hash, buf: &[u8]) -> Vec<u8> {
+    let line = buf.strip_suffix(b"\n").unwrap_or(buf);
+    *last = next(last, line);
+    let tag = hex(&last[..TAG_LEN / 2]);
+    let mut sealed = Vec::with_capacity(buf.len() + JSON_SUFFIX.len() + TAG_LEN + 3);
+    match line.strip_suffix(b"}") {
+        // a JSON object gets a field, to stay parseable
+        Some(object) if line.starts_with(b"{") && object.len() > 1 => {
+            sealed.extend_from_slice(object);
+            sealed.extend_from_slice(JSON_SUFFIX);
+            sealed.extend_from_slice(tag.as_bytes());
+            sealed.extend_from_slice(b"\"}");
+        }
+        _ => {
+            sealed.extend_from_slice(line);
+            sealed.extend_from_slice(TEXT_SUFFIX);
+            sealed.extend_from_slice(tag.as_bytes());
+        }
+    }
+    sealed.push(b'\n');
+    sealed
+}
+
+/// The line without its chain value, and the chain value.
+fn unseal(line: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    if let Some(rest) = line.strip_suffix(b"\"}") {
+        let (object, tag) = rest.split_at_checked(rest.len().checked_sub(TAG_LEN)?)?;
+        if let Some(object) = object.strip_suffix(JSON_SUFFIX) {
+            let mut original = object.to_vec();
+            original.push(b'}');
+            return Some((original, tag));
+        }
+    }
+    let (text, tag) = line.split_at_checked(line.len().checked_sub(TAG_LEN)?)?;
+    let text = text.strip_suffix(TEXT_SUFFIX)?;
+    Some((text.to_vec(), tag))
+}
+
+/// Check the chain of a whole log file, returning its number of lines.
+///
+/// Fails at the first line whose chain value does not match, i.e. the first
+/// line after a modified, inserted or deleted one.
+pub fn verify(mut reader: impl BufRead) -> Result<u64> {
+    let mut last = [0; 32];
+    let (mut lines, mut record) = (0, Vec::new());
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader
+            .read_until(b'\n', &mut line)
+            .context(HashChainSnafu)?
+            == 0
+        {
+            break;
+        }
+        lines += 1;
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        // lines of a multi-line event until the sealed one
+        let Some((original, tag)) = unseal(content) else {
+            record.extend_from_slice(content);
+            record.push(b'\n');
+            continue;
+        };
+        record.extend_from_slice(&original);
+        last = next(&last, &record);
+        record.clear();
+        if hex(&last[..TAG_LEN / 2]).as_bytes() != tag {
+            return HashChainBrokenSnafu { line: lines }.fail();
+        }
+    }
+    if !record.is_empty() {
+        return HashChainBrokenSnafu { line: lines }.fail();
+    }
+    Ok(lines)
+}
+
+/// The digest file of a log file or of its archive.
+pub(crate) fn digest_path(path: &Path) -> PathBuf {
+    let mut name = path.to_string_lossy().into_owned();
+    for extension in [".enc", ".gz", ".zst"] {
+        if let Some(stripped) = name.strip_suffix(extension) {
+            name.truncate(stripped.len());
+        }
+    }
+    PathBuf::from(format!("{name}.sha256"))
+}
+
+/// Write the `sha256sum` line of `path` to its digest file.
+pub(crate) fn write_digest(path: &Path) -> io::Result<PathBuf> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let digest_path = digest_path(path);
+    fs::write(
+        &digest_path,
+        format!("{}  {name}\n", hex(&hasher.finalize())),
+    )?;
+    Ok(digest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(lines: &[&str]) -> Vec<u8> {
+        let mut last = [0; 32];
+        lines
+            .iter()
+            .flat_map(|line| seal(&mut last, line.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn verify_test() {
+        let lines = [
+            "INFO first\n",
+            "{\"message\":\"json\"}\n",
+            "ERROR multi\nline\n",
+            "{}\n",
+        ];
+        let file = sealed(&lines);
+        let text = String::from_utf8(file.clone()).unwrap();
+        assert!(text.starts_with("INFO first chain:"), "{text}");
+        let json = text.lines().nth(1).unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(json).unwrap()["chain"].is_string());
+        assert_eq!(verify(file.as_slice()).unwrap(), 5);
+
+        // modified
+        let modified = text.replace("first", "frist");
+        assert_eq!(
+            verify(modified.as_bytes()).unwrap_err().to_string(),
+            "Hash chain broken at line 1"
+        );
+        // deleted
+        let deleted: String = text
+            .lines()
+            .skip(1)
+            .map(|line| format!("{line}\n"))
+            .collect();
+        assert!(verify(deleted.as_bytes()).is_err());
+        // unsealed tail
+        assert!(verify(format!("{text}appended\n").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn digest_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("taosx_1_20240901.log.1");
+        fs::write(&path, "hello\n").unwrap();
+        let digest = write_digest(&path).unwrap();
+        assert_eq!(digest, dir.path().join("taosx_1_20240901.log.1.sha256"));
+        assert_eq!(
+            fs::read_to_string(digest).unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  taosx_1_20240901.log.1\n"
+        );
+        assert_eq!(
+            digest_path(&dir.path().join("taosx_1_20240901.log.1.gz.enc")),
+            dir.path().join("taosx_1_20240901.log.1.sha256")
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! QID propagation for outgoing reqwest calls, in the `x-qid` header.
+//!
+//! ```ignore
+//! // one request
+//! let mut request = client.post(url).body(sql).build()?;
+//! propagate_qid::<Qid>(&mut request);
+//! let response = client.execute(request).await?;
+//!
+//! // every request of a client
+//! let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(QidMiddleware::<Qid>::new())
+//!     .build();
+//! ```
+
+use std::marker::PhantomData;
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Request, Response,
+};
+use reqwest_middleware::{Middleware, Next};
+
+use crate::{
+    utils::{qid_to_hex, QidMetadataGetter, Span, QID_HEADER_KEY},
+    QidManager,
+};
+
+fn set_qid<Q: QidManager>(headers: &mut HeaderMap) {
+    if headers.contains_key(QID_HEADER_KEY) {
+        return;
+    }
+    if let Some(qid) = Span.get_qid::<Q>() {
+        if let Ok(value) = HeaderValue::from_str(&qid_to_hex(qid.get())) {
+            headers.insert(QID_HEADER_KEY, value);
+        }
+    }
+}
+
+/// Set the `x-qid` header of `request` to the QID of the current span, unless
+/// it has one.
+pub fn propagate_qid<Q: QidManager>(request: &mut Request) {
+    set_qid::<Q>(request.headers_mut());
+}
+
+/// Sets the `x-qid` header of outgoing requests without one to the QID of
+/// the current span.
+pub struct QidMiddleware<Q>(PhantomData<fn(Q)>);
+
+impl<Q> QidMiddleware<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for QidMiddleware<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> Clone for QidMiddleware<Q> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<Q: QidManager> Middleware for QidMiddleware<Q> {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        propagate_qid::<Q>(&mut request);
+        next.run(request, extensions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    use super::*;
+    use crate::{fake::Qid, layer::TaosLayer, utils::QidMetadataSetter};
+
+    /// Answers with the `x-qid` header of the request as body.
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl Middleware for Echo {
+        async fn handle(
+            &self,
+            request: Request,
+            _: &mut http::Extensions,
+            _: Next<'_>,
+        ) -> reqwest_middleware::Result<Response> {
+            let qid = request
+                .headers()
+                .get(QID_HEADER_KEY)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            Ok(Response::from(http::Response::new(qid)))
+        }
+    }
+
+    #[test]
+    fn propagate_qid_test() {
+        let _guard = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(std::sync::Mutex::new(
+                std::io::empty(),
+            )))
+            .set_default();
+        let client = reqwest::Client::new();
+
+        let request = tracing::info_span!("call").in_scope(|| {
+            Span.set_qid(&Qid::from(0xabc));
+            let mut request = client
+                .get("http://taosadapter:6041/rest/sql")
+                .build()
+                .unwrap();
+            propagate_qid::<Qid>(&mut request);
+            request
+        });
+        assert_eq!(request.headers()[QID_HEADER_KEY], "0x0000000000000abc");
+
+        // the qid of the caller is kept
+        let request = tracing::info_span!("call").in_scope(|| {
+            Span.set_qid(&Qid::from(0xabc));
+            let mut request = client
+                .get("http://taosadapter:6041/rest/sql")
+                .header(QID_HEADER_KEY, "0x0000000000000def")
+                .build()
+                .unwrap();
+            propagate_qid::<Qid>(&mut request);
+            request
+        });
+        assert_eq!(request.headers()[QID_HEADER_KEY], "0x0000000000000def");
+
+        let client = reqwest_middleware::ClientBuilder::new(client)
+            .with(QidMiddleware::<Qid>::new())
+            .with(Echo)
+            .build();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let send = |request: reqwest_middleware::RequestBuilder| {
+            tracing::info_span!("call").in_scope(|| {
+                Span.set_qid(&Qid::from(0x123));
+                runtime.block_on(async { request.send().await.unwrap().text().await.unwrap() })
+            })
+        };
+        let body = send(client.get("http://taosadapter:6041/rest/sql"));
+        assert_eq!(body, "0x0000000000000123");
+
+        // the qid of the caller is kept
+        let body = send(
+            client
+                .get("http://taosadapter:6041/rest/sql")
+                .header(QID_HEADER_KEY, "0x0000000000000def"),
+        );
+        assert_eq!(body, "0x0000000000000def");
+    }
+}
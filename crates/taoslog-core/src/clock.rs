@@ -0,0 +1,346 @@
+//! Clock used for event timestamps.
+
+use std::{
+    fmt::Write,
+    sync::{Arc, Once},
+    time::{Duration, Instant},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, FixedOffset, Local, TimeDelta, Timelike, Utc,
+};
+use parking_lot::Mutex;
+
+use crate::{InvalidTimeFormatSnafu, Result};
+
+/// Interval to re-anchor the monotonic clock on the system clock.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Source of event timestamps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The system clock, which may go backwards under clock jumps.
+    #[default]
+    System,
+    /// A monotonic clock anchored on the system clock at startup and
+    /// re-synced every minute. Timestamps never decrease within a process.
+    Monotonic,
+}
+
+pub(crate) struct Clock {
+    source: ClockSource,
+    resync: Duration,
+    /// `None` for the local timezone
+    offset: Option<FixedOffset>,
+    anchor: Mutex<Anchor>,
+}
+
+struct Anchor {
+    wall: DateTime<Utc>,
+    instant: Instant,
+    last: DateTime<Utc>,
+}
+
+impl Clock {
+    pub(crate) fn new(source: ClockSource) -> Self {
+        Self::with_resync(source, RESYNC_INTERVAL)
+    }
+
+    fn with_resync(source: ClockSource, resync: Duration) -> Self {
+        let wall = Utc::now();
+        Self {
+            source,
+            resync,
+            offset: None,
+            anchor: Mutex::new(Anchor {
+                wall,
+                instant: Instant::now(),
+                last: wall,
+            }),
+        }
+    }
+
+    /// Format timestamps with `offset` instead of the local timezone.
+    pub(crate) fn with_offset(self, offset: Option<FixedOffset>) -> Self {
+        if offset.is_none() {
+            check_local_timezone();
+        }
+        Self { offset, ..self }
+    }
+
+    pub(crate) fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    pub(crate) fn offset(&self) -> Option<FixedOffset> {
+        self.offset
+    }
+
+    pub(crate) fn now(&self) -> DateTime<FixedOffset> {
+        let now = self.now_utc();
+        match self.offset {
+            Some(offset) => now.with_timezone(&offset),
+            None => now.with_timezone(&Local).fixed_offset(),
+        }
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        match self.source {
+            ClockSource::System => Utc::now(),
+            ClockSource::Monotonic => {
+                let mut anchor = self.anchor.lock();
+                let mut elapsed = anchor.instant.elapsed();
+                if elapsed >= self.resync {
+                    anchor.wall = Utc::now();
+                    anchor.instant = Instant::now();
+                    elapsed = Duration::ZERO;
+                }
+                let now = anchor.wall + TimeDelta::from_std(elapsed).unwrap_or(TimeDelta::MAX);
+                // the system clock went backwards at resync, hold until it catches up
+                anchor.last = anchor.last.max(now);
+                anchor.last
+            }
+        }
+    }
+}
+
+/// Fractional digits of the seconds in timestamps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    Millis,
+    #[default]
+    Micros,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+enum TimeFormat {
+    /// `08/23 17:28:01.123456`, as taosd
+    #[default]
+    Short,
+    Rfc3339,
+    Strftime(String),
+}
+
+/// Timestamp format of the text lines, `%m/%d %H:%M:%S.%6f` in the timezone
+/// of the layer by default.
+///
+/// In UTC, JSON lines also have UTC timestamps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timer {
+    format: TimeFormat,
+    precision: Precision,
+    utc: bool,
+}
+
+impl Timer {
+    /// Full RFC 3339 timestamps, like `2024-08-23T17:28:01.123456+08:00`.
+    pub fn rfc3339() -> Self {
+        Self {
+            format: TimeFormat::Rfc3339,
+            ..Self::default()
+        }
+    }
+
+    /// Timestamps in a chrono strftime `format`, like `%Y-%m-%d %H:%M:%S%.3f`,
+    /// which sets its own precision.
+    pub fn strftime(format: &str) -> Result<Self> {
+        snafu::ensure!(
+            StrftimeItems::new(format).all(|item| item != Item::Error),
+            InvalidTimeFormatSnafu { format }
+        );
+        Ok(Self {
+            format: TimeFormat::Strftime(format.to_string()),
+            ..Self::default()
+        })
+    }
+
+    /// Timestamps in UTC instead of the timezone of the layer.
+    pub fn utc(self) -> Self {
+        Self { utc: true, ..self }
+    }
+
+    pub fn precision(self, precision: Precision) -> Self {
+        Self { precision, ..self }
+    }
+
+    /// `now` in the timezone of the timer.
+    pub(crate) fn zone(&self, now: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        if self.utc {
+            now.with_timezone(&Utc).fixed_offset()
+        } else {
+            now
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn format(&self, now: DateTime<FixedOffset>) -> String {
+        let mut buf = String::new();
+        self.write(&mut buf, now);
+        buf
+    }
+
+    /// Write the timestamp of `now` to `buf`. The built-in formats are written
+    /// field by field, as chrono allocates when formatting.
+    pub(crate) fn write(&self, buf: &mut String, now: DateTime<FixedOffset>) {
+        let now = self.zone(now);
+        let (digits, fraction) = match self.precision {
+            Precision::Millis => (3, now.timestamp_subsec_millis().min(999)),
+            Precision::Micros => (6, now.timestamp_subsec_micros().min(999_999)),
+        };
+        match &self.format {
+            TimeFormat::Short => write!(
+                buf,
+                "{:02}/{:02} {:02}:{:02}:{:02}.{fraction:0digits$}",
+                now.month(),
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second(),
+            ),
+            TimeFormat::Rfc3339 => {
+                write!(
+                    buf,
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{fraction:0digits$}",
+                    now.year(),
+                    now.month(),
+                    now.day(),
+                    now.hour(),
+                    now.minute(),
+                    now.second(),
+                )
+                .ok();
+                // as `to_rfc3339_opts`, with `Z` in UTC
+                let offset = now.offset().local_minus_utc();
+                if self.utc {
+                    buf.push('Z');
+                    Ok(())
+                } else {
+                    let sign = if offset < 0 { '-' } else { '+' };
+                    let offset = offset.unsigned_abs() / 60;
+                    write!(buf, "{sign}{:02}:{:02}", offset / 60, offset % 60)
+                }
+            }
+            TimeFormat::Strftime(format) => write!(buf, "{}", now.format(format)),
+        }
+        .ok();
+    }
+}
+
+/// Wall clock of the appender, deciding rotations and file ages. A manual
+/// time replaces the system clock in tests.
+#[derive(Clone, Default)]
+pub(crate) struct WallClock(Option<Arc<Mutex<DateTime<Local>>>>);
+
+impl WallClock {
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn manual(time: Arc<Mutex<DateTime<Local>>>) -> Self {
+        Self(Some(time))
+    }
+
+    pub(crate) fn now(&self) -> DateTime<Local> {
+        match &self.0 {
+            Some(time) => *time.lock(),
+            None => Local::now(),
+        }
+    }
+}
+
+/// Whether the local timezone can be resolved: without `TZ` nor
+/// `/etc/localtime`, as in scratch containers, chrono uses UTC.
+#[cfg(unix)]
+fn has_local_timezone() -> bool {
+    std::env::var_os("TZ").is_some() || std::path::Path::new("/etc/localtime").exists()
+}
+
+#[cfg(not(unix))]
+fn has_local_timezone() -> bool {
+    true
+}
+
+fn check_local_timezone() {
+    static CHECK: Once = Once::new();
+    CHECK.call_once(|| {
+        if !has_local_timezone() {
+            eprintln!(
+                "[TaosLayer] No local timezone found (TZ or /etc/localtime), timestamps are in UTC. \
+                 Use TaosLayer::with_timezone_offset to set one."
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{SecondsFormat, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn monotonic_test() {
+        let clock = Clock::new(ClockSource::Monotonic);
+        let mut prev = clock.now();
+        for _ in 0..1000 {
+            let now = clock.now();
+            assert!(now >= prev);
+            prev = now;
+        }
+    }
+
+    #[test]
+    fn resync_backwards_test() {
+        let clock = Clock::with_resync(ClockSource::Monotonic, Duration::from_millis(10));
+        // the anchor was taken before a backward clock jump of one hour
+        let ahead = Utc::now() + TimeDelta::hours(1);
+        clock.anchor.lock().wall = ahead;
+        let before = clock.now();
+        assert!(before >= ahead);
+
+        std::thread::sleep(Duration::from_millis(20));
+        // re-synced on the system clock, held at the last timestamp
+        assert_eq!(clock.now(), before);
+        assert!(clock.anchor.lock().wall < ahead);
+    }
+
+    #[test]
+    fn timer_test() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let now = offset.with_ymd_and_hms(2024, 8, 23, 1, 2, 3).unwrap()
+            + TimeDelta::microseconds(123456);
+        assert_eq!(Timer::default().format(now), "08/23 01:02:03.123456");
+        assert_eq!(
+            Timer::default().precision(Precision::Millis).format(now),
+            "08/23 01:02:03.123"
+        );
+        assert_eq!(
+            Timer::rfc3339().format(now),
+            "2024-08-23T01:02:03.123456+08:00"
+        );
+        assert_eq!(
+            Timer::rfc3339()
+                .utc()
+                .precision(Precision::Millis)
+                .format(now),
+            "2024-08-22T17:02:03.123Z"
+        );
+        let west = FixedOffset::west_opt(3 * 3600 + 1800).unwrap();
+        assert_eq!(
+            Timer::rfc3339().format(now.with_timezone(&west)),
+            now.with_timezone(&west)
+                .to_rfc3339_opts(SecondsFormat::Micros, false)
+        );
+        let timer = Timer::strftime("%Y-%m-%d %H:%M:%S").unwrap().utc();
+        assert_eq!(timer.format(now), "2024-08-22 17:02:03");
+        assert!(Timer::strftime("%Y-%Q").is_err());
+    }
+
+    #[test]
+    fn offset_test() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let clock = Clock::new(ClockSource::System).with_offset(Some(offset));
+        let now = clock.now();
+        assert_eq!(now.offset(), &offset);
+        assert!(now.to_rfc3339().ends_with("+08:00"));
+        assert!((now.with_timezone(&Utc) - Utc::now()).abs() < TimeDelta::seconds(1));
+    }
+}
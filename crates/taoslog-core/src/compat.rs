@@ -0,0 +1,83 @@
+//! Constructors mirroring `tracing_appender::rolling`, easing migration off
+//! tracing-appender.
+//!
+//! ```ignore
+//! // before
+//! let appender = tracing_appender::rolling::daily("/var/log/taos", "taosx");
+//! // after
+//! let appender = taoslog::compat::daily("/var/log/taos", "taosx");
+//! let appender = taoslog::compat::hourly("/var/log/taos", "taosx");
+//! ```
+//!
+//! Files are named `<prefix>_0_<date>.log` and get the defaults of
+//! [`RollingFileAppender::builder`]. Use the builder directly to configure
+//! compression and retention.
+
+use std::path::Path;
+
+use crate::writer::{RollingFileAppender, RotationInterval};
+
+/// A [`RollingFileAppender`] rotated daily, with instance id 0.
+///
+/// # Panics
+///
+/// Like `tracing_appender::rolling::daily`, panics if the appender cannot be
+/// initialized.
+pub fn daily(
+    directory: impl AsRef<Path>,
+    file_name_prefix: impl AsRef<Path>,
+) -> RollingFileAppender {
+    let prefix = file_name_prefix.as_ref().to_string_lossy();
+    RollingFileAppender::builder(directory, &prefix, 0)
+        .build()
+        .expect("initializing rolling file appender failed")
+}
+
+/// A [`RollingFileAppender`] rotated hourly, with instance id 0.
+///
+/// # Panics
+///
+/// Like `tracing_appender::rolling::hourly`, panics if the appender cannot be
+/// initialized.
+pub fn hourly(
+    directory: impl AsRef<Path>,
+    file_name_prefix: impl AsRef<Path>,
+) -> RollingFileAppender {
+    let prefix = file_name_prefix.as_ref().to_string_lossy();
+    RollingFileAppender::builder(directory, &prefix, 0)
+        .rotation_interval(RotationInterval::Hourly)
+        .build()
+        .expect("initializing rolling file appender failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+
+    #[test]
+    fn daily_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_dir = dir.path().join("logs");
+        let appender = daily(&log_dir, "compat");
+        appender.make_writer().write_all(b"line\n").unwrap();
+
+        let today = chrono::Local::now().format("%Y%m%d");
+        let content = std::fs::read_to_string(log_dir.join(format!("compat_0_{today}.log")));
+        assert_eq!(content.unwrap(), "line\n");
+    }
+
+    #[test]
+    fn hourly_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = hourly(dir.path(), "compat_hourly");
+        appender.make_writer().write_all(b"line\n").unwrap();
+
+        let hour = chrono::Local::now().format("%Y%m%d%H");
+        let path = dir.path().join(format!("compat_hourly_0_{hour}.log"));
+        assert!(path.is_file());
+    }
+}
@@ -0,0 +1,217 @@
+//! Logging configuration read from the configuration file of a service.
+//!
+//! [`LogConfig`] deserializes the `[log]` section of a TOML file, or any other
+//! serde format. Unset keys keep the defaults of
+//! [`RollingFileAppender::builder`] and [`TaosLayer`]. The keys of taosadapter,
+//! like `path` or `rotationCount`, are accepted as aliases.
+//!
+//! ```toml
+//! [log]
+//! dir = "/var/log/taos"
+//! component = "taosx"
+//! instance_id = 1
+//! rotation_size = "1GB"
+//! rotation_count = 30
+//! compress = true
+//! compression = "zstd"
+//! reserved_disk_size = "2GB"
+//! level = "info,hyper=warn"
+//! format = "json"
+//! ```
+//!
+//! ```ignore
+//! let config: Config = toml::from_str(&text)?;
+//! let appender = RollingFileAppender::from_config(&config.log)?;
+//! tracing_subscriber::registry()
+//!     .with(config.log.layer::<Qid, _, _>(appender)?)
+//!     .try_init()?;
+//! ```
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::{
+    filter::Directives,
+    layer::{Format, TaosLayer},
+    writer::{Compression, RollingFileAppender, DEFAULT_LOG_DIR},
+    Result,
+};
+
+/// Compression algorithm of rotated files, by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionName {
+    Gzip,
+    /// Needs the `zstd` feature.
+    Zstd,
+}
+
+/// The `[log]` section of a service configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    #[serde(alias = "path")]
+    pub dir: PathBuf,
+    pub component: String,
+    #[serde(alias = "instanceId")]
+    pub instance_id: u8,
+    /// e.g. `"1GB"`
+    #[serde(alias = "rotationSize")]
+    pub rotation_size: Option<String>,
+    #[serde(alias = "rotationCount")]
+    pub rotation_count: Option<u16>,
+    #[serde(alias = "maxTotalSize")]
+    pub max_total_size: Option<String>,
+    #[serde(alias = "maxAgeDays")]
+    pub max_age_days: Option<u32>,
+    pub compress: Option<bool>,
+    pub compression: Option<CompressionName>,
+    /// zstd level, 0 for the zstd default
+    #[serde(alias = "compressionLevel")]
+    pub compression_level: Option<i32>,
+    #[serde(alias = "reservedDiskSize")]
+    pub reserved_disk_size: Option<String>,
+    /// Directives like `info,hyper=warn`.
+    pub level: Option<String>,
+    pub format: Option<Format>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(DEFAULT_LOG_DIR),
+            component: "taos".to_string(),
+            instance_id: 0,
+            rotation_size: None,
+            rotation_count: None,
+            max_total_size: None,
+            max_age_days: None,
+            compress: None,
+            compression: None,
+            compression_level: None,
+            reserved_disk_size: None,
+            level: None,
+            format: None,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The directives of `level`, or [`None`] if unset.
+    pub fn directives(&self) -> Result<Option<Directives>> {
+        self.level.as_deref().map(Directives::parse).transpose()
+    }
+
+    /// A [`TaosLayer`] writing to `make_writer` with the level and format of
+    /// the configuration.
+    pub fn layer<Q, S, M>(&self, make_writer: M) -> Result<TaosLayer<Q, S, M>> {
+        let mut layer = TaosLayer::new(make_writer);
+        if let Some(directives) = self.directives()? {
+            layer = layer.with_directives(directives);
+        }
+        if let Some(format) = self.format {
+            layer = layer.with_format(format);
+        }
+        Ok(layer)
+    }
+
+    fn compression(&self) -> Result<Option<Compression>> {
+        match self.compression {
+            None => Ok(None),
+            Some(CompressionName::Gzip) => Ok(Some(Compression::Gzip)),
+            #[cfg(feature = "zstd")]
+            Some(CompressionName::Zstd) => Ok(Some(Compression::Zstd {
+                level: self.compression_level.unwrap_or(0),
+            })),
+            #[cfg(not(feature = "zstd"))]
+            Some(CompressionName::Zstd) => crate::InvalidConfigSnafu {
+                key: "compression",
+                reason: "zstd feature disabled",
+            }
+            .fail(),
+        }
+    }
+}
+
+impl RollingFileAppender {
+    /// An appender configured by `config`, see [`config`](crate::config).
+    pub fn from_config(config: &LogConfig) -> Result<Self> {
+        let mut builder = Self::builder(&config.dir, &config.component, config.instance_id);
+        if let Some(size) = &config.rotation_size {
+            builder = builder.rotation_size(size);
+        }
+        if let Some(count) = config.rotation_count {
+            builder = builder.rotation_count(count);
+        }
+        if let Some(size) = &config.max_total_size {
+            builder = builder.max_total_size(size);
+        }
+        if let Some(days) = config.max_age_days {
+            builder = builder.max_age_days(days);
+        }
+        if let Some(compress) = config.compress {
+            builder = builder.compress(compress);
+        }
+        if let Some(compression) = config.compression()? {
+            builder = builder.compression_algorithm(compression);
+        }
+        if let Some(size) = &config.reserved_disk_size {
+            builder = builder.reserved_disk_size(size);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::fake::{Capture, Qid};
+
+    #[test]
+    fn from_config_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: LogConfig = serde_json::from_value(serde_json::json!({
+            "path": dir.path(),
+            "component": "taosx",
+            "instance_id": 2,
+            "rotationSize": "1KB",
+            "reserved_disk_size": "1KB",
+            "compress": true,
+            "compression": "gzip",
+            "level": "warn",
+            "format": "json",
+        }))
+        .unwrap();
+        assert_eq!(config.rotation_count, None);
+
+        let appender = RollingFileAppender::from_config(&config).unwrap();
+        assert_eq!(appender.component(), "taosx");
+        assert_eq!(appender.instance_id(), 2);
+
+        let capture = Capture::default();
+        let layer = config.layer::<Qid, _, _>(capture.clone()).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("hidden");
+            tracing::warn!("shown");
+        });
+        let content = capture.content();
+        let event: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(event["message"], "shown", "{content}");
+
+        let config = LogConfig {
+            rotation_size: Some("1TB".to_string()),
+            dir: dir.path().to_path_buf(),
+            ..LogConfig::default()
+        };
+        assert!(RollingFileAppender::from_config(&config).is_err());
+        let config = LogConfig {
+            level: Some("loud".to_string()),
+            ..LogConfig::default()
+        };
+        assert!(config.directives().is_err());
+    }
+}
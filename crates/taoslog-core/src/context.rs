@@ -0,0 +1,163 @@
+//! Task-local fields appended to every event of an async task.
+//!
+//! ```ignore
+//! use taoslog::context::{self, Context};
+//!
+//! context::scope(Context::new().with("job_id", 42), async {
+//!     let _phase = context::set("phase", "sync");
+//!     // job_id:42, phase:sync
+//!     tracing::info!("started");
+//! })
+//! .await;
+//! ```
+
+use std::{cell::RefCell, fmt::Display, future::Future};
+
+tokio::task_local! {
+    static CONTEXT: RefCell<Context>;
+}
+
+/// Fields of the current task, in insertion order.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    fields: Vec<(String, String)>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: &str, value: impl Display) -> Self {
+        self.insert(key, value.to_string());
+        self
+    }
+
+    fn insert(&mut self, key: &str, value: String) -> Option<String> {
+        match self.fields.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                self.fields.push((key.to_string(), value));
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.fields.retain(|(k, _)| k != key);
+    }
+}
+
+/// Run `f` with `context` as the task-local context.
+pub async fn scope<F: Future>(context: Context, f: F) -> F::Output {
+    CONTEXT.scope(RefCell::new(context), f).await
+}
+
+/// Set `key` in the current task-local context until the guard is dropped.
+///
+/// Does nothing outside of [`scope`].
+#[must_use = "the field is removed when the guard is dropped"]
+pub fn set(key: &str, value: impl Display) -> ContextGuard {
+    let prev = CONTEXT
+        .try_with(|context| context.borrow_mut().insert(key, value.to_string()))
+        .ok();
+    ContextGuard {
+        key: key.to_string(),
+        prev,
+    }
+}
+
+/// Remove every field of the current task-local context.
+pub fn clear() {
+    CONTEXT
+        .try_with(|context| context.borrow_mut().fields.clear())
+        .ok();
+}
+
+/// Restores the previous value of a field set with [`set`].
+pub struct ContextGuard {
+    key: String,
+    /// `None` outside of a scope, `Some(None)` if the field was not set
+    prev: Option<Option<String>>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let Some(prev) = self.prev.take() else {
+            return;
+        };
+        CONTEXT
+            .try_with(|context| {
+                let mut context = context.borrow_mut();
+                match prev {
+                    Some(value) => {
+                        context.insert(&self.key, value);
+                    }
+                    None => context.remove(&self.key),
+                }
+            })
+            .ok();
+    }
+}
+
+/// Call `f` with each field of the current task-local context.
+pub(crate) fn for_each_field(mut f: impl FnMut(&str, &str)) {
+    CONTEXT
+        .try_with(|context| {
+            for (key, value) in &context.borrow().fields {
+                f(key, value);
+            }
+        })
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn context_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            runtime.block_on(scope(Context::new().with("job_id", 42), async {
+                let span = tracing::info_span!("job", "k" = "v");
+                let _enter = span.enter();
+                tracing::info!("started");
+                {
+                    let _phase = set("phase", "sync");
+                    tracing::info!("syncing");
+                    let _phase = set("phase", "final step");
+                    tracing::info!("finishing");
+                }
+                tracing::info!("done");
+                clear();
+                tracing::info!("cleared");
+            }));
+            // outside of a scope
+            let _guard = set("ignored", 1);
+            tracing::info_span!("outside").in_scope(|| tracing::info!("no context"));
+        });
+
+        let content = capture.content();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("k:v, job_id:42 started"));
+        assert!(lines[1].contains(" job_id:42, phase:sync syncing"));
+        assert!(lines[2].contains(" job_id:42, phase:\"final step\" finishing"));
+        assert!(lines[3].contains(" job_id:42 done"));
+        assert!(lines[4].contains(" cleared"));
+        assert!(!lines[4].contains("job_id"));
+        assert!(!lines[5].contains("ignored"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Control channel on a unix socket, for components without an HTTP server.
+//!
+//! [`ControlSocket`] answers one command per connection, [`send`] is the
+//! client side:
+//!
+//! | Command | |
+//! |---|---|
+//! | `level` | the directives, e.g. `info,hyper=warn` |
+//! | `level <level or directives>` | a level like `debug` sets the default level, directives replace all of them |
+//! | `rotate` | switch the appender to a new file |
+//! | `status` | directives, current file, its size and the available disk space |
+//!
+//! ```ignore
+//! let handle = Handle::new(appender);
+//! let layer = TaosLayer::<Qid, _, _>::new(handle.clone());
+//! let _control = ControlSocket::bind("/var/run/taos/taosx.sock", layer.reload_handle(), Some(handle))?;
+//! // elsewhere, e.g. a `taosx log` subcommand
+//! println!("{}", taoslog::control::send("/var/run/taos/taosx.sock", "level debug")?);
+//! ```
+//!
+//! The socket is created with the umask of the process, its directory decides
+//! who can send commands.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use snafu::ResultExt;
+use tracing::level_filters::LevelFilter;
+
+use crate::{
+    filter::ReloadHandle,
+    writer::{Handle, RotationReason},
+    ControlSocketSnafu, Result,
+};
+
+/// A listener answering commands in a background thread, stopped and removed
+/// on drop.
+pub struct ControlSocket {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    /// Listen on `path`, replacing a stale socket left by a crashed process.
+    pub fn bind(
+        path: impl AsRef<Path>,
+        reload: impl Into<ReloadHandle>,
+        appender: Option<Handle>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() && UnixStream::connect(&path).is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path).context(ControlSocketSnafu { path: &path })?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let control = Control {
+            reload: reload.into(),
+            appender,
+        };
+        let thread = thread::Builder::new()
+            .name("taoslog-control".to_string())
+            .spawn({
+                let stop = stop.clone();
+                move || {
+                    for stream in listener.incoming() {
+                        if stop.load(Ordering::Acquire) {
+                            break;
+                        }
+                        match stream {
+                            Ok(stream) => control.serve(stream),
+                            Err(e) => eprintln!("[ControlSocket] Accept error: {e}"),
+                        }
+                    }
+                }
+            })
+            .context(ControlSocketSnafu { path: &path })?;
+        Ok(Self {
+            path,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // wake up the accept loop
+        let _ = UnixStream::connect(&self.path);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Send `command` to the socket at `path` and return the answer.
+pub fn send(path: impl AsRef<Path>, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{command}")?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    Ok(answer)
+}
+
+struct Control {
+    reload: ReloadHandle,
+    appender: Option<Handle>,
+}
+
+impl Control {
+    fn serve(&self, stream: UnixStream) {
+        let mut command = String::new();
+        if let Err(e) = BufReader::new(&stream).read_line(&mut command) {
+            eprintln!("[ControlSocket] Read command error: {e}");
+            return;
+        }
+        let answer = match self.execute(command.trim()) {
+            Ok(answer) => answer,
+            Err(e) => format!("error: {e}"),
+        };
+        if let Err(e) = writeln!(&stream, "{answer}") {
+            eprintln!("[ControlSocket] Write answer error: {e}");
+        }
+    }
+
+    fn execute(&self, command: &str) -> std::result::Result<String, String> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let args = args.trim();
+        match name {
+            "level" if args.is_empty() => Ok(self.reload.directives().to_string()),
+            "level" => {
+                if let Ok(level) = args.parse::<LevelFilter>() {
+                    self.reload.set_level(level);
+                } else {
+                    self.reload.reload(args).map_err(|e| e.to_string())?;
+                }
+                tracing::warn!("log level changed to {args} through the control socket");
+                Ok(self.reload.directives().to_string())
+            }
+            "rotate" => {
+                let appender = self.appender.as_ref().ok_or("no appender")?;
+                appender
+                    .rotate_now(RotationReason::Manual)
+                    .map_err(|e| e.to_string())?;
+                Ok(appender.current_file_path().display().to_string())
+            }
+            "status" => {
+                let mut status = format!("level: {}", self.reload.directives());
+                if let Some(appender) = &self.appender {
+                    let file = appender.current_file_path();
+                    write!(status, "\nfile: {}", file.display()).ok();
+                    write!(status, "\nsize: {}", appender.current_file_size()).ok();
+                    write!(status, "\navailable: {}", appender.available_disk_space()).ok();
+                    write!(status, "\ndowngraded: {}", appender.is_downgraded()).ok();
+                }
+                Ok(status)
+            }
+            _ => Err(format!(
+                "unknown command {command:?}, expected level, rotate or status"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter::Directives, writer::RollingFileAppender};
+
+    #[test]
+    fn control_socket_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let handle = Handle::new(
+            RollingFileAppender::builder(dir.path(), "taosx", 1)
+                .build()
+                .unwrap(),
+        );
+        let directives = Directives::parse("info,hyper=warn").unwrap();
+        let path = dir.path().join("taosx.sock");
+        // a stale socket is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        let control = ControlSocket::bind(&path, directives.clone(), Some(handle.clone())).unwrap();
+
+        assert_eq!(send(&path, "level").unwrap(), "info,hyper=warn\n");
+        assert_eq!(send(&path, "level debug").unwrap(), "debug,hyper=warn\n");
+        assert_eq!(directives.default_level(), Some(LevelFilter::DEBUG));
+        assert!(send(&path, "level warn,h2=oops")
+            .unwrap()
+            .starts_with("error: "));
+
+        let first = handle.current_file_path();
+        let rotated = send(&path, "rotate").unwrap();
+        assert_ne!(handle.current_file_path(), first);
+        assert_eq!(rotated.trim(), handle.current_file_path().to_str().unwrap());
+
+        let status = send(&path, "status").unwrap();
+        assert!(
+            status.starts_with("level: debug,hyper=warn\nfile: "),
+            "{status}"
+        );
+        assert!(status.contains("\ndowngraded: false\n"), "{status}");
+        assert!(send(&path, "restart")
+            .unwrap()
+            .starts_with("error: unknown command"));
+
+        drop(control);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,139 @@
+//! The file being written by an appender, swapped on rotation without locking
+//! the write path.
+
+use std::{fs::File, marker::PhantomData, ops::Deref, sync::atomic::Ordering, time::Duration};
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned};
+
+/// Like an `ArcSwap<File>`: writers pin an epoch instead of taking a lock, and
+/// a replaced file is closed once the writers holding it are done.
+pub(crate) struct CurrentFile {
+    file: Atomic<File>,
+}
+
+impl CurrentFile {
+    pub(crate) fn new(file: File) -> Self {
+        Self {
+            file: Atomic::new(file),
+        }
+    }
+
+    pub(crate) fn load(&self) -> FileRef<'_> {
+        let guard = epoch::pin();
+        let file = self.file.load(Ordering::Acquire, &guard).as_raw();
+        FileRef {
+            file,
+            _guard: guard,
+            _current: PhantomData,
+        }
+    }
+
+    /// Replace the file, the new one is written by the writers loading it
+    /// from now on, the old one is closed once released by the others.
+    pub(crate) fn swap(&self, file: File) -> Released {
+        let (tx, rx) = flume::bounded(1);
+        let guard = epoch::pin();
+        let old = self.file.swap(Owned::new(file), Ordering::AcqRel, &guard);
+        // SAFETY: `old` is not reachable from `self` anymore, and is destroyed
+        // after the guards pinned before this one are dropped
+        unsafe {
+            guard.defer_unchecked(move || {
+                drop(old.into_owned());
+                tx.send(()).ok();
+            })
+        };
+        // close it soon, not after the next 128 pins
+        guard.flush();
+        Released(rx)
+    }
+}
+
+/// Signaled once a replaced file is closed, no writer appending to it
+/// anymore.
+pub(crate) struct Released(flume::Receiver<()>);
+
+impl Released {
+    /// Wait for the file to be closed, advancing the epoch meanwhile as the
+    /// writers may be idle.
+    pub(crate) fn wait(self) {
+        loop {
+            epoch::pin().flush();
+            if !matches!(
+                self.0.recv_timeout(Duration::from_millis(10)),
+                Err(flume::RecvTimeoutError::Timeout)
+            ) {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for CurrentFile {
+    fn drop(&mut self) {
+        // SAFETY: `&mut self`, no writer holds the file
+        unsafe {
+            let file = self.file.load(Ordering::Relaxed, epoch::unprotected());
+            drop(file.into_owned());
+        }
+    }
+}
+
+/// The current file, kept open while borrowed.
+pub(crate) struct FileRef<'a> {
+    file: *const File,
+    _guard: Guard,
+    _current: PhantomData<&'a CurrentFile>,
+}
+
+impl Deref for FileRef<'_> {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        // SAFETY: never null, and not destroyed while the guard is pinned
+        unsafe { &*self.file }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn swap_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = |name: &str| {
+            File::options()
+                .create(true)
+                .append(true)
+                .open(dir.path().join(name))
+                .unwrap()
+        };
+        let current = Arc::new(CurrentFile::new(open("a")));
+        let held = current.load();
+        let released = current.swap(open("b"));
+        // a writer still holding the old file writes to it
+        (&*held).write_all(b"old\n").unwrap();
+        assert!(released.0.try_recv().is_err());
+        drop(held);
+        released.wait();
+
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let current = current.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        (&*current.load()).write_all(b"new\n").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("a"), "old\n");
+        assert_eq!(read("b").lines().count(), 800);
+    }
+}
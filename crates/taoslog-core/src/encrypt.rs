@@ -0,0 +1,250 @@
+//! At-rest encryption of rotated log files.
+//!
+//! With [`RollingFileAppenderBuilder::encrypt`], each rotated file, or its
+//! archive when compression is on, is encrypted to `<name>.enc` with AES-256-GCM
+//! and the plain file deleted. The active file stays plain text.
+//!
+//! A file is the magic `TAOSENC1`, a random 7-byte nonce prefix, then the
+//! [STREAM](https://eprint.iacr.org/2015/189.pdf) chunks of 64 KiB, each
+//! authenticated, so truncated, reordered or modified files fail to decrypt.
+//!
+//! ```ignore
+//! let appender = RollingFileAppender::builder("/var/log/taos", "taosx", 1)
+//!     .compress(true)
+//!     .encrypt(KeyFile::new("/etc/taos/log.key"))
+//!     .build()?;
+//!
+//! // taosx_1_20240101.log.gz.enc back to taosx_1_20240101.log.gz
+//! let key = KeyFile::new("/etc/taos/log.key").key()?;
+//! decrypt(&key, File::open(encrypted)?, File::create(archive)?)?;
+//! ```
+//!
+//! [`RollingFileAppenderBuilder::encrypt`]: crate::writer::RollingFileAppenderBuilder::encrypt
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        KeyInit, OsRng,
+    },
+    Aes256Gcm,
+};
+use snafu::ResultExt;
+
+use crate::{DecryptSnafu, EncryptSnafu, Result};
+
+/// Extension of encrypted files.
+pub const EXTENSION: &str = "enc";
+
+const MAGIC: &[u8; 8] = b"TAOSENC1";
+const NONCE_PREFIX_LEN: usize = 7;
+const CHUNK: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// An AES-256 key.
+pub type Key = [u8; 32];
+
+/// Source of the key, asked on each rotation so it can be rotated.
+pub trait KeyProvider: Send + Sync {
+    fn key(&self) -> io::Result<Key>;
+}
+
+impl KeyProvider for Key {
+    fn key(&self) -> io::Result<Key> {
+        Ok(*self)
+    }
+}
+
+impl<F: Fn() -> io::Result<Key> + Send + Sync> KeyProvider for F {
+    fn key(&self) -> io::Result<Key> {
+        self()
+    }
+}
+
+/// A key read from a file of 64 hex digits.
+#[derive(Debug, Clone)]
+pub struct KeyFile(PathBuf);
+
+impl KeyFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self(path.as_ref().to_path_buf())
+    }
+}
+
+impl KeyProvider for KeyFile {
+    fn key(&self) -> io::Result<Key> {
+        let text = fs::read_to_string(&self.0)?;
+        parse_hex_key(text.trim()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not 64 hex digits", self.0.display()),
+            )
+        })
+    }
+}
+
+fn parse_hex_key(text: &str) -> Option<Key> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read until `buf` is full or the end of `reader`.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Encrypt `reader` to `writer`, returning the plain size.
+pub fn encrypt(key: &Key, mut reader: impl Read, mut writer: impl Write) -> io::Result<u64> {
+    let mut prefix = [0; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&prefix)?;
+
+    let mut encryptor =
+        EncryptorBE32::from_aead(Aes256Gcm::new(key.into()), prefix.as_slice().into());
+    let mut buf = vec![0; CHUNK];
+    let mut size = 0;
+    loop {
+        let len = read_full(&mut reader, &mut buf)?;
+        size += len as u64;
+        // full chunks are never last, the last one may be empty
+        if len == CHUNK {
+            let chunk = encryptor
+                .encrypt_next(buf.as_slice())
+                .map_err(|_| invalid("encryption failed"))?;
+            writer.write_all(&chunk)?;
+        } else {
+            let chunk = encryptor
+                .encrypt_last(&buf[..len])
+                .map_err(|_| invalid("encryption failed"))?;
+            writer.write_all(&chunk)?;
+            return Ok(size);
+        }
+    }
+}
+
+/// Decrypt a file written by [`encrypt`] from `reader` to `writer`, returning
+/// the plain size.
+///
+/// Fails on a wrong key or a modified or truncated file, after writing the
+/// chunks authenticated so far.
+pub fn decrypt(key: &Key, mut reader: impl Read, mut writer: impl Write) -> Result<u64> {
+    let mut header = [0; MAGIC.len() + NONCE_PREFIX_LEN];
+    if read_full(&mut reader, &mut header).context(DecryptSnafu)? < header.len()
+        || &header[..MAGIC.len()] != MAGIC
+    {
+        return Err(invalid("not an encrypted log file")).context(DecryptSnafu);
+    }
+    let prefix = &header[MAGIC.len()..];
+
+    let mut decryptor = DecryptorBE32::from_aead(Aes256Gcm::new(key.into()), prefix.into());
+    let mut buf = vec![0; CHUNK + TAG_LEN];
+    let mut size = 0;
+    loop {
+        let len = read_full(&mut reader, &mut buf).context(DecryptSnafu)?;
+        let authentication_failed = |_| invalid("wrong key, or modified or truncated file");
+        if len == buf.len() {
+            let chunk = decryptor
+                .decrypt_next(buf.as_slice())
+                .map_err(authentication_failed)
+                .context(DecryptSnafu)?;
+            writer.write_all(&chunk).context(DecryptSnafu)?;
+            size += chunk.len() as u64;
+        } else {
+            let chunk = decryptor
+                .decrypt_last(&buf[..len])
+                .map_err(authentication_failed)
+                .context(DecryptSnafu)?;
+            writer.write_all(&chunk).context(DecryptSnafu)?;
+            return Ok(size + chunk.len() as u64);
+        }
+    }
+}
+
+/// Encrypt `path` into `<path>.enc` synced to disk, the source file is kept.
+pub(crate) fn encrypt_file(provider: &dyn KeyProvider, path: &Path) -> Result<PathBuf> {
+    let dest_path = PathBuf::from(format!("{}.{EXTENSION}", path.display()));
+    let key = provider.key().context(EncryptSnafu { path })?;
+    let src = File::open(path).context(EncryptSnafu { path })?;
+    let mut dest = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&dest_path)
+        .context(EncryptSnafu { path })?;
+    let res = encrypt(&key, io::BufReader::new(src), &mut dest).and_then(|_| dest.sync_all());
+    if let Err(e) = res {
+        fs::remove_file(&dest_path).ok();
+        return Err(e).context(EncryptSnafu { path });
+    }
+    Ok(dest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let key = [7; 32];
+        let plain: Vec<u8> = (0..CHUNK * 2 + 100).map(|i| i as u8).collect();
+        for len in [0, 10, CHUNK, plain.len()] {
+            let mut encrypted = Vec::new();
+            assert_eq!(
+                encrypt(&key, &plain[..len], &mut encrypted).unwrap(),
+                len as u64
+            );
+            let mut decrypted = Vec::new();
+            decrypt(&key, encrypted.as_slice(), &mut decrypted).unwrap();
+            assert_eq!(decrypted, &plain[..len]);
+        }
+
+        let mut encrypted = Vec::new();
+        encrypt(&key, plain.as_slice(), &mut encrypted).unwrap();
+        // wrong key
+        assert!(decrypt(&[8; 32], encrypted.as_slice(), io::sink()).is_err());
+        // modified
+        let mut modified = encrypted.clone();
+        modified[100] ^= 1;
+        assert!(decrypt(&key, modified.as_slice(), io::sink()).is_err());
+        // truncated at a chunk boundary
+        let truncated = &encrypted[..MAGIC.len() + NONCE_PREFIX_LEN + CHUNK + TAG_LEN];
+        assert!(decrypt(&key, truncated, io::sink()).is_err());
+        // not encrypted
+        assert!(decrypt(&key, &b"plain text"[..], io::sink()).is_err());
+    }
+
+    #[test]
+    fn key_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.key");
+        fs::write(&path, format!("{}\n", "0a".repeat(32))).unwrap();
+        assert_eq!(KeyFile::new(&path).key().unwrap(), [10; 32]);
+        fs::write(&path, "0a").unwrap();
+        assert!(KeyFile::new(&path).key().is_err());
+    }
+}
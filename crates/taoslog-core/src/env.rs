@@ -0,0 +1,210 @@
+//! Logging configuration from environment variables, for containers.
+//!
+//! With the prefix `TAOS`:
+//!
+//! | Variable | Default |
+//! |---|---|
+//! | `TAOS_LOG_DIR` | `/var/log/taos` |
+//! | `TAOS_LOG_COMPONENT` | the prefix in lower case, `taos` |
+//! | `TAOS_LOG_INSTANCE_ID` | `0` |
+//! | `TAOS_LOG_ROTATION_SIZE` | `1GB` |
+//! | `TAOS_LOG_ROTATION_COUNT` | `30` |
+//! | `TAOS_LOG_MAX_TOTAL_SIZE` | none |
+//! | `TAOS_LOG_MAX_AGE_DAYS` | none |
+//! | `TAOS_LOG_COMPRESS` | `false` |
+//! | `TAOS_LOG_RESERVED_DISK_SIZE` | `2GB` |
+//! | `TAOS_LOG_LEVEL` | none, see [`Directives::from_env`] |
+//!
+//! Empty variables are ignored. Bad values fail with the variable name and
+//! value, e.g. `Invalid environment variable TAOS_LOG_ROTATION_SIZE=1TB:
+//! expected a size like 512MB`.
+
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    filter::Directives,
+    size::ByteSize,
+    writer::{RollingFileAppender, RollingFileAppenderBuilder, DEFAULT_LOG_DIR},
+    InvalidEnvSnafu, Result,
+};
+
+struct Vars<'p, F> {
+    prefix: &'p str,
+    lookup: F,
+}
+
+impl<F: Fn(&str) -> Option<String>> Vars<'_, F> {
+    fn key(&self, name: &str) -> String {
+        format!("{}_LOG_{name}", self.prefix)
+    }
+
+    fn get(&self, name: &str) -> Option<(String, String)> {
+        let key = self.key(name);
+        (self.lookup)(&key)
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| (key, value.trim().to_string()))
+    }
+
+    fn parse<T: FromStr>(&self, name: &str, expected: &str) -> Result<Option<T>> {
+        let Some((key, value)) = self.get(name) else {
+            return Ok(None);
+        };
+        match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => InvalidEnvSnafu {
+                key,
+                value,
+                reason: format!("expected {expected}"),
+            }
+            .fail(),
+        }
+    }
+
+    fn size(&self, name: &str) -> Result<Option<ByteSize>> {
+        self.parse(name, "a size like 512MB")
+    }
+
+    fn bool(&self, name: &str) -> Result<Option<bool>> {
+        let Some((key, value)) = self.get(name) else {
+            return Ok(None);
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" => Ok(Some(false)),
+            _ => InvalidEnvSnafu {
+                key,
+                value,
+                reason: "expected true or false",
+            }
+            .fail(),
+        }
+    }
+
+    fn builder(&self) -> Result<RollingFileAppenderBuilder<'static>> {
+        let dir = self
+            .get("DIR")
+            .map_or_else(|| PathBuf::from(DEFAULT_LOG_DIR), |(_, dir)| dir.into());
+        let component = self.get("COMPONENT").map_or_else(
+            || self.prefix.to_ascii_lowercase(),
+            |(_, component)| component,
+        );
+        let instance_id = self.parse("INSTANCE_ID", "an integer from 0 to 255")?;
+        let mut builder = RollingFileAppender::builder(dir, &component, instance_id.unwrap_or(0));
+        if let Some(size) = self.size("ROTATION_SIZE")? {
+            builder = builder.rotation_size_bytes(size);
+        }
+        if let Some(count) = self.parse("ROTATION_COUNT", "an integer from 0 to 65535")? {
+            builder = builder.rotation_count(count);
+        }
+        if let Some(size) = self.size("MAX_TOTAL_SIZE")? {
+            builder = builder.max_total_size_bytes(size);
+        }
+        if let Some(days) = self.parse("MAX_AGE_DAYS", "a number of days")? {
+            builder = builder.max_age_days(days);
+        }
+        if let Some(compress) = self.bool("COMPRESS")? {
+            builder = builder.compress(compress);
+        }
+        if let Some(size) = self.size("RESERVED_DISK_SIZE")? {
+            builder = builder.reserved_disk_size_bytes(size);
+        }
+        Ok(builder)
+    }
+
+    fn directives(&self) -> Result<Option<Directives>> {
+        let Some((key, value)) = self.get("LEVEL") else {
+            return Ok(None);
+        };
+        match Directives::parse(&value) {
+            Ok(directives) => Ok(Some(directives)),
+            Err(e) => InvalidEnvSnafu {
+                key,
+                value,
+                reason: e.to_string(),
+            }
+            .fail(),
+        }
+    }
+}
+
+fn vars(prefix: &str) -> Vars<'_, impl Fn(&str) -> Option<String>> {
+    Vars {
+        prefix,
+        lookup: |key: &str| std::env::var(key).ok(),
+    }
+}
+
+impl RollingFileAppenderBuilder<'static> {
+    /// A builder configured by the `<prefix>_LOG_*` environment variables,
+    /// see [`env`](crate::env).
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        vars(prefix).builder()
+    }
+}
+
+impl Directives {
+    /// The directives of `<prefix>_LOG_LEVEL`, like `info,hyper=warn`, or
+    /// [`None`] if unset.
+    pub fn from_env(prefix: &str) -> Result<Option<Self>> {
+        vars(prefix).directives()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn test_vars(vars: &[(&str, &str)]) -> Vars<'static, impl Fn(&str) -> Option<String>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Vars {
+            prefix: "TAOS",
+            lookup: move |key: &str| vars.get(key).cloned(),
+        }
+    }
+
+    #[test]
+    fn from_env_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+        let vars = test_vars(&[
+            ("TAOS_LOG_DIR", dir_str),
+            ("TAOS_LOG_INSTANCE_ID", "3"),
+            ("TAOS_LOG_ROTATION_SIZE", "1KB"),
+            ("TAOS_LOG_RESERVED_DISK_SIZE", "1KB"),
+            ("TAOS_LOG_COMPRESS", "yes"),
+            ("TAOS_LOG_MAX_AGE_DAYS", ""),
+            ("TAOS_LOG_LEVEL", "warn,hyper=error"),
+        ]);
+        let appender = vars.builder().unwrap().build().unwrap();
+        assert_eq!(appender.component(), "taos");
+        assert_eq!(appender.instance_id(), 3);
+        assert!(vars.directives().unwrap().is_some());
+
+        let error = |name: &str, value: &str| {
+            let vars = test_vars(&[(name, value)]);
+            match (vars.builder(), vars.directives()) {
+                (Err(e), _) | (_, Err(e)) => e.to_string(),
+                _ => panic!("{name}={value} accepted"),
+            }
+        };
+        assert_eq!(
+            error("TAOS_LOG_ROTATION_SIZE", "1TB"),
+            "Invalid environment variable TAOS_LOG_ROTATION_SIZE=1TB: expected a size like 512MB"
+        );
+        assert_eq!(
+            error("TAOS_LOG_INSTANCE_ID", "256"),
+            "Invalid environment variable TAOS_LOG_INSTANCE_ID=256: expected an integer from 0 to 255"
+        );
+        assert_eq!(
+            error("TAOS_LOG_COMPRESS", "maybe"),
+            "Invalid environment variable TAOS_LOG_COMPRESS=maybe: expected true or false"
+        );
+        assert!(error("TAOS_LOG_LEVEL", "loud")
+            .starts_with("Invalid environment variable TAOS_LOG_LEVEL=loud: Invalid directive"));
+    }
+}
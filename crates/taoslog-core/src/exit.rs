@@ -0,0 +1,115 @@
+//! Final structured event for process exits.
+
+use std::{fmt::Display, io::Write, path::Path};
+
+use chrono::Local;
+use snafu::ResultExt;
+
+use crate::{writer::RollingFileAppender, Result, WriteLastExitSnafu};
+
+/// Name of the file written by [`report_to`].
+pub const LAST_EXIT_FILE: &str = "last_exit.json";
+
+/// Emit the final event of the process with the exit `reason` and `code`.
+///
+/// The event is logged at INFO for a zero exit code and at ERROR otherwise,
+/// with target `taoslog::exit`. Only stdout and stderr are flushed: with a
+/// [`write_buffer`](crate::writer::RollingFileAppenderBuilder::write_buffer),
+/// an INFO event stays in the buffer of the appender until
+/// [`RollingFileAppender::shutdown`], use [`report_with`] to run it.
+pub fn report(reason: impl Display, code: i32) {
+    let pid = std::process::id();
+    if code == 0 {
+        tracing::info!(target: "taoslog::exit", pid, code, "process exit: {reason}");
+    } else {
+        tracing::error!(target: "taoslog::exit", pid, code, "process exit: {reason}");
+    }
+    std::io::stdout().flush().ok();
+    std::io::stderr().flush().ok();
+}
+
+/// Like [`report`], then [shut down](RollingFileAppender::shutdown) the
+/// `appender` of the layer, so the event is in the file when the process exits
+/// right after. Takes a [`Handle`](crate::writer::Handle) too.
+pub fn report_with(
+    appender: &RollingFileAppender,
+    reason: impl Display,
+    code: i32,
+) -> std::io::Result<()> {
+    report(reason, code);
+    appender.shutdown()
+}
+
+/// Like [`report`], and also write a [`LAST_EXIT_FILE`] into `log_dir`.
+pub fn report_to(log_dir: impl AsRef<Path>, reason: impl Display, code: i32) -> Result<()> {
+    let reason = reason.to_string();
+    report(&reason, code);
+
+    let path = log_dir.as_ref().join(LAST_EXIT_FILE);
+    let content = serde_json::json!({
+        "reason": reason,
+        "code": code,
+        "pid": std::process::id(),
+        "timestamp": Local::now().to_rfc3339(),
+    });
+    std::fs::write(&path, content.to_string()).context(WriteLastExitSnafu { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn report_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+        let dir = tempfile::tempdir().unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            report_to(dir.path(), "config error", 2).unwrap();
+            report("done", 0);
+        });
+
+        let content = capture.content();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains(" ERROR "));
+        assert!(lines[0].contains("code:2"));
+        assert!(lines[0].contains("process exit: config error"));
+        assert!(lines[1].contains(" INFO "));
+        assert!(lines[1].contains("code:0"));
+
+        let last_exit: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join(LAST_EXIT_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(last_exit["reason"], "config error");
+        assert_eq!(last_exit["code"], 2);
+        assert_eq!(last_exit["pid"], std::process::id());
+    }
+
+    #[test]
+    fn report_with_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let handle = crate::writer::Handle::new(
+            RollingFileAppender::builder(dir.path(), "taosx", 1)
+                .write_buffer("64KB")
+                .build()
+                .unwrap(),
+        );
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(handle.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            report_with(&handle, "done", 0).unwrap();
+        });
+
+        let content = std::fs::read_to_string(handle.current_file_path()).unwrap();
+        assert!(content.contains("process exit: done"), "{content}");
+    }
+}
@@ -0,0 +1,233 @@
+//! Fault injection for the appender's filesystem paths.
+//!
+//! A [`FaultInjector`] is attached to a [`RollingFileAppender`] with
+//! [`RollingFileAppenderBuilder::fault_injector`] and consulted whenever the
+//! appender rotates, compresses or deletes a file. The write path is covered by
+//! wrapping any `MakeWriter` in a [`FailingWriter`] sharing the same injector.
+//!
+//! [`RollingFileAppender`]: crate::writer::RollingFileAppender
+//! [`RollingFileAppenderBuilder::fault_injector`]: crate::writer::RollingFileAppenderBuilder::fault_injector
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use parking_lot::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The filesystem operation a fault is injected into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    Write,
+    Rotate,
+    Compress,
+    Delete,
+}
+
+/// The error returned by an injected fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// ENOSPC
+    NoSpace,
+    /// EIO
+    Io,
+    /// EACCES
+    PermissionDenied,
+}
+
+impl Fault {
+    fn to_io_error(self) -> io::Error {
+        match self {
+            Fault::NoSpace => io::Error::new(io::ErrorKind::StorageFull, "injected ENOSPC"),
+            Fault::Io => io::Error::other("injected EIO"),
+            Fault::PermissionDenied => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "injected EACCES")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rule {
+    fault: Fault,
+    /// remaining hits, `None` means fail until cleared
+    remaining: Option<usize>,
+}
+
+/// Shared set of fault rules, cheap to clone.
+#[derive(Clone, Default)]
+pub struct FaultInjector(Arc<Mutex<HashMap<FaultPoint, Rule>>>);
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail every operation at `point` until [`clear`](Self::clear) is called.
+    pub fn fail(&self, point: FaultPoint, fault: Fault) {
+        self.0.lock().insert(
+            point,
+            Rule {
+                fault,
+                remaining: None,
+            },
+        );
+    }
+
+    /// Fail the next `times` operations at `point`.
+    pub fn fail_times(&self, point: FaultPoint, fault: Fault, times: usize) {
+        self.0.lock().insert(
+            point,
+            Rule {
+                fault,
+                remaining: Some(times),
+            },
+        );
+    }
+
+    pub fn clear(&self, point: FaultPoint) {
+        self.0.lock().remove(&point);
+    }
+
+    pub fn clear_all(&self) {
+        self.0.lock().clear();
+    }
+
+    pub(crate) fn check(&self, point: FaultPoint) -> io::Result<()> {
+        let mut rules = self.0.lock();
+        let Some(rule) = rules.get_mut(&point) else {
+            return Ok(());
+        };
+        let fault = rule.fault;
+        match &mut rule.remaining {
+            Some(0) => {
+                rules.remove(&point);
+                return Ok(());
+            }
+            Some(n) => {
+                *n -= 1;
+                if *n == 0 {
+                    rules.remove(&point);
+                }
+            }
+            None => {}
+        }
+        Err(fault.to_io_error())
+    }
+}
+
+/// A `MakeWriter` whose writers fail according to [`FaultPoint::Write`] rules.
+pub struct FailingWriter<M> {
+    inner: M,
+    faults: FaultInjector,
+}
+
+impl<M> FailingWriter<M> {
+    pub fn new(inner: M, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl<'a, M> MakeWriter<'a> for FailingWriter<M>
+where
+    M: MakeWriter<'a>,
+{
+    type Writer = FailingWrite<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        FailingWrite {
+            inner: self.inner.make_writer(),
+            faults: self.faults.clone(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        FailingWrite {
+            inner: self.inner.make_writer_for(meta),
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+pub struct FailingWrite<W> {
+    inner: W,
+    faults: FaultInjector,
+}
+
+impl<W: io::Write> io::Write for FailingWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.faults.check(FaultPoint::Write)?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.faults.check(FaultPoint::Write)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn fault_injector_test() {
+        let faults = FaultInjector::new();
+        assert!(faults.check(FaultPoint::Write).is_ok());
+
+        faults.fail_times(FaultPoint::Write, Fault::NoSpace, 2);
+        assert_eq!(
+            faults.check(FaultPoint::Write).unwrap_err().kind(),
+            io::ErrorKind::StorageFull
+        );
+        assert!(faults.check(FaultPoint::Rotate).is_ok());
+        assert!(faults.check(FaultPoint::Write).is_err());
+        assert!(faults.check(FaultPoint::Write).is_ok());
+
+        faults.fail(FaultPoint::Delete, Fault::PermissionDenied);
+        for _ in 0..3 {
+            assert_eq!(
+                faults.check(FaultPoint::Delete).unwrap_err().kind(),
+                io::ErrorKind::PermissionDenied
+            );
+        }
+        faults.clear(FaultPoint::Delete);
+        assert!(faults.check(FaultPoint::Delete).is_ok());
+    }
+
+    #[test]
+    fn layer_drops_failed_write_test() {
+        let faults = FaultInjector::new();
+        let capture = Capture::default();
+        let writer = FailingWriter::new(capture.clone(), faults.clone());
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outer").in_scope(|| {
+                faults.fail_times(FaultPoint::Write, Fault::Io, 1);
+                tracing::info!("dropped");
+                tracing::info!("written");
+            });
+        });
+
+        let content = capture.content();
+        assert!(!content.contains("dropped"));
+        assert!(content.contains("written"));
+    }
+
+    #[test]
+    fn failing_write_test() {
+        let faults = FaultInjector::new();
+        let writer = FailingWriter::new(Mutex::new(Vec::new()), faults.clone());
+        faults.fail(FaultPoint::Write, Fault::NoSpace);
+        assert!(writer.make_writer().write_all(b"x").is_err());
+        faults.clear_all();
+        assert!(writer.make_writer().write_all(b"x").is_ok());
+    }
+}
@@ -0,0 +1,524 @@
+//! Per-target level directives applied by [`TaosLayer`](crate::layer::TaosLayer).
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use parking_lot::RwLock;
+use snafu::OptionExt;
+use tracing::{level_filters::LevelFilter, Metadata};
+
+use crate::{InvalidDirectiveSnafu, InvalidQidSnafu, Result};
+
+/// Directives applied by [`crate::init`] unless overridden, keeping dependency
+/// chatter out of production log files.
+pub const DEFAULT_DIRECTIVES: &str = "hyper=warn,h2=warn,rustls=warn,sqlx=warn,actix_server=warn";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    target: String,
+    level: LevelFilter,
+}
+
+#[derive(Clone, Default)]
+struct Inner {
+    /// level of targets without a directive, `None` enables every level
+    default_level: Option<LevelFilter>,
+    list: Vec<Directive>,
+}
+
+/// A shared set of `target=level` directives, with an optional default level.
+///
+/// Clones share the same set, so a clone kept by the application can query and
+/// change the directives of a running layer. Changes rebuild the callsite
+/// interest cache of tracing, so filtering decisions stay cached per callsite.
+#[derive(Clone, Default)]
+pub struct Directives(Arc<RwLock<Inner>>);
+
+impl Directives {
+    /// An empty set, every target and level is enabled.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Parse a comma separated list like `info,hyper=warn,h2=error`, where a
+    /// bare level sets the default level.
+    pub fn parse(directives: &str) -> Result<Self> {
+        let mut inner = Inner::default();
+        for directive in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            let Some((target, level)) = directive.split_once('=') else {
+                let level = LevelFilter::from_str(directive)
+                    .ok()
+                    .context(InvalidDirectiveSnafu { directive })?;
+                inner.default_level = Some(level);
+                continue;
+            };
+            let level = LevelFilter::from_str(level.trim())
+                .ok()
+                .context(InvalidDirectiveSnafu { directive })?;
+            inner.list.retain(|d| d.target != target.trim());
+            inner.list.push(Directive {
+                target: target.trim().to_string(),
+                level,
+            });
+        }
+        Ok(Self(Arc::new(RwLock::new(inner))))
+    }
+
+    /// A copy of the directives, not shared with this set.
+    pub(crate) fn detached(&self) -> Self {
+        Self(Arc::new(RwLock::new(self.0.read().clone())))
+    }
+
+    /// Set the level of `target`, replacing any existing directive for it.
+    pub fn set(&self, target: &str, level: LevelFilter) {
+        {
+            let mut inner = self.0.write();
+            inner.list.retain(|d| d.target != target);
+            inner.list.push(Directive {
+                target: target.to_string(),
+                level,
+            });
+        }
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    /// Remove the directive for `target`.
+    pub fn remove(&self, target: &str) {
+        self.0.write().list.retain(|d| d.target != target);
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    /// Set the level of targets without a directive, `None` enables every level.
+    pub fn set_default_level(&self, level: Option<LevelFilter>) {
+        self.0.write().default_level = level;
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    pub fn default_level(&self) -> Option<LevelFilter> {
+        self.0.read().default_level
+    }
+
+    /// The level of the directive matching `target`, `None` if no directive
+    /// matches.
+    pub fn level_for(&self, target: &str) -> Option<LevelFilter> {
+        self.0
+            .read()
+            .list
+            .iter()
+            .filter(|d| {
+                target == d.target
+                    || target
+                        .strip_prefix(d.target.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|d| d.target.len())
+            .map(|d| d.level)
+    }
+
+    /// All target directives in effect.
+    pub fn list(&self) -> Vec<(String, LevelFilter)> {
+        self.0
+            .read()
+            .list
+            .iter()
+            .map(|d| (d.target.clone(), d.level))
+            .collect()
+    }
+
+    pub(crate) fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.level_for(metadata.target())
+            .or(self.default_level())
+            .is_none_or(|level| level >= *metadata.level())
+    }
+
+    /// The most verbose level any target can be enabled at.
+    pub(crate) fn max_level_hint(&self) -> LevelFilter {
+        let inner = self.0.read();
+        inner
+            .list
+            .iter()
+            .map(|d| d.level)
+            .chain(Some(inner.default_level.unwrap_or(LevelFilter::TRACE)))
+            .max()
+            .unwrap_or(LevelFilter::TRACE)
+    }
+}
+
+/// Handle to change the filter of a running [`TaosLayer`](crate::layer::TaosLayer)
+/// without rebuilding the subscriber.
+///
+/// The level filter applies before the disk pressure policy of the writer: in
+/// the downgrade stage only ERROR events are written whatever the level set
+/// here, and a level set here is back in effect once disk space recovers.
+#[derive(Clone)]
+pub struct ReloadHandle(Directives);
+
+impl ReloadHandle {
+    pub(crate) fn new(directives: Directives) -> Self {
+        Self(directives)
+    }
+
+    /// Set the level of targets without a directive, e.g. INFO to DEBUG.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.0.set_default_level(Some(level));
+    }
+
+    pub fn level(&self) -> Option<LevelFilter> {
+        self.0.default_level()
+    }
+
+    /// Replace every directive with the parsed `directives`, keeping the
+    /// current ones if they are invalid.
+    pub fn reload(&self, directives: &str) -> Result<()> {
+        let parsed = Directives::parse(directives)?;
+        let parsed = std::mem::take(&mut *parsed.0.write());
+        *self.0 .0.write() = parsed;
+        tracing::callsite::rebuild_interest_cache();
+        Ok(())
+    }
+
+    /// The directives of the layer, shared with it.
+    pub fn directives(&self) -> &Directives {
+        &self.0
+    }
+}
+
+impl From<Directives> for ReloadHandle {
+    fn from(directives: Directives) -> Self {
+        Self(directives)
+    }
+}
+
+impl fmt::Display for Directives {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.0.read();
+        let list = inner
+            .default_level
+            .iter()
+            .map(|level| level.to_string())
+            .chain(
+                inner
+                    .list
+                    .iter()
+                    .map(|d| format!("{}={}", d.target, d.level)),
+            )
+            .collect::<Vec<_>>();
+        f.write_str(&list.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QidPattern {
+    value: u64,
+    mask: u64,
+}
+
+impl QidPattern {
+    fn matches(&self, qid: u64) -> bool {
+        qid & self.mask == self.value
+    }
+}
+
+/// QIDs whose events are written at every level, whatever the directives,
+/// to debug one stuck query without flooding the log.
+///
+/// Clones share the same set, like [`Directives`]. Events outside of these
+/// QIDs are still filtered by the directives.
+#[derive(Clone, Default)]
+pub struct DebugQids(Arc<RwLock<Vec<QidPattern>>>);
+
+impl DebugQids {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write every event of `qid`.
+    pub fn add(&self, qid: u64) {
+        self.insert(QidPattern {
+            value: qid,
+            mask: u64::MAX,
+        });
+    }
+
+    /// Write every event of the QIDs starting with the hex digits of
+    /// `prefix`, like `0x12ab`.
+    pub fn add_prefix(&self, prefix: &str) -> Result<()> {
+        let pattern = Self::parse_prefix(prefix)?;
+        self.insert(pattern);
+        Ok(())
+    }
+
+    pub fn remove(&self, qid: u64) {
+        self.0
+            .write()
+            .retain(|p| p.value != qid || p.mask != u64::MAX);
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    pub fn remove_prefix(&self, prefix: &str) -> Result<()> {
+        let pattern = Self::parse_prefix(prefix)?;
+        self.0.write().retain(|p| *p != pattern);
+        tracing::callsite::rebuild_interest_cache();
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.0.write().clear();
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().is_empty()
+    }
+
+    /// Whether the events of `qid` are written at every level.
+    pub fn matches(&self, qid: u64) -> bool {
+        self.0.read().iter().any(|p| p.matches(qid))
+    }
+
+    fn insert(&self, pattern: QidPattern) {
+        {
+            let mut patterns = self.0.write();
+            if !patterns.contains(&pattern) {
+                patterns.push(pattern);
+            }
+        }
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    fn parse_prefix(prefix: &str) -> Result<QidPattern> {
+        let digits = prefix.trim();
+        let digits = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+            .unwrap_or(digits);
+        let value = (1..=16)
+            .contains(&digits.len())
+            .then(|| u64::from_str_radix(digits, 16).ok())
+            .flatten()
+            .context(InvalidQidSnafu { qid: prefix })?;
+        let shift = 64 - 4 * digits.len() as u32;
+        Ok(QidPattern {
+            value: value.checked_shl(shift).unwrap_or(0),
+            mask: u64::MAX.checked_shl(shift).unwrap_or(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn parse_test() {
+        let directives = Directives::parse(DEFAULT_DIRECTIVES).unwrap();
+        assert_eq!(directives.list().len(), 5);
+        assert_eq!(directives.to_string(), DEFAULT_DIRECTIVES);
+
+        let directives = Directives::parse("a=info, a=debug,b::c=off").unwrap();
+        assert_eq!(
+            directives.list(),
+            vec![
+                ("a".to_string(), LevelFilter::DEBUG),
+                ("b::c".to_string(), LevelFilter::OFF)
+            ]
+        );
+
+        let directives = Directives::parse("info,a=debug").unwrap();
+        assert_eq!(directives.default_level(), Some(LevelFilter::INFO));
+        assert_eq!(directives.to_string(), "info,a=debug");
+
+        assert!(Directives::parse("hyper").is_err());
+        assert!(Directives::parse("hyper=loud").is_err());
+        assert!(Directives::parse("").unwrap().list().is_empty());
+    }
+
+    #[test]
+    fn level_for_test() {
+        let directives = Directives::parse("hyper=warn,hyper::client=debug").unwrap();
+        assert_eq!(directives.level_for("hyper"), Some(LevelFilter::WARN));
+        assert_eq!(
+            directives.level_for("hyper::proto"),
+            Some(LevelFilter::WARN)
+        );
+        assert_eq!(
+            directives.level_for("hyper::client::pool"),
+            Some(LevelFilter::DEBUG)
+        );
+        assert_eq!(directives.level_for("hyper_util"), None);
+        assert_eq!(directives.level_for("taosx"), None);
+
+        directives.set("hyper", LevelFilter::INFO);
+        assert_eq!(directives.level_for("hyper"), Some(LevelFilter::INFO));
+        directives.remove("hyper");
+        assert_eq!(directives.level_for("hyper"), None);
+    }
+
+    #[test]
+    fn layer_directives_test() {
+        let capture = Capture::default();
+        let directives = Directives::parse("noisy=warn").unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(capture.clone()).with_directives(directives.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let emit = || {
+                tracing::info_span!("outer").in_scope(|| {
+                    tracing::info!(target: "noisy::conn", "noisy info");
+                    tracing::warn!(target: "noisy::conn", "noisy warn");
+                    tracing::info!(target: "taosx", "app info");
+                })
+            };
+            emit();
+            assert!(!capture.content().contains("noisy info"));
+            assert!(capture.content().contains("noisy warn"));
+            assert!(capture.content().contains("app info"));
+
+            capture.clear();
+            directives.set("noisy", LevelFilter::INFO);
+            emit();
+            assert!(capture.content().contains("noisy info"));
+        });
+    }
+
+    #[test]
+    fn max_level_test() {
+        let directives = Directives::parse("warn").unwrap();
+        assert_eq!(directives.max_level_hint(), LevelFilter::WARN);
+        directives.set("taosx::job", LevelFilter::DEBUG);
+        assert_eq!(directives.max_level_hint(), LevelFilter::DEBUG);
+        directives.set_default_level(None);
+        assert_eq!(directives.max_level_hint(), LevelFilter::TRACE);
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_directives(Directives::parse("taosx::job=debug").unwrap())
+                .with_max_level(LevelFilter::INFO),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outer").in_scope(|| {
+                tracing::debug!(target: "taosx::api", "api debug");
+                tracing::info!(target: "taosx::api", "api info");
+                tracing::debug!(target: "taosx::job", "job debug");
+                tracing::trace!(target: "taosx::job", "job trace");
+            })
+        });
+
+        let content = capture.content();
+        assert!(!content.contains("api debug"));
+        assert!(content.contains("api info"));
+        assert!(content.contains("job debug"));
+        assert!(!content.contains("job trace"));
+    }
+
+    #[test]
+    fn debug_qids_test() {
+        use crate::utils::QidMetadataSetter;
+
+        let capture = Capture::default();
+        let layer = TaosLayer::<Qid, _, _>::new(capture.clone()).with_max_level(LevelFilter::INFO);
+        let debug_qids = layer.debug_qids();
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut stuck = tracing::info_span!("query");
+            stuck.set_qid(&Qid::from(0x1234));
+            let mut other = tracing::info_span!("query");
+            other.set_qid(&Qid::from(0x5678));
+            let mut prefixed = tracing::info_span!("query");
+            prefixed.set_qid(&Qid::from(0xab00_0000_0000_0001));
+
+            stuck.in_scope(|| tracing::debug!("before"));
+            debug_qids.add(0x1234);
+            debug_qids.add_prefix("0xab").unwrap();
+            stuck.in_scope(|| {
+                tracing::debug!("stuck debug");
+                tracing::debug_span!("inner").in_scope(|| tracing::trace!("stuck trace"));
+            });
+            other.in_scope(|| {
+                tracing::debug!("other debug");
+                tracing::info!("other info");
+            });
+            prefixed.in_scope(|| tracing::debug!("prefixed debug"));
+            crate::scope::with_qid(Qid::from(0x1234), || tracing::debug!("scoped debug"));
+            tracing::debug!("no qid");
+
+            debug_qids.remove(0x1234);
+            stuck.in_scope(|| tracing::debug!("after"));
+        });
+
+        let content = capture.content();
+        for line in [
+            "stuck debug",
+            "stuck trace",
+            "other info",
+            "prefixed debug",
+            "scoped debug",
+        ] {
+            assert!(content.contains(line), "{line}: {content}");
+        }
+        for line in ["before", "other debug", "no qid", "after"] {
+            assert!(!content.contains(line), "{line}: {content}");
+        }
+
+        assert!(debug_qids.add_prefix("0x").is_err());
+        assert!(debug_qids.add_prefix("xyz").is_err());
+        debug_qids.add_prefix("0x1234567890abcdef").unwrap();
+        assert!(debug_qids.matches(0x1234_5678_90ab_cdef));
+        assert!(!debug_qids.matches(0x1234_5678_90ab_cdee));
+        debug_qids.clear();
+        assert!(debug_qids.is_empty());
+    }
+
+    #[test]
+    fn reload_handle_test() {
+        use std::sync::{atomic::AtomicU64, Arc};
+
+        use crate::{guard::DiskCheck, writer::DiskPressure};
+
+        let capture = Capture::default();
+        let available_space = Arc::new(AtomicU64::new(2048));
+        let guard = crate::guard::DiskGuard::with_check(
+            capture.clone(),
+            DiskCheck::new(available_space.clone(), 1024, Arc::new(DiskPressure::new())),
+        );
+        let layer = TaosLayer::<Qid, _, _>::new(guard).with_max_level(LevelFilter::INFO);
+        let handle = layer.reload_handle();
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("filtered");
+            handle.set_level(LevelFilter::DEBUG);
+            assert_eq!(handle.level(), Some(LevelFilter::DEBUG));
+            tracing::debug!("debug");
+
+            // disk downgrade wins over the user level
+            available_space.store(512, std::sync::atomic::Ordering::SeqCst);
+            tracing::debug!("downgraded");
+            available_space.store(2048, std::sync::atomic::Ordering::SeqCst);
+
+            assert!(handle.reload("info,a=loud").is_err());
+            assert_eq!(handle.directives().to_string(), "debug");
+            handle.reload("warn").unwrap();
+            tracing::info!("filtered again");
+            tracing::warn!("warn");
+        });
+
+        let content = capture.content();
+        assert!(!content.contains("filtered"), "{content}");
+        assert!(!content.contains("downgraded"));
+        assert!(content.contains("DEBUG debug"));
+        assert!(content.contains("WARN  warn"));
+    }
+}
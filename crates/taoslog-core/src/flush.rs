@@ -0,0 +1,173 @@
+//! Flush scheduling shared by many appenders.
+//!
+//! Appenders registered on the same [`FlushScheduler`] are flushed to disk on
+//! the scheduler interval, each with its own random jitter so their IO does not
+//! align, and at most `max_concurrent` at a time.
+
+use std::{
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::current::CurrentFile;
+
+struct Entry {
+    file: Weak<CurrentFile>,
+    next: Instant,
+}
+
+struct Inner {
+    interval: Duration,
+    jitter: Duration,
+    entries: Mutex<Vec<Entry>>,
+    flushed: AtomicU64,
+}
+
+impl Inner {
+    fn random(&self, max: Duration) -> Duration {
+        let nanos = max.as_nanos() as u64;
+        if nanos == 0 {
+            return Duration::ZERO;
+        }
+        let random = std::collections::hash_map::RandomState::new().hash_one(Instant::now());
+        Duration::from_nanos(random % nanos)
+    }
+}
+
+/// Central flush scheduler, cheap to clone and share between appenders.
+#[derive(Clone)]
+pub struct FlushScheduler {
+    inner: Arc<Inner>,
+    wake: flume::Sender<()>,
+}
+
+impl FlushScheduler {
+    /// Flush every registered appender each `interval` plus a random delay up
+    /// to `jitter`, with at most `max_concurrent` flushes at the same time.
+    pub fn new(interval: Duration, jitter: Duration, max_concurrent: usize) -> Self {
+        let inner = Arc::new(Inner {
+            interval,
+            jitter,
+            entries: Mutex::new(Vec::new()),
+            flushed: AtomicU64::new(0),
+        });
+        let (wake, wake_rx) = flume::unbounded();
+        let (flush_tx, flush_rx) = flume::bounded::<Arc<CurrentFile>>(0);
+        for _ in 0..max_concurrent.max(1) {
+            let flush_rx = flush_rx.clone();
+            let inner = Arc::downgrade(&inner);
+            thread::spawn(move || {
+                while let Ok(file) = flush_rx.recv() {
+                    file.load().sync_data().ok();
+                    if let Some(inner) = inner.upgrade() {
+                        inner.flushed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+
+        let scheduler = Arc::downgrade(&inner);
+        thread::spawn(move || loop {
+            let Some(inner) = scheduler.upgrade() else {
+                break;
+            };
+            let now = Instant::now();
+            let mut due = Vec::new();
+            let mut wait = inner.interval;
+            {
+                let mut entries = inner.entries.lock();
+                entries.retain(|entry| entry.file.strong_count() > 0);
+                for entry in entries.iter_mut() {
+                    if entry.next <= now {
+                        if let Some(file) = entry.file.upgrade() {
+                            due.push(file);
+                        }
+                        entry.next = now + inner.interval + inner.random(inner.jitter);
+                    }
+                    wait = wait.min(entry.next.saturating_duration_since(now));
+                }
+            }
+            drop(inner);
+            for file in due {
+                // blocks while max_concurrent flushes are running
+                if flush_tx.send(file).is_err() {
+                    return;
+                }
+            }
+            match wake_rx.recv_timeout(wait) {
+                Err(flume::RecvTimeoutError::Disconnected) => break,
+                _ => continue,
+            }
+        });
+
+        Self { inner, wake }
+    }
+
+    pub(crate) fn register(&self, file: &Arc<CurrentFile>) {
+        let next = Instant::now() + self.inner.random(self.inner.interval);
+        self.inner.entries.lock().push(Entry {
+            file: Arc::downgrade(file),
+            next,
+        });
+        self.wake.send(()).ok();
+    }
+
+    /// Number of registered appenders still alive.
+    pub fn len(&self) -> usize {
+        let mut entries = self.inner.entries.lock();
+        entries.retain(|entry| entry.file.strong_count() > 0);
+        entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of flushes done since creation.
+    pub fn flushed(&self) -> u64 {
+        self.inner.flushed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::RollingFileAppender;
+
+    #[test]
+    fn flush_scheduler_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler =
+            FlushScheduler::new(Duration::from_millis(20), Duration::from_millis(10), 2);
+        let appenders: Vec<_> = (0..4)
+            .map(|i| {
+                RollingFileAppender::builder(dir.path(), "flush", i)
+                    .reserved_disk_size("1KB")
+                    .flush_scheduler(scheduler.clone())
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(scheduler.len(), 4);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(scheduler.flushed() >= 4, "{}", scheduler.flushed());
+
+        drop(appenders);
+        // a worker may still hold a file it is flushing
+        for _ in 0..100 {
+            if scheduler.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(scheduler.is_empty());
+    }
+}
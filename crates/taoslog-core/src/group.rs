@@ -0,0 +1,69 @@
+//! Log groups, writing related events contiguously.
+//!
+//! Events of a group are buffered by [`TaosLayer`](crate::layer::TaosLayer)
+//! and written in one piece when the group closes, so lines of other threads
+//! cannot interleave with them. Each line carries the `group:<id>` field.
+//!
+//! ```ignore
+//! let group = taoslog::group::begin("startup configuration");
+//! tracing::info!(log_dir = %dir, "appender");
+//! tracing::info!(workers = 8, "runtime");
+//! drop(group);
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{span::EnteredSpan, Metadata};
+
+/// Target of group spans.
+pub const TARGET: &str = "taoslog::group";
+
+/// An open group on the current thread, closed on drop.
+#[must_use = "the group is closed when dropped"]
+pub struct LogGroup {
+    _span: EnteredSpan,
+}
+
+impl LogGroup {
+    /// Close the group and write its events.
+    pub fn end(self) {}
+}
+
+/// Open a group on the current thread.
+pub fn begin(name: &str) -> LogGroup {
+    LogGroup {
+        _span: span(name).entered(),
+    }
+}
+
+/// A group span, to instrument futures. The group is written when the span
+/// and all its clones are closed.
+pub fn span(name: &str) -> tracing::Span {
+    tracing::info_span!(target: TARGET, "group", group_name = name)
+}
+
+/// Events of a group waiting to be written.
+pub(crate) struct GroupBuffer {
+    pub(crate) id: u64,
+    pub(crate) buf: String,
+    /// Metadata of the most severe event, to pick the writer.
+    pub(crate) metadata: Option<&'static Metadata<'static>>,
+}
+
+impl GroupBuffer {
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            buf: String::new(),
+            metadata: None,
+        }
+    }
+
+    pub(crate) fn push(&mut self, line: &str, metadata: &'static Metadata<'static>) {
+        self.buf.push_str(line);
+        if self.metadata.is_none_or(|m| metadata.level() < m.level()) {
+            self.metadata = Some(metadata);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! QID propagation for tonic gRPC calls, with the same `x-qid` key as HTTP.
+//!
+//! ```ignore
+//! // client: send the QID of the current span
+//! let client = GreeterClient::with_interceptor(channel, ClientQidInterceptor::<Qid>::new());
+//!
+//! // server: keep the QID of the caller in the request extensions
+//! let service = GreeterServer::with_interceptor(greeter, ServerQidInterceptor::<Qid>::new());
+//! async fn say_hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {
+//!     let _span = taoslog::grpc::span::<Qid, _>(&request).entered();
+//!     ...
+//! }
+//! ```
+
+use std::marker::PhantomData;
+
+use tonic::{service::Interceptor, Request, Status};
+
+use crate::{
+    utils::{QidMetadataGetter, QidMetadataSetter, Span},
+    QidManager,
+};
+
+/// Injects the QID of the current span into outgoing requests.
+pub struct ClientQidInterceptor<Q>(PhantomData<fn(Q)>);
+
+impl<Q> ClientQidInterceptor<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for ClientQidInterceptor<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> Clone for ClientQidInterceptor<Q> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<Q: QidManager> Interceptor for ClientQidInterceptor<Q> {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if request.metadata().get_qid::<Q>().is_none() {
+            if let Some(qid) = Span.get_qid::<Q>() {
+                request.metadata_mut().set_qid(&qid);
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Puts the QID of incoming requests, or a new one, in the request
+/// extensions.
+pub struct ServerQidInterceptor<Q>(PhantomData<fn(Q)>);
+
+impl<Q> ServerQidInterceptor<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for ServerQidInterceptor<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> Clone for ServerQidInterceptor<Q> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<Q: QidManager> Interceptor for ServerQidInterceptor<Q> {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let qid = request.metadata().get_qid::<Q>().unwrap_or_else(Q::init);
+        request.extensions_mut().insert(qid);
+        Ok(request)
+    }
+}
+
+/// A root span for a server request, carrying the QID of the request.
+pub fn span<Q: QidManager, T>(request: &Request<T>) -> tracing::Span {
+    let method = request
+        .extensions()
+        .get::<tonic::GrpcMethod>()
+        .map(|m| format!("{}/{}", m.service(), m.method()))
+        .unwrap_or_default();
+    let mut span = tracing::info_span!(parent: None, "gRPC request", rpc.method = %method);
+    let qid = request
+        .extensions()
+        .get::<Q>()
+        .cloned()
+        .or_else(|| request.metadata().get_qid());
+    if let Some(qid) = qid {
+        span.set_qid(&qid);
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::{fake::Qid, layer::TaosLayer, utils::QID_HEADER_KEY};
+
+    #[test]
+    fn interceptor_test() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let _guard = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(std::sync::Mutex::new(
+                std::io::empty(),
+            )))
+            .set_default();
+
+        let request = tracing::info_span!("call").in_scope(|| {
+            Span.set_qid(&Qid::from(0xabc));
+            ClientQidInterceptor::<Qid>::new()
+                .call(Request::new(()))
+                .unwrap()
+        });
+        assert_eq!(
+            request.metadata().get(QID_HEADER_KEY).unwrap(),
+            "0x0000000000000abc"
+        );
+
+        let request = ServerQidInterceptor::<Qid>::new().call(request).unwrap();
+        assert_eq!(request.extensions().get::<Qid>().unwrap().get(), 0xabc);
+        let span = span::<Qid, _>(&request);
+        assert_eq!(span.get_qid::<Qid>().unwrap().get(), 0xabc);
+
+        // a new qid without upstream
+        let request = ServerQidInterceptor::<Qid>::new()
+            .call(Request::new(()))
+            .unwrap();
+        assert_eq!(
+            request.extensions().get::<Qid>().unwrap().get(),
+            Qid::init().get()
+        );
+    }
+}
@@ -0,0 +1,465 @@
+//! Disk pressure policy for any [`MakeWriter`].
+//!
+//! Below the reserved disk size only ERROR events are written, below 20% of it
+//! no event is written. [`RollingFileAppender`](crate::writer::RollingFileAppender)
+//! applies this policy to its files, [`DiskGuard`] applies it to other sinks.
+//! A [`DiskPolicy`] replaces it, e.g. to keep WARN events and only drop TRACE
+//! and DEBUG ones.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::Level;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    catalog::Language,
+    size::ByteSize,
+    writer::{find_disk, parse_unit_size, DiskPressure, DiskPressureStats, DiskSpace, DiskStage},
+    Result,
+};
+
+/// What to do with an event, decided by a [`DiskPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskAction {
+    Write,
+    Drop,
+    /// Write it to the file right away, bypassing the write buffer of the
+    /// appender. Other sinks write it like [`DiskAction::Write`].
+    ForceFlush,
+}
+
+/// Decides whether events are written from the available space of the disk.
+///
+/// ```ignore
+/// /// Only drop TRACE and DEBUG events before the disk is nearly full.
+/// struct KeepWarnings;
+///
+/// impl DiskPolicy for KeepWarnings {
+///     fn decide(&self, available_space: u64, reserved_disk_size: u64, level: &Level) -> DiskAction {
+///         match DiskStage::of(available_space, reserved_disk_size) {
+///             DiskStage::Downgrade if *level <= Level::INFO => DiskAction::Write,
+///             _ => DefaultDiskPolicy.decide(available_space, reserved_disk_size, level),
+///         }
+///     }
+/// }
+///
+/// let appender = RollingFileAppender::builder(&dir, "taosx", 1)
+///     .disk_policy(KeepWarnings)
+///     .build()?;
+/// ```
+///
+/// The [`DiskStage`] of the stats and the level downgrade banners still follow
+/// the default thresholds.
+pub trait DiskPolicy: Send + Sync + 'static {
+    fn decide(&self, available_space: u64, reserved_disk_size: u64, level: &Level) -> DiskAction;
+}
+
+/// The default policy: only ERROR events below the reserved disk size,
+/// flushed right away, nothing below 20% of it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultDiskPolicy;
+
+impl DiskPolicy for DefaultDiskPolicy {
+    fn decide(&self, available_space: u64, reserved_disk_size: u64, level: &Level) -> DiskAction {
+        match DiskStage::of(available_space, reserved_disk_size) {
+            DiskStage::Stopped => DiskAction::Drop,
+            DiskStage::Downgrade if *level > Level::ERROR => DiskAction::Drop,
+            _ if *level == Level::ERROR => DiskAction::ForceFlush,
+            _ => DiskAction::Write,
+        }
+    }
+}
+
+/// Disk pressure state of one destination.
+pub(crate) struct DiskCheck {
+    pub(crate) available_space: Arc<AtomicU64>,
+    reserved_disk_size: u64,
+    pressure: Arc<DiskPressure>,
+    policy: Arc<dyn DiskPolicy>,
+    level_downgrade: AtomicBool,
+    /// events dropped since the last level upgrade, from TRACE to ERROR
+    suppressed: [AtomicU64; 5],
+    language: Language,
+}
+
+const LEVELS: [Level; 5] = [
+    Level::TRACE,
+    Level::DEBUG,
+    Level::INFO,
+    Level::WARN,
+    Level::ERROR,
+];
+
+impl DiskCheck {
+    pub(crate) fn new(
+        available_space: Arc<AtomicU64>,
+        reserved_disk_size: u64,
+        pressure: Arc<DiskPressure>,
+    ) -> Self {
+        Self {
+            available_space,
+            reserved_disk_size,
+            pressure,
+            policy: Arc::new(DefaultDiskPolicy),
+            level_downgrade: AtomicBool::default(),
+            suppressed: Default::default(),
+            language: Language::default(),
+        }
+    }
+
+    /// Same disk monitor and counters, own downgrade banner state.
+    pub(crate) fn share(&self) -> Self {
+        Self::new(
+            self.available_space.clone(),
+            self.reserved_disk_size,
+            self.pressure.clone(),
+        )
+        .with_language(self.language)
+        .with_policy(self.policy.clone())
+    }
+
+    pub(crate) fn with_language(self, language: Language) -> Self {
+        Self { language, ..self }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn language(&self) -> Language {
+        self.language
+    }
+
+    pub(crate) fn with_policy(self, policy: Arc<dyn DiskPolicy>) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// The stage with the current available space.
+    pub(crate) fn stage(&self) -> DiskStage {
+        DiskStage::of(
+            self.available_space.load(atomic::Ordering::SeqCst),
+            self.reserved_disk_size,
+        )
+    }
+
+    pub(crate) fn stats(&self) -> DiskPressureStats {
+        self.pressure.stats(
+            self.available_space.load(atomic::Ordering::SeqCst),
+            self.reserved_disk_size,
+        )
+    }
+
+    /// What to do with an event of `level`. `banner` writes the level
+    /// downgrade and upgrade lines when the stage changes.
+    pub(crate) fn admit(&self, level: &Level, banner: impl FnOnce(&[u8])) -> DiskAction {
+        let current_disk_space = self.available_space.load(atomic::Ordering::SeqCst);
+        let stage = DiskStage::of(current_disk_space, self.reserved_disk_size);
+        self.pressure.enter(stage);
+
+        let level_downgrade = stage == DiskStage::Downgrade;
+        if stage != DiskStage::Stopped
+            && self
+                .level_downgrade
+                .compare_exchange(
+                    !level_downgrade,
+                    level_downgrade,
+                    atomic::Ordering::AcqRel,
+                    atomic::Ordering::Acquire,
+                )
+                .is_ok()
+        {
+            if level_downgrade {
+                banner(self.language.level_downgrade().as_bytes());
+            } else {
+                let mut text = self.language.level_upgrade().to_string();
+                if let Some(counts) = self.take_suppressed() {
+                    text.push_str(&self.language.suppressed_during_downgrade(&counts));
+                }
+                banner(text.as_bytes());
+            }
+        }
+        let action = self
+            .policy
+            .decide(current_disk_space, self.reserved_disk_size, level);
+        if action == DiskAction::Drop {
+            self.pressure.drop_event(stage);
+            self.suppress(level);
+        }
+        action
+    }
+}
+
+impl DiskCheck {
+    fn suppress(&self, level: &Level) {
+        if let Some(i) = LEVELS.iter().position(|l| l == level) {
+            self.suppressed[i].fetch_add(1, atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// The dropped events by level, like `1243 INFO, 15 WARN`, resetting the
+    /// counts.
+    fn take_suppressed(&self) -> Option<String> {
+        let counts: Vec<_> = LEVELS
+            .iter()
+            .zip(&self.suppressed)
+            .filter_map(|(level, count)| {
+                let count = count.swap(0, atomic::Ordering::Relaxed);
+                (count > 0).then(|| format!("{count} {level}"))
+            })
+            .collect();
+        (!counts.is_empty()).then(|| counts.join(", "))
+    }
+}
+
+/// A [`MakeWriter`] applying the disk pressure policy to an inner one, e.g. a
+/// network sink or a tee.
+pub struct DiskGuard<M> {
+    inner: M,
+    check: DiskCheck,
+}
+
+impl<M> DiskGuard<M> {
+    /// Guard `inner` with the available space of the disk holding `path`,
+    /// refreshed every 30 seconds.
+    ///
+    /// Use [`RollingFileAppender::disk_guard`](crate::writer::RollingFileAppender::disk_guard)
+    /// to share the disk monitor of an appender instead.
+    pub fn new(inner: M, path: impl AsRef<Path>, reserved_disk_size: &str) -> Result<Self> {
+        Self::with_reserved_size(inner, path, parse_unit_size(reserved_disk_size)?.into())
+    }
+
+    /// [`new`](Self::new) with the reserved size in bytes.
+    pub fn with_reserved_size(
+        inner: M,
+        path: impl AsRef<Path>,
+        reserved_disk_size: ByteSize,
+    ) -> Result<Self> {
+        let mut disk = find_disk(path.as_ref())?;
+        let available_space = Arc::new(AtomicU64::new(disk.available_space()));
+        thread::spawn({
+            let available_space = Arc::downgrade(&available_space);
+            move || loop {
+                thread::sleep(Duration::from_secs(30));
+                let Some(available_space) = available_space.upgrade() else {
+                    break;
+                };
+                disk.refresh();
+                available_space.store(disk.available_space(), atomic::Ordering::SeqCst);
+            }
+        });
+        let check = DiskCheck::new(
+            available_space,
+            reserved_disk_size.bytes(),
+            Arc::new(DiskPressure::new()),
+        );
+        Ok(Self::with_check(inner, check))
+    }
+
+    pub(crate) fn with_check(inner: M, check: DiskCheck) -> Self {
+        Self { inner, check }
+    }
+
+    /// Decide which events are written with `policy` instead of the
+    /// [`DefaultDiskPolicy`].
+    pub fn with_policy(self, policy: impl DiskPolicy) -> Self {
+        Self {
+            check: self.check.with_policy(Arc::new(policy)),
+            ..self
+        }
+    }
+
+    /// Write the level downgrade and upgrade banners in `language`.
+    pub fn with_language(self, language: Language) -> Self {
+        Self {
+            check: self.check.with_language(language),
+            ..self
+        }
+    }
+
+    pub fn stats(&self) -> DiskPressureStats {
+        self.check.stats()
+    }
+
+    pub fn get_ref(&self) -> &M {
+        &self.inner
+    }
+}
+
+/// Writer of a [`DiskGuard`], discarding events dropped by the policy.
+pub enum DiskGuardWriter<W> {
+    Inner(W),
+    Null(io::Empty),
+}
+
+impl<W: Write> Write for DiskGuardWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DiskGuardWriter::Inner(w) => w.write(buf),
+            DiskGuardWriter::Null(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DiskGuardWriter::Inner(w) => w.flush(),
+            DiskGuardWriter::Null(w) => w.flush(),
+        }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for DiskGuard<M> {
+    type Writer = DiskGuardWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        DiskGuardWriter::Inner(self.inner.make_writer())
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        let action = self.check.admit(meta.level(), |banner| {
+            let mut writer = self.inner.make_writer();
+            writer.write_all(banner).ok();
+            writer.flush().ok();
+        });
+        match action {
+            DiskAction::Drop => DiskGuardWriter::Null(io::empty()),
+            _ => DiskGuardWriter::Inner(self.inner.make_writer_for(meta)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn disk_guard_test() {
+        let capture = Capture::default();
+        let guard = DiskGuard::with_check(
+            capture.clone(),
+            DiskCheck::new(
+                Arc::new(AtomicU64::new(2048)),
+                1024,
+                Arc::new(DiskPressure::new()),
+            ),
+        );
+        let available_space = guard.check.available_space.clone();
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(guard));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("normal");
+            available_space.store(512, atomic::Ordering::SeqCst);
+            tracing::info!("dropped");
+            tracing::info!("dropped");
+            tracing::warn!("dropped");
+            tracing::error!("error");
+            available_space.store(100, atomic::Ordering::SeqCst);
+            tracing::error!("stopped");
+            available_space.store(2048, atomic::Ordering::SeqCst);
+            tracing::info!("again");
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 6, "{content}");
+        assert!(lines[0].ends_with("normal"));
+        assert_eq!(lines[1], "=======level downgrade=====");
+        assert!(lines[2].ends_with("error"));
+        assert_eq!(lines[3], "=======level upgrade=====");
+        assert_eq!(
+            lines[4],
+            "suppressed 2 INFO, 1 WARN, 1 ERROR events during downgrade"
+        );
+        assert!(lines[5].ends_with("again"));
+    }
+
+    #[test]
+    fn disk_policy_test() {
+        struct KeepWarnings;
+
+        impl DiskPolicy for KeepWarnings {
+            fn decide(&self, available: u64, reserved: u64, level: &Level) -> DiskAction {
+                match DefaultDiskPolicy.decide(available, reserved, level) {
+                    DiskAction::Drop if *level <= Level::INFO && available > 0 => DiskAction::Write,
+                    action => action,
+                }
+            }
+        }
+
+        assert_eq!(
+            DefaultDiskPolicy.decide(2048, 1024, &Level::ERROR),
+            DiskAction::ForceFlush
+        );
+        assert_eq!(
+            DefaultDiskPolicy.decide(512, 1024, &Level::WARN),
+            DiskAction::Drop
+        );
+        let capture = Capture::default();
+        let guard = DiskGuard::with_check(
+            capture.clone(),
+            DiskCheck::new(
+                Arc::new(AtomicU64::new(512)),
+                1024,
+                Arc::new(DiskPressure::new()),
+            ),
+        )
+        .with_policy(KeepWarnings);
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(guard));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("dropped");
+            tracing::info!("kept");
+            tracing::warn!("kept");
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3, "{content}");
+        assert_eq!(lines[0], "=======level downgrade=====");
+        assert!(lines[1].ends_with("INFO  kept") && lines[2].ends_with("WARN  kept"));
+    }
+
+    #[test]
+    fn appender_disk_guard_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = crate::writer::RollingFileAppender::builder(dir.path(), "guard", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let guard = appender.disk_guard(Capture::default());
+        assert_eq!(guard.stats().stage, DiskStage::Normal);
+        assert_eq!(guard.stats().downgrade_threshold, 1024);
+    }
+
+    #[test]
+    fn language_test() {
+        let capture = Capture::default();
+        let guard = DiskGuard::with_check(
+            capture.clone(),
+            DiskCheck::new(
+                Arc::new(AtomicU64::new(512)),
+                1024,
+                Arc::new(DiskPressure::new()),
+            ),
+        )
+        .with_language(Language::Chinese);
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(guard));
+        tracing::subscriber::with_default(subscriber, || tracing::error!("error"));
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines[0], "=======日志级别降级=====");
+        // levels stay machine readable
+        assert!(lines[1].contains(" ERROR ") && lines[1].ends_with("error"));
+    }
+}
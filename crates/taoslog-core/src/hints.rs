@@ -0,0 +1,168 @@
+//! Checkpoint hints for log shippers.
+//!
+//! `<component>_<instance_id>.positions-hints.json` in the log dir describes
+//! the active file and the last rotation events, so shippers like Vector or
+//! promtail can follow a file through rotate, compress and delete without
+//! losing or duplicating lines. It is rewritten atomically on each event.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use snafu::ResultExt;
+
+use crate::{PositionHintsSnafu, Result};
+
+/// Rotation events kept in the hints file.
+const MAX_EVENTS: usize = 32;
+
+pub(crate) struct PositionHints {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    active: Value,
+    events: VecDeque<Value>,
+}
+
+fn file_hint(path: &Path) -> Value {
+    let metadata = fs::metadata(path).ok();
+    #[cfg(unix)]
+    let (inode, device) = {
+        use std::os::unix::fs::MetadataExt;
+        (
+            metadata.as_ref().map(|m| m.ino()),
+            metadata.as_ref().map(|m| m.dev()),
+        )
+    };
+    #[cfg(not(unix))]
+    let (inode, device) = (None::<u64>, None::<u64>);
+    json!({
+        "path": path.file_name().map(|s| s.to_string_lossy()),
+        "inode": inode,
+        "device": device,
+        "size": metadata.map(|m| m.len()),
+    })
+}
+
+impl PositionHints {
+    /// `extension` of the log files, the `errlog` ones get their own hints.
+    pub(crate) fn new(log_dir: &Path, component: &str, instance_id: u8, extension: &str) -> Self {
+        let name = match extension {
+            "log" => format!("{component}_{instance_id}.positions-hints.json"),
+            _ => format!("{component}_{instance_id}.{extension}.positions-hints.json"),
+        };
+        Self {
+            path: log_dir.join(name),
+            state: Mutex::default(),
+        }
+    }
+
+    /// `path` is the file being written.
+    pub(crate) fn active(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.active = file_hint(path);
+        self.save(&state)
+    }
+
+    /// Writing moved from `from` to `to`, `from` is complete.
+    pub(crate) fn rotated(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        let mut event = file_hint(from);
+        event["event"] = "rotated".into();
+        event["next"] = to.file_name().map(|s| s.to_string_lossy()).into();
+        state.active = file_hint(to);
+        self.push(&mut state, event)
+    }
+
+    /// `from` was replaced by the archive `to`.
+    pub(crate) fn compressed(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut event = json!({
+            "event": "compressed",
+            "path": from.file_name().map(|s| s.to_string_lossy()),
+        });
+        event["archive"] = file_hint(to);
+        self.push(&mut self.state.lock(), event)
+    }
+
+    /// `from` was moved to `to`, under `log_dir`.
+    pub(crate) fn archived(&self, from: &Path, to: &Path, log_dir: &Path) -> Result<()> {
+        let mut event = json!({
+            "event": "archived",
+            "path": from.file_name().map(|s| s.to_string_lossy()),
+        });
+        event["archive"] = file_hint(to);
+        event["archive"]["path"] = to
+            .strip_prefix(log_dir)
+            .unwrap_or(to)
+            .to_string_lossy()
+            .into();
+        self.push(&mut self.state.lock(), event)
+    }
+
+    /// `path` was deleted by retention.
+    pub(crate) fn deleted(&self, path: &Path) -> Result<()> {
+        let event = json!({
+            "event": "deleted",
+            "path": path.file_name().map(|s| s.to_string_lossy()),
+        });
+        self.push(&mut self.state.lock(), event)
+    }
+
+    fn push(&self, state: &mut State, mut event: Value) -> Result<()> {
+        event["time"] = Local::now().to_rfc3339().into();
+        if state.events.len() == MAX_EVENTS {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+        self.save(state)
+    }
+
+    fn save(&self, state: &State) -> Result<()> {
+        let hints = json!({
+            "updated": Local::now().to_rfc3339(),
+            "active": state.active,
+            "events": state.events,
+        });
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, hints.to_string()).context(PositionHintsSnafu { path: &tmp })?;
+        fs::rename(&tmp, &self.path).context(PositionHintsSnafu { path: &self.path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let hints = PositionHints::new(dir.path(), "taosx", 1, "log");
+        let (a, b) = (dir.path().join("a.log"), dir.path().join("b.log"));
+        fs::write(&a, "line\n").unwrap();
+        fs::write(&b, "").unwrap();
+
+        hints.active(&a).unwrap();
+        hints.rotated(&a, &b).unwrap();
+        hints.deleted(&a).unwrap();
+
+        let content = fs::read_to_string(dir.path().join("taosx_1.positions-hints.json")).unwrap();
+        let hints: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(hints["active"]["path"], "b.log");
+        let events = hints["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "rotated");
+        assert_eq!(events[0]["size"], 5);
+        assert_eq!(events[0]["next"], "b.log");
+        #[cfg(unix)]
+        assert!(events[0]["inode"].is_u64());
+        assert_eq!(events[1]["event"], "deleted");
+    }
+}
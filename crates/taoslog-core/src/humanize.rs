@@ -0,0 +1,78 @@
+//! Human readable rendering of byte sizes and durations.
+//!
+//! In the text format, [`TaosLayer`](crate::layer::TaosLayer) renders integer
+//! fields named `bytes_*` with [`bytes`] and fields named `duration_*`
+//! (milliseconds) with [`duration`]. The JSON format keeps the raw numbers.
+
+use std::time::Duration;
+
+const BYTE_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+/// Render a byte size with binary units, e.g. `1073741824` as `1.0GiB`.
+pub fn bytes(size: u64) -> String {
+    if size < 1024 {
+        return format!("{size}B");
+    }
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1}{}", BYTE_UNITS[unit])
+}
+
+/// Render a duration, e.g. `1530ms`, `2m5s` or `1h0m3s`.
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration < Duration::from_millis(1) {
+        format!("{}us", duration.as_micros())
+    } else if secs < 60 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 3600 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{}m{}s", secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
+
+/// Render an integer field value according to the field name convention.
+pub(crate) fn field(name: &str, value: u64) -> Option<String> {
+    if name.starts_with("bytes_") {
+        Some(bytes(value))
+    } else if name.starts_with("duration_") {
+        Some(duration(Duration::from_millis(value)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_test() {
+        assert_eq!(bytes(0), "0B");
+        assert_eq!(bytes(1023), "1023B");
+        assert_eq!(bytes(1024), "1.0KiB");
+        assert_eq!(bytes(1536), "1.5KiB");
+        assert_eq!(bytes(1073741824), "1.0GiB");
+        assert_eq!(bytes(u64::MAX), "16384.0PiB");
+    }
+
+    #[test]
+    fn duration_test() {
+        assert_eq!(duration(Duration::from_micros(12)), "12us");
+        assert_eq!(duration(Duration::from_millis(1530)), "1530ms");
+        assert_eq!(duration(Duration::from_secs(125)), "2m5s");
+        assert_eq!(duration(Duration::from_secs(3603)), "1h0m3s");
+    }
+
+    #[test]
+    fn field_test() {
+        assert_eq!(field("bytes_written", 2048).as_deref(), Some("2.0KiB"));
+        assert_eq!(field("duration_query", 1530).as_deref(), Some("1530ms"));
+        assert_eq!(field("rows", 1530), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Instance ids picked at runtime for sidecar processes.
+//!
+//! An instance id is taken while `<component>_<instance_id>.pid` in the log
+//! dir is locked. The lock goes away with the process, so ids are reused
+//! after crashes without colliding with live processes.
+
+use std::{
+    fs::{self, File, TryLockError},
+    io::Write,
+    path::Path,
+};
+
+use snafu::{OptionExt, ResultExt};
+
+use crate::{CreateLogDirSnafu, InstanceLockSnafu, NoFreeInstanceSnafu, Result};
+
+/// An exclusive lock on an instance id, released on drop.
+#[derive(Debug)]
+pub(crate) struct InstanceLock {
+    file: File,
+    instance_id: u8,
+}
+
+impl InstanceLock {
+    /// Lock the lowest free instance id of `component` in `log_dir`.
+    pub(crate) fn acquire(log_dir: &Path, component: &str) -> Result<Self> {
+        fs::create_dir_all(log_dir).context(CreateLogDirSnafu { path: log_dir })?;
+        (0..=u8::MAX)
+            .find_map(|instance_id| Self::try_acquire(log_dir, component, instance_id).transpose())
+            .context(NoFreeInstanceSnafu { component })?
+    }
+
+    /// `None` if another process holds the instance id.
+    fn try_acquire(log_dir: &Path, component: &str, instance_id: u8) -> Result<Option<Self>> {
+        let path = log_dir.join(format!("{component}_{instance_id}.pid"));
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context(InstanceLockSnafu { path: &path })?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(source)) => {
+                return Err(source).context(InstanceLockSnafu { path })
+            }
+        }
+        file.set_len(0)
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .context(InstanceLockSnafu { path })?;
+        Ok(Some(Self { file, instance_id }))
+    }
+
+    pub(crate) fn instance_id(&self) -> u8 {
+        self.instance_id
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // keep the file, removing it could let two processes lock different
+        // files of the same path
+        self.file.set_len(0).ok();
+        self.file.unlock().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acquire_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = InstanceLock::acquire(dir.path(), "sidecar").unwrap();
+        let b = InstanceLock::acquire(dir.path(), "sidecar").unwrap();
+        assert_eq!((a.instance_id(), b.instance_id()), (0, 1));
+        let pid = fs::read_to_string(dir.path().join("sidecar_1.pid")).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        // the lowest free id is reused
+        drop(a);
+        assert!(fs::read_to_string(dir.path().join("sidecar_0.pid"))
+            .unwrap()
+            .is_empty());
+        let c = InstanceLock::acquire(dir.path(), "sidecar").unwrap();
+        assert_eq!(c.instance_id(), 0);
+    }
+}
@@ -0,0 +1,24 @@
+//! Logging from the appender's own maintenance threads.
+//!
+//! Compression and cleanup run on threads marked with [`enter_maintenance`].
+//! Their events are written to the current log file without rotating, so they
+//! never wait on the maintenance queue they are serving. Events use the
+//! [`TARGET`] target.
+
+use std::cell::Cell;
+
+/// Target of the events emitted by maintenance threads.
+pub const TARGET: &str = "taoslog::maintenance";
+
+thread_local! {
+    static MAINTENANCE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Mark the calling thread as a maintenance thread.
+pub(crate) fn enter_maintenance() {
+    MAINTENANCE.with(|m| m.set(true));
+}
+
+pub(crate) fn in_maintenance() -> bool {
+    MAINTENANCE.with(Cell::get)
+}
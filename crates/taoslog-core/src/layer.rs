@@ -0,0 +1,1951 @@
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    fmt::Write as _,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, FixedOffset};
+use tracing::{
+    field::{self, Visit},
+    level_filters::LevelFilter,
+    subscriber::Interest,
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    filter::Filtered,
+    fmt::MakeWriter,
+    layer::{Context, Filter},
+    registry::{LookupSpan, Scope},
+    Registry,
+};
+
+use crate::{
+    catalog::Language,
+    clock::{Clock, ClockSource, Timer},
+    filter::{DebugQids, Directives, ReloadHandle},
+    group::{self, GroupBuffer},
+    humanize,
+    limit::{self, Limiter, RateLimit},
+    redact::Redactor,
+    timing::{self, Timings},
+    writer::RollingFileAppender,
+    QidManager,
+};
+
+const GRAY_COLOR: usize = 90;
+const RED_COLOR: usize = 91;
+const GREEN_COLOR: usize = 92;
+const YELLOW_COLOR: usize = 93;
+const BLUE_COLOR: usize = 94;
+const PURPLE_COLOR: usize = 95;
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Caps of the fields recorded on one span, past which fields are dropped and
+/// counted in a `fields_dropped` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanFieldLimits {
+    pub max_fields: usize,
+    /// formatted `key:value` bytes
+    pub max_bytes: usize,
+}
+
+impl Default for SpanFieldLimits {
+    fn default() -> Self {
+        Self {
+            max_fields: 256,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Fields held by the open spans of a [`TaosLayer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpanFieldStats {
+    pub fields: u64,
+    pub bytes: u64,
+    /// dropped by the [`SpanFieldLimits`] since the layer was created
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct SpanMemory {
+    fields: AtomicU64,
+    bytes: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Fields of a span within its limits, accounted in the layer memory until
+/// dropped.
+struct CappedFields {
+    limits: SpanFieldLimits,
+    memory: Arc<SpanMemory>,
+    count: usize,
+    bytes: usize,
+    dropped: usize,
+}
+
+impl CappedFields {
+    fn new(limits: SpanFieldLimits, memory: &Arc<SpanMemory>) -> Self {
+        Self {
+            limits,
+            memory: memory.clone(),
+            count: 0,
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Whether a field of `bytes` fits, accounting it if so.
+    fn admit(&mut self, bytes: usize) -> bool {
+        if self.count >= self.limits.max_fields || self.bytes + bytes > self.limits.max_bytes {
+            self.dropped += 1;
+            self.memory.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.count += 1;
+        self.bytes += bytes;
+        self.memory.fields.fetch_add(1, Ordering::Relaxed);
+        self.memory.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+impl Drop for CappedFields {
+    fn drop(&mut self) {
+        self.memory
+            .fields
+            .fetch_sub(self.count as u64, Ordering::Relaxed);
+        self.memory
+            .bytes
+            .fetch_sub(self.bytes as u64, Ordering::Relaxed);
+    }
+}
+
+struct RecordFields {
+    fields: Vec<String>,
+    message: Option<String>,
+    cap: CappedFields,
+}
+
+impl RecordFields {
+    fn extend(&mut self, fields: Vec<String>, message: String) {
+        for field in fields {
+            if self.cap.admit(field.len()) {
+                self.fields.push(field);
+            }
+        }
+        if !message.is_empty() {
+            self.message = Some(message);
+        }
+    }
+}
+
+/// Span fields kept for the JSON format.
+struct JsonFields {
+    fields: serde_json::Map<String, serde_json::Value>,
+    cap: CappedFields,
+}
+
+impl JsonFields {
+    fn extend(&mut self, fields: serde_json::Map<String, serde_json::Value>) {
+        for (key, value) in fields {
+            // a recorded field is replaced, not accounted twice
+            if self.fields.contains_key(&key) || self.cap.admit(key.len() + value.to_string().len())
+            {
+                self.fields.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Line format of [`TaosLayer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Format {
+    /// `<timestamp> <thread> <LEVEL> qid:<qid> <fields> <message>`
+    #[default]
+    Text,
+    /// One JSON object per line, see [`TaosLayer::json`].
+    Json,
+    /// The layout of the taosd C logger, for existing log parsing scripts:
+    /// `<timestamp> <tid:08> <module> <flags><message>`, where flags are
+    /// `FATAL`, `ERROR ` and `WARN ` (nothing below), and the QID is written as
+    /// `QID:0x<hex>` at the start of the message.
+    #[cfg_attr(feature = "config", serde(alias = "taosd"))]
+    TaosdCompat,
+}
+
+/// Newlines in the messages and field values of text lines, see
+/// [`TaosLayer::with_newlines`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Newlines {
+    /// Written as is, an event may span several lines.
+    #[default]
+    Keep,
+    /// Written as `\n` and `\r`, one event per line.
+    Escape,
+    /// Continuation lines start with a tab, for shippers joining them to the
+    /// event, like the multiline rules of promtail or Vector.
+    Indent,
+}
+
+impl Newlines {
+    fn apply(self, buf: &mut String) {
+        if self == Newlines::Keep || !buf.contains(['\n', '\r']) {
+            return;
+        }
+        *buf = match self {
+            Newlines::Escape => buf.replace('\r', "\\r").replace('\n', "\\n"),
+            _ => buf.replace("\r\n", "\n").replace('\n', "\n\t"),
+        };
+    }
+}
+
+/// `value` from `start` cut to `max` bytes, on a char boundary, followed by a
+/// `...<truncated N bytes>` marker.
+fn truncate(value: &mut String, start: usize, max: Option<usize>) {
+    let Some(max) = max.filter(|&max| value.len() - start > max) else {
+        return;
+    };
+    let mut end = start + max;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = value.len() - end;
+    value.truncate(end);
+    write!(value, "...<truncated {truncated} bytes>").ok();
+}
+
+/// Module token of the [`Format::TaosdCompat`] lines, unless changed.
+pub const TAOSD_DEFAULT_MODULE: &str = "UTL";
+
+/// Events with this field belong to a past date, e.g. replayed data, and go to
+/// the [backfill](TaosLayer::with_backfill) appender.
+pub const EVENT_TIME_FIELD: &str = "event_time";
+
+/// Display options of a [`TaosLayer`], shared with it so a clone changes them
+/// at runtime.
+#[derive(Clone, Default)]
+pub struct DisplayHandle(Arc<DisplayFlags>);
+
+#[derive(Default)]
+struct DisplayFlags {
+    ansi: AtomicBool,
+    location: AtomicBool,
+}
+
+impl DisplayHandle {
+    /// Whether text lines are colored.
+    pub fn ansi(&self) -> bool {
+        self.0.ansi.load(Ordering::Relaxed)
+    }
+
+    pub fn set_ansi(&self, ansi: bool) {
+        self.0.ansi.store(ansi, Ordering::Relaxed);
+    }
+
+    /// Whether text lines print the `file:line` of events.
+    pub fn location(&self) -> bool {
+        self.0.location.load(Ordering::Relaxed)
+    }
+
+    pub fn set_location(&self, location: bool) {
+        self.0.location.store(location, Ordering::Relaxed);
+    }
+}
+
+/// Writes events and spans in the taos format, filtered by its [`Directives`]
+/// and [`DebugQids`].
+///
+/// The filter applies to this layer only: the other layers of the subscriber,
+/// like an OpenTelemetry or journald one, still see the events it leaves out.
+pub struct TaosLayer<Q, S = Registry, M = RollingFileAppender>(
+    Filtered<Inner<Q, M>, TaosFilter<Q>, S>,
+);
+
+struct Inner<Q, M> {
+    make_writer: M,
+    display: DisplayHandle,
+    format: Format,
+    newlines: Newlines,
+    max_field_len: Option<usize>,
+    max_line_len: Option<usize>,
+    taosd_module: &'static str,
+    identity: Option<(String, u8)>,
+    /// the directives of the filter, for the closed lines of span timings
+    directives: Directives,
+    clock: Clock,
+    timer: Timer,
+    redactor: Redactor,
+    limiter: Option<Limiter>,
+    language: Language,
+    backfill: Option<RollingFileAppender>,
+    span_limits: SpanFieldLimits,
+    span_memory: Arc<SpanMemory>,
+    span_timings: bool,
+    _q: PhantomData<Q>,
+}
+
+/// The per-layer filter of a [`TaosLayer`].
+struct TaosFilter<Q> {
+    directives: Directives,
+    debug_qids: DebugQids,
+    _q: PhantomData<fn(Q)>,
+}
+
+impl<Q, S, M> TaosLayer<Q, S, M> {
+    pub fn new(make_writer: M) -> Self {
+        let directives = Directives::empty();
+        let inner = Inner {
+            make_writer,
+            display: DisplayHandle::default(),
+            format: Format::Text,
+            newlines: Newlines::Keep,
+            max_field_len: None,
+            max_line_len: None,
+            taosd_module: TAOSD_DEFAULT_MODULE,
+            identity: None,
+            directives: directives.clone(),
+            clock: Clock::new(ClockSource::System).with_offset(None),
+            timer: Timer::default(),
+            redactor: Redactor::default(),
+            limiter: None,
+            language: Language::default(),
+            backfill: None,
+            span_limits: SpanFieldLimits::default(),
+            span_memory: Arc::default(),
+            span_timings: false,
+            _q: PhantomData,
+        };
+        let filter = TaosFilter {
+            directives,
+            debug_qids: DebugQids::new(),
+            _q: PhantomData,
+        };
+        Self(Filtered::new(inner, filter))
+    }
+
+    fn inner(&self) -> &Inner<Q, M> {
+        self.0.inner()
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner<Q, M> {
+        self.0.inner_mut()
+    }
+
+    pub fn with_ansi(self) -> Self {
+        self.display_handle()
+            .set_ansi(self.inner().format == Format::Text);
+        self
+    }
+
+    /// Print the `file:line` of events after the level of text lines.
+    pub fn with_location(self) -> Self {
+        self.display_handle().set_location(true);
+        self
+    }
+
+    /// Print `<component>_<instance>` in the prefix of each line, for outputs
+    /// like stdout where the file name does not tell it.
+    pub fn with_identity(mut self, component: &str, instance: u8) -> Self {
+        self.inner_mut().identity = Some((component.to_string(), instance));
+        self
+    }
+
+    /// Write each event as one JSON object per line instead of text.
+    ///
+    /// Unlike the text format, span fields are repeated on every event. Field
+    /// values keep their raw types, `bytes_*` and `duration_*` included.
+    pub fn json(self) -> Self {
+        self.with_format(Format::Json)
+    }
+
+    /// Write lines in `format`. Only [`Format::Text`] keeps ANSI colors.
+    pub fn with_format(mut self, format: Format) -> Self {
+        if format != Format::Text {
+            self.display_handle().set_ansi(false);
+        }
+        self.inner_mut().format = format;
+        self
+    }
+
+    /// Keep events with newlines in their message or fields on one line, or
+    /// mark their continuation lines. JSON lines are always escaped.
+    pub fn with_newlines(mut self, newlines: Newlines) -> Self {
+        self.inner_mut().newlines = newlines;
+        self
+    }
+
+    /// Truncate messages and field values longer than `len` bytes, e.g. a full
+    /// SQL statement or request body, marking them with
+    /// `...<truncated N bytes>`.
+    pub fn with_max_field_len(mut self, len: usize) -> Self {
+        self.inner_mut().max_field_len = Some(len);
+        self
+    }
+
+    /// Truncate text lines longer than `len` bytes the same way, so one event
+    /// cannot blow through the rotation size. JSON lines are kept valid, only
+    /// capped by [`with_max_field_len`](Self::with_max_field_len).
+    pub fn with_max_line_len(mut self, len: usize) -> Self {
+        self.inner_mut().max_line_len = Some(len);
+        self
+    }
+
+    /// Module token of [`Format::TaosdCompat`] lines, three upper case letters
+    /// like taosd's `DND` or `QRY`.
+    pub fn with_taosd_module(mut self, module: &'static str) -> Self {
+        self.inner_mut().taosd_module = module;
+        self
+    }
+
+    /// Filter events by target and level with `directives`, replacing the
+    /// current ones.
+    pub fn with_directives(mut self, directives: Directives) -> Self {
+        self.inner_mut().directives = directives.clone();
+        self.0.filter_mut().directives = directives;
+        self
+    }
+
+    /// Take event timestamps from `source`.
+    pub fn with_clock(mut self, source: ClockSource) -> Self {
+        let inner = self.inner_mut();
+        inner.clock = Clock::new(source).with_offset(inner.clock.offset());
+        self
+    }
+
+    /// Write timestamps with a fixed UTC `offset` instead of the local
+    /// timezone, for containers without a timezone database, where the local
+    /// timezone falls back to UTC.
+    pub fn with_timezone_offset(mut self, offset: FixedOffset) -> Self {
+        let inner = self.inner_mut();
+        inner.clock = Clock::new(inner.clock.source()).with_offset(Some(offset));
+        self
+    }
+
+    /// Format timestamps of text lines with `timer`, see [`Timer`].
+    pub fn with_timer(mut self, timer: Timer) -> Self {
+        self.inner_mut().timer = timer;
+        self
+    }
+
+    /// Write `***` in place of the values of fields named `names`, ignoring
+    /// case, in events and spans.
+    pub fn with_redacted_fields<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.inner_mut()
+            .redactor
+            .add_names(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Redact the fields whose name matches `pattern`.
+    #[cfg(feature = "regex")]
+    pub fn with_redacted_pattern(mut self, pattern: regex::Regex) -> Self {
+        self.inner_mut().redactor.add_pattern(pattern);
+        self
+    }
+
+    /// Redact the fields matching [`redact::DEFAULT_PATTERNS`](crate::redact::DEFAULT_PATTERNS),
+    /// the patterns of `#[derive(LogSafe)]`.
+    pub fn with_default_redaction(mut self) -> Self {
+        self.inner_mut().redactor.add_defaults();
+        self
+    }
+
+    /// Drop events over `limit`, see [`limit`](crate::limit).
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.inner_mut().limiter = Some(Limiter::new(limit));
+        self
+    }
+
+    /// Write the marker lines, like the suppressed events summaries, in
+    /// `language`.
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.inner_mut().language = language;
+        self
+    }
+
+    /// Write the events with an [`EVENT_TIME_FIELD`] field to `appender`
+    /// instead, so replayed or backfilled data does not pollute today's
+    /// operational log. Its retention is the one of `appender`.
+    pub fn with_backfill(mut self, appender: RollingFileAppender) -> Self {
+        self.inner_mut().backfill = Some(appender);
+        self
+    }
+
+    /// Write a line with the busy and idle time of each span when it closes,
+    /// see [`timing`].
+    pub fn with_span_timings(mut self) -> Self {
+        self.inner_mut().span_timings = true;
+        self
+    }
+
+    /// Cap the fields recorded on each span, e.g. against a loop recording
+    /// fields on a long lived span.
+    pub fn with_span_field_limits(mut self, span_limits: SpanFieldLimits) -> Self {
+        self.inner_mut().span_limits = span_limits;
+        self
+    }
+
+    /// Fields currently held by open spans.
+    pub fn span_stats(&self) -> SpanFieldStats {
+        let memory = &self.inner().span_memory;
+        SpanFieldStats {
+            fields: memory.fields.load(Ordering::Relaxed),
+            bytes: memory.bytes.load(Ordering::Relaxed),
+            dropped: memory.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A handle to change the level and directives of this layer at runtime.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle::new(self.0.filter().directives.clone())
+    }
+
+    /// The QIDs whose events this layer writes at every level, shared with it.
+    pub fn debug_qids(&self) -> DebugQids {
+        self.0.filter().debug_qids.clone()
+    }
+
+    /// A handle to toggle colors and locations of this layer at runtime.
+    pub fn display_handle(&self) -> DisplayHandle {
+        self.inner().display.clone()
+    }
+
+    /// Set the default level of the layer directives.
+    ///
+    /// The layer gets its own copy of the directives first, so the
+    /// [`Directives`] given to [`with_directives`](Self::with_directives) are
+    /// left unchanged and no longer shared with it. Use
+    /// [`reload_handle`](Self::reload_handle) to change them afterwards.
+    pub fn with_max_level(self, level: LevelFilter) -> Self {
+        let directives = self.0.filter().directives.detached();
+        directives.set_default_level(Some(level));
+        self.with_directives(directives)
+    }
+}
+
+impl<Q, M> Inner<Q, M> {
+    fn record_fields(&self) -> RecordFields {
+        RecordFields {
+            fields: Vec::new(),
+            message: None,
+            cap: CappedFields::new(self.span_limits, &self.span_memory),
+        }
+    }
+
+    fn json_fields(&self) -> JsonFields {
+        JsonFields {
+            fields: serde_json::Map::new(),
+            cap: CappedFields::new(self.span_limits, &self.span_memory),
+        }
+    }
+}
+
+impl<Q, S> Filter<S> for TaosFilter<Q>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    Q: QidManager,
+{
+    fn callsite_enabled(&self, metadata: &'static tracing::Metadata<'static>) -> Interest {
+        if self.directives.enabled(metadata) {
+            Interest::always()
+        } else if !self.debug_qids.is_empty() {
+            // decided by the QID of each event
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &tracing::Metadata<'_>, ctx: &Context<'_, S>) -> bool {
+        if self.directives.enabled(metadata) {
+            return true;
+        }
+        if self.debug_qids.is_empty() {
+            return false;
+        }
+        let qid = ctx
+            .lookup_current()
+            .and_then(|span| span.extensions().get::<Q>().map(Q::get))
+            .or_else(crate::scope::current);
+        qid.is_some_and(|qid| self.debug_qids.matches(qid))
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        if self.debug_qids.is_empty() {
+            Some(self.directives.max_level_hint())
+        } else {
+            Some(LevelFilter::TRACE)
+        }
+    }
+}
+
+impl<Q, S, M> tracing_subscriber::Layer<S> for TaosLayer<Q, S, M>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + 'static,
+    M: for<'writer> MakeWriter<'writer> + 'static,
+    Q: QidManager,
+{
+    fn on_register_dispatch(&self, subscriber: &tracing::Dispatch) {
+        self.0.on_register_dispatch(subscriber)
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.0.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static tracing::Metadata<'static>) -> Interest {
+        self.0.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &tracing::Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.0.enabled(metadata, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.0.max_level_hint()
+    }
+
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        self.0.on_new_span(attrs, id, ctx)
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        self.0.on_record(id, values, ctx)
+    }
+
+    fn on_follows_from(
+        &self,
+        id: &tracing::span::Id,
+        follows: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        self.0.on_follows_from(id, follows, ctx)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.0.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        self.0.on_event(event, ctx)
+    }
+
+    fn on_enter(&self, id: &tracing::span::Id, ctx: Context<'_, S>) {
+        self.0.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &tracing::span::Id, ctx: Context<'_, S>) {
+        self.0.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: tracing::span::Id, ctx: Context<'_, S>) {
+        self.0.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &tracing::span::Id, new: &tracing::span::Id, ctx: Context<'_, S>) {
+        self.0.on_id_change(old, new, ctx)
+    }
+
+    #[doc(hidden)]
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            return Some(self as *const _ as *const ());
+        }
+        // the per-layer filter marker included, so the subscriber knows about
+        // the filter
+        unsafe { self.0.downcast_raw(id) }
+    }
+}
+
+impl<Q, S, M> tracing_subscriber::Layer<S> for Inner<Q, M>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    M: for<'writer> MakeWriter<'writer> + 'static,
+    Q: QidManager,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let span = ctx
+            .span(id)
+            .expect("Span not found, this is a bug in tracing");
+        let qid = match span
+            .parent()
+            .as_ref()
+            .and_then(|p| p.extensions().get::<Q>().cloned())
+        {
+            Some(qid) => qid,
+            None => crate::scope::current().map_or_else(Q::init, Q::from),
+        };
+        let mut extensions = span.extensions_mut();
+        extensions.replace(qid);
+        if attrs.metadata().target() == group::TARGET {
+            extensions.replace(GroupBuffer::new());
+        }
+        if self.span_timings {
+            extensions.replace(Timings::new());
+        }
+
+        // kept in the extension of the format only, not to count twice
+        // against the span limits
+        if self.format == Format::Json {
+            if extensions.get_mut::<JsonFields>().is_none() {
+                let mut fields = serde_json::Map::new();
+                attrs.values().record(&mut self.redactor.visit(JsonVisit(
+                    &mut fields,
+                    &mut None,
+                    self.max_field_len,
+                )));
+                let mut json = self.json_fields();
+                json.extend(fields);
+                extensions.replace(json);
+            }
+        } else if extensions.get_mut::<RecordFields>().is_none() {
+            let mut fields = Vec::new();
+            let mut message = String::new();
+            attrs.values().record(&mut self.redactor.visit(RecordVisit(
+                &mut fields,
+                &mut message,
+                self.max_field_len,
+            )));
+            let mut record = self.record_fields();
+            record.extend(fields, message);
+            extensions.replace(record);
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let span = ctx
+            .span(id)
+            .expect("Span not found, this is a bug in tracing");
+        let mut extensions = span.extensions_mut();
+        if self.format != Format::Json {
+            let mut fields = Vec::new();
+            let mut message = String::new();
+            values.record(&mut self.redactor.visit(RecordVisit(
+                &mut fields,
+                &mut message,
+                self.max_field_len,
+            )));
+            match extensions.get_mut::<RecordFields>() {
+                Some(record) => record.extend(fields, message),
+                None => {
+                    let mut record = self.record_fields();
+                    record.extend(fields, message);
+                    extensions.replace(record);
+                }
+            }
+        } else {
+            let mut fields = serde_json::Map::new();
+            values.record(&mut self.redactor.visit(JsonVisit(
+                &mut fields,
+                &mut None,
+                self.max_field_len,
+            )));
+            match extensions.get_mut::<JsonFields>() {
+                Some(json) => json.extend(fields),
+                None => {
+                    let mut json = self.json_fields();
+                    json.extend(fields);
+                    extensions.replace(json);
+                }
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(span) = self.span_timings.then(|| ctx.span(id)).flatten() {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                timings.enter();
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(span) = self.span_timings.then(|| ctx.span(id)).flatten() {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                timings.exit();
+            }
+        }
+    }
+
+    fn on_close(&self, id: tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        // before the group flush, so a group ends with its closed line
+        let timings = span.extensions_mut().remove::<Timings>();
+        if let Some(timings) = timings {
+            if self.directives.enabled(&timing::CLOSED) {
+                timings.close(&id, span.name(), |event| self.on_event(event, ctx.clone()));
+            }
+        }
+        let Some(group) = span.extensions_mut().remove::<GroupBuffer>() else {
+            return;
+        };
+        let Some(metadata) = group.metadata else {
+            return;
+        };
+        let mut writer = self.make_writer.make_writer_for(metadata);
+        if let Err(e) = std::io::Write::write_all(&mut writer, group.buf.as_bytes()) {
+            eprintln!("[TaosLayer] Unable to write a log group to the Writer for this Subscriber! Error: {}\n", e);
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(limiter) = &self.limiter {
+            match limiter.check(event.metadata()) {
+                None => return,
+                Some(0) => {}
+                Some(suppressed) => {
+                    limit::summary(suppressed, event.metadata(), self.language, |summary| {
+                        self.on_event(summary, ctx.clone())
+                    })
+                }
+            }
+        }
+
+        thread_local! {
+            static BUF: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+        }
+
+        BUF.with(|buf| {
+            let borrow = buf.try_borrow_mut();
+            let mut a;
+            let mut b;
+            let buf = match borrow {
+                Ok(buf) => {
+                    a = buf;
+                    &mut *a
+                }
+                _ => {
+                    b = String::new();
+                    &mut b
+                }
+            };
+
+            let metadata = event.metadata();
+            if self.format == Format::Json {
+                let identity = self.identity.as_ref().map(|(c, i)| (c.as_str(), *i));
+                fmt_json::<_, Q>(
+                    buf,
+                    self.timer.zone(self.clock.now()),
+                    identity,
+                    &self.redactor,
+                    self.max_field_len,
+                    event,
+                    ctx.event_scope(event),
+                );
+            } else if self.format == Format::TaosdCompat {
+                fmt_taosd_head(buf, self.clock.now(), self.taosd_module, metadata.level());
+                fmt_fields_and_qid::<_, Q>(
+                    buf,
+                    &self.redactor,
+                    self.max_field_len,
+                    event,
+                    ctx.event_scope(event),
+                    false,
+                    true,
+                );
+            } else {
+                let with_ansi = self.display.ansi();
+                // Part 1: timestamp
+                fmt_timestamp(buf, &self.timer, self.clock.now(), with_ansi);
+                // Part 2: process id
+                fmt_thread_id(buf, with_ansi);
+                if let Some((component, instance)) = &self.identity {
+                    write!(buf, " {component}_{instance}").ok();
+                }
+                // Part 3: level
+                fmt_level(buf, metadata.level(), with_ansi);
+                if self.display.location() {
+                    fmt_location(buf, metadata, with_ansi);
+                }
+                // Part 4 and Part 5:  span and QID
+                fmt_fields_and_qid::<_, Q>(
+                    buf,
+                    &self.redactor,
+                    self.max_field_len,
+                    event,
+                    ctx.event_scope(event),
+                    with_ansi,
+                    false,
+                );
+            }
+            if self.format != Format::Json {
+                truncate(buf, 0, self.max_line_len);
+                self.newlines.apply(buf);
+            }
+            // Part 6: write event content
+            buf.push('\n');
+            if let Some(backfill) = &self.backfill {
+                if metadata.fields().field(EVENT_TIME_FIELD).is_some() {
+                    let mut writer = backfill.make_writer_for(metadata);
+                    if let Err(e) = std::io::Write::write_all(&mut writer, buf.as_bytes()) {
+                        eprintln!("[TaosLayer] Unable to write an event to the backfill appender! Error: {}\n", e);
+                    }
+                    buf.clear();
+                    return;
+                }
+            }
+            // buffer events of a log group until it closes
+            let group = ctx.event_scope(event).and_then(|scope| {
+                scope
+                    .into_iter()
+                    .find(|span| span.extensions().get::<GroupBuffer>().is_some())
+            });
+            if let Some(span) = group {
+                if let Some(group) = span.extensions_mut().get_mut::<GroupBuffer>() {
+                    group.push(buf, metadata);
+                }
+                buf.clear();
+                return;
+            }
+            // put all to writer
+            let mut writer = self.make_writer.make_writer_for(metadata);
+            let res = std::io::Write::write_all(&mut writer, buf.as_bytes());
+            if let Err(e) = res {
+                eprintln!("[TaosLayer] Unable to write an event to the Writer for this Subscriber! Error: {}\n", e);
+            }
+            buf.clear();
+        });
+    }
+}
+
+fn fmt_timestamp(buf: &mut String, timer: &Timer, now: DateTime<FixedOffset>, with_ansi: bool) {
+    if with_ansi {
+        ansi_start(buf, GRAY_COLOR);
+    }
+    timer.write(buf, now);
+    buf.push(' ');
+    if with_ansi {
+        buf.push_str(ANSI_RESET);
+    }
+}
+
+fn fmt_thread_id(buf: &mut String, with_ansi: bool) {
+    if with_ansi {
+        ansi_start(buf, GRAY_COLOR);
+    }
+    write!(buf, "{:0>8}", thread_id::get()).ok();
+    if with_ansi {
+        buf.push_str(ANSI_RESET);
+    }
+}
+
+fn fmt_level(buf: &mut String, level: &tracing::Level, with_ansi: bool) {
+    buf.push(' ');
+    let (level_str, color) = match *level {
+        tracing::Level::TRACE => ("TRACE", PURPLE_COLOR),
+        tracing::Level::DEBUG => ("DEBUG", BLUE_COLOR),
+        tracing::Level::INFO => ("INFO ", GREEN_COLOR),
+        tracing::Level::WARN => ("WARN ", YELLOW_COLOR),
+        tracing::Level::ERROR => ("ERROR", RED_COLOR),
+    };
+    if with_ansi {
+        ansi_start(buf, color);
+    }
+    buf.push_str(level_str);
+    if with_ansi {
+        buf.push_str(ANSI_RESET);
+    }
+    buf.push(' ');
+}
+
+fn fmt_location(buf: &mut String, metadata: &tracing::Metadata<'_>, with_ansi: bool) {
+    let Some(file) = metadata.file() else {
+        return;
+    };
+    if with_ansi {
+        ansi_start(buf, GRAY_COLOR);
+    }
+    buf.push_str(file);
+    if let Some(line) = metadata.line() {
+        write!(buf, ":{line}").ok();
+    }
+    if with_ansi {
+        buf.push_str(ANSI_RESET);
+    }
+    buf.push(' ');
+}
+
+/// `taosBuildLogHead` of taosd: `%02d/%02d %02d:%02d:%02d.%06d %08d ` and
+/// the flags of the level.
+fn fmt_taosd_head(
+    buf: &mut String,
+    local: DateTime<FixedOffset>,
+    module: &str,
+    level: &tracing::Level,
+) {
+    let flags = match *level {
+        tracing::Level::ERROR => "ERROR ",
+        tracing::Level::WARN => "WARN ",
+        _ => "",
+    };
+    write!(
+        buf,
+        "{} {:08} {module} {flags}",
+        local.format("%m/%d %H:%M:%S.%6f"),
+        crate::sys::gettid(),
+    )
+    .ok();
+}
+
+fn fmt_fields_and_qid<S, Q>(
+    buf: &mut String,
+    redactor: &Redactor,
+    max_field_len: Option<usize>,
+    event: &Event,
+    scope: Option<Scope<S>>,
+    with_ansi: bool,
+    taosd: bool,
+) where
+    S: for<'s> LookupSpan<'s>,
+    Q: QidManager,
+{
+    Scratch::with(
+        |Scratch {
+             kvs,
+             message,
+             stack,
+         }| {
+            event.record(&mut redactor.visit(RecordVisit(kvs, message, max_field_len)));
+
+            let mut qid_field = None;
+
+            let print_stacktrace = !taosd && event.metadata().level() >= &tracing::Level::DEBUG;
+
+            for span in scope.into_iter().flat_map(Scope::from_root) {
+                if print_stacktrace {
+                    if !stack.is_empty() {
+                        stack.push_str("->");
+                    }
+                    write_str(stack, span.name());
+                }
+
+                {
+                    if let Some(qid) = span.extensions().get::<Q>().cloned() {
+                        qid_field.replace(qid.get());
+                    }
+                }
+                if let Some(group) = span.extensions().get::<GroupBuffer>() {
+                    kvs.push_with(|buf| {
+                        write!(buf, "group:{}", group.id).ok();
+                    });
+                }
+                {
+                    if let Some(record) = span.extensions_mut().remove::<RecordFields>() {
+                        for field in &record.fields {
+                            kvs.push_with(|buf| buf.push_str(field));
+                        }
+                        if record.cap.dropped > 0 {
+                            kvs.push_with(|buf| {
+                                write!(buf, "fields_dropped:{}", record.cap.dropped).ok();
+                            });
+                        }
+                    }
+                }
+            }
+
+            if qid_field.is_none() {
+                qid_field = crate::scope::current();
+            }
+            #[cfg(feature = "log")]
+            if qid_field.is_none() && crate::log_bridge::is_log(event) {
+                qid_field = Some(Q::init().get());
+            }
+
+            #[cfg(feature = "tokio")]
+            crate::context::for_each_field(|key, value| {
+                let value = if redactor.is_redacted(key) {
+                    crate::redact::MASK
+                } else {
+                    value
+                };
+                kvs.push_with(|buf| {
+                    write_str(buf, key);
+                    buf.push(':');
+                    write_str(buf, value);
+                });
+            });
+
+            match qid_field {
+                Some(qid) if taosd => write!(buf, "QID:{qid:#x} ").ok(),
+                Some(qid) => write!(buf, "qid:{qid:#018x} ").ok(),
+                None => None,
+            };
+
+            if !kvs.is_empty() {
+                if with_ansi {
+                    ansi_start(buf, GRAY_COLOR);
+                }
+                buf.push_str(kvs);
+                if with_ansi {
+                    buf.push_str(ANSI_RESET);
+                }
+                buf.push(' ');
+            }
+
+            buf.push_str(message);
+
+            if print_stacktrace {
+                buf.push_str(" stack:");
+                buf.push_str(stack);
+            }
+        },
+    )
+}
+
+/// Buffers of [`fmt_fields_and_qid`], reused by the events of a thread.
+#[derive(Default)]
+struct Scratch {
+    kvs: String,
+    message: String,
+    stack: String,
+}
+
+impl Scratch {
+    /// Run `f` with the cleared buffers of this thread, or new ones if they
+    /// are in use, by an event logged while formatting another one.
+    fn with<R>(f: impl FnOnce(&mut Scratch) -> R) -> R {
+        thread_local! {
+            static SCRATCH: RefCell<Scratch> = RefCell::default();
+        }
+        SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) => {
+                scratch.kvs.clear();
+                scratch.message.clear();
+                scratch.stack.clear();
+                f(&mut scratch)
+            }
+            Err(_) => f(&mut Scratch::default()),
+        })
+    }
+}
+
+fn fmt_json<S, Q>(
+    buf: &mut String,
+    time: DateTime<FixedOffset>,
+    identity: Option<(&str, u8)>,
+    redactor: &Redactor,
+    max_field_len: Option<usize>,
+    event: &Event,
+    scope: Option<Scope<S>>,
+) where
+    S: for<'s> LookupSpan<'s>,
+    Q: QidManager,
+{
+    use serde_json::Value;
+
+    let metadata = event.metadata();
+    let mut fields = serde_json::Map::new();
+    let mut message = None;
+    let mut qid = crate::scope::current();
+    let mut spans = Vec::new();
+    for span in scope.into_iter().flat_map(Scope::from_root) {
+        spans.push(Value::from(span.name()));
+        let extensions = span.extensions();
+        if let Some(q) = extensions.get::<Q>() {
+            qid = Some(q.get());
+        }
+        if let Some(json) = extensions.get::<JsonFields>() {
+            fields.extend(json.fields.clone());
+            if json.cap.dropped > 0 {
+                fields.insert("fields_dropped".to_string(), Value::from(json.cap.dropped));
+            }
+        }
+        if let Some(group) = extensions.get::<GroupBuffer>() {
+            fields.insert("group".to_string(), Value::from(group.id));
+        }
+    }
+    #[cfg(feature = "tokio")]
+    crate::context::for_each_field(|key, value| {
+        let value = if redactor.is_redacted(key) {
+            crate::redact::MASK
+        } else {
+            value
+        };
+        fields.insert(key.to_string(), Value::from(value));
+    });
+    event.record(&mut redactor.visit(JsonVisit(&mut fields, &mut message, max_field_len)));
+    #[cfg(feature = "log")]
+    if qid.is_none() && crate::log_bridge::is_log(event) {
+        qid = Some(Q::init().get());
+    }
+    // the target and location of a `log` record are in its fields
+    #[cfg(feature = "log")]
+    let normalized = tracing_log::NormalizeEvent::normalized_metadata(event);
+    #[cfg(feature = "log")]
+    let metadata = normalized.as_ref().unwrap_or(metadata);
+
+    let mut object = vec![
+        (
+            "timestamp",
+            Value::from(time.to_rfc3339_opts(chrono::SecondsFormat::Micros, false)),
+        ),
+        ("level", Value::from(metadata.level().as_str())),
+        ("thread", Value::from(thread_id::get())),
+    ];
+    if let Some((component, instance)) = identity {
+        object.push(("component", Value::from(component)));
+        object.push(("instance", Value::from(instance)));
+    }
+    if let Some(qid) = qid {
+        object.push(("qid", Value::from(crate::utils::qid_to_hex(qid))));
+    }
+    object.push(("target", Value::from(metadata.target())));
+    object.push(("spans", Value::Array(spans)));
+    object.push(("fields", Value::Object(fields)));
+    object.push(("message", message.map_or(Value::Null, Value::from)));
+    if let Some(file) = metadata.file() {
+        object.push(("file", Value::from(file)));
+    }
+    if let Some(line) = metadata.line() {
+        object.push(("line", Value::from(line)));
+    }
+
+    // keep the keys in order, serde_json::Map sorts them
+    buf.push('{');
+    for (i, (key, value)) in object.into_iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        write!(buf, "{}:{value}", Value::from(key)).ok();
+    }
+    buf.push('}');
+}
+
+/// Collect fields as JSON values, keeping their raw types.
+struct JsonVisit<'a>(
+    &'a mut serde_json::Map<String, serde_json::Value>,
+    &'a mut Option<String>,
+    Option<usize>,
+);
+
+impl<'a> JsonVisit<'a> {
+    fn insert(&mut self, field: &field::Field, value: impl Into<serde_json::Value>) {
+        if !is_log_field(field) {
+            self.0.insert(field.name().to_string(), value.into());
+        }
+    }
+}
+
+impl<'a> Visit for JsonVisit<'a> {
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        let mut value = value.to_string();
+        truncate(&mut value, 0, self.2);
+        if field.name() == "message" {
+            self.1.replace(value);
+        } else {
+            self.insert(field, value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+        let mut value = format!("{value:?}");
+        truncate(&mut value, 0, self.2);
+        if field.name() == "message" {
+            self.1.replace(value);
+        } else {
+            self.insert(field, value);
+        }
+    }
+}
+
+/// The `log.target`, `log.file`... fields of a `log` record.
+fn is_log_field(field: &field::Field) -> bool {
+    cfg!(feature = "log") && field.name().starts_with("log.")
+}
+
+/// Where [`RecordVisit`] writes the `key:value` pairs of the fields.
+pub(crate) trait Kvs {
+    /// Add a pair written by `write`.
+    fn push_with(&mut self, write: impl FnOnce(&mut String));
+}
+
+/// Pairs kept apart, e.g. to cap the fields of a span.
+impl Kvs for Vec<String> {
+    fn push_with(&mut self, write: impl FnOnce(&mut String)) {
+        let mut kv = String::new();
+        write(&mut kv);
+        self.push(kv);
+    }
+}
+
+/// Pairs of a line, separated by `, `.
+impl Kvs for String {
+    fn push_with(&mut self, write: impl FnOnce(&mut String)) {
+        if !self.is_empty() {
+            self.push_str(", ");
+        }
+        write(self);
+    }
+}
+
+pub(crate) struct RecordVisit<'a, K>(&'a mut K, &'a mut String, Option<usize>);
+
+impl<K: Kvs> Visit for RecordVisit<'_, K> {
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        if is_log_field(field) {
+            return;
+        }
+        if field.name() == "message" {
+            self.1.clear();
+            self.1.push_str(value);
+            truncate(self.1, 0, self.2);
+            return;
+        }
+        let max = self.2;
+        self.0.push_with(|buf| {
+            write_str(buf, field.name());
+            buf.push(':');
+            if max.is_some_and(|max| value.len() > max) {
+                let mut value = value.to_string();
+                truncate(&mut value, 0, max);
+                write_str(buf, &value);
+            } else {
+                write_str(buf, value);
+            }
+        });
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        if is_log_field(field) {
+            return;
+        }
+        match humanize::field(field.name(), value) {
+            Some(value) => self.0.push_with(|buf| {
+                write_str(buf, field.name());
+                buf.push(':');
+                buf.push_str(&value);
+            }),
+            None => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        match u64::try_from(value) {
+            Ok(value) => self.record_u64(field, value),
+            Err(_) => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+        if is_log_field(field) {
+            return;
+        }
+        if field.name() == "message" {
+            self.1.clear();
+            write!(self.1, "{value:?}").ok();
+            truncate(self.1, 0, self.2);
+            return;
+        }
+        let max = self.2;
+        self.0.push_with(|buf| {
+            write_str(buf, field.name());
+            buf.push(':');
+            let start = buf.len();
+            write!(buf, "{value:?}").ok();
+            truncate(buf, start, max);
+        });
+    }
+}
+
+/// Write `value`, quoted if it has spaces.
+fn write_str(buf: &mut String, value: &str) {
+    if value.contains(' ') {
+        write!(buf, "{value:?}").ok();
+    } else {
+        buf.push_str(value);
+    }
+}
+
+fn ansi_start(buf: &mut String, color: usize) {
+    write!(buf, "\x1b[{color}m").ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Mutex};
+
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::{
+        fake::{Capture, Qid},
+        layer::{Format, Newlines, SpanFieldLimits, TaosLayer},
+        limit::{Limiter, RateLimit},
+        utils::{QidMetadataGetter, QidMetadataSetter, Span},
+        QidManager,
+    };
+
+    #[test]
+    fn layer_test() {
+        use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+        let _guard = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(Mutex::new(std::io::empty())))
+            .set_default();
+
+        tracing::info_span!("outer", "k" = "kkk").in_scope(|| {
+            // test qid init
+            let qid: Qid = Span.get_qid().unwrap();
+            assert_eq!(qid.get(), 9223372036854775807);
+            Span.set_qid(&Qid::from(999));
+            tracing::info_span!("inner").in_scope(|| {
+                // test qid inherit
+                let qid: Qid = Span.get_qid().unwrap();
+                assert_eq!(qid.get(), 999);
+            })
+        });
+    }
+
+    #[test]
+    fn per_layer_filter_test() {
+        let capture = Capture::default();
+        let other = Capture::default();
+        let directives = crate::filter::Directives::parse("taosx=debug").unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(
+                TaosLayer::<Qid, _, _>::new(capture.clone())
+                    .with_directives(directives.clone())
+                    .with_max_level(LevelFilter::INFO),
+            )
+            .with(tracing_subscriber::fmt::layer().with_writer(other.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug_span!("query").in_scope(|| {
+                tracing::debug!("debug event");
+                tracing::info!("info event");
+            });
+        });
+
+        let content = capture.content();
+        assert!(!content.contains("debug event"));
+        assert!(content.contains("info event"));
+        // left out by the taos layer only
+        let content = other.content();
+        assert!(content.contains("query: taoslog_core::layer::tests: debug event"));
+        assert!(content.contains("info event"));
+        // the default level was set on a copy
+        assert_eq!(directives.default_level(), None);
+    }
+
+    #[test]
+    fn humanize_fields_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outer", bytes_total = 1073741824u64).in_scope(|| {
+                tracing::info!(duration_flush = 1530, rows = 1530, "flushed");
+            })
+        });
+
+        let content = capture.content();
+        assert!(content.contains("duration_flush:1530ms, rows:1530, bytes_total:1.0GiB"));
+    }
+
+    #[test]
+    fn json_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(capture.clone()).json());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outer", task = "sync").in_scope(|| {
+                Span.set_qid(&Qid::from(0x1234));
+                tracing::info_span!("inner", bytes_total = 2048u64).in_scope(|| {
+                    tracing::warn!(rows = 10, ok = true, "flushed \"a\"");
+                    tracing::info!("again");
+                })
+            });
+        });
+
+        let content = capture.content();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        let event = &lines[0];
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["qid"], "0x0000000000001234");
+        assert_eq!(event["spans"], serde_json::json!(["outer", "inner"]));
+        assert_eq!(
+            event["fields"],
+            serde_json::json!({"task": "sync", "bytes_total": 2048, "rows": 10, "ok": true})
+        );
+        assert_eq!(event["message"], "flushed \"a\"");
+        assert_eq!(event["file"], file!());
+        assert!(content.starts_with(r#"{"timestamp":"#));
+        // span fields are repeated
+        assert_eq!(lines[1]["fields"]["task"], "sync");
+    }
+
+    #[test]
+    fn log_group_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let group = crate::group::begin("startup");
+            tracing::info!(workers = 8, "runtime");
+            let dispatch = tracing::dispatcher::get_default(|d| d.clone());
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    tracing::dispatcher::with_default(&dispatch, || tracing::info!("other thread"))
+                });
+            });
+            tracing::warn!("appender");
+            assert!(!capture.content().contains("runtime"));
+            group.end();
+            tracing::info!("after");
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 4, "{content}");
+        assert!(lines[0].ends_with("other thread"));
+        let id = lines[1]
+            .split("group:")
+            .nth(1)
+            .unwrap()
+            .split(',')
+            .next()
+            .unwrap();
+        assert!(lines[1].contains("group_name:startup") && lines[1].ends_with("runtime"));
+        assert!(lines[2].contains(&format!("group:{id} appender")));
+        assert!(lines[3].ends_with("INFO  after"));
+    }
+
+    #[test]
+    fn identity_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(capture.clone()).with_identity("taosx", 3));
+        tracing::subscriber::with_default(subscriber, || tracing::info!("text"));
+        assert!(capture.content().contains(" taosx_3 INFO  text"));
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_identity("taosx", 3)
+                .json(),
+        );
+        tracing::subscriber::with_default(subscriber, || tracing::info!("json"));
+        let event: serde_json::Value = serde_json::from_str(&capture.content()).unwrap();
+        assert_eq!(event["component"], "taosx");
+        assert_eq!(event["instance"], 3);
+    }
+
+    #[test]
+    fn newlines_test() {
+        let lines = |newlines: Newlines| {
+            let capture = Capture::default();
+            let subscriber = tracing_subscriber::registry()
+                .with(TaosLayer::<Qid, _, _>::new(capture.clone()).with_newlines(newlines));
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::info!(sql = "select *\r\nfrom t", "query\nfailed")
+            });
+            capture.content()
+        };
+        assert!(lines(Newlines::Keep).ends_with("sql:\"select *\\r\\nfrom t\" query\nfailed\n"));
+        let escaped = lines(Newlines::Escape);
+        assert_eq!(escaped.lines().count(), 1);
+        assert!(escaped.ends_with("query\\nfailed\n"), "{escaped}");
+        assert!(lines(Newlines::Indent).ends_with(" query\n\tfailed\n"));
+    }
+
+    #[test]
+    fn max_len_test() {
+        let capture = Capture::default();
+        let layer = TaosLayer::<Qid, _, _>::new(capture.clone())
+            .with_max_field_len(8)
+            .with_max_line_len(200);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request", body = "é".repeat(10)).in_scope(|| {
+                tracing::info!(sql = "select * from t", "{}", "x".repeat(20));
+            });
+            tracing::info!(id = 1, "{}", "y".repeat(300));
+        });
+
+        let content = capture.content();
+        let mut lines = content.lines();
+        let first = lines.next().unwrap();
+        assert!(
+            first.contains(r#"sql:"select *...<truncated 7 bytes>""#),
+            "{first}"
+        );
+        assert!(first.contains(r#"body:"éééé...<truncated 12 bytes>""#));
+        assert!(first.contains(" xxxxxxxx...<truncated 12 bytes>"));
+        let second = lines.next().unwrap();
+        assert!(
+            second.len() < 230 && second.ends_with(" bytes>"),
+            "{second}"
+        );
+        assert!(!second.contains("yyyyyyyyy"));
+    }
+
+    #[test]
+    fn scoped_qid_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("no qid");
+            crate::scope::with_qid(Qid::from(0x1234), || {
+                tracing::info!("scoped");
+                tracing::info_span!("root").in_scope(|| {
+                    assert_eq!(Span.get_qid::<Qid>().unwrap().get(), 0x1234);
+                    Span.set_qid(&Qid::from(0x5678));
+                    tracing::info!("span");
+                });
+            });
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("INFO  no qid"));
+        assert!(lines[1].ends_with("qid:0x0000000000001234 scoped"));
+        assert!(lines[2].ends_with("qid:0x0000000000005678 span"));
+    }
+
+    #[test]
+    fn display_handle_test() {
+        let capture = Capture::default();
+        let layer = TaosLayer::<Qid, _, _>::new(capture.clone());
+        let display = layer.display_handle();
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("plain");
+            display.set_location(true);
+            tracing::info!("located");
+            display.set_ansi(true);
+            tracing::info!("colored");
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert!(lines[0].ends_with("INFO  plain"), "{content}");
+        let location = format!("INFO  {}:", file!());
+        assert!(lines[1].contains(&location), "{content}");
+        assert!(lines[1].ends_with(" located"), "{content}");
+        assert!(!lines[1].contains('\x1b') && lines[2].contains('\x1b'));
+    }
+
+    #[test]
+    fn taosd_compat_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_ansi()
+                .with_format(Format::TaosdCompat)
+                .with_taosd_module("TXS"),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!("disk full");
+            tracing::info_span!("query").in_scope(|| {
+                tracing::warn!(rows = 10, "slow");
+                tracing::debug!("done");
+            });
+        });
+
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        let head = regex::Regex::new(r"^\d{2}/\d{2} \d{2}:\d{2}:\d{2}\.\d{6} \d{8} TXS ").unwrap();
+        assert!(lines.iter().all(|line| head.is_match(line)), "{content}");
+        assert!(lines[0].ends_with(" TXS ERROR disk full"));
+        assert!(lines[1].ends_with(" TXS WARN QID:0x7fffffffffffffff rows:10 slow"));
+        assert!(lines[2].ends_with(" TXS QID:0x7fffffffffffffff done"));
+        assert!(!content.contains('\x1b'));
+    }
+
+    #[test]
+    fn timezone_offset_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_timezone_offset(chrono::FixedOffset::east_opt(8 * 3600).unwrap())
+                .with_clock(crate::clock::ClockSource::Monotonic)
+                .json(),
+        );
+        tracing::subscriber::with_default(subscriber, || tracing::info!("shanghai"));
+
+        let event: serde_json::Value = serde_json::from_str(&capture.content()).unwrap();
+        let timestamp = event["timestamp"].as_str().unwrap();
+        assert!(timestamp.ends_with("+08:00"), "{timestamp}");
+    }
+
+    #[test]
+    fn timer_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_timezone_offset(chrono::FixedOffset::east_opt(8 * 3600).unwrap())
+                .with_timer(crate::clock::Timer::rfc3339().utc()),
+        );
+        tracing::subscriber::with_default(subscriber, || tracing::info!("utc"));
+
+        let content = capture.content();
+        let (timestamp, _) = content.split_once(' ').unwrap();
+        let time = chrono::DateTime::parse_from_rfc3339(timestamp).unwrap();
+        assert!(timestamp.ends_with('Z'), "{content}");
+        assert_eq!(time.offset().local_minus_utc(), 0);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn redaction_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_redacted_fields(["password"])
+                .with_redacted_pattern(regex::Regex::new("(?i)_key$").unwrap()),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("connect", user = "root", PASSWORD = "taosdata").in_scope(|| {
+                tracing::info!(api_key = "ak-1234", retries = 3, "connected");
+            });
+        });
+        let content = capture.content();
+        assert!(
+            content.contains("api_key:***, retries:3, user:root, PASSWORD:*** connected"),
+            "{content}"
+        );
+        assert!(!content.contains("taosdata") && !content.contains("ak-1234"));
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_default_redaction()
+                .json(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("connect", db_password = "taosdata").in_scope(|| {
+                tracing::info!(token = 1234, "connected");
+            });
+        });
+        let event: serde_json::Value = serde_json::from_str(&capture.content()).unwrap();
+        assert_eq!(
+            event["fields"],
+            serde_json::json!({"db_password": "***", "token": "***"})
+        );
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let capture = Capture::default();
+        let mut layer = TaosLayer::<Qid, _, _>::new(capture.clone());
+        layer.inner_mut().limiter = Some(
+            Limiter::new(RateLimit::default().per_callsite(3))
+                .with_window(std::time::Duration::from_millis(100)),
+        );
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let storm = |n| {
+                for i in 0..n {
+                    tracing::info!(i, "storm");
+                }
+            };
+            storm(10);
+            tracing::info!("other callsite");
+            std::thread::sleep(std::time::Duration::from_millis(150));
+            storm(1);
+        });
+        let content = capture.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 6, "{content}");
+        assert!(lines[3].ends_with("other callsite"), "{content}");
+        assert!(lines[4].contains("WARN"), "{content}");
+        assert!(
+            lines[4].contains("suppressed:7 suppressed 7 events from"),
+            "{content}"
+        );
+        assert!(lines[5].ends_with("i:0 storm"), "{content}");
+
+        // sampling keeps no DEBUG event at rate 0, INFO is untouched
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_rate_limit(RateLimit::default().sample_debug(0.))
+                .with_max_level(LevelFilter::TRACE),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..10 {
+                tracing::debug!("sampled");
+                tracing::info!("kept");
+            }
+        });
+        let content = capture.content();
+        assert_eq!(content.matches("kept").count(), 10, "{content}");
+        assert!(!content.contains("sampled") && !content.contains("suppressed"));
+    }
+
+    #[test]
+    fn backfill_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let backfill = crate::writer::RollingFileAppender::builder(dir.path(), "taosx_backfill", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(capture.clone()).with_backfill(backfill));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(event_time = "2024-01-01T00:00:00Z", rows = 10, "replayed");
+            tracing::info!("live");
+        });
+
+        let content = capture.content();
+        assert!(
+            content.contains("live") && !content.contains("replayed"),
+            "{content}"
+        );
+        let file = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with(".log"))
+            .unwrap();
+        let backfill = std::fs::read_to_string(file).unwrap();
+        assert!(
+            backfill.contains("event_time:2024-01-01T00:00:00Z, rows:10 replayed"),
+            "{backfill}"
+        );
+    }
+
+    #[test]
+    fn span_timings_test() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(
+            TaosLayer::<Qid, _, _>::new(capture.clone())
+                .with_max_level(LevelFilter::INFO)
+                .with_span_timings(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::info_span!("outer", rows = 10);
+            outer.in_scope(|| std::thread::sleep(std::time::Duration::from_millis(5)));
+            tracing::debug_span!("hidden").in_scope(|| {});
+        });
+
+        let content = capture.content();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1, "{content}");
+        let line = lines[0];
+        assert!(line.contains(" INFO  qid:0x"), "{line}");
+        assert!(line.contains("rows:10"), "{line}");
+        assert!(line.ends_with("span outer closed"), "{line}");
+        let busy = millis_after(line, "busy:");
+        assert!(busy >= 5, "{line}");
+    }
+
+    /// Milliseconds after `key` in `line`.
+    fn millis_after(line: &str, key: &str) -> u64 {
+        let (_, rest) = line.split_once(key).unwrap();
+        rest.split("ms").next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn span_field_limits_test() {
+        let capture = Capture::default();
+        let layer =
+            TaosLayer::<Qid, _, _>::new(capture.clone()).with_span_field_limits(SpanFieldLimits {
+                max_fields: 10,
+                max_bytes: 1024,
+            });
+        let memory = layer.inner().span_memory.clone();
+        let stats = || {
+            (
+                memory.fields.load(Ordering::Relaxed),
+                memory.dropped.load(Ordering::Relaxed),
+            )
+        };
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("loop", i = tracing::field::Empty);
+            for i in 0..1000 {
+                span.record("i", i);
+            }
+            assert_eq!(stats(), (10, 990));
+            span.in_scope(|| tracing::info!("done"));
+            // printed once, released with the span
+            assert_eq!(stats().0, 0);
+            drop(span);
+
+            let span = tracing::info_span!("big", value = "x".repeat(2048));
+            assert_eq!(stats(), (0, 991));
+            span.in_scope(|| tracing::info!("big"));
+        });
+        let content = capture.content();
+        assert!(
+            content.contains("i:8, i:9, fields_dropped:990 done"),
+            "{content}"
+        );
+        assert!(content.contains("fields_dropped:1 big"), "{content}");
+        assert!(!content.contains("xxxx"));
+
+        // JSON lines keep the fields once
+        let layer = TaosLayer::<Qid, _, _>::new(Capture::default()).json();
+        let memory = layer.inner().span_memory.clone();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("json", a = 1, b = 2, c = tracing::field::Empty);
+            span.record("c", 3);
+            assert_eq!(memory.fields.load(Ordering::Relaxed), 3);
+        });
+    }
+}
@@ -0,0 +1,282 @@
+use std::path::PathBuf;
+
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[cfg(feature = "log")]
+pub use log_bridge::init_log_bridge;
+pub use panic::install_panic_hook;
+
+use crate::{filter::Directives, layer::TaosLayer, writer::RollingFileAppender};
+
+pub mod advisor;
+pub mod catalog;
+#[cfg(feature = "hash-chain")]
+pub mod chain;
+#[cfg(feature = "reqwest")]
+pub mod client;
+pub mod clock;
+pub mod compat;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "tokio")]
+pub mod context;
+#[cfg(unix)]
+pub mod control;
+mod current;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+pub mod env;
+pub mod exit;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod filter;
+pub mod flush;
+pub mod group;
+#[cfg(feature = "tonic")]
+pub mod grpc;
+pub mod guard;
+mod hints;
+pub mod humanize;
+mod instance;
+pub mod internal;
+pub mod layer;
+pub mod limit;
+#[cfg(feature = "log")]
+pub mod log_bridge;
+mod manifest;
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod panic;
+pub mod redact;
+pub mod resume;
+pub mod ring;
+pub mod scope;
+#[cfg(unix)]
+pub mod signal;
+pub mod size;
+pub mod split;
+mod sys;
+pub mod tee;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(test, feature = "testing"))]
+pub use testing as test_util;
+pub mod timing;
+pub mod utils;
+pub mod writer;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("Create log dir {} error: {source}", path.display()))]
+    CreateLogDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Open log file {} error: {source}", path.display()))]
+    OpenLogFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Get file {} size error: {source}", path.display()))]
+    GetFileSize {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Compress file {} error: {source}", path.display()))]
+    Compress {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Archive file {} error: {source}", path.display()))]
+    Archive {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Encrypt file {} error: {source}", path.display()))]
+    Encrypt {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Decrypt error: {source}"))]
+    Decrypt { source: std::io::Error },
+    #[snafu(display("Verify hash chain error: {source}"))]
+    HashChain { source: std::io::Error },
+    #[snafu(display("Hash chain broken at line {line}"))]
+    HashChainBroken { line: u64 },
+    #[snafu(display("List dir {} error: {source}", path.display()))]
+    ReadDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Parse date error: {source}"))]
+    ParseDate { source: chrono::ParseError },
+    #[snafu(display("Invalid rotation size: {size}"))]
+    InvalidRotationSize { size: String },
+    #[snafu(display("Get disk space error"))]
+    DiskMountPointNotFound,
+    #[snafu(display("Get log absolute path error: {source}"))]
+    GetLogAbsolutePath { source: std::io::Error },
+    #[snafu(display("Invalid config {key}: {reason}"))]
+    InvalidConfig { key: String, reason: String },
+    #[snafu(display("Invalid environment variable {key}={value}: {reason}"))]
+    InvalidEnv {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    #[snafu(display("Invalid time format: {format}"))]
+    InvalidTimeFormat { format: String },
+    #[snafu(display("Invalid directive: {directive}"))]
+    InvalidDirective { directive: String },
+    #[snafu(display("Invalid QID: {qid}"))]
+    InvalidQid { qid: String },
+    #[snafu(display("Write exit report {} error: {source}", path.display()))]
+    WriteLastExit {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Verify archive {} error: {reason}", path.display()))]
+    VerifyArchive { path: PathBuf, reason: String },
+    #[snafu(display("Write manifest {} error: {source}", path.display()))]
+    Manifest {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Write position hints {} error: {source}", path.display()))]
+    PositionHints {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Lock instance file {} error: {source}", path.display()))]
+    InstanceLock {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("No free instance id for component {component}"))]
+    NoFreeInstance { component: String },
+    #[snafu(display("Invalid job id: {job_id}"))]
+    InvalidJobId { job_id: String },
+    #[snafu(display("Access QID state file {} error: {source}", path.display()))]
+    QidState {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Listen on control socket {} error: {source}", path.display()))]
+    ControlSocket {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Install signal handler error: {source}"))]
+    SignalHandler { source: std::io::Error },
+    #[cfg(feature = "log")]
+    #[snafu(display("Init log bridge error: {source}"))]
+    InitLogBridge {
+        source: tracing_log::log::SetLoggerError,
+    },
+    #[snafu(display("Init global subscriber error: {source}"))]
+    InitSubscriber {
+        source: tracing_subscriber::util::TryInitError,
+    },
+}
+
+pub trait QidManager: Send + Sync + 'static + Clone + From<u64> {
+    fn init() -> Self;
+
+    fn get(&self) -> u64;
+}
+
+/// Install a global subscriber writing to `appender` with the
+/// [default directives](filter::DEFAULT_DIRECTIVES).
+///
+/// Lines carry the component and instance id of the appender. The returned
+/// [`Directives`] can be used to query or change them at runtime.
+pub fn init<Q: QidManager>(appender: RollingFileAppender) -> Result<Directives> {
+    let directives = Directives::parse(filter::DEFAULT_DIRECTIVES)?;
+    init_with_directives::<Q>(appender, directives)
+}
+
+/// Like [`init`], with the given directives instead of the defaults.
+pub fn init_with_directives<Q: QidManager>(
+    appender: RollingFileAppender,
+    directives: Directives,
+) -> Result<Directives> {
+    use snafu::ResultExt;
+
+    let (component, instance) = (appender.component().to_string(), appender.instance_id());
+    tracing_subscriber::registry()
+        .with(
+            TaosLayer::<Q>::new(appender)
+                .with_identity(&component, instance)
+                .with_directives(directives.clone()),
+        )
+        .try_init()
+        .context(InitSubscriberSnafu)?;
+    Ok(directives)
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use crate::QidManager;
+
+    /// In-memory writer capturing every line written by a layer.
+    #[derive(Clone, Default)]
+    pub(crate) struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Capture {
+        pub(crate) fn content(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+        }
+
+        pub(crate) fn clear(&self) {
+            self.0.lock().unwrap().clear();
+        }
+    }
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct Qid(u64);
+
+    impl QidManager for Qid {
+        fn init() -> Self {
+            Self(9223372036854775807)
+        }
+
+        fn get(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<u64> for Qid {
+        fn from(value: u64) -> Self {
+            Self(value)
+        }
+    }
+}
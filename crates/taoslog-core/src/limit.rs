@@ -0,0 +1,180 @@
+//! Rate limiting and sampling of events, protecting the disk during log
+//! storms.
+//!
+//! With [`TaosLayer::with_rate_limit`], each callsite writes at most
+//! [`RateLimit::per_callsite`] events per second. The events dropped in a
+//! second are reported by a `suppressed N events` warning, with the [`TARGET`]
+//! target, written before the next event of the callsite.
+//!
+//! [`TaosLayer::with_rate_limit`]: crate::layer::TaosLayer::with_rate_limit
+
+use std::{
+    cell::Cell,
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tracing::{
+    callsite::{DefaultCallsite, Identifier},
+    field::{display, FieldSet, Value},
+    metadata::Kind,
+    Event, Level, Metadata,
+};
+
+use crate::catalog::Language;
+
+/// Target of the suppressed events summaries.
+pub const TARGET: &str = "taoslog::limit";
+
+static SUMMARY_CALLSITE: DefaultCallsite = DefaultCallsite::new(&SUMMARY);
+static SUMMARY: Metadata<'static> = Metadata::new(
+    "suppressed events",
+    TARGET,
+    Level::WARN,
+    Some(file!()),
+    Some(line!()),
+    Some(module_path!()),
+    FieldSet::new(&["message", "suppressed"], Identifier(&SUMMARY_CALLSITE)),
+    Kind::EVENT,
+);
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Limits of a [`TaosLayer`](crate::layer::TaosLayer), none by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimit {
+    per_callsite: Option<u32>,
+    sample: Option<f64>,
+}
+
+impl RateLimit {
+    /// At most `per_second` events per second from each callsite.
+    pub fn per_callsite(self, per_second: u32) -> Self {
+        Self {
+            per_callsite: Some(per_second),
+            ..self
+        }
+    }
+
+    /// Keep DEBUG and TRACE events with probability `rate`, from 0 to 1.
+    /// Sampled out events are not reported as suppressed.
+    pub fn sample_debug(self, rate: f64) -> Self {
+        Self {
+            sample: Some(rate.clamp(0., 1.)),
+            ..self
+        }
+    }
+}
+
+struct Window {
+    start: Instant,
+    count: u32,
+    suppressed: u64,
+}
+
+pub(crate) struct Limiter {
+    limit: RateLimit,
+    window: Duration,
+    windows: Mutex<HashMap<Identifier, Window>>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            window: WINDOW,
+            windows: Mutex::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_window(self, window: Duration) -> Self {
+        Self { window, ..self }
+    }
+
+    /// `None` to drop the event, otherwise the number of events of its
+    /// callsite suppressed since the last one written.
+    pub(crate) fn check(&self, metadata: &Metadata<'_>) -> Option<u64> {
+        if metadata.target() == TARGET {
+            return Some(0);
+        }
+        if let Some(rate) = self.limit.sample {
+            if *metadata.level() >= Level::DEBUG && random() >= rate {
+                return None;
+            }
+        }
+        let Some(max) = self.limit.per_callsite else {
+            return Some(0);
+        };
+        let now = Instant::now();
+        let mut windows = self.windows.lock();
+        let window = windows
+            .entry(metadata.callsite())
+            .or_insert_with(|| Window {
+                start: now,
+                count: 0,
+                suppressed: 0,
+            });
+        if now.duration_since(window.start) >= self.window {
+            window.start = now;
+            window.count = 0;
+        }
+        if window.count >= max {
+            window.suppressed += 1;
+            return None;
+        }
+        window.count += 1;
+        Some(std::mem::take(&mut window.suppressed))
+    }
+}
+
+/// Call `f` with the summary of `suppressed` events of the callsite of
+/// `metadata`, in the current span.
+pub(crate) fn summary(
+    suppressed: u64,
+    metadata: &Metadata<'_>,
+    language: Language,
+    f: impl FnOnce(&Event<'_>),
+) {
+    let fields = SUMMARY.fields();
+    let (Some(message), Some(count)) = (fields.field("message"), fields.field("suppressed")) else {
+        return;
+    };
+    let callsite = match (metadata.file(), metadata.line()) {
+        (Some(file), Some(line)) => format!("{file}:{line}"),
+        _ => metadata.target().to_string(),
+    };
+    let text = display(language.suppressed(suppressed, &callsite));
+    let values: [(&_, Option<&dyn Value>); 2] =
+        [(&message, Some(&text)), (&count, Some(&suppressed))];
+    f(&Event::new(&SUMMARY, &fields.value_set(&values)));
+}
+
+/// xorshift64, uniform in `[0, 1)`.
+fn random() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(0u8) | 1);
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_test() {
+        let n = 10000;
+        let kept = (0..n).filter(|_| random() < 0.25).count();
+        assert!((2000..3000).contains(&kept), "{kept}");
+    }
+}
@@ -0,0 +1,70 @@
+//! Records of the `log` crate as events of [`TaosLayer`](crate::layer::TaosLayer).
+//!
+//! After [`init_log_bridge`], libraries logging with the `log` macros are
+//! written like `tracing` events: filtered by the directives on their target
+//! and level, with the default QID when outside any span carrying one.
+
+use snafu::ResultExt;
+use tracing_log::{LogTracer, NormalizeEvent};
+
+use crate::{InitLogBridgeSnafu, Result};
+
+/// Install the `log` logger forwarding records to the `tracing` dispatcher.
+///
+/// Fails if another `log` logger is installed.
+pub fn init_log_bridge() -> Result<()> {
+    LogTracer::init().context(InitLogBridgeSnafu)
+}
+
+/// `event` comes from a `log` record.
+pub(crate) fn is_log(event: &tracing::Event<'_>) -> bool {
+    event.is_log()
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_log::log;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        filter::Directives,
+        layer::TaosLayer,
+        utils::{QidMetadataSetter, Span},
+        QidManager,
+    };
+
+    #[test]
+    fn log_bridge_test() {
+        init_log_bridge().ok();
+        let (text, json) = (Capture::default(), Capture::default());
+        let subscriber = tracing_subscriber::registry()
+            .with(
+                TaosLayer::<Qid, _, _>::new(text.clone())
+                    .with_directives(Directives::parse("info,noisy=error").unwrap()),
+            )
+            .with(TaosLayer::<Qid, _, _>::new(json.clone()).json());
+        tracing::subscriber::with_default(subscriber, || {
+            log::warn!(target: "hyper::client", "connection reset");
+            log::info!(target: "noisy", "dropped");
+            tracing::info_span!("request").in_scope(|| {
+                Span.set_qid(&Qid::from(0x1234));
+                log::error!("in span");
+            });
+        });
+
+        let content = text.content();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 2, "{content}");
+        let default_qid = format!("qid:{:#018x} ", Qid::init().get());
+        assert!(lines[0].ends_with(&format!("WARN  {default_qid}connection reset")));
+        assert!(lines[1].contains(" ERROR qid:0x0000000000001234 in span"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(json.content().lines().next().unwrap()).unwrap();
+        assert_eq!(json["target"], "hyper::client");
+        assert_eq!(json["fields"], serde_json::json!({}));
+        assert_eq!(json["file"], file!());
+    }
+}
@@ -0,0 +1,37 @@
+//! Per-appender manifest of archived log files.
+//!
+//! The manifest `<component>_<instance_id>.manifest` lives in the log dir and
+//! holds one JSON object per line describing each produced archive.
+
+use std::{fs, io::Write, path::Path};
+
+use chrono::Local;
+use snafu::ResultExt;
+
+use crate::{ManifestSnafu, Result};
+
+pub(crate) fn manifest_path(
+    log_dir: &Path,
+    component: &str,
+    instance_id: u8,
+) -> std::path::PathBuf {
+    log_dir.join(format!("{component}_{instance_id}.manifest"))
+}
+
+/// Append `entry` to the manifest, adding a `time` field.
+pub(crate) fn append(
+    log_dir: &Path,
+    component: &str,
+    instance_id: u8,
+    mut entry: serde_json::Value,
+) -> Result<()> {
+    let path = manifest_path(log_dir, component, instance_id);
+    entry["time"] = Local::now().to_rfc3339().into();
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .context(ManifestSnafu { path: &path })?;
+    file.write_all(format!("{entry}\n").as_bytes())
+        .context(ManifestSnafu { path })
+}
@@ -0,0 +1,230 @@
+//! Counters of an appender, for scraping into Prometheus.
+//!
+//! ```ignore
+//! let metrics = appender.metrics();
+//! // in the /metrics handler
+//! let body = metrics.prometheus();
+//! ```
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+
+use crate::writer::{DiskPressure, RotationReason};
+
+/// The last failed write of an appender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteError {
+    pub time: DateTime<Local>,
+    pub message: String,
+}
+
+/// Counters updated by the appender and its maintenance thread.
+#[derive(Default)]
+pub(crate) struct Counters {
+    bytes_written: AtomicU64,
+    events_written: AtomicU64,
+    write_errors: AtomicU64,
+    last_write_error: Mutex<Option<WriteError>>,
+    /// indexed by [`RotationReason`]
+    rotations: [AtomicU64; 4],
+    compression_backlog: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn written(&self, len: usize) {
+        self.events_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_failed(&self, error: &std::io::Error) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_write_error.lock() = Some(WriteError {
+            time: Local::now(),
+            message: error.to_string(),
+        });
+    }
+
+    pub(crate) fn rotated(&self, reason: RotationReason) {
+        self.rotations[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn compression_queued(&self) {
+        self.compression_backlog.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn compression_done(&self) {
+        self.compression_backlog.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A handle on the counters of a
+/// [`RollingFileAppender`](crate::writer::RollingFileAppender), cheap to clone
+/// and kept valid after the appender is dropped.
+#[derive(Clone)]
+pub struct Metrics {
+    pub(crate) component: String,
+    pub(crate) instance_id: u8,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) pressure: Arc<DiskPressure>,
+}
+
+/// Snapshot of the counters since the appender was built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Bytes handed to the file, buffered or not.
+    pub bytes_written: u64,
+    pub events_written: u64,
+    pub write_errors: u64,
+    pub last_write_error: Option<WriteError>,
+    /// Events dropped below the reserved disk size.
+    pub dropped_downgrade: u64,
+    /// Events dropped with the disk almost full.
+    pub dropped_stopped: u64,
+    pub time_rotations: u64,
+    pub size_rotations: u64,
+    pub missing_file_rotations: u64,
+    pub manual_rotations: u64,
+    /// Rotated files waiting for compression.
+    pub compression_backlog: u64,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let counters = &self.counters;
+        let rotations =
+            |reason: RotationReason| counters.rotations[reason as usize].load(Ordering::Relaxed);
+        let disk = self.pressure.stats(0, 0);
+        MetricsSnapshot {
+            bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            events_written: counters.events_written.load(Ordering::Relaxed),
+            write_errors: counters.write_errors.load(Ordering::Relaxed),
+            last_write_error: counters.last_write_error.lock().clone(),
+            dropped_downgrade: disk.dropped_downgrade,
+            dropped_stopped: disk.dropped_stopped,
+            time_rotations: rotations(RotationReason::Time),
+            size_rotations: rotations(RotationReason::Size),
+            missing_file_rotations: rotations(RotationReason::MissingFile),
+            manual_rotations: rotations(RotationReason::Manual),
+            compression_backlog: counters.compression_backlog.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The counters in the Prometheus text format, labelled with the
+    /// component and instance id of the appender.
+    pub fn prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let labels = format!(
+            "component=\"{}\",instance=\"{}\"",
+            self.component, self.instance_id
+        );
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(&str, u64)]| {
+            writeln!(out, "# HELP taoslog_{name} {help}").ok();
+            writeln!(out, "# TYPE taoslog_{name} {kind}").ok();
+            for (extra, value) in values {
+                writeln!(out, "taoslog_{name}{{{labels}{extra}}} {value}").ok();
+            }
+        };
+        metric(
+            "bytes_written_total",
+            "counter",
+            "Bytes written to log files.",
+            &[("", snapshot.bytes_written)],
+        );
+        metric(
+            "events_written_total",
+            "counter",
+            "Events written to log files.",
+            &[("", snapshot.events_written)],
+        );
+        metric(
+            "write_errors_total",
+            "counter",
+            "Failed writes to log files.",
+            &[("", snapshot.write_errors)],
+        );
+        metric(
+            "events_dropped_total",
+            "counter",
+            "Events dropped for lack of disk space.",
+            &[
+                (",stage=\"downgrade\"", snapshot.dropped_downgrade),
+                (",stage=\"stopped\"", snapshot.dropped_stopped),
+            ],
+        );
+        metric(
+            "rotations_total",
+            "counter",
+            "Switches to a new log file.",
+            &[
+                (",reason=\"time\"", snapshot.time_rotations),
+                (",reason=\"size\"", snapshot.size_rotations),
+                (",reason=\"missing_file\"", snapshot.missing_file_rotations),
+                (",reason=\"manual\"", snapshot.manual_rotations),
+            ],
+        );
+        metric(
+            "compression_backlog",
+            "gauge",
+            "Rotated files waiting for compression.",
+            &[("", snapshot.compression_backlog)],
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use crate::writer::RollingFileAppender;
+
+    #[test]
+    fn metrics_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("1KB")
+            .reserved_disk_size("1KB")
+            .compress(true)
+            .build()
+            .unwrap();
+        let metrics = appender.metrics();
+
+        appender.make_writer().write_all(&[b'x'; 1024]).unwrap();
+        appender.make_writer().write_all(b"x\n").unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.events_written, 2);
+        assert_eq!(snapshot.bytes_written, 1026);
+        assert_eq!(snapshot.size_rotations, 1);
+        assert_eq!(snapshot.time_rotations, 0);
+        assert_eq!(snapshot.last_write_error, None);
+
+        // the rotated file leaves the backlog once compressed
+        for _ in 0..1000 {
+            if metrics.snapshot().compression_backlog == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(metrics.snapshot().compression_backlog, 0);
+
+        let text = metrics.prometheus();
+        assert!(
+            text.contains("taoslog_bytes_written_total{component=\"taosx\",instance=\"1\"} 1026\n")
+        );
+        assert!(text.contains(
+            "taoslog_rotations_total{component=\"taosx\",instance=\"1\",reason=\"size\"} 1\n"
+        ));
+        assert!(text.contains("# TYPE taoslog_compression_backlog gauge\n"));
+    }
+}
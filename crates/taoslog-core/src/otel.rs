@@ -0,0 +1,138 @@
+//! OpenTelemetry bridge, exporting the spans and events of the application to
+//! an OpenTelemetry tracer (Jaeger, OTLP) next to the log files of
+//! [`TaosLayer`](crate::layer::TaosLayer), which are left unchanged.
+//!
+//! ```ignore
+//! tracing_subscriber::registry()
+//!     .with(TaosLayer::<Qid>::new(appender))
+//!     .with(taoslog::otel::layer::<Qid, _, _>(tracer))
+//!     .try_init()
+//!     .unwrap();
+//! ```
+
+use std::marker::PhantomData;
+
+use tracing::span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::{
+    layer::{Context, Layered},
+    registry::LookupSpan,
+    Layer,
+};
+
+use crate::{utils::qid_to_hex, QidManager};
+
+/// Span attribute holding the QID, with the same format as the `x-qid` header.
+pub const QID_ATTRIBUTE: &str = "qid";
+
+/// An OpenTelemetry layer for `tracer`, adding the QID of each span as the
+/// [`QID_ATTRIBUTE`] attribute.
+///
+/// The QID is the one set by [`TaosLayer`](crate::layer::TaosLayer), so the
+/// layer is added after it.
+pub fn layer<Q, S, T>(tracer: T) -> Layered<QidAttributeLayer<Q>, OpenTelemetryLayer<S, T>, S>
+where
+    Q: QidManager,
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    T: opentelemetry::trace::Tracer + 'static,
+    T::Span: Send + Sync,
+{
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .and_then(QidAttributeLayer::new())
+}
+
+/// Adds the QID of spans to their OpenTelemetry span, when first entered.
+pub struct QidAttributeLayer<Q>(PhantomData<fn(Q)>);
+
+struct QidAttributeSet;
+
+impl<Q> QidAttributeLayer<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for QidAttributeLayer<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, Q> Layer<S> for QidAttributeLayer<Q>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    Q: QidManager,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let qid = {
+            let mut extensions = span.extensions_mut();
+            if extensions.get_mut::<QidAttributeSet>().is_some() {
+                return;
+            }
+            let Some(qid) = extensions.get_mut::<Q>().map(|q| q.get()) else {
+                return;
+            };
+            extensions.insert(QidAttributeSet);
+            qid
+        };
+        // the span has just been pushed on the stack of the registry, the
+        // OpenTelemetry data is reachable through the current span only
+        let current = tracing::Span::current();
+        if current.id().as_ref() == Some(id) {
+            current.set_attribute(QID_ATTRIBUTE, qid_to_hex(qid));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{trace::TracerProvider as _, Value};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn otel_qid_attribute_test() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(capture.clone()))
+            .with(layer::<Qid, _, _>(provider.tracer("taoslog")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("outer").in_scope(|| {
+                tracing::info_span!("inner").in_scope(|| tracing::info!(rows = 10, "synced"));
+            });
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        for span in &spans {
+            let qid = span
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == QID_ATTRIBUTE)
+                .map(|kv| kv.value.clone());
+            assert_eq!(qid, Some(Value::from("0x7fffffffffffffff")));
+        }
+        let inner = spans.iter().find(|s| s.name == "inner").unwrap();
+        assert_eq!(inner.events.len(), 1);
+
+        // the log file is unchanged
+        let content = capture.content();
+        assert!(content.contains("INFO  qid:0x7fffffffffffffff rows:10 synced"));
+    }
+}
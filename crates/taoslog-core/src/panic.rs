@@ -0,0 +1,81 @@
+//! Panics logged as ERROR events, not only printed to stderr.
+
+use std::{backtrace::Backtrace, io::Write, panic::PanicHookInfo};
+
+/// Target of the panic events.
+pub const TARGET: &str = "taoslog::panic";
+
+/// Log each panic as an ERROR event with its message, location and backtrace,
+/// then run the previous hook, printing it to stderr by default.
+///
+/// The event is emitted in the span active where the panic happened, so its
+/// line carries the QID of the request or task. ERROR events are written to
+/// the file right away, bypassing the write buffer of the appender.
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log_panic(info);
+        previous(info);
+    }));
+}
+
+fn log_panic(info: &PanicHookInfo<'_>) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let location = info
+        .location()
+        .map(|location| location.to_string())
+        .unwrap_or_default();
+    let thread = std::thread::current();
+    let thread = thread.name().unwrap_or("<unnamed>");
+    let backtrace = Backtrace::force_capture();
+    tracing::error!(
+        target: TARGET,
+        thread,
+        location,
+        "panicked: {message}\nbacktrace:\n{backtrace}"
+    );
+    std::io::stderr().flush().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+        utils::{QidMetadataSetter, Span},
+    };
+
+    #[test]
+    fn panic_hook_test() {
+        install_panic_hook();
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                Span.set_qid(&Qid::from(0x1234));
+                std::panic::catch_unwind(|| panic!("index {} out of range", 3)).unwrap_err();
+            });
+            std::panic::catch_unwind(|| panic!("no span")).unwrap_err();
+        });
+
+        let content = capture.content();
+        let mut lines = content.lines().filter(|line| line.contains("panicked: "));
+        let first = lines.next().unwrap();
+        assert!(first.contains(" ERROR "), "{first}");
+        assert!(first.contains("qid:0x0000000000001234"), "{first}");
+        assert!(first.contains("panicked: index 3 out of range"));
+        assert!(first.contains(&format!("location:{}", file!())));
+        assert!(content.contains("\nbacktrace:\n"));
+        assert!(lines.next().unwrap().contains("panicked: no span"));
+    }
+}
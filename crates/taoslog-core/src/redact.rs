@@ -0,0 +1,214 @@
+//! Masking of secrets in logged values.
+//!
+//! The patterns here are shared by `#[derive(LogSafe)]` (`derive` feature),
+//! which generates `Debug` implementations masking sensitive fields, and by
+//! the redaction rules of [`TaosLayer`](crate::layer::TaosLayer).
+
+use std::fmt;
+
+use tracing::field::{Field, Visit};
+
+#[cfg(feature = "derive")]
+pub use taoslog_derive::LogSafe;
+
+/// Written in place of a masked value.
+pub const MASK: &str = "***";
+
+/// Field names containing one of these words, ignoring case, are sensitive.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "authorization",
+    "credential",
+];
+
+/// Whether a field named `name` holds a secret.
+pub fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    DEFAULT_PATTERNS.iter().any(|p| name.contains(p))
+}
+
+/// Debug formats as [`MASK`].
+#[derive(Clone, Copy, Default)]
+pub struct Mask;
+
+impl fmt::Debug for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+/// A value formatted as [`MASK`], e.g. `tracing::info!(password = ?Redacted(&pass))`.
+#[derive(Clone, Copy, Default)]
+pub struct Redacted<T>(pub T);
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+/// Field names whose values are masked by a layer.
+#[derive(Clone, Default)]
+pub(crate) struct Redactor {
+    names: Vec<String>,
+    defaults: bool,
+    #[cfg(feature = "regex")]
+    patterns: Vec<regex::Regex>,
+}
+
+impl Redactor {
+    pub(crate) fn add_names(&mut self, names: impl IntoIterator<Item = String>) {
+        self.names
+            .extend(names.into_iter().map(|name| name.to_ascii_lowercase()));
+    }
+
+    /// Mask the field names matching [`DEFAULT_PATTERNS`] too.
+    pub(crate) fn add_defaults(&mut self) {
+        self.defaults = true;
+    }
+
+    #[cfg(feature = "regex")]
+    pub(crate) fn add_pattern(&mut self, pattern: regex::Regex) {
+        self.patterns.push(pattern);
+    }
+
+    pub(crate) fn is_redacted(&self, name: &str) -> bool {
+        if name == "message" {
+            return false;
+        }
+        #[cfg(feature = "regex")]
+        if self.patterns.iter().any(|p| p.is_match(name)) {
+            return true;
+        }
+        (self.defaults && is_sensitive(name))
+            || self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// Wrap `visit` to record [`MASK`] for redacted fields.
+    pub(crate) fn visit<'a, V: Visit>(&'a self, visit: V) -> Redacting<'a, V> {
+        Redacting {
+            inner: visit,
+            redactor: self,
+        }
+    }
+}
+
+pub(crate) struct Redacting<'a, V> {
+    inner: V,
+    redactor: &'a Redactor,
+}
+
+macro_rules! redact_record {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(&mut self, field: &Field, value: $ty) {
+                if self.redactor.is_redacted(field.name()) {
+                    self.inner.record_str(field, MASK);
+                } else {
+                    self.inner.$method(field, value);
+                }
+            }
+        )*
+    };
+}
+
+impl<V: Visit> Visit for Redacting<'_, V> {
+    redact_record!(
+        record_f64: f64,
+        record_i64: i64,
+        record_u64: u64,
+        record_i128: i128,
+        record_u128: u128,
+        record_bool: bool,
+        record_str: &str,
+        record_bytes: &[u8],
+        record_error: &(dyn std::error::Error + 'static),
+        record_debug: &dyn fmt::Debug
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use taoslog_derive::LogSafe;
+
+    use super::*;
+
+    #[derive(LogSafe)]
+    #[log_safe(crate = "crate")]
+    struct Dsn {
+        host: String,
+        password: String,
+        #[log_safe(redact)]
+        cloud_key: String,
+        #[log_safe(show)]
+        token_ttl: u64,
+    }
+
+    #[derive(LogSafe)]
+    #[log_safe(crate = "crate")]
+    enum Auth<T> {
+        Anonymous,
+        Basic { user: String, passwd: String },
+        Token(#[log_safe(redact)] String, T),
+    }
+
+    #[test]
+    fn is_sensitive_test() {
+        assert!(is_sensitive("password"));
+        assert!(is_sensitive("DB_PASSWORD"));
+        assert!(is_sensitive("refresh_token"));
+        assert!(!is_sensitive("host"));
+        assert_eq!(format!("{:?}", Redacted("taosdata")), MASK);
+    }
+
+    #[test]
+    fn log_safe_test() {
+        let dsn = Dsn {
+            host: "localhost".to_string(),
+            password: "taosdata".to_string(),
+            cloud_key: "ak-1234".to_string(),
+            token_ttl: 3600,
+        };
+        assert_eq!(
+            format!("{dsn:?}"),
+            r#"Dsn { host: "localhost", password: ***, cloud_key: ***, token_ttl: 3600 }"#
+        );
+
+        assert_eq!(format!("{:?}", Auth::<u8>::Anonymous), "Anonymous");
+        let basic = Auth::<u8>::Basic {
+            user: "root".to_string(),
+            passwd: "taosdata".to_string(),
+        };
+        assert_eq!(
+            format!("{basic:?}"),
+            r#"Basic { user: "root", passwd: *** }"#
+        );
+        let token = Auth::Token("secret".to_string(), 1u8);
+        assert_eq!(format!("{token:?}"), "Token(***, 1)");
+        assert!(!format!("{token:#?}").contains("secret"));
+    }
+
+    #[test]
+    fn redactor_test() {
+        let mut redactor = Redactor::default();
+        assert!(!redactor.is_redacted("password"));
+        redactor.add_names(["Token".to_string(), "message".to_string()]);
+        assert!(redactor.is_redacted("token"));
+        assert!(!redactor.is_redacted("refresh_token"));
+        assert!(!redactor.is_redacted("message"));
+        redactor.add_defaults();
+        assert!(redactor.is_redacted("refresh_token"));
+        assert!(!redactor.is_redacted("host"));
+    }
+}
@@ -0,0 +1,129 @@
+//! QID continuity for jobs resumed after a process restart.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use snafu::{ensure, ResultExt};
+
+use crate::{
+    utils::{qid_from_hex, qid_to_hex, QidMetadataSetter},
+    CreateLogDirSnafu, InvalidJobIdSnafu, QidManager, QidStateSnafu, Result,
+};
+
+/// Persists the QID of long-running jobs in small `<job_id>.qid` state files.
+pub struct QidStore {
+    dir: PathBuf,
+}
+
+impl QidStore {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.is_dir() {
+            fs::create_dir_all(&dir).context(CreateLogDirSnafu { path: &dir })?;
+        }
+        Ok(Self { dir })
+    }
+
+    fn path(&self, job_id: &str) -> Result<PathBuf> {
+        ensure!(
+            !job_id.is_empty() && !job_id.starts_with('.') && !job_id.contains(['/', '\\', '\0']),
+            InvalidJobIdSnafu { job_id }
+        );
+        Ok(self.dir.join(format!("{job_id}.qid")))
+    }
+
+    pub fn save<Q: QidManager>(&self, job_id: &str, qid: &Q) -> Result<()> {
+        let path = self.path(job_id)?;
+        let tmp = path.with_extension("qid.tmp");
+        fs::write(&tmp, qid_to_hex(qid.get())).context(QidStateSnafu { path: &tmp })?;
+        fs::rename(&tmp, &path).context(QidStateSnafu { path })
+    }
+
+    pub fn load<Q: QidManager>(&self, job_id: &str) -> Result<Option<Q>> {
+        let path = self.path(job_id)?;
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(qid_from_hex(content.trim()).map(Q::from)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(QidStateSnafu { path }),
+        }
+    }
+
+    /// Remove the state of a finished job.
+    pub fn remove(&self, job_id: &str) -> Result<()> {
+        let path = self.path(job_id)?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context(QidStateSnafu { path })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A `job` span for `job_id` carrying its QID.
+    ///
+    /// The QID persisted by a previous run is restored and the span is marked
+    /// with `resumed=true`, otherwise `qid` is persisted and used.
+    pub fn job_span<Q: QidManager>(&self, job_id: &str, qid: Q) -> Result<tracing::Span> {
+        let (qid, resumed) = match self.load::<Q>(job_id)? {
+            Some(qid) => (qid, true),
+            None => {
+                self.save(job_id, &qid)?;
+                (qid, false)
+            }
+        };
+        let mut span = tracing::info_span!("job", job_id, resumed);
+        span.set_qid(&qid);
+        Ok(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn save_load_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = QidStore::new(dir.path().join("state")).unwrap();
+
+        assert!(store.load::<Qid>("job1").unwrap().is_none());
+        store.save("job1", &Qid::from(0x1234)).unwrap();
+        assert_eq!(store.load::<Qid>("job1").unwrap().unwrap().get(), 0x1234);
+        store.remove("job1").unwrap();
+        store.remove("job1").unwrap();
+        assert!(store.load::<Qid>("job1").unwrap().is_none());
+
+        assert!(store.save("../job1", &Qid::from(1)).is_err());
+        assert!(store.save("", &Qid::from(1)).is_err());
+    }
+
+    #[test]
+    fn job_span_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = QidStore::new(dir.path()).unwrap();
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = store.job_span("job1", Qid::from(0xabc)).unwrap();
+            span.in_scope(|| tracing::info!("first run"));
+            // restarted with a fresh QID
+            let span = store.job_span("job1", Qid::from(0xdef)).unwrap();
+            span.in_scope(|| tracing::info!("second run"));
+        });
+
+        let content = capture.content();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("qid:0x0000000000000abc job_id:job1, resumed:false first run"));
+        assert!(lines[1].contains("qid:0x0000000000000abc job_id:job1, resumed:true second run"));
+    }
+}
@@ -0,0 +1,210 @@
+//! Keep the last events in memory, for postmortems.
+//!
+//! [`RingBufferWriter`] is a `MakeWriter` keeping the last N lines written by
+//! [`TaosLayer`](crate::layer::TaosLayer), dumped on demand or on panic. With
+//! a layer of its own at TRACE, it holds the lines filtered out of the files,
+//! the level of each layer only filtering its own lines:
+//!
+//! ```ignore
+//! let ring = RingBufferWriter::new(10000);
+//! ring.dump_on_panic(Dump::File("/var/log/taos/taosx.crash".into()));
+//! tracing_subscriber::registry()
+//!     .with(TaosLayer::<Qid>::new(appender).with_max_level(LevelFilter::INFO))
+//!     .with(TaosLayer::<Qid, _, _>::new(ring.clone()).with_max_level(LevelFilter::TRACE))
+//!     .init();
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Where [`RingBufferWriter::dump_on_panic`] writes the events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dump {
+    Stderr,
+    /// Created or truncated.
+    File(PathBuf),
+}
+
+struct Ring {
+    events: Mutex<VecDeque<Vec<u8>>>,
+    capacity: usize,
+}
+
+/// Keeps the last events in memory, see the [module](self) documentation.
+#[derive(Clone)]
+pub struct RingBufferWriter {
+    ring: Arc<Ring>,
+}
+
+impl RingBufferWriter {
+    /// Keep the last `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            ring: Arc::new(Ring {
+                events: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+            }),
+        }
+    }
+
+    /// The kept events, oldest first.
+    pub fn events(&self) -> Vec<String> {
+        let events = self.ring.events.lock();
+        events
+            .iter()
+            .map(|event| String::from_utf8_lossy(event).into_owned())
+            .collect()
+    }
+
+    /// Write the kept events to `writer`, oldest first.
+    pub fn dump(&self, mut writer: impl Write) -> io::Result<()> {
+        // the lock may be held by the panicking thread
+        let Some(events) = self.ring.events.try_lock_for(Duration::from_secs(1)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "ring buffer locked",
+            ));
+        };
+        for event in events.iter() {
+            writer.write_all(event)?;
+        }
+        writer.flush()
+    }
+
+    /// Write the kept events to `target`.
+    pub fn dump_to(&self, target: &Dump) -> io::Result<()> {
+        match target {
+            Dump::Stderr => {
+                let mut stderr = io::stderr().lock();
+                writeln!(stderr, "---- last {} events ----", self.ring.capacity)?;
+                self.dump(stderr)
+            }
+            Dump::File(path) => self.dump(File::create(path)?),
+        }
+    }
+
+    /// Dump the kept events to `target` on panic, after the previous panic
+    /// hook, e.g. the one of [`install_panic_hook`](crate::install_panic_hook)
+    /// logging the panic, which is then the last event.
+    pub fn dump_on_panic(&self, target: Dump) {
+        let ring = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            if let Err(e) = ring.dump_to(&target) {
+                eprintln!("[RingBufferWriter] Unable to dump the last events: {e}");
+            }
+        }));
+    }
+
+    fn push(&self, event: &[u8]) {
+        let mut events = self.ring.events.lock();
+        // the buffer of the oldest event is reused
+        let mut buf = match events.len() >= self.ring.capacity {
+            true => events.pop_front().unwrap_or_default(),
+            false => Vec::new(),
+        };
+        buf.clear();
+        buf.extend_from_slice(event);
+        events.push_back(buf);
+    }
+}
+
+/// Buffers one event, kept when dropped.
+pub struct RingLine<'a> {
+    writer: &'a RingBufferWriter,
+    buf: Vec<u8>,
+}
+
+impl io::Write for RingLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RingLine<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.writer.push(&self.buf);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RingBufferWriter {
+    type Writer = RingLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RingLine {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    #[test]
+    fn ring_buffer_test() {
+        let ring = RingBufferWriter::new(3);
+        let files = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TaosLayer::<Qid, _, _>::new(files.clone()).with_max_level(LevelFilter::INFO))
+            .with(TaosLayer::<Qid, _, _>::new(ring.clone()).with_max_level(LevelFilter::TRACE));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                for i in 0..5 {
+                    tracing::debug!("step {i}");
+                }
+            });
+        });
+
+        assert_eq!(files.content(), "");
+        let events = ring.events();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].contains("DEBUG") && events[0].contains("step 2"));
+        assert!(events[2].contains("step 4"));
+
+        let mut dump = Vec::new();
+        ring.dump(&mut dump).unwrap();
+        assert_eq!(String::from_utf8(dump).unwrap(), events.concat());
+    }
+
+    #[test]
+    fn dump_on_panic_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash.log");
+        let ring = RingBufferWriter::new(10);
+        ring.make_writer().write_all(b"before the panic\n").unwrap();
+        ring.dump_on_panic(Dump::File(path.clone()));
+
+        std::panic::catch_unwind(|| panic!("crash")).unwrap_err();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "before the panic\n"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Thread-local QID for code that does not use spans.
+//!
+//! ```ignore
+//! taoslog::scope::with_qid(Qid::from(0x1234), || {
+//!     // qid:0x0000000000001234
+//!     tracing::info!("exporting");
+//! });
+//! ```
+
+use std::cell::Cell;
+
+use crate::QidManager;
+
+thread_local! {
+    static QID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Run `f` with `qid` installed as the QID of the current thread.
+///
+/// Events without a span carrying a QID, and new root spans, use it. The
+/// previous QID is restored when `f` returns or panics.
+pub fn with_qid<Q: QidManager, R>(qid: Q, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<u64>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            QID.with(|cell| cell.set(self.0));
+        }
+    }
+
+    let _restore = Restore(QID.with(|cell| cell.replace(Some(qid.get()))));
+    f()
+}
+
+/// The QID installed by [`with_qid`] on the current thread.
+pub fn current() -> Option<u64> {
+    QID.with(Cell::get)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::Qid;
+
+    #[test]
+    fn with_qid_test() {
+        assert_eq!(current(), None);
+        let qid = with_qid(Qid::from(1), || {
+            with_qid(Qid::from(2), || assert_eq!(current(), Some(2)));
+            current()
+        });
+        assert_eq!(qid, Some(1));
+        assert_eq!(current(), None);
+
+        let res = std::panic::catch_unwind(|| with_qid(Qid::from(3), || panic!("boom")));
+        assert!(res.is_err());
+        assert_eq!(current(), None);
+    }
+}
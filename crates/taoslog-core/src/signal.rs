@@ -0,0 +1,253 @@
+//! Unix signals: rotate the appender on SIGHUP, like the `postrotate` reload
+//! of a logrotate config, and raise the level to DEBUG on SIGUSR1 until
+//! SIGUSR2, to debug a production incident without a restart.
+//!
+//! ```ignore
+//! let handle = Handle::new(appender);
+//! let layer = TaosLayer::<Qid, _, _>::new(handle.clone());
+//! taoslog::signal::rotate_on_sighup(&handle)?;
+//! taoslog::signal::debug_on_sigusr(layer.reload_handle(), &handle, Duration::from_secs(600))?;
+//! tracing_subscriber::registry().with(layer).init();
+//! ```
+
+use std::{
+    io::{self, Read},
+    os::fd::{AsRawFd, IntoRawFd},
+    sync::{
+        atomic::{AtomicI32, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use snafu::ResultExt;
+use tracing::level_filters::LevelFilter;
+
+use crate::{
+    filter::ReloadHandle,
+    humanize,
+    writer::{Handle, RollingFileAppender, RotationReason},
+    Result, SignalHandlerSnafu,
+};
+
+/// Write end of the pipe of each watched signal, -1 for the others.
+static PIPES: [AtomicI32; 32] = [const { AtomicI32::new(-1) }; 32];
+
+/// Only async-signal-safe calls here: wake up the thread of the signal.
+extern "C" fn on_signal(signal: libc::c_int) {
+    let Some(pipe) = PIPES.get(signal as usize) else {
+        return;
+    };
+    let fd = pipe.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signal as u8;
+        unsafe { libc::write(fd, (&byte as *const u8).cast(), 1) };
+    }
+}
+
+/// Run `f` in a thread on each `signal`, instead of the previous watcher of
+/// this signal.
+pub(crate) fn watch(signal: libc::c_int, mut f: impl FnMut() + Send + 'static) -> Result<()> {
+    let (mut reader, writer) = io::pipe().context(SignalHandlerSnafu)?;
+    // signals are dropped with the pipe full, the handler never blocks
+    let flags = unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_GETFL) };
+    if unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error()).context(SignalHandlerSnafu);
+    }
+    thread::Builder::new()
+        .name(format!("taoslog-signal-{signal}"))
+        .spawn(move || {
+            let mut byte = [0];
+            loop {
+                match reader.read(&mut byte) {
+                    Ok(0) => break,
+                    Ok(_) => f(),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        })
+        .context(SignalHandlerSnafu)?;
+
+    let previous = PIPES[signal as usize].swap(writer.into_raw_fd(), Ordering::Relaxed);
+    if previous >= 0 {
+        // the thread of the previous watcher reads the end of its pipe
+        unsafe { libc::close(previous) };
+    }
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error()).context(SignalHandlerSnafu);
+    }
+    Ok(())
+}
+
+/// Rotate the appender of `handle` on each SIGHUP, see
+/// [`rotate_now`](crate::writer::RollingFileAppender::rotate_now).
+pub fn rotate_on_sighup(handle: &Handle) -> Result<()> {
+    let appender = Arc::downgrade(handle.appender());
+    watch(libc::SIGHUP, move || {
+        let Some(appender) = appender.upgrade() else {
+            return;
+        };
+        if let Err(e) = appender.rotate_now(RotationReason::Manual) {
+            eprintln!("[RollingFileAppender] Unable to rotate on SIGHUP: {e}");
+        }
+    })
+}
+
+/// Raise the default level to DEBUG on SIGUSR1, until SIGUSR2 or `duration`
+/// elapsed, writing a marker line to the appender of `handle` on each change.
+/// Another SIGUSR1 restarts the `duration`.
+pub fn debug_on_sigusr(
+    reload: impl Into<ReloadHandle>,
+    handle: &Handle,
+    duration: Duration,
+) -> Result<()> {
+    let toggle = Arc::new(LevelToggle {
+        reload: reload.into(),
+        appender: Arc::downgrade(handle.appender()),
+        duration,
+        saved: Mutex::new(None),
+        raised: AtomicU64::new(0),
+    });
+    watch(libc::SIGUSR1, {
+        let toggle = toggle.clone();
+        move || toggle.raise()
+    })?;
+    watch(libc::SIGUSR2, move || toggle.restore(None))
+}
+
+struct LevelToggle {
+    reload: ReloadHandle,
+    appender: Weak<RollingFileAppender>,
+    duration: Duration,
+    /// The default level before SIGUSR1, while raised.
+    saved: Mutex<Option<Option<LevelFilter>>>,
+    /// Count of SIGUSR1, a timer only restores the level it raised.
+    raised: AtomicU64,
+}
+
+impl LevelToggle {
+    fn raise(self: &Arc<Self>) {
+        let mut saved = self.saved.lock();
+        if saved.is_none() {
+            *saved = Some(self.reload.level());
+        }
+        self.reload.set_level(LevelFilter::DEBUG);
+        let raised = self.raised.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(appender) = self.appender.upgrade() {
+            let duration = humanize::duration(self.duration);
+            appender.write_marker(|language| language.level_debug(&duration));
+        }
+        drop(saved);
+
+        let toggle = Arc::downgrade(self);
+        let duration = self.duration;
+        let timer = thread::Builder::new()
+            .name("taoslog-sigusr-timer".to_string())
+            .spawn(move || {
+                thread::sleep(duration);
+                if let Some(toggle) = toggle.upgrade() {
+                    toggle.restore(Some(raised));
+                }
+            });
+        if let Err(e) = timer {
+            eprintln!("[TaosLayer] Unable to schedule the level restore: {e}");
+        }
+    }
+
+    /// Restore the saved level, if still raised by the SIGUSR1 `raised`.
+    fn restore(&self, raised: Option<u64>) {
+        let mut saved = self.saved.lock();
+        if raised.is_some_and(|raised| raised != self.raised.load(Ordering::Relaxed)) {
+            return;
+        }
+        let Some(level) = saved.take() else {
+            return;
+        };
+        self.reload.directives().set_default_level(level);
+        if let Some(appender) = self.appender.upgrade() {
+            let directives = self.reload.directives().to_string();
+            appender.write_marker(|language| language.level_restore(&directives));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Directives;
+    use crate::writer::RollingFileAppender;
+
+    #[test]
+    fn sighup_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let handle = Handle::new(appender);
+        rotate_on_sighup(&handle).unwrap();
+        let first = handle.current_file_path();
+
+        unsafe { libc::raise(libc::SIGHUP) };
+        for _ in 0..500 {
+            if handle.current_file_path() != first {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_ne!(handle.current_file_path(), first);
+        assert_eq!(handle.metrics().snapshot().manual_rotations, 1);
+    }
+
+    #[test]
+    fn sigusr_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let handle = Handle::new(appender);
+        let directives = Directives::parse("info,hyper=warn").unwrap();
+        debug_on_sigusr(directives.clone(), &handle, Duration::from_millis(300)).unwrap();
+        let wait_level = |level| {
+            for _ in 0..500 {
+                if directives.default_level() == Some(level) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!(
+                "level is {:?}, expected {level}",
+                directives.default_level()
+            );
+        };
+
+        unsafe { libc::raise(libc::SIGUSR1) };
+        wait_level(LevelFilter::DEBUG);
+        unsafe { libc::raise(libc::SIGUSR2) };
+        wait_level(LevelFilter::INFO);
+        // restored once the duration elapsed
+        unsafe { libc::raise(libc::SIGUSR1) };
+        wait_level(LevelFilter::DEBUG);
+        wait_level(LevelFilter::INFO);
+
+        let content = std::fs::read_to_string(handle.current_file_path()).unwrap();
+        let markers: Vec<_> = content.lines().collect();
+        assert_eq!(
+            markers,
+            [
+                "=======level debug for 300ms=====",
+                "=======level restored to info,hyper=warn=====",
+                "=======level debug for 300ms=====",
+                "=======level restored to info,hyper=warn=====",
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Byte sizes of the appender settings.
+//!
+//! The builder takes sizes as strings like `"512MB"`, parsed when the appender
+//! is built, or as bytes checked by the compiler:
+//!
+//! ```ignore
+//! use taoslog::size::MB;
+//!
+//! RollingFileAppender::builder("/var/log/taos", "taosx", 1)
+//!     .rotation_size_bytes(512 * MB)
+//!     .reserved_disk_size_bytes(ByteSize::gb(2))
+//!     .build()?;
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use crate::{humanize, writer::parse_unit_size, Error};
+
+pub const KB: u64 = 1024;
+pub const MB: u64 = 1024 * KB;
+pub const GB: u64 = 1024 * MB;
+
+/// A size in bytes, parsed from `"5KB"`, `"5MB"` or `"5GB"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    pub const fn kb(count: u64) -> Self {
+        Self(count * KB)
+    }
+
+    pub const fn mb(count: u64) -> Self {
+        Self(count * MB)
+    }
+
+    pub const fn gb(count: u64) -> Self {
+        Self(count * GB)
+    }
+
+    pub const fn bytes(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(size: &str) -> Result<Self, Error> {
+        parse_unit_size(size).map(Self)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&humanize::bytes(self.0))
+    }
+}
+
+/// A size setting of the builder, parsed on build.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SizeArg<'a> {
+    Text(&'a str),
+    Bytes(ByteSize),
+}
+
+impl SizeArg<'_> {
+    pub(crate) fn bytes(self) -> crate::Result<u64> {
+        match self {
+            Self::Text(size) => parse_unit_size(size),
+            Self::Bytes(size) => Ok(size.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_size_test() {
+        assert_eq!(ByteSize::mb(512), ByteSize(512 * MB));
+        assert_eq!("2GB".parse::<ByteSize>().unwrap(), ByteSize::gb(2));
+        assert!("2TB".parse::<ByteSize>().is_err());
+        assert_eq!(ByteSize::kb(1).to_string(), "1.0KiB");
+        assert_eq!(SizeArg::Text("1KB").bytes().unwrap(), 1024);
+        assert_eq!(SizeArg::Bytes(ByteSize(10)).bytes().unwrap(), 10);
+    }
+}
@@ -0,0 +1,231 @@
+//! Route events by level to two sinks, like the info and error logs of taosd,
+//! or by target, like the access lines of the HTTP middlewares.
+
+use tracing::Level;
+use tracing_subscriber::fmt::{writer::EitherWriter, MakeWriter};
+
+/// A [`MakeWriter`] writing WARN and ERROR events to `E`, the others to `O`,
+/// e.g. an [`errlog`](crate::writer::RollingFileAppenderBuilder::errlog)
+/// appender and the normal one, each rotated and cleaned up on its own.
+///
+/// ```ignore
+/// let errors = RollingFileAppender::builder(&dir, "taosx", 1).errlog().build()?;
+/// let others = RollingFileAppender::builder(&dir, "taosx", 1).build()?;
+/// let writer = LevelSplit::new(errors, others);
+/// tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+/// ```
+#[derive(Clone)]
+pub struct LevelSplit<E, O> {
+    errors: E,
+    others: O,
+    level: Level,
+}
+
+impl<E, O> LevelSplit<E, O> {
+    pub fn new(errors: E, others: O) -> Self {
+        Self {
+            errors,
+            others,
+            level: Level::WARN,
+        }
+    }
+
+    /// The least severe level going to the errors sink, WARN by default.
+    pub fn level(self, level: Level) -> Self {
+        Self { level, ..self }
+    }
+
+    pub fn errors(&self) -> &E {
+        &self.errors
+    }
+
+    pub fn others(&self) -> &O {
+        &self.others
+    }
+}
+
+impl<'a, E, O> MakeWriter<'a> for LevelSplit<E, O>
+where
+    E: MakeWriter<'a>,
+    O: MakeWriter<'a>,
+{
+    type Writer = EitherWriter<E::Writer, O::Writer>;
+
+    /// Writes without metadata go to the other sink.
+    fn make_writer(&'a self) -> Self::Writer {
+        EitherWriter::B(self.others.make_writer())
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        if *meta.level() <= self.level {
+            EitherWriter::A(self.errors.make_writer_for(meta))
+        } else {
+            EitherWriter::B(self.others.make_writer_for(meta))
+        }
+    }
+}
+
+/// Target of the access lines of the HTTP middlewares.
+pub const ACCESS_TARGET: &str = "taoslog::access";
+
+/// A [`MakeWriter`] writing the events of `target` and its submodules to `T`,
+/// the others to `O`, e.g. the access lines to an
+/// [`access_log`](crate::writer::RollingFileAppenderBuilder::access_log)
+/// appender with its own rotation settings.
+///
+/// ```ignore
+/// let access = RollingFileAppender::builder(&dir, "taosx", 1)
+///     .access_log()
+///     .rotation_size("100MB")
+///     .build()?;
+/// let others = RollingFileAppender::builder(&dir, "taosx", 1).build()?;
+/// let writer = TargetSplit::access(access, others);
+/// tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+/// ```
+#[derive(Clone)]
+pub struct TargetSplit<T, O> {
+    target: String,
+    matched: T,
+    others: O,
+}
+
+impl<T, O> TargetSplit<T, O> {
+    pub fn new(target: &str, matched: T, others: O) -> Self {
+        Self {
+            target: target.to_string(),
+            matched,
+            others,
+        }
+    }
+
+    /// Route the [`ACCESS_TARGET`] events to `access`.
+    pub fn access(access: T, others: O) -> Self {
+        Self::new(ACCESS_TARGET, access, others)
+    }
+
+    pub fn matched(&self) -> &T {
+        &self.matched
+    }
+
+    pub fn others(&self) -> &O {
+        &self.others
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        target
+            .strip_prefix(self.target.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+impl<'a, T, O> MakeWriter<'a> for TargetSplit<T, O>
+where
+    T: MakeWriter<'a>,
+    O: MakeWriter<'a>,
+{
+    type Writer = EitherWriter<T::Writer, O::Writer>;
+
+    /// Writes without metadata go to the other sink.
+    fn make_writer(&'a self) -> Self::Writer {
+        EitherWriter::B(self.others.make_writer())
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        if self.matches(meta.target()) {
+            EitherWriter::A(self.matched.make_writer_for(meta))
+        } else {
+            EitherWriter::B(self.others.make_writer_for(meta))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+        writer::RollingFileAppender,
+    };
+
+    #[test]
+    fn level_split_test() {
+        let (errors, others) = (Capture::default(), Capture::default());
+        let writer = LevelSplit::new(errors.clone(), others.clone());
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("started");
+            tracing::warn!("slow");
+            tracing::error!("failed");
+        });
+
+        assert!(others.content().ends_with("INFO  started\n"));
+        assert_eq!(others.content().lines().count(), 1);
+        let errors = errors.content();
+        assert!(errors.contains("WARN  slow\n") && errors.contains("ERROR failed\n"));
+    }
+
+    #[test]
+    fn target_split_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let access = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .access_log()
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let others = Capture::default();
+        let writer = TargetSplit::access(access, others.clone());
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: ACCESS_TARGET, "GET /health 200");
+            tracing::info!(target: "taoslog::access::slow", "GET /query 200");
+            tracing::info!(target: "taoslog::accessor", "not an access line");
+            tracing::info!("started");
+        });
+
+        let others = others.content();
+        assert_eq!(others.lines().count(), 2, "{others}");
+        assert!(others.contains("not an access line") && others.contains("started"));
+        let today = chrono::Local::now().format("%Y%m%d");
+        let access =
+            std::fs::read_to_string(dir.path().join(format!("taosx_1_{today}.access"))).unwrap();
+        assert_eq!(access.lines().count(), 2, "{access}");
+        assert!(access.contains("GET /health 200") && access.contains("GET /query 200"));
+    }
+
+    #[test]
+    fn errlog_rotation_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = || {
+            RollingFileAppender::builder(dir.path(), "taosx", 1)
+                .rotation_size("1KB")
+                .reserved_disk_size("1KB")
+        };
+        let errors = builder().errlog().build().unwrap();
+        let others = builder().build().unwrap();
+
+        for _ in 0..3 {
+            others.make_writer().write_all(&[b'i'; 600]).unwrap();
+        }
+        errors.make_writer().write_all(b"error\n").unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        let today = chrono::Local::now().format("%Y%m%d");
+        let errlogs: Vec<_> = files.iter().filter(|f| f.contains(".errlog")).collect();
+        // the .log files rotated, the .errlog one did not
+        assert_eq!(errlogs, [&format!("taosx_1_{today}.errlog")], "{files:?}");
+        assert!(files.iter().filter(|f| f.contains(".log")).count() > 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(format!("taosx_1_{today}.errlog"))).unwrap(),
+            "error\n"
+        );
+    }
+}
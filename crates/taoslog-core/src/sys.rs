@@ -0,0 +1,238 @@
+//! Platform specific helpers.
+
+use crate::writer::IoPriority;
+
+/// Apply CPU niceness and IO priority to the calling thread.
+///
+/// Errors are ignored, the thread keeps its inherited priority.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_thread_priority(nice: Option<i32>, io_priority: Option<IoPriority>) {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const IOPRIO_CLASS_BE: libc::c_int = 2;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+    // SAFETY: plain syscalls on the calling thread
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if let Some(nice) = nice {
+            libc::setpriority(libc::PRIO_PROCESS as _, tid, nice);
+        }
+        if let Some(io_priority) = io_priority {
+            let ioprio = match io_priority {
+                IoPriority::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                IoPriority::BestEffort(level) => {
+                    IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | level.min(7) as libc::c_int
+                }
+            };
+            libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio);
+        }
+    }
+}
+
+/// Resident set size of the current process in bytes.
+#[cfg(target_os = "linux")]
+pub(crate) fn rss_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+/// Number of open file descriptors of the current process.
+#[cfg(target_os = "linux")]
+pub(crate) fn open_fds() -> Option<u64> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+/// Time since the current process started.
+#[cfg(target_os = "linux")]
+pub(crate) fn process_uptime() -> Option<std::time::Duration> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let since_boot: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // fields after the `(comm)` field start at field 3, starttime is field 22
+    let start_ticks: f64 = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()?;
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    std::time::Duration::try_from_secs_f64(since_boot - start_ticks / ticks_per_sec).ok()
+}
+
+/// Whether the filesystem of `path` is mounted read-only, e.g. after an
+/// `errors=remount-ro` remount.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_read_only(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid C string and `stat` a valid out pointer
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        libc::statvfs(path.as_ptr(), &mut stat) == 0 && stat.f_flag & libc::ST_RDONLY != 0
+    }
+}
+
+/// Disk space from `statvfs`, in place of sysinfo.
+#[cfg(all(unix, any(not(feature = "sysinfo"), test)))]
+pub(crate) struct StatvfsDisk {
+    path: std::ffi::CString,
+    available_space: u64,
+}
+
+#[cfg(all(unix, any(not(feature = "sysinfo"), test)))]
+impl StatvfsDisk {
+    pub(crate) fn new(path: &std::path::Path) -> Option<Self> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut disk = Self {
+            path: std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?,
+            available_space: 0,
+        };
+        disk.available_space = disk.statvfs()?;
+        Some(disk)
+    }
+
+    fn statvfs(&self) -> Option<u64> {
+        // SAFETY: `path` is a valid C string and `stat` a valid out pointer
+        unsafe {
+            let mut stat: libc::statvfs = std::mem::zeroed();
+            (libc::statvfs(self.path.as_ptr(), &mut stat) == 0)
+                .then(|| stat.f_bavail as u64 * stat.f_frsize as u64)
+        }
+    }
+}
+
+#[cfg(all(unix, any(not(feature = "sysinfo"), test)))]
+impl crate::writer::DiskSpace for StatvfsDisk {
+    fn available_space(&self) -> u64 {
+        self.available_space
+    }
+
+    fn refresh(&mut self) {
+        if let Some(available_space) = self.statvfs() {
+            self.available_space = available_space;
+        }
+    }
+}
+
+/// Without sysinfo nor statvfs the disk space is unknown, and never low.
+#[cfg(all(not(unix), not(feature = "sysinfo")))]
+pub(crate) struct StatvfsDisk;
+
+#[cfg(all(not(unix), not(feature = "sysinfo")))]
+impl StatvfsDisk {
+    pub(crate) fn new(_path: &std::path::Path) -> Option<Self> {
+        Some(Self)
+    }
+}
+
+#[cfg(all(not(unix), not(feature = "sysinfo")))]
+impl crate::writer::DiskSpace for StatvfsDisk {
+    fn available_space(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn refresh(&mut self) {}
+}
+
+/// The kernel id of the calling thread, as printed by taosd.
+#[cfg(target_os = "linux")]
+pub(crate) fn gettid() -> u64 {
+    // SAFETY: gettid has no preconditions
+    unsafe { libc::syscall(libc::SYS_gettid) as u64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn gettid() -> u64 {
+    thread_id::get() as u64
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn is_read_only(_path: &std::path::Path) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn rss_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn open_fds() -> Option<u64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn process_uptime() -> Option<std::time::Duration> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_thread_priority(_nice: Option<i32>, _io_priority: Option<IoPriority>) {}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_thread_priority_test() {
+        std::thread::spawn(|| {
+            set_thread_priority(Some(5), Some(IoPriority::Idle));
+            // SAFETY: plain syscalls on the calling thread
+            let (nice, ioprio) = unsafe {
+                let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+                (
+                    libc::getpriority(libc::PRIO_PROCESS as _, tid),
+                    libc::syscall(libc::SYS_ioprio_get, 1, 0),
+                )
+            };
+            assert!(nice >= 5);
+            assert_eq!(ioprio >> 13, 3);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn process_info_test() {
+        assert!(rss_bytes().unwrap() > 0);
+        assert!(open_fds().unwrap() >= 3);
+        assert!(process_uptime().unwrap() < std::time::Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn is_read_only_test() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_read_only(dir.path()));
+        assert!(!is_read_only(&dir.path().join("not-exists")));
+    }
+
+    #[test]
+    fn statvfs_disk_test() {
+        use crate::writer::{find_disk, DiskSpace};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut disk = StatvfsDisk::new(dir.path()).unwrap();
+        disk.refresh();
+        assert!(disk.available_space() > 0);
+        assert!(StatvfsDisk::new(&dir.path().join("not-exists")).is_none());
+
+        // the same space as the default backend, give or take concurrent writes
+        let default = find_disk(dir.path()).unwrap().available_space();
+        let diff = default.abs_diff(disk.available_space());
+        assert!(
+            diff < 64 * 1024 * 1024,
+            "{default} {}",
+            disk.available_space()
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Duplicate each event to two sinks from a single layer.
+
+use std::io;
+
+use tracing_subscriber::fmt::MakeWriter;
+
+/// A [`MakeWriter`] writing each event to both `A` and `B`, e.g. the appender
+/// and stdout, so [`TaosLayer`](crate::layer::TaosLayer) formats it once.
+///
+/// ```ignore
+/// let writer = TeeWriter::new(appender, std::io::stdout);
+/// tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+/// ```
+#[derive(Clone)]
+pub struct TeeWriter<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> TeeWriter<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.a
+    }
+
+    pub fn second(&self) -> &B {
+        &self.b
+    }
+}
+
+impl<'a, A, B> MakeWriter<'a> for TeeWriter<A, B>
+where
+    A: MakeWriter<'a>,
+    B: MakeWriter<'a>,
+{
+    type Writer = Tee<A::Writer, B::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Tee {
+            a: self.a.make_writer(),
+            b: self.b.make_writer(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        Tee {
+            a: self.a.make_writer_for(meta),
+            b: self.b.make_writer_for(meta),
+        }
+    }
+}
+
+/// Writer of a [`TeeWriter`]. A failing sink does not keep the other from
+/// being written, its error is returned afterwards.
+pub struct Tee<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: io::Write, B: io::Write> io::Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf).map(|_| buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let a = self.a.write_all(buf);
+        let b = self.b.write_all(buf);
+        a.and(b)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let a = self.a.flush();
+        let b = self.b.flush();
+        a.and(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        fake::{Capture, Qid},
+        layer::TaosLayer,
+    };
+
+    struct Broken;
+
+    impl io::Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tee_test() {
+        let (file, stdout) = (Capture::default(), Capture::default());
+        let writer = TeeWriter::new(file.clone(), stdout.clone());
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+        tracing::subscriber::with_default(subscriber, || tracing::info!(rows = 10, "synced"));
+
+        assert!(file.content().ends_with("INFO  rows:10 synced\n"));
+        assert_eq!(file.content(), stdout.content());
+    }
+
+    #[test]
+    fn broken_sink_test() {
+        let capture = Capture::default();
+        let mut tee = Tee {
+            a: Broken,
+            b: capture.clone(),
+        };
+        assert!(io::Write::write_all(&mut tee, b"line\n").is_err());
+        assert_eq!(capture.content(), "line\n");
+    }
+}
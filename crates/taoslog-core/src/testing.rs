@@ -0,0 +1,528 @@
+//! Helpers for integration tests of a logging configuration.
+//!
+//! A [`TestLogDir`] is a temporary log dir with a [`TestClock`] driving the
+//! rotations of the appenders built from it, so a day rollover takes a call to
+//! [`TestClock::advance`] instead of a day:
+//!
+//! ```ignore
+//! let dir = TestLogDir::at(Local.with_ymd_and_hms(2024, 8, 23, 23, 59, 0).unwrap());
+//! let appender = dir.builder("taosx", 1).compress(true).build()?;
+//! // log through the appender ...
+//! dir.clock().advance(TimeDelta::minutes(2));
+//! // log again, the next write rotates
+//! dir.assert_files(&["taosx_1_20240823.log.gz", "taosx_1_20240824.log"]);
+//! dir.assert_contains("taosx_1_20240823.log.gz", "before midnight");
+//! ```
+//!
+//! Compression and cleanup run on the maintenance thread of the appender, the
+//! assertions wait for them up to [`WAIT_TIMEOUT`].
+//!
+//! [`with_subscriber`] captures the lines of a [`TaosLayer`] in memory, with a
+//! dispatcher scoped to the calling thread, so tests asserting on log lines can
+//! run in parallel without fighting over the global subscriber:
+//!
+//! ```ignore
+//! let lines = taoslog::testing::with_subscriber::<Qid>(|layer| layer.json(), || {
+//!     tracing::info!(rows = 10, "flushed");
+//! });
+//! assert!(lines[0].contains("flushed"));
+//! ```
+//!
+//! A [`CaptureWriter`] keeps each event apart, with assertions on the level,
+//! QID and fields of the JSON lines of its [`layer`](CaptureWriter::layer):
+//!
+//! ```ignore
+//! let capture = CaptureWriter::new();
+//! let subscriber = tracing_subscriber::registry().with(capture.layer::<Qid, _>());
+//! tracing::subscriber::with_default(subscriber, || {
+//!     tracing::info!(rows = 10, "flushed");
+//! });
+//! capture
+//!     .assert_event(Level::INFO, "flushed")
+//!     .assert_field("rows", 10);
+//! capture.assert_no_event(Level::ERROR);
+//! ```
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use flate2::read::GzDecoder;
+use parking_lot::Mutex;
+use serde_json::Value;
+use tempfile::TempDir;
+use tracing::Level;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, Registry};
+
+use crate::{
+    clock::WallClock,
+    layer::TaosLayer,
+    writer::{RollingFileAppender, RollingFileAppenderBuilder},
+    QidManager,
+};
+
+/// How long assertions wait for the maintenance thread.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A manual clock for the appender, shared by its clones.
+#[derive(Clone)]
+pub struct TestClock(Arc<Mutex<DateTime<Local>>>);
+
+impl TestClock {
+    pub fn new(start: DateTime<Local>) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn now(&self) -> DateTime<Local> {
+        *self.0.lock()
+    }
+
+    pub fn set(&self, time: DateTime<Local>) {
+        *self.0.lock() = time;
+    }
+
+    pub fn advance(&self, delta: TimeDelta) {
+        *self.0.lock() += delta;
+    }
+
+    pub(crate) fn wall_clock(&self) -> WallClock {
+        WallClock::manual(self.0.clone())
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new(Local::now())
+    }
+}
+
+/// A temporary log dir, removed on drop, with assertions over its files.
+pub struct TestLogDir {
+    dir: TempDir,
+    clock: TestClock,
+}
+
+impl TestLogDir {
+    /// A log dir with a clock starting at the current time.
+    pub fn new() -> Self {
+        Self::with_clock(TestClock::default())
+    }
+
+    /// A log dir with a clock starting at `start`.
+    pub fn at(start: DateTime<Local>) -> Self {
+        Self::with_clock(TestClock::new(start))
+    }
+
+    pub fn with_clock(clock: TestClock) -> Self {
+        let dir = tempfile::tempdir().expect("create temp log dir");
+        Self { dir, clock }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn clock(&self) -> &TestClock {
+        &self.clock
+    }
+
+    /// An appender builder for this dir, driven by its clock.
+    pub fn builder<'a>(&self, component: &str, instance_id: u8) -> RollingFileAppenderBuilder<'a> {
+        RollingFileAppender::builder(self.path(), component, instance_id).clock(self.clock.clone())
+    }
+
+    /// Sorted names of the files in the dir.
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(self.path())
+            .expect("list temp log dir")
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Wait until `predicate` holds for the file names, panicking after
+    /// [`WAIT_TIMEOUT`].
+    pub fn wait_for(&self, predicate: impl Fn(&[String]) -> bool) -> Vec<String> {
+        let start = Instant::now();
+        loop {
+            let files = self.files();
+            if predicate(&files) {
+                return files;
+            }
+            if start.elapsed() > WAIT_TIMEOUT {
+                panic!("timed out waiting for log files, found {files:?}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Wait until the dir holds exactly `expected`, in any order.
+    pub fn assert_files(&self, expected: &[&str]) {
+        let mut expected: Vec<&str> = expected.to_vec();
+        expected.sort();
+        let start = Instant::now();
+        loop {
+            let files = self.files();
+            if files == expected {
+                return;
+            }
+            if start.elapsed() > WAIT_TIMEOUT {
+                assert_eq!(files, expected, "log files");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Wait until the dir holds `count` files.
+    pub fn assert_count(&self, count: usize) {
+        self.wait_for(|files| files.len() == count);
+    }
+
+    /// Content of `name`, decompressing `.gz` and `.zst` archives.
+    pub fn read(&self, name: &str) -> String {
+        let path = self.path().join(name);
+        let file = File::open(&path).unwrap_or_else(|e| panic!("open {}: {e}", path.display()));
+        let mut reader: Box<dyn Read> = if name.ends_with(".gz") {
+            Box::new(GzDecoder::new(file))
+        } else if name.ends_with(".zst") {
+            #[cfg(feature = "zstd")]
+            {
+                Box::new(zstd::stream::Decoder::new(file).expect("zstd decoder"))
+            }
+            #[cfg(not(feature = "zstd"))]
+            panic!("read {name}: zstd feature disabled")
+        } else {
+            Box::new(file)
+        };
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .unwrap_or_else(|e| panic!("read {}: {e}", path.display()));
+        content
+    }
+
+    pub fn assert_contains(&self, name: &str, needle: &str) {
+        let content = self.read(name);
+        assert!(
+            content.contains(needle),
+            "{name} does not contain {needle:?}:\n{content}"
+        );
+    }
+
+    pub fn assert_not_contains(&self, name: &str, needle: &str) {
+        let content = self.read(name);
+        assert!(
+            !content.contains(needle),
+            "{name} contains {needle:?}:\n{content}"
+        );
+    }
+}
+
+impl Default for TestLogDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// In-memory writer keeping every line written, shared by its clones.
+#[derive(Clone, Default)]
+pub struct MemoryWriter(Arc<Mutex<Vec<u8>>>);
+
+impl MemoryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn content(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock()).to_string()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.content().lines().map(str::to_string).collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+}
+
+impl io::Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for MemoryWriter {
+    type Writer = MemoryWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// In-memory writer keeping each event as a line, shared by its clones.
+#[derive(Clone, Default)]
+pub struct CaptureWriter(Arc<Mutex<Vec<String>>>);
+
+impl CaptureWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A layer writing JSON lines to this writer, at TRACE.
+    pub fn layer<Q, S>(&self) -> TaosLayer<Q, S, CaptureWriter>
+    where
+        Q: QidManager,
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        TaosLayer::new(self.clone())
+            .json()
+            .with_max_level(tracing::level_filters::LevelFilter::TRACE)
+    }
+
+    /// The events written, without their trailing newline.
+    pub fn events(&self) -> Vec<String> {
+        self.0.lock().clone()
+    }
+
+    /// The events written, parsed from JSON lines.
+    pub fn captured(&self) -> Vec<CapturedEvent> {
+        self.0
+            .lock()
+            .iter()
+            .map(|event| {
+                let value = serde_json::from_str(event)
+                    .unwrap_or_else(|e| panic!("event is not a JSON line ({e}): {event}"));
+                CapturedEvent(value)
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    /// The first event at `level` whose message contains `message`.
+    pub fn assert_event(&self, level: Level, message: &str) -> CapturedEvent {
+        let events = self.captured();
+        events
+            .iter()
+            .find(|event| event.level() == Some(level) && event.message().contains(message))
+            .cloned()
+            .unwrap_or_else(|| {
+                panic!(
+                    "no {level} event with message {message:?}, captured:\n{}",
+                    self.events().join("\n")
+                )
+            })
+    }
+
+    pub fn assert_no_event(&self, level: Level) {
+        let events = self.captured();
+        if let Some(event) = events.iter().find(|event| event.level() == Some(level)) {
+            panic!("unexpected {level} event: {}", event.0);
+        }
+    }
+}
+
+/// Buffers one event, kept when dropped.
+pub struct CaptureLine<'a> {
+    writer: &'a CaptureWriter,
+    buf: Vec<u8>,
+}
+
+impl io::Write for CaptureLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CaptureLine<'_> {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let event = String::from_utf8_lossy(&self.buf);
+        let event = event.strip_suffix('\n').unwrap_or(&event).to_string();
+        self.writer.0.lock().push(event);
+    }
+}
+
+impl<'a> MakeWriter<'a> for CaptureWriter {
+    type Writer = CaptureLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CaptureLine {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// An event captured by a [`CaptureWriter`], with chained assertions.
+#[derive(Debug, Clone)]
+pub struct CapturedEvent(pub Value);
+
+impl CapturedEvent {
+    pub fn level(&self) -> Option<Level> {
+        self.0["level"].as_str()?.parse().ok()
+    }
+
+    pub fn qid(&self) -> Option<u64> {
+        crate::utils::qid_from_hex(self.0["qid"].as_str()?)
+    }
+
+    pub fn message(&self) -> &str {
+        self.0["message"].as_str().unwrap_or_default()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.0["fields"].get(name)
+    }
+
+    pub fn assert_qid(self, qid: u64) -> Self {
+        assert_eq!(self.qid(), Some(qid), "qid of {}", self.0);
+        self
+    }
+
+    pub fn assert_field(self, name: &str, value: impl Into<Value>) -> Self {
+        assert_eq!(
+            self.field(name),
+            Some(&value.into()),
+            "field {name} of {}",
+            self.0
+        );
+        self
+    }
+
+    pub fn assert_no_field(self, name: &str) -> Self {
+        assert_eq!(self.field(name), None, "field {name} of {}", self.0);
+        self
+    }
+}
+
+/// Run `f` with a [`TaosLayer`] writing to memory as the subscriber of the
+/// current thread, and return the lines it wrote. `configure` sets up the
+/// layer, e.g. its format or directives.
+pub fn with_subscriber<Q: QidManager>(
+    configure: impl FnOnce(TaosLayer<Q, Registry, MemoryWriter>) -> TaosLayer<Q, Registry, MemoryWriter>,
+    f: impl FnOnce(),
+) -> Vec<String> {
+    let writer = MemoryWriter::new();
+    let layer = configure(TaosLayer::new(writer.clone()));
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+    writer.lines()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use chrono::TimeZone;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+    use crate::fake::Qid;
+
+    #[test]
+    fn with_subscriber_test() {
+        let lines = with_subscriber::<Qid>(
+            |layer| layer.with_max_level(tracing::level_filters::LevelFilter::INFO),
+            || {
+                tracing::info!(rows = 10, "flushed");
+                tracing::debug!("hidden");
+            },
+        );
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("rows:10 flushed"), "{lines:?}");
+
+        // parallel tests do not see each other's lines
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    with_subscriber::<Qid>(|layer| layer.json(), || tracing::info!(i, "worker"))
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let lines = handle.join().unwrap();
+            assert_eq!(lines.len(), 1);
+            let event: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+            assert_eq!(event["fields"]["i"], i);
+        }
+    }
+
+    #[test]
+    fn capture_writer_test() {
+        let capture = CaptureWriter::new();
+        let subscriber = tracing_subscriber::registry().with(capture.layer::<Qid, _>());
+        tracing::subscriber::with_default(subscriber, || {
+            crate::scope::with_qid(Qid::from(0x1234), || {
+                tracing::info!(rows = 10, table = "meters", "flushed");
+            });
+            tracing::debug!("multi\nline");
+        });
+
+        assert_eq!(capture.events().len(), 2);
+        capture
+            .assert_event(Level::INFO, "flushed")
+            .assert_qid(0x1234)
+            .assert_field("rows", 10)
+            .assert_field("table", "meters")
+            .assert_no_field("db");
+        assert_eq!(capture.captured()[1].message(), "multi\nline");
+        capture.assert_no_event(Level::ERROR);
+
+        let missing = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            capture.assert_event(Level::WARN, "flushed")
+        }));
+        assert!(missing.is_err());
+        capture.clear();
+        assert!(capture.events().is_empty());
+    }
+
+    #[test]
+    fn day_rollover_test() {
+        let dir = TestLogDir::at(Local.with_ymd_and_hms(2024, 8, 23, 23, 59, 0).unwrap());
+        let appender = dir.builder("taosx", 1).compress(true).build().unwrap();
+        dir.assert_files(&["taosx_1_20240823.log"]);
+
+        writeln!(appender.make_writer(), "before midnight").unwrap();
+        dir.clock().advance(TimeDelta::minutes(2));
+        writeln!(appender.make_writer(), "after midnight").unwrap();
+
+        dir.assert_files(&["taosx_1_20240823.log.gz", "taosx_1_20240824.log"]);
+        dir.assert_contains("taosx_1_20240823.log.gz", "before midnight");
+        dir.assert_not_contains("taosx_1_20240823.log.gz", "after midnight");
+        dir.assert_contains("taosx_1_20240824.log", "after midnight");
+
+        // retention follows the clock too
+        drop(appender);
+        dir.clock().advance(TimeDelta::days(5));
+        let _appender = dir
+            .builder("taosx", 1)
+            .max_age_days(3)
+            .compress(true)
+            .build()
+            .unwrap();
+        dir.wait_for(|files| files.iter().all(|f| !f.contains("20240823")));
+        assert!(dir.files().iter().any(|f| f.contains("20240829")));
+    }
+}
@@ -0,0 +1,106 @@
+//! Busy and idle time of spans, written when they close.
+//!
+//! With [`TaosLayer::with_span_timings`], closing a span writes an INFO line
+//! with the [`TARGET`] target in the span, like
+//! `qid:0x... busy:12ms, idle:3ms span outer closed`. Busy is the time spent
+//! entered, idle the rest of its life.
+//!
+//! [`TaosLayer::with_span_timings`]: crate::layer::TaosLayer::with_span_timings
+
+use std::time::{Duration, Instant};
+
+use tracing::{
+    callsite::{DefaultCallsite, Identifier},
+    field::{display, FieldSet, Value},
+    metadata::Kind,
+    span, Event, Level, Metadata,
+};
+
+use crate::humanize;
+
+/// Target of the span closed lines.
+pub const TARGET: &str = "taoslog::span";
+
+static CLOSED_CALLSITE: DefaultCallsite = DefaultCallsite::new(&CLOSED);
+pub(crate) static CLOSED: Metadata<'static> = Metadata::new(
+    "span closed",
+    TARGET,
+    Level::INFO,
+    Some(file!()),
+    Some(line!()),
+    Some(module_path!()),
+    FieldSet::new(&["message", "busy", "idle"], Identifier(&CLOSED_CALLSITE)),
+    Kind::EVENT,
+);
+
+/// Span extension timing its enters and exits.
+pub(crate) struct Timings {
+    busy: Duration,
+    idle: Duration,
+    last: Instant,
+}
+
+impl Timings {
+    pub(crate) fn new() -> Self {
+        Self {
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn enter(&mut self) {
+        let now = Instant::now();
+        self.idle += now - self.last;
+        self.last = now;
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let now = Instant::now();
+        self.busy += now - self.last;
+        self.last = now;
+    }
+
+    /// Call `f` with the closed line of span `id`, as a child of the span.
+    pub(crate) fn close(mut self, id: &span::Id, name: &str, f: impl FnOnce(&Event<'_>)) {
+        self.enter();
+        let fields = CLOSED.fields();
+        let (Some(message), Some(busy), Some(idle)) = (
+            fields.field("message"),
+            fields.field("busy"),
+            fields.field("idle"),
+        ) else {
+            return;
+        };
+        let text = display(format!("span {name} closed"));
+        let busy_text = display(humanize::duration(self.busy));
+        let idle_text = display(humanize::duration(self.idle));
+        let values: [(&_, Option<&dyn Value>); 3] = [
+            (&message, Some(&text)),
+            (&busy, Some(&busy_text)),
+            (&idle, Some(&idle_text)),
+        ];
+        f(&Event::new_child_of(
+            id.clone(),
+            &CLOSED,
+            &fields.value_set(&values),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_test() {
+        let mut timings = Timings::new();
+        std::thread::sleep(Duration::from_millis(20));
+        timings.enter();
+        std::thread::sleep(Duration::from_millis(10));
+        timings.exit();
+        assert!(timings.idle >= Duration::from_millis(20));
+        assert!(timings.busy >= Duration::from_millis(10));
+        assert!(timings.busy < timings.idle);
+    }
+}
@@ -0,0 +1,424 @@
+use tracing_subscriber::{registry::LookupSpan, Registry};
+
+use crate::QidManager;
+
+#[cfg(feature = "reqwest")]
+pub use crate::client::propagate_qid;
+
+/// Header and metadata key carrying the QID.
+pub const QID_HEADER_KEY: &str = "x-qid";
+
+pub struct Span;
+
+/// Hex form of a QID as carried in headers and metadata, e.g. `0x7fffffffffffffff`.
+pub fn qid_to_hex(qid: u64) -> String {
+    format!("{qid:#018x}")
+}
+
+/// The QID of a [hex form](qid_to_hex), `None` if it is not one.
+pub fn qid_from_hex(value: &str) -> Option<u64> {
+    value.get(2..).and_then(|x| u64::from_str_radix(x, 16).ok())
+}
+
+/// W3C trace context header.
+pub const TRACEPARENT_HEADER_KEY: &str = "traceparent";
+
+/// Encode `qid` as a W3C `traceparent` value, with the QID as the low 64 bits
+/// of the trace id and as the parent id, sampled.
+///
+/// A QID of 0 has no valid trace id and gives `None`.
+pub fn qid_to_traceparent<Q: QidManager>(qid: &Q) -> Option<String> {
+    let qid = qid.get();
+    (qid != 0).then(|| format!("00-{:032x}-{qid:016x}-01", qid))
+}
+
+/// Parse a W3C `traceparent` value into a QID from the low 64 bits of the
+/// trace id.
+pub fn qid_from_traceparent<Q: QidManager>(value: &str) -> Option<Q> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let is_hex = |s: &str, len| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || !is_hex(parent_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+    let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+    if trace_id == 0 || u64::from_str_radix(parent_id, 16).ok()? == 0 {
+        return None;
+    }
+    Some(Q::from(trace_id as u64))
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+pub trait QidMetadataGetter: private::Sealed {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager;
+}
+
+pub trait QidMetadataSetter: private::Sealed {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager;
+}
+
+#[cfg(feature = "actix")]
+impl QidMetadataGetter for actix_web::http::header::HeaderMap {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        self.get(QID_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(qid_from_hex)
+            .map(|x| Q::from(x))
+    }
+}
+
+#[cfg(feature = "actix")]
+impl QidMetadataSetter for actix_web::http::header::HeaderMap {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        self.insert(
+            actix_web::http::header::HeaderName::from_static(QID_HEADER_KEY),
+            actix_web::http::header::HeaderValue::from_str(&qid_to_hex(qid.get())).unwrap(),
+        );
+    }
+}
+
+#[cfg(feature = "actix")]
+impl private::Sealed for actix_web::http::header::HeaderMap {}
+
+#[cfg(feature = "http")]
+impl QidMetadataGetter for http::header::HeaderMap {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        self.get(QID_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(qid_from_hex)
+            .map(|x| Q::from(x))
+    }
+}
+
+#[cfg(feature = "http")]
+impl QidMetadataSetter for http::header::HeaderMap {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        self.insert(
+            QID_HEADER_KEY,
+            http::header::HeaderValue::from_str(&qid_to_hex(qid.get())).unwrap(),
+        );
+    }
+}
+
+#[cfg(feature = "http")]
+impl private::Sealed for http::header::HeaderMap {}
+
+#[cfg(feature = "arrow")]
+impl QidMetadataGetter for arrow_schema::Schema {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        self.metadata
+            .get(QID_HEADER_KEY)
+            .and_then(|x| qid_from_hex(x))
+            .map(|x| Q::from(x))
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl QidMetadataSetter for arrow_schema::Schema {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        self.metadata
+            .insert(QID_HEADER_KEY.to_owned(), qid_to_hex(qid.get()));
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl private::Sealed for arrow_schema::Schema {}
+
+#[cfg(feature = "tonic")]
+impl QidMetadataGetter for tonic::metadata::MetadataMap {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        self.get(QID_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(qid_from_hex)
+            .map(|x| Q::from(x))
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl QidMetadataSetter for tonic::metadata::MetadataMap {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        self.insert(QID_HEADER_KEY, qid_to_hex(qid.get()).parse().unwrap());
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl private::Sealed for tonic::metadata::MetadataMap {}
+
+/// Arrow Flight requests carry the QID in their `x-qid` gRPC metadata, as the
+/// descriptor and ticket bytes belong to the service.
+#[cfg(feature = "flight")]
+macro_rules! flight_request_carrier {
+    ($($message:ty),+) => {$(
+        impl QidMetadataGetter for tonic::Request<$message> {
+            fn get_qid<Q>(&self) -> Option<Q>
+            where
+                Q: QidManager,
+            {
+                self.metadata()
+                    .get(QID_HEADER_KEY)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(qid_from_hex)
+                    .map(|x| Q::from(x))
+            }
+        }
+
+        impl QidMetadataSetter for tonic::Request<$message> {
+            fn set_qid<Q>(&mut self, qid: &Q)
+            where
+                Q: QidManager,
+            {
+                self.metadata_mut()
+                    .insert(QID_HEADER_KEY, qid_to_hex(qid.get()).parse().unwrap());
+            }
+        }
+
+        impl private::Sealed for tonic::Request<$message> {}
+    )+};
+}
+
+#[cfg(feature = "flight")]
+flight_request_carrier!(arrow_flight::FlightDescriptor, arrow_flight::Ticket);
+
+impl QidMetadataGetter for Span {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch
+                .downcast_ref::<Registry>()
+                .expect("no global default dispatcher found");
+            dispatch.current_span().into_inner().and_then(|(id, _)| {
+                let span = registry.span(&id).unwrap();
+                let ext = span.extensions();
+                ext.get::<Q>().cloned()
+            })
+        })
+    }
+}
+
+impl QidMetadataSetter for Span {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch
+                .downcast_ref::<Registry>()
+                .expect("no global default dispatcher found");
+            if let Some((id, _meta)) = dispatch.current_span().into_inner() {
+                let span = registry.span(&id).unwrap();
+                let mut ext = span.extensions_mut();
+                ext.replace(qid.clone());
+            }
+        })
+    }
+}
+
+impl private::Sealed for Span {}
+
+impl QidMetadataGetter for tracing::Span {
+    fn get_qid<Q>(&self) -> Option<Q>
+    where
+        Q: QidManager,
+    {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch
+                .downcast_ref::<Registry>()
+                .expect("no global default dispatcher found");
+            self.id().and_then(|id| {
+                let span = registry.span(&id).unwrap();
+                let ext = span.extensions();
+                ext.get::<Q>().cloned()
+            })
+        })
+    }
+}
+
+impl QidMetadataSetter for tracing::Span {
+    fn set_qid<Q>(&mut self, qid: &Q)
+    where
+        Q: QidManager,
+    {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch
+                .downcast_ref::<Registry>()
+                .expect("no global default dispatcher found");
+            if let Some(id) = self.id() {
+                let span = registry.span(&id).unwrap();
+                let mut ext = span.extensions_mut();
+                ext.replace(qid.clone());
+            }
+        })
+    }
+}
+
+impl private::Sealed for tracing::Span {}
+
+#[cfg(test)]
+mod tests {
+
+    use tracing::info_span;
+
+    use crate::fake::Qid;
+
+    use super::*;
+
+    #[test]
+    fn traceparent_test() {
+        let traceparent = qid_to_traceparent(&Qid::from(0x7fffffffffffffff)).unwrap();
+        assert_eq!(
+            traceparent,
+            "00-00000000000000007fffffffffffffff-7fffffffffffffff-01"
+        );
+        let qid: Qid = qid_from_traceparent(&traceparent).unwrap();
+        assert_eq!(qid.get(), 0x7fffffffffffffff);
+        assert!(qid_to_traceparent(&Qid::from(0)).is_none());
+
+        // from an OpenTelemetry service, the low 64 bits are kept
+        let qid: Qid =
+            qid_from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                .unwrap();
+        assert_eq!(qid.get(), 0xa3ce929d0e0e4736);
+
+        for invalid in [
+            "",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01",
+        ] {
+            assert!(qid_from_traceparent::<Qid>(invalid).is_none(), "{invalid}");
+        }
+        // future versions may append fields
+        assert!(qid_from_traceparent::<Qid>(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn qid_set_get_test() {
+        let qid_u64 = 9223372036854775807;
+        let qid = Qid::from(qid_u64);
+
+        #[cfg(feature = "actix")]
+        {
+            let mut header = actix_web::http::header::HeaderMap::new();
+            header.set_qid(&qid);
+
+            assert_eq!(header.get(QID_HEADER_KEY).unwrap(), "0x7fffffffffffffff");
+
+            let qid: Qid = header.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+
+        #[cfg(feature = "http")]
+        {
+            let mut header = http::header::HeaderMap::new();
+            header.set_qid(&qid);
+
+            assert_eq!(header.get(QID_HEADER_KEY).unwrap(), "0x7fffffffffffffff");
+
+            let qid: Qid = header.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+
+        #[cfg(feature = "tonic")]
+        {
+            let mut metadata = tonic::metadata::MetadataMap::new();
+            metadata.set_qid(&qid);
+
+            assert_eq!(metadata.get(QID_HEADER_KEY).unwrap(), "0x7fffffffffffffff");
+
+            let qid: Qid = metadata.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+
+        #[cfg(feature = "flight")]
+        {
+            let mut request = tonic::Request::new(arrow_flight::Ticket::new("q1"));
+            request.set_qid(&qid);
+
+            assert_eq!(
+                request.metadata().get(QID_HEADER_KEY).unwrap(),
+                "0x7fffffffffffffff"
+            );
+            assert_eq!(&request.get_ref().ticket[..], b"q1");
+
+            let qid: Qid = request.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+
+        #[cfg(feature = "arrow")]
+        {
+            let mut schema = arrow_schema::Schema::empty();
+            schema.set_qid(&qid);
+
+            assert_eq!(
+                schema.metadata.get(QID_HEADER_KEY).unwrap(),
+                "0x7fffffffffffffff"
+            );
+
+            let qid: Qid = schema.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+
+        use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+        let _guard = tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer())
+            .set_default();
+
+        {
+            tracing::info_span!("outer", "k" = "kkk").in_scope(|| {
+                Span.set_qid(&qid);
+                let qid: Qid = Span.get_qid().unwrap();
+                assert_eq!(qid.get(), qid_u64);
+            });
+        }
+
+        {
+            let mut span = info_span!("example");
+            span.set_qid(&qid);
+            let qid: Qid = span.get_qid().unwrap();
+            assert_eq!(qid.get(), qid_u64);
+        }
+    }
+}
@@ -0,0 +1,4729 @@
+use std::{
+    cmp,
+    fs::{self, File},
+    io::{IoSlice, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicBool, AtomicI64, AtomicU64, AtomicU8},
+        Arc, Weak,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{
+    format::{DelayedFormat, StrftimeItems},
+    DateTime, Datelike, Local, Months, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, CrcReader};
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use snafu::{ensure, OptionExt, ResultExt};
+
+#[cfg(feature = "hash-chain")]
+use crate::chain::{self, HashChain};
+#[cfg(feature = "fault-injection")]
+use crate::fault::{FaultInjector, FaultPoint};
+use crate::{
+    advisor::{Settings, Usage, UsageStats},
+    catalog::Language,
+    clock::WallClock,
+    current::{CurrentFile, Released},
+    flush::FlushScheduler,
+    guard::{DefaultDiskPolicy, DiskAction, DiskCheck, DiskGuard, DiskPolicy},
+    hints::PositionHints,
+    instance::InstanceLock,
+    internal::{self, enter_maintenance},
+    manifest,
+    metrics::{Counters, Metrics},
+    size::{ByteSize, SizeArg},
+    sys::{self, set_thread_priority},
+    ArchiveSnafu, CompressSnafu, CreateLogDirSnafu, DiskMountPointNotFoundSnafu, GetFileSizeSnafu,
+    GetLogAbsolutePathSnafu, InvalidConfigSnafu, InvalidRotationSizeSnafu, OpenLogFileSnafu,
+    ReadDirSnafu, Result, VerifyArchiveSnafu,
+};
+#[cfg(feature = "encryption")]
+use crate::{encrypt::KeyProvider, EncryptSnafu};
+
+/// Directory of the log files of taosdata services.
+pub const DEFAULT_LOG_DIR: &str = "/var/log/taos";
+
+const DATE_FORMAT: &str = "%Y%m%d";
+const DEFAULT_BUFFER_FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+const DISK_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const DATE_HOUR_FORMAT: &str = "%Y%m%d%H";
+const DATE_TIME_FORMAT: &str = "%Y%m%d %H%M%S";
+
+/// How often the appender switches to a new file, at local time boundaries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RotationInterval {
+    /// At the start of every hour.
+    Hourly,
+    /// Every N hours from midnight, e.g. `Hours(6)` rotates at 00:00, 06:00,
+    /// 12:00 and 18:00. A day always starts a new file.
+    Hours(u32),
+    /// At midnight.
+    #[default]
+    Daily,
+    /// At midnight on Monday.
+    Weekly,
+    /// At midnight on the first day of the month.
+    Monthly,
+    /// Never, files only roll on size. They are named by the day they were
+    /// created.
+    Never,
+}
+
+#[derive(Clone)]
+struct Rotation {
+    interval: RotationInterval,
+    /// local time of the rotations, shifting the interval boundaries
+    at: NaiveTime,
+    /// file size in bytes
+    file_size: u64,
+}
+
+#[cfg(test)]
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            interval: RotationInterval::Daily,
+            at: NaiveTime::MIN,
+            file_size: Default::default(),
+        }
+    }
+}
+
+impl Rotation {
+    fn hours(&self) -> Option<u32> {
+        match self.interval {
+            RotationInterval::Hourly => Some(1),
+            RotationInterval::Hours(hours) => Some(hours.clamp(1, 24)),
+            _ => None,
+        }
+    }
+
+    /// Shift of the boundaries from midnight.
+    fn offset(&self) -> TimeDelta {
+        self.at - NaiveTime::MIN
+    }
+
+    /// Shift of the file name periods, within the hour for hourly names.
+    fn name_offset(&self) -> TimeDelta {
+        match self.hours() {
+            Some(_) => self.offset() - TimeDelta::hours(self.at.hour() as i64),
+            None => self.offset(),
+        }
+    }
+
+    fn next_timestamp(&self, now: DateTime<Local>) -> i64 {
+        // boundaries are computed from midnight, then shifted to `at`
+        let now = now.naive_local() - self.offset();
+        let today = now.date();
+        let next = match self.interval {
+            RotationInterval::Never => return i64::MAX,
+            RotationInterval::Daily => today + TimeDelta::days(1),
+            RotationInterval::Weekly => {
+                today + TimeDelta::days(7 - now.weekday().num_days_from_monday() as i64)
+            }
+            RotationInterval::Monthly => {
+                let first = today.with_day(1).unwrap();
+                first.checked_add_months(Months::new(1)).unwrap()
+            }
+            RotationInterval::Hourly | RotationInterval::Hours(_) => {
+                let hours = self.hours().unwrap();
+                let next_hour = (now.hour() / hours + 1) * hours;
+                if next_hour < 24 {
+                    return local_timestamp(
+                        today.and_hms_opt(next_hour, 0, 0).unwrap() + self.offset(),
+                    );
+                }
+                today + TimeDelta::days(1)
+            }
+        };
+        local_timestamp(next.and_hms_opt(0, 0, 0).unwrap() + self.offset())
+    }
+
+    /// End of the period of a file dated `date`, the day after for files
+    /// never rotated by time.
+    fn period_end(&self, date: DateTime<Local>) -> i64 {
+        let start = date + self.name_offset();
+        match self.interval {
+            RotationInterval::Never => Rotation {
+                interval: RotationInterval::Daily,
+                ..self.clone()
+            }
+            .next_timestamp(start),
+            _ => self.next_timestamp(start),
+        }
+    }
+
+    /// The time part of file names created at `now`, the start of their
+    /// period.
+    fn format<'a>(&self, now: DateTime<Local>) -> DelayedFormat<StrftimeItems<'a>> {
+        let now = now - self.name_offset();
+        match self.hours() {
+            Some(_) => now.naive_local().format(DATE_HOUR_FORMAT),
+            None => time_format(now),
+        }
+    }
+
+    /// Start of the file name period of `now`, as parsed back from file names.
+    fn period(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        parse_date_str(&self.format(now).to_string())
+    }
+}
+
+fn local_timestamp(datetime: NaiveDateTime) -> i64 {
+    match Local.from_local_datetime(&datetime).earliest() {
+        Some(datetime) => datetime.timestamp(),
+        // skipped by a DST transition
+        None => datetime.and_utc().timestamp(),
+    }
+}
+
+/// IO scheduling class of the maintenance threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Only get disk time when no other process needs it.
+    Idle,
+    /// Best effort class with a level from 0 (highest) to 7 (lowest).
+    BestEffort(u8),
+}
+
+/// Compression policy when `rotation_count` is 1, i.e. only the current file
+/// would be kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SingleFileCompression {
+    /// Disable compression, rotated files are deleted right away.
+    #[default]
+    Skip,
+    /// Compress the rotated file and keep it next to the current one, deleting
+    /// older files, i.e. `rotation_count` is raised to 2.
+    CompressThenDeleteOlder,
+}
+
+pub(crate) const STOP_LOGGING_THREDHOLD: usize = 20;
+
+/// Disk pressure stage, from the available space of the log disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskStage {
+    /// Available space above `reserved_disk_size`, every event is written.
+    Normal,
+    /// Available space below `reserved_disk_size`, only ERROR events are written.
+    Downgrade,
+    /// Available space below 20% of `reserved_disk_size`, no event is written.
+    Stopped,
+}
+
+impl DiskStage {
+    /// The stage with `available_space` left.
+    pub fn of(available_space: u64, reserved_disk_size: u64) -> Self {
+        if available_space as f64 / reserved_disk_size as f64
+            <= STOP_LOGGING_THREDHOLD as f64 / 100f64
+        {
+            DiskStage::Stopped
+        } else if available_space <= reserved_disk_size {
+            DiskStage::Downgrade
+        } else {
+            DiskStage::Normal
+        }
+    }
+
+    fn from_u8(stage: u8) -> Self {
+        match stage {
+            1 => DiskStage::Downgrade,
+            2 => DiskStage::Stopped,
+            _ => DiskStage::Normal,
+        }
+    }
+}
+
+/// Snapshot of the disk pressure state of an appender.
+#[derive(Debug, Clone)]
+pub struct DiskPressureStats {
+    pub stage: DiskStage,
+    /// When the current stage was entered.
+    pub since: DateTime<Local>,
+    /// Events dropped while in [`DiskStage::Downgrade`].
+    pub dropped_downgrade: u64,
+    /// Events dropped while in [`DiskStage::Stopped`].
+    pub dropped_stopped: u64,
+    pub available_space: u64,
+    /// Available space below which the appender enters [`DiskStage::Downgrade`].
+    pub downgrade_threshold: u64,
+    /// Available space below which the appender enters [`DiskStage::Stopped`].
+    pub stop_threshold: u64,
+}
+
+/// Compression algorithm of rotated files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// `.gz` archives.
+    #[default]
+    Gzip,
+    /// `.zst` archives, `level` from 1 to 22, 0 for the zstd default.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => "zst",
+        }
+    }
+}
+
+/// When the appender calls `File::sync_data`, for crash-durable logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the OS.
+    #[default]
+    Never,
+    /// After each ERROR event.
+    OnError,
+    /// After every N bytes written.
+    EveryBytes(u64),
+    /// Every interval, from a background thread.
+    Every(Duration),
+}
+
+/// Sync state of the file being written.
+struct Durability {
+    policy: SyncPolicy,
+    unsynced: AtomicU64,
+    syncs: AtomicU64,
+}
+
+impl Durability {
+    fn new(policy: SyncPolicy) -> Self {
+        Self {
+            policy,
+            unsynced: AtomicU64::new(0),
+            syncs: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a write of `len` bytes, `urgent` for ERROR events, is synced.
+    fn due(&self, len: usize, urgent: bool) -> bool {
+        match self.policy {
+            SyncPolicy::Never | SyncPolicy::Every(_) => false,
+            SyncPolicy::OnError => urgent,
+            SyncPolicy::EveryBytes(bytes) => {
+                let unsynced = self
+                    .unsynced
+                    .fetch_add(len as u64, atomic::Ordering::Relaxed)
+                    + len as u64;
+                unsynced >= bytes
+            }
+        }
+    }
+
+    fn sync(&self, file: &File) -> std::io::Result<()> {
+        self.unsynced.store(0, atomic::Ordering::Relaxed);
+        self.syncs.fetch_add(1, atomic::Ordering::Relaxed);
+        file.sync_data()
+    }
+}
+
+/// Snapshot of the state of a [`RollingFileAppender`].
+#[derive(Debug, Clone)]
+pub struct AppenderStats {
+    pub disk: DiskPressureStats,
+    /// The log filesystem is read-only, events go to stderr.
+    pub read_only: bool,
+    pub usage: UsageStats,
+    /// `sync_data` calls made by the [`SyncPolicy`].
+    pub syncs: u64,
+}
+
+impl AppenderStats {
+    /// Suggestions on the rotation, compression and cleanup settings from
+    /// their usage, e.g. `rotation_size too small: 75 rotations/day`.
+    pub fn advisor(&self) -> Vec<String> {
+        self.usage.advisor()
+    }
+}
+
+/// Read-only state of the log filesystem, shared with the disk monitor.
+struct ReadOnly {
+    log_dir: PathBuf,
+    read_only: AtomicBool,
+}
+
+impl ReadOnly {
+    fn get(&self) -> bool {
+        self.read_only.load(atomic::Ordering::Relaxed)
+    }
+
+    fn set(&self, read_only: bool) {
+        if self.read_only.swap(read_only, atomic::Ordering::Relaxed) == read_only {
+            return;
+        }
+        if read_only {
+            eprintln!(
+                "[RollingFileAppender] log filesystem of {} is read-only, writing logs to stderr",
+                self.log_dir.display()
+            );
+        } else {
+            eprintln!(
+                "[RollingFileAppender] log filesystem of {} is writable again, writing logs to files",
+                self.log_dir.display()
+            );
+        }
+    }
+}
+
+/// Write `buf` to `file`, or to stderr once the filesystem is read-only.
+fn write_file(file: &File, read_only: &ReadOnly, buf: &[u8]) -> std::io::Result<()> {
+    match (&*file).write_all(buf) {
+        Err(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => {
+            // remounted read-only, switch to stderr instead of failing every event
+            read_only.set(true);
+            std::io::stderr().write_all(buf)
+        }
+        res => res,
+    }
+}
+
+/// Write all of `events` to `file` with `write_vectored`, or to stderr once
+/// the filesystem is read-only.
+fn write_file_vectored(
+    file: &File,
+    read_only: &ReadOnly,
+    events: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let mut slices: Vec<_> = events.iter().map(|event| IoSlice::new(event)).collect();
+    let mut slices = &mut slices[..];
+    while !slices.is_empty() {
+        match (&*file).write_vectored(slices) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => {
+                read_only.set(true);
+                let mut stderr = std::io::stderr().lock();
+                return slices.iter().try_for_each(|slice| stderr.write_all(slice));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Events kept in memory until `capacity` bytes, or events, an ERROR event,
+/// a flush or the periodic flush, to save a syscall per event.
+struct WriteBuffer {
+    pending: Mutex<Pending>,
+    capacity: usize,
+}
+
+enum Pending {
+    /// Events end to end, up to `capacity` bytes.
+    Bytes(Vec<u8>),
+    /// Events apart, up to `capacity` events, written with `write_vectored`.
+    Events {
+        events: Vec<Vec<u8>>,
+        /// cleared event buffers, reused
+        spare: Vec<Vec<u8>>,
+    },
+}
+
+/// Largest event buffer kept for reuse.
+const MAX_SPARE_EVENT: usize = 64 * 1024;
+
+impl WriteBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            pending: Mutex::new(Pending::Bytes(Vec::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    fn batch(max_events: usize) -> Self {
+        Self {
+            pending: Mutex::new(Pending::Events {
+                events: Vec::with_capacity(max_events),
+                spare: Vec::new(),
+            }),
+            capacity: max_events,
+        }
+    }
+
+    fn write(
+        &self,
+        file: &File,
+        read_only: &ReadOnly,
+        buf: &[u8],
+        urgent: bool,
+    ) -> std::io::Result<()> {
+        let mut pending = self.pending.lock();
+        let full = match &mut *pending {
+            Pending::Bytes(bytes) => {
+                if bytes.is_empty() && (urgent || buf.len() >= self.capacity) {
+                    return write_file(file, read_only, buf);
+                }
+                bytes.extend_from_slice(buf);
+                bytes.len() >= self.capacity
+            }
+            Pending::Events { events, spare } => {
+                let mut event = spare.pop().unwrap_or_default();
+                event.extend_from_slice(buf);
+                events.push(event);
+                events.len() >= self.capacity
+            }
+        };
+        if urgent || full {
+            return pending.write(file, read_only);
+        }
+        Ok(())
+    }
+
+    fn drain(&self, file: &File, read_only: &ReadOnly) -> std::io::Result<()> {
+        self.pending.lock().write(file, read_only)
+    }
+}
+
+impl Pending {
+    /// Write the pending events, dropped on error, not retried forever.
+    fn write(&mut self, file: &File, read_only: &ReadOnly) -> std::io::Result<()> {
+        match self {
+            Pending::Bytes(bytes) if bytes.is_empty() => Ok(()),
+            Pending::Bytes(bytes) => {
+                let res = write_file(file, read_only, bytes);
+                bytes.clear();
+                res
+            }
+            Pending::Events { events, .. } if events.is_empty() => Ok(()),
+            Pending::Events { events, spare } => {
+                let res = write_file_vectored(file, read_only, events);
+                for mut event in events.drain(..) {
+                    if event.capacity() <= MAX_SPARE_EVENT {
+                        event.clear();
+                        spare.push(event);
+                    }
+                }
+                res
+            }
+        }
+    }
+}
+
+pub(crate) struct DiskPressure {
+    stage: AtomicU8,
+    /// timestamp in microseconds
+    since: AtomicI64,
+    dropped: [AtomicU64; 3],
+}
+
+impl DiskPressure {
+    pub(crate) fn new() -> Self {
+        Self {
+            stage: AtomicU8::new(DiskStage::Normal as u8),
+            since: AtomicI64::new(Local::now().timestamp_micros()),
+            dropped: Default::default(),
+        }
+    }
+
+    pub(crate) fn stage(&self) -> DiskStage {
+        DiskStage::from_u8(self.stage.load(atomic::Ordering::Acquire))
+    }
+
+    pub(crate) fn enter(&self, stage: DiskStage) {
+        let prev = self.stage.swap(stage as u8, atomic::Ordering::AcqRel);
+        if prev != stage as u8 {
+            self.since
+                .store(Local::now().timestamp_micros(), atomic::Ordering::Release);
+        }
+    }
+
+    pub(crate) fn drop_event(&self, stage: DiskStage) {
+        self.dropped[stage as usize].fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self, available_space: u64, reserved_disk_size: u64) -> DiskPressureStats {
+        DiskPressureStats {
+            stage: self.stage(),
+            since: DateTime::from_timestamp_micros(self.since.load(atomic::Ordering::Acquire))
+                .unwrap_or_default()
+                .with_timezone(&Local),
+            dropped_downgrade: self.dropped[DiskStage::Downgrade as usize]
+                .load(atomic::Ordering::Relaxed),
+            dropped_stopped: self.dropped[DiskStage::Stopped as usize]
+                .load(atomic::Ordering::Relaxed),
+            available_space,
+            downgrade_threshold: reserved_disk_size,
+            stop_threshold: reserved_disk_size * STOP_LOGGING_THREDHOLD as u64 / 100,
+        }
+    }
+}
+
+/// Why the appender switched to a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationReason {
+    /// A new period of the [`RotationInterval`] started.
+    Time,
+    /// The file reached the rotation size.
+    Size,
+    /// The file was deleted or moved away by someone else.
+    MissingFile,
+    /// Requested with [`rotate_now`](RollingFileAppender::rotate_now).
+    Manual,
+}
+
+/// A switch of the appender to a new file, see
+/// [`on_rotate`](RollingFileAppenderBuilder::on_rotate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationEvent {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub reason: RotationReason,
+}
+
+type OnRotate = Arc<dyn Fn(RotationEvent) + Send + Sync>;
+
+struct State {
+    max_seq_id: usize,
+    file_path: PathBuf,
+    /// size of the current file at the last check, to detect truncation
+    file_size: u64,
+}
+
+#[derive(Clone)]
+struct Config {
+    log_dir: PathBuf,
+    component_name: String,
+    instance_id: u8,
+    extension: &'static str,
+    rotation: Rotation,
+    compress: bool,
+    compression: Compression,
+    rotate_count: usize,
+    verify_compression: bool,
+    archive: bool,
+    max_total_size: Option<u64>,
+    max_age_days: Option<u32>,
+    position_hints: Option<Arc<PositionHints>>,
+    usage: Arc<Usage>,
+    metrics: Arc<Counters>,
+    clock: WallClock,
+    #[cfg(feature = "fault-injection")]
+    faults: FaultInjector,
+    on_rotate: Option<OnRotate>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<Arc<dyn KeyProvider>>,
+    #[cfg(feature = "hash-chain")]
+    hash_chain: bool,
+}
+
+pub struct RollingFileAppenderBuilder<'a> {
+    log_dir: PathBuf,
+    component_name: String,
+    instance_id: u8,
+    extension: &'static str,
+    rotation_count: usize,
+    rotation_size: SizeArg<'a>,
+    max_total_size: Option<SizeArg<'a>>,
+    max_age_days: Option<u32>,
+    compress: bool,
+    compression: Compression,
+    reserved_disk_size: SizeArg<'a>,
+    maintenance_nice: Option<i32>,
+    maintenance_io_priority: Option<IoPriority>,
+    heartbeat: Option<Duration>,
+    disk_summary_interval: Duration,
+    single_file_compression: SingleFileCompression,
+    cleanup_interval: Option<Duration>,
+    verify_compression: bool,
+    archive: bool,
+    rotation_interval: RotationInterval,
+    rotate_at: NaiveTime,
+    flush_scheduler: Option<FlushScheduler>,
+    position_hints: bool,
+    instance_lock: Option<InstanceLock>,
+    write_buffer: Option<SizeArg<'a>>,
+    batch_events: Option<usize>,
+    buffer_flush_interval: Duration,
+    language: Language,
+    disk_policy: Arc<dyn DiskPolicy>,
+    sync_policy: SyncPolicy,
+    clock: WallClock,
+    #[cfg(feature = "fault-injection")]
+    faults: FaultInjector,
+    on_rotate: Option<OnRotate>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<Arc<dyn KeyProvider>>,
+    #[cfg(feature = "hash-chain")]
+    hash_chain: bool,
+    shared_maintenance: Option<SharedMaintenance>,
+    /// the maintenance thread of the primary appender, for a fallback
+    maintenance: Option<flume::Sender<MaintenanceEvent>>,
+    fallback_dir: Option<PathBuf>,
+}
+
+impl<'a> RollingFileAppenderBuilder<'a> {
+    pub fn rotation_count(self, rotation_count: u16) -> Self {
+        Self {
+            rotation_count: rotation_count as usize,
+            ..self
+        }
+    }
+
+    pub fn rotation_size(self, rotation_size: &'a str) -> Self {
+        Self {
+            rotation_size: SizeArg::Text(rotation_size),
+            ..self
+        }
+    }
+
+    /// [`rotation_size`](Self::rotation_size) in bytes, e.g. `512 * MB`.
+    pub fn rotation_size_bytes(self, rotation_size: impl Into<ByteSize>) -> Self {
+        Self {
+            rotation_size: SizeArg::Bytes(rotation_size.into()),
+            ..self
+        }
+    }
+
+    /// Delete the oldest files, compressed or not, until the log files of this
+    /// appender use less than `max_total_size`, e.g. `"10GB"`. Applies with
+    /// `rotation_count`, the file being written is never deleted.
+    pub fn max_total_size(self, max_total_size: &'a str) -> Self {
+        Self {
+            max_total_size: Some(SizeArg::Text(max_total_size)),
+            ..self
+        }
+    }
+
+    /// [`max_total_size`](Self::max_total_size) in bytes.
+    pub fn max_total_size_bytes(self, max_total_size: impl Into<ByteSize>) -> Self {
+        Self {
+            max_total_size: Some(SizeArg::Bytes(max_total_size.into())),
+            ..self
+        }
+    }
+
+    /// Delete rotated files older than `days`, whatever their count, by the
+    /// date in their name. Needs `cleanup_interval` to apply without rotations.
+    pub fn max_age_days(self, days: u32) -> Self {
+        Self {
+            max_age_days: Some(days),
+            ..self
+        }
+    }
+
+    /// Never roll files on size, only on the
+    /// [`rotation_interval`](Self::rotation_interval).
+    pub fn no_size_rotation(self) -> Self {
+        Self {
+            rotation_size: SizeArg::Bytes(ByteSize(u64::MAX)),
+            ..self
+        }
+    }
+
+    /// Time based rotation interval, daily by default,
+    /// [`RotationInterval::Never`] to only roll files on size.
+    pub fn rotation_interval(self, rotation_interval: RotationInterval) -> Self {
+        Self {
+            rotation_interval,
+            ..self
+        }
+    }
+
+    /// Rotate at `at` local time instead of midnight, e.g. 04:00 to keep
+    /// rotation and compression away from the midnight batch jobs. Hourly
+    /// intervals keep the minutes of `at`. Files are named by the start of
+    /// their period, so the daily file written at 02:00 has the date of the
+    /// day before.
+    pub fn rotate_at(self, at: NaiveTime) -> Self {
+        Self {
+            rotate_at: at,
+            ..self
+        }
+    }
+
+    pub fn compress(self, compress: bool) -> Self {
+        Self { compress, ..self }
+    }
+
+    /// Write `<component>_<instance_id>_<date>.errlog` files instead of
+    /// `.log` ones, rotated and cleaned up on their own, like the error log
+    /// of taosd. Route events to it with [`LevelSplit`](crate::split::LevelSplit).
+    pub fn errlog(self) -> Self {
+        Self {
+            extension: "errlog",
+            ..self
+        }
+    }
+
+    /// Write `<component>_<instance_id>_<date>.access` files instead of `.log`
+    /// ones, for the access lines of the HTTP middlewares routed with
+    /// [`TargetSplit`](crate::split::TargetSplit).
+    pub fn access_log(self) -> Self {
+        Self {
+            extension: "access",
+            ..self
+        }
+    }
+
+    /// Algorithm used to compress rotated files, gzip by default.
+    pub fn compression_algorithm(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn reserved_disk_size(self, reserved_disk_size: &'a str) -> Self {
+        Self {
+            reserved_disk_size: SizeArg::Text(reserved_disk_size),
+            ..self
+        }
+    }
+
+    /// [`reserved_disk_size`](Self::reserved_disk_size) in bytes.
+    pub fn reserved_disk_size_bytes(self, reserved_disk_size: impl Into<ByteSize>) -> Self {
+        Self {
+            reserved_disk_size: SizeArg::Bytes(reserved_disk_size.into()),
+            ..self
+        }
+    }
+
+    /// Move rotated files, once compressed, into `archive/YYYYMM/` under the
+    /// log dir by their date, instead of keeping them next to the current one.
+    /// Archived files still count in the retention.
+    pub fn archive(self, archive: bool) -> Self {
+        Self { archive, ..self }
+    }
+
+    /// Decompress each archive and compare it with its source before deleting
+    /// the source. Results are recorded in `<component>_<instance_id>.manifest`.
+    pub fn verify_compression(self, verify_compression: bool) -> Self {
+        Self {
+            verify_compression,
+            ..self
+        }
+    }
+
+    /// What to do when compression is enabled with a `rotation_count` of 1.
+    pub fn single_file_compression(self, single_file_compression: SingleFileCompression) -> Self {
+        Self {
+            single_file_compression,
+            ..self
+        }
+    }
+
+    /// Also run retention cleanup every `interval`, not only on rotation, so
+    /// old files are removed even if the appender rarely rotates.
+    pub fn cleanup_interval(self, interval: Duration) -> Self {
+        Self {
+            cleanup_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// CPU niceness of the background disk monitor and compression/cleanup
+    /// threads, Linux only.
+    pub fn maintenance_nice(self, nice: i32) -> Self {
+        Self {
+            maintenance_nice: Some(nice),
+            ..self
+        }
+    }
+
+    /// IO priority of the background disk monitor and compression/cleanup
+    /// threads, Linux only.
+    pub fn maintenance_io_priority(self, io_priority: IoPriority) -> Self {
+        Self {
+            maintenance_io_priority: Some(io_priority),
+            ..self
+        }
+    }
+
+    /// Buffer events in memory up to `size`, e.g. `"64KB"`, instead of a write
+    /// syscall per event. The buffer is written when full, on ERROR events,
+    /// on rotation, on drop and every
+    /// [`buffer_flush_interval`](Self::buffer_flush_interval).
+    pub fn write_buffer(self, size: &'a str) -> Self {
+        Self {
+            write_buffer: Some(SizeArg::Text(size)),
+            ..self
+        }
+    }
+
+    /// [`write_buffer`](Self::write_buffer) in bytes.
+    pub fn write_buffer_bytes(self, size: impl Into<ByteSize>) -> Self {
+        Self {
+            write_buffer: Some(SizeArg::Bytes(size.into())),
+            ..self
+        }
+    }
+
+    /// Gather the events of each `tick`, e.g. 1ms, up to `max_events`, and
+    /// write them with one `write_vectored` call, for components writing
+    /// tens of thousands of lines per second. Replaces the
+    /// [`write_buffer`](Self::write_buffer), written on ERROR events,
+    /// rotation and drop the same way.
+    pub fn batch_writes(self, max_events: usize, tick: Duration) -> Self {
+        Self {
+            batch_events: Some(max_events.max(1)),
+            buffer_flush_interval: tick,
+            ..self
+        }
+    }
+
+    /// Interval of the write buffer flush, 200ms by default.
+    pub fn buffer_flush_interval(self, interval: Duration) -> Self {
+        Self {
+            buffer_flush_interval: interval,
+            ..self
+        }
+    }
+
+    /// When to sync the written events to disk, never by default.
+    pub fn sync_policy(self, sync_policy: SyncPolicy) -> Self {
+        Self {
+            sync_policy,
+            ..self
+        }
+    }
+
+    /// Write the level downgrade and upgrade banners in `language`.
+    pub fn language(self, language: Language) -> Self {
+        Self { language, ..self }
+    }
+
+    /// Decide which events are written under disk pressure with `policy`
+    /// instead of the [`DefaultDiskPolicy`], see [`guard`](crate::guard).
+    pub fn disk_policy(self, policy: impl DiskPolicy) -> Self {
+        Self {
+            disk_policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    /// Log a heartbeat line with process and appender stats every `interval`.
+    pub fn with_heartbeat(self, interval: Duration) -> Self {
+        Self {
+            heartbeat: Some(interval),
+            ..self
+        }
+    }
+
+    /// Log a summary of the disk pressure, see [`DiskPressureStats`], every
+    /// `interval` while events are dropped for lack of space, every minute by
+    /// default. It is logged by the disk monitor, with a reserved disk size
+    /// only.
+    pub fn disk_summary_interval(self, interval: Duration) -> Self {
+        Self {
+            disk_summary_interval: interval,
+            ..self
+        }
+    }
+
+    /// Maintain `<component>_<instance_id>.positions-hints.json` with the
+    /// active file and the rotate, compress and delete events, for log
+    /// shippers following the files.
+    pub fn position_hints(self, position_hints: bool) -> Self {
+        Self {
+            position_hints,
+            ..self
+        }
+    }
+
+    /// Flush the log file to disk through a scheduler shared with other
+    /// appenders.
+    pub fn flush_scheduler(self, scheduler: FlushScheduler) -> Self {
+        Self {
+            flush_scheduler: Some(scheduler),
+            ..self
+        }
+    }
+
+    /// Inject filesystem faults into rotate, compress and delete operations.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(self, faults: FaultInjector) -> Self {
+        Self { faults, ..self }
+    }
+
+    /// Call `f` on each switch to a new file, e.g. to upload the closed file or
+    /// count rotations.
+    ///
+    /// `f` runs on the maintenance thread before the old file is compressed or
+    /// deleted, so it is still at `old_path`; a slow callback delays the
+    /// maintenance of the appender, not the writes.
+    pub fn on_rotate(self, f: impl Fn(RotationEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            on_rotate: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Encrypt rotated files, after compression, to `<name>.enc` with the key
+    /// of `key_provider`, see [`encrypt`](crate::encrypt).
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(self, key_provider: impl KeyProvider + 'static) -> Self {
+        Self {
+            encryption: Some(Arc::new(key_provider)),
+            ..self
+        }
+    }
+
+    /// End each line with a hash chained from the previous line, and write a
+    /// `.sha256` digest of each rotated file, see [`chain`](crate::chain).
+    #[cfg(feature = "hash-chain")]
+    pub fn hash_chain(self, hash_chain: bool) -> Self {
+        Self { hash_chain, ..self }
+    }
+
+    /// Use the disk monitor and maintenance thread of `shared`, made for the
+    /// same log dir, instead of spawning new ones. The maintenance settings of
+    /// this builder are then ignored.
+    pub fn shared_maintenance(self, shared: &SharedMaintenance) -> Self {
+        Self {
+            shared_maintenance: Some(shared.clone()),
+            ..self
+        }
+    }
+
+    /// Take rotation times and file ages from `clock` instead of the system
+    /// clock.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn clock(self, clock: crate::testing::TestClock) -> Self {
+        Self {
+            clock: clock.wall_clock(),
+            ..self
+        }
+    }
+
+    /// Write to `dir`, e.g. on another volume, while the disk of the log dir
+    /// is below the reserved size or read-only, and back to the log dir when
+    /// space recovers, instead of dropping events.
+    ///
+    /// The fallback files have the same names, rotation and retention, and
+    /// the disk pressure policy applies to `dir` in turn.
+    pub fn fallback_dir(self, dir: impl AsRef<Path>) -> Self {
+        Self {
+            fallback_dir: Some(dir.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    /// The builder of the appender of the fallback dir, with the same files
+    /// settings, its rotated files handled by the maintenance thread of
+    /// `event_tx`.
+    fn fallback(
+        &self,
+        dir: PathBuf,
+        event_tx: &flume::Sender<MaintenanceEvent>,
+    ) -> RollingFileAppenderBuilder<'a> {
+        RollingFileAppenderBuilder {
+            rotation_count: self.rotation_count,
+            rotation_size: self.rotation_size,
+            max_total_size: self.max_total_size,
+            max_age_days: self.max_age_days,
+            compress: self.compress,
+            compression: self.compression,
+            reserved_disk_size: self.reserved_disk_size,
+            maintenance_nice: self.maintenance_nice,
+            maintenance_io_priority: self.maintenance_io_priority,
+            single_file_compression: self.single_file_compression,
+            cleanup_interval: self.cleanup_interval,
+            disk_summary_interval: self.disk_summary_interval,
+            verify_compression: self.verify_compression,
+            archive: self.archive,
+            rotation_interval: self.rotation_interval,
+            rotate_at: self.rotate_at,
+            position_hints: self.position_hints,
+            extension: self.extension,
+            write_buffer: self.write_buffer,
+            batch_events: self.batch_events,
+            buffer_flush_interval: self.buffer_flush_interval,
+            language: self.language,
+            disk_policy: self.disk_policy.clone(),
+            sync_policy: self.sync_policy,
+            clock: self.clock.clone(),
+            #[cfg(feature = "fault-injection")]
+            faults: self.faults.clone(),
+            on_rotate: self.on_rotate.clone(),
+            #[cfg(feature = "encryption")]
+            encryption: self.encryption.clone(),
+            #[cfg(feature = "hash-chain")]
+            hash_chain: self.hash_chain,
+            maintenance: Some(event_tx.clone()),
+            ..RollingFileAppender::builder(dir, &self.component_name, self.instance_id)
+        }
+    }
+
+    pub fn build(mut self) -> Result<RollingFileAppender> {
+        // init log dir
+        if !self.log_dir.is_dir() {
+            fs::create_dir_all(&self.log_dir).context(CreateLogDirSnafu {
+                path: &self.log_dir,
+            })?;
+        }
+        if !self.log_dir.is_absolute() {
+            self.log_dir = self
+                .log_dir
+                .canonicalize()
+                .context(GetLogAbsolutePathSnafu)?;
+        }
+        #[cfg(windows)]
+        {
+            self.log_dir = strip_verbatim(&self.log_dir);
+        }
+
+        if self.compress && self.rotation_count == 1 {
+            match self.single_file_compression {
+                SingleFileCompression::Skip => {
+                    eprintln!("[RollingFileAppender] rotation_count is 1, compression disabled");
+                    self.compress = false;
+                }
+                SingleFileCompression::CompressThenDeleteOlder => {
+                    eprintln!("[RollingFileAppender] compression enabled, rotation_count raised from 1 to 2");
+                    self.rotation_count = 2;
+                }
+            }
+        }
+
+        let rotation = Rotation {
+            interval: self.rotation_interval,
+            at: self.rotate_at,
+            file_size: self.rotation_size.bytes()?,
+        };
+        let buffer = match (self.batch_events, self.write_buffer) {
+            (Some(max_events), _) => Some(Arc::new(WriteBuffer::batch(max_events))),
+            (None, Some(size)) => Some(Arc::new(WriteBuffer::new(size.bytes()? as usize))),
+            (None, None) => None,
+        };
+
+        // current max seq id
+        let now = self.clock.now();
+        let mut max_seq_id = max_seq_id(
+            &self.component_name,
+            self.instance_id,
+            self.extension,
+            &self.log_dir,
+            rotation.period(now),
+        )?;
+
+        // init log file
+        let today = rotation.format(now);
+        let (file_path, file) = loop {
+            let filename = if max_seq_id == 0 {
+                format!(
+                    "{}_{}_{}.{}",
+                    &self.component_name, self.instance_id, today, self.extension
+                )
+            } else {
+                format!(
+                    "{}_{}_{}.{}.{}",
+                    &self.component_name, self.instance_id, today, self.extension, max_seq_id
+                )
+            };
+            let file_path = self.log_dir.join(&filename);
+            match create_file(&file_path)? {
+                Some(file) => break (file_path, file),
+                None => max_seq_id += 1,
+            }
+        };
+
+        // next rotate time
+        let next_date = rotation.next_timestamp(now);
+
+        let state = State {
+            max_seq_id,
+            file_path,
+            file_size: 0,
+        };
+
+        // calc disk available space
+        let mut shared_monitor = None;
+        let owns_maintenance = self.shared_maintenance.is_none() && self.maintenance.is_none();
+        let SharedMaintenance {
+            disk_available_space,
+            read_only,
+            event_tx,
+            summaries,
+            stop,
+            stopped,
+            ..
+        } = match self.shared_maintenance.take() {
+            Some(shared) => {
+                ensure!(
+                    shared.log_dir == self.log_dir,
+                    InvalidConfigSnafu {
+                        key: "shared_maintenance",
+                        reason: format!("made for {}", shared.log_dir.display()),
+                    }
+                );
+                // the threads of this appender stop on their own
+                shared_monitor = Some(shared.stop.clone());
+                let (stop, stopped) = flume::bounded(0);
+                SharedMaintenance {
+                    stop,
+                    stopped,
+                    ..shared
+                }
+            }
+            None => match self.maintenance.take() {
+                // a fallback has a disk monitor of its own
+                Some(event_tx) => SharedMaintenance::monitor(
+                    self.log_dir.clone(),
+                    self.maintenance_nice,
+                    self.maintenance_io_priority,
+                    event_tx,
+                )?,
+                None => SharedMaintenance::spawn(
+                    self.log_dir.clone(),
+                    self.maintenance_nice,
+                    self.maintenance_io_priority,
+                    1,
+                )?,
+            },
+        };
+        let fallback = match self.fallback_dir.take() {
+            Some(dir) => Some(Box::new(self.fallback(dir, &event_tx).build()?)),
+            None => None,
+        };
+
+        let reserved_disk_size = self.reserved_disk_size.bytes()?;
+        let disk_pressure = Arc::new(DiskPressure::new());
+        if let Some(interval) = self.heartbeat {
+            let heartbeat = Heartbeat {
+                started: Instant::now(),
+                disk_available_space: disk_available_space.clone(),
+                disk_pressure: disk_pressure.clone(),
+                reserved_disk_size,
+            };
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                while !wait_stop(&stopped, interval) {
+                    heartbeat.emit();
+                }
+            });
+        }
+        // no disk pressure without reserved size
+        let disk_summary = (reserved_disk_size > 0).then(|| {
+            let summary = Arc::new(DiskSummary {
+                disk_available_space: disk_available_space.clone(),
+                disk_pressure: disk_pressure.clone(),
+                reserved_disk_size,
+                interval: self.disk_summary_interval,
+            });
+            summaries.add(&summary);
+            summary
+        });
+
+        let position_hints = self.position_hints.then(|| {
+            Arc::new(PositionHints::new(
+                &self.log_dir,
+                &self.component_name,
+                self.instance_id,
+                self.extension,
+            ))
+        });
+        let max_total_size = self.max_total_size.map(SizeArg::bytes).transpose()?;
+        let config = Config {
+            log_dir: self.log_dir,
+            instance_id: self.instance_id,
+            extension: self.extension,
+            rotation,
+            compress: self.compress,
+            compression: self.compression,
+            component_name: self.component_name,
+            rotate_count: self.rotation_count,
+            verify_compression: self.verify_compression,
+            archive: self.archive,
+            max_total_size,
+            max_age_days: self.max_age_days,
+            position_hints,
+            usage: Arc::new(Usage::new()),
+            metrics: Arc::default(),
+            clock: self.clock,
+            #[cfg(feature = "fault-injection")]
+            faults: self.faults,
+            on_rotate: self.on_rotate,
+            #[cfg(feature = "encryption")]
+            encryption: self.encryption,
+            #[cfg(feature = "hash-chain")]
+            hash_chain: self.hash_chain,
+        };
+        if let Some(hints) = &config.position_hints {
+            hints.active(&state.file_path).ok();
+        }
+
+        // 处理旧文件
+        event_tx
+            .send(MaintenanceEvent::OldFiles(Box::new(HandleOldFileEvent {
+                config: config.clone(),
+                compress_file: None,
+                rotated: None,
+                released: None,
+            })))
+            .ok();
+
+        // 定时清理旧文件
+        if let Some(interval) = self.cleanup_interval {
+            let event_tx = event_tx.downgrade();
+            let config = config.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || loop {
+                if wait_stop(&stopped, interval) {
+                    break;
+                }
+                let Some(event_tx) = event_tx.upgrade() else {
+                    break;
+                };
+                event_tx
+                    .send(MaintenanceEvent::OldFiles(Box::new(HandleOldFileEvent {
+                        config: config.clone(),
+                        compress_file: None,
+                        rotated: None,
+                        released: None,
+                    })))
+                    .ok();
+            });
+        }
+
+        let this = RollingFileAppender {
+            config,
+            disk: DiskCheck::new(
+                disk_available_space,
+                reserved_disk_size,
+                disk_pressure.clone(),
+            )
+            .with_language(self.language)
+            .with_policy(self.disk_policy),
+            fallback,
+            on_fallback: AtomicBool::new(false),
+            read_only,
+            event_tx,
+            stop: Mutex::new(Some(stop)),
+            _shared_monitor: shared_monitor,
+            owns_maintenance,
+            state: RwLock::new(state),
+            next_date: AtomicI64::new(next_date),
+            written: AtomicU64::new(0),
+            next_check: AtomicI64::new(0),
+            writer: Arc::new(CurrentFile::new(file)),
+            buffer,
+            durability: Arc::new(Durability::new(self.sync_policy)),
+            flush_scheduler: self.flush_scheduler,
+            disk_pressure,
+            #[cfg(feature = "hash-chain")]
+            chain: self.hash_chain.then(HashChain::new),
+            _disk_summary: disk_summary,
+            _instance_lock: self.instance_lock,
+        };
+        if let Some(scheduler) = &this.flush_scheduler {
+            scheduler.register(&this.writer);
+        }
+        if let Some(buffer) = &this.buffer {
+            let interval = self.buffer_flush_interval;
+            let (buffer, writer) = (Arc::downgrade(buffer), Arc::downgrade(&this.writer));
+            let read_only = this.read_only.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || loop {
+                if wait_stop(&stopped, interval) {
+                    break;
+                }
+                let (Some(buffer), Some(writer)) = (buffer.upgrade(), writer.upgrade()) else {
+                    break;
+                };
+                buffer.drain(&writer.load(), &read_only).ok();
+            });
+        }
+        if let SyncPolicy::Every(interval) = self.sync_policy {
+            let buffer = this.buffer.as_ref().map(Arc::downgrade);
+            let (durability, writer) = (
+                Arc::downgrade(&this.durability),
+                Arc::downgrade(&this.writer),
+            );
+            let read_only = this.read_only.clone();
+            thread::spawn(move || loop {
+                if wait_stop(&stopped, interval) {
+                    break;
+                }
+                let (Some(durability), Some(writer)) = (durability.upgrade(), writer.upgrade())
+                else {
+                    break;
+                };
+                let file = writer.load();
+                if let Some(buffer) = buffer.as_ref().and_then(Weak::upgrade) {
+                    buffer.drain(&file, &read_only).ok();
+                }
+                durability.sync(&file).ok();
+            });
+        }
+
+        Ok(this)
+    }
+}
+
+/// A disk monitor and a maintenance thread, compressing and deleting rotated
+/// files, shared by the appenders of several components in one log dir.
+///
+/// Without it, each appender spawns its own.
+///
+/// ```ignore
+/// let shared = SharedMaintenance::new("/var/log/taos")?;
+/// let taosx = RollingFileAppender::builder("/var/log/taos", "taosx", 1)
+///     .shared_maintenance(&shared)
+///     .build()?;
+/// let agent = RollingFileAppender::builder("/var/log/taos", "taosx-agent", 1)
+///     .shared_maintenance(&shared)
+///     .build()?;
+/// ```
+#[derive(Clone)]
+pub struct SharedMaintenance {
+    log_dir: PathBuf,
+    disk_available_space: Arc<AtomicU64>,
+    read_only: Arc<ReadOnly>,
+    event_tx: flume::Sender<MaintenanceEvent>,
+    summaries: Arc<DiskSummaries>,
+    /// the disk monitor stops once all clones are dropped
+    stop: flume::Sender<()>,
+    stopped: flume::Receiver<()>,
+}
+
+impl SharedMaintenance {
+    pub fn new(log_dir: impl AsRef<Path>) -> Result<Self> {
+        Self::with_priority(log_dir, None, None)
+    }
+
+    /// Like [`new`](Self::new), with the nice value and I/O priority of
+    /// [`maintenance_nice`](RollingFileAppenderBuilder::maintenance_nice) and
+    /// [`maintenance_io_priority`](RollingFileAppenderBuilder::maintenance_io_priority).
+    pub fn with_priority(
+        log_dir: impl AsRef<Path>,
+        nice: Option<i32>,
+        io_priority: Option<IoPriority>,
+    ) -> Result<Self> {
+        // the same path as the builder makes of it
+        let mut log_dir = log_dir.as_ref().to_path_buf();
+        if !log_dir.is_dir() {
+            fs::create_dir_all(&log_dir).context(CreateLogDirSnafu { path: &log_dir })?;
+        }
+        if !log_dir.is_absolute() {
+            log_dir = log_dir.canonicalize().context(GetLogAbsolutePathSnafu)?;
+        }
+        #[cfg(windows)]
+        {
+            log_dir = strip_verbatim(&log_dir);
+        }
+        // appenders of several components queue their rotated files
+        Self::spawn(log_dir, nice, io_priority, 16)
+    }
+
+    fn spawn(
+        log_dir: PathBuf,
+        nice: Option<i32>,
+        io_priority: Option<IoPriority>,
+        queue: usize,
+    ) -> Result<Self> {
+        let (event_tx, event_rx) = flume::bounded(queue);
+        let this = Self::monitor(log_dir, nice, io_priority, event_tx)?;
+        thread::spawn(move || {
+            enter_maintenance();
+            set_thread_priority(nice, io_priority);
+            while let Ok(event) = event_rx.recv() {
+                match event {
+                    MaintenanceEvent::OldFiles(event) => {
+                        let HandleOldFileEvent {
+                            config,
+                            compress_file,
+                            rotated,
+                            released,
+                        } = *event;
+                        // the old file is complete once no writer appends to it
+                        if let Some(released) = released {
+                            released.wait();
+                        }
+                        if let (Some(on_rotate), Some(rotated)) = (&config.on_rotate, rotated) {
+                            on_rotate(rotated);
+                        }
+                        handle_old_files(config, compress_file).ok();
+                    }
+                    MaintenanceEvent::Barrier { ack, stop } => {
+                        ack.send(()).ok();
+                        if stop {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(this)
+    }
+
+    /// A disk monitor of `log_dir`, the rotated files being handled by the
+    /// maintenance thread of `event_tx`.
+    fn monitor(
+        log_dir: PathBuf,
+        nice: Option<i32>,
+        io_priority: Option<IoPriority>,
+        event_tx: flume::Sender<MaintenanceEvent>,
+    ) -> Result<Self> {
+        let mut disk = find_disk(&log_dir)?;
+        let disk_available_space = Arc::new(AtomicU64::new(disk.available_space()));
+        let read_only = Arc::new(ReadOnly {
+            log_dir: log_dir.clone(),
+            read_only: AtomicBool::new(false),
+        });
+        read_only.set(sys::is_read_only(&log_dir));
+        let summaries = Arc::new(DiskSummaries::default());
+        let (stop, stopped) = flume::bounded(0);
+        thread::spawn({
+            let disk_available_space = disk_available_space.clone();
+            let read_only = Arc::downgrade(&read_only);
+            let summaries = summaries.clone();
+            let stopped = stopped.clone();
+            move || {
+                enter_maintenance();
+                set_thread_priority(nice, io_priority);
+                let mut refresh = Instant::now() + DISK_REFRESH_INTERVAL;
+                loop {
+                    let wake = summaries.next_due().map_or(refresh, |due| due.min(refresh));
+                    if wait_stop(&stopped, wake.saturating_duration_since(Instant::now())) {
+                        break;
+                    }
+                    if Instant::now() >= refresh {
+                        refresh = Instant::now() + DISK_REFRESH_INTERVAL;
+                        disk.refresh();
+                        disk_available_space
+                            .store(disk.available_space(), atomic::Ordering::SeqCst);
+                        let Some(read_only) = read_only.upgrade() else {
+                            break;
+                        };
+                        read_only.set(sys::is_read_only(&read_only.log_dir));
+                    }
+                    summaries.emit_due();
+                }
+            }
+        });
+
+        Ok(Self {
+            log_dir,
+            disk_available_space,
+            read_only,
+            event_tx,
+            summaries,
+            stop,
+            stopped,
+        })
+    }
+}
+
+/// Wait `timeout` for the sender of `stopped` to be dropped, `true` if it was.
+fn wait_stop(stopped: &flume::Receiver<()>, timeout: Duration) -> bool {
+    !matches!(
+        stopped.recv_timeout(timeout),
+        Err(flume::RecvTimeoutError::Timeout)
+    )
+}
+
+struct Heartbeat {
+    started: Instant,
+    disk_available_space: Arc<AtomicU64>,
+    disk_pressure: Arc<DiskPressure>,
+    reserved_disk_size: u64,
+}
+
+impl Heartbeat {
+    fn emit(&self) {
+        let uptime = sys::process_uptime().unwrap_or_else(|| self.started.elapsed());
+        let disk = self.disk_pressure.stats(
+            self.disk_available_space.load(atomic::Ordering::SeqCst),
+            self.reserved_disk_size,
+        );
+        tracing::info!(
+            target: "taoslog::heartbeat",
+            duration_uptime = uptime.as_millis() as u64,
+            bytes_rss = sys::rss_bytes(),
+            open_fds = sys::open_fds(),
+            bytes_disk_available = disk.available_space,
+            disk_stage = ?disk.stage,
+            dropped_downgrade = disk.dropped_downgrade,
+            dropped_stopped = disk.dropped_stopped,
+            "heartbeat"
+        );
+    }
+}
+
+struct DiskSummary {
+    disk_available_space: Arc<AtomicU64>,
+    disk_pressure: Arc<DiskPressure>,
+    reserved_disk_size: u64,
+    interval: Duration,
+}
+
+impl DiskSummary {
+    /// Log the disk pressure stats unless the stage is normal, at ERROR, the
+    /// only level written in the downgrade stage.
+    fn emit(&self) {
+        let disk = self.disk_pressure.stats(
+            self.disk_available_space.load(atomic::Ordering::SeqCst),
+            self.reserved_disk_size,
+        );
+        if disk.stage == DiskStage::Normal {
+            return;
+        }
+        tracing::error!(
+            target: internal::TARGET,
+            disk_stage = ?disk.stage,
+            since = %disk.since.to_rfc3339(),
+            bytes_disk_available = disk.available_space,
+            bytes_downgrade_threshold = disk.downgrade_threshold,
+            bytes_stop_threshold = disk.stop_threshold,
+            dropped_downgrade = disk.dropped_downgrade,
+            dropped_stopped = disk.dropped_stopped,
+            "disk pressure"
+        );
+    }
+}
+
+/// The disk summaries of the appenders sharing a disk monitor, with the
+/// time each one is due.
+#[derive(Default)]
+struct DiskSummaries(Mutex<Vec<(Weak<DiskSummary>, Instant)>>);
+
+impl DiskSummaries {
+    fn add(&self, summary: &Arc<DiskSummary>) {
+        let due = Instant::now() + summary.interval;
+        self.0.lock().push((Arc::downgrade(summary), due));
+    }
+
+    /// The first due summary of the appenders still alive.
+    fn next_due(&self) -> Option<Instant> {
+        let mut summaries = self.0.lock();
+        summaries.retain(|(summary, _)| summary.strong_count() > 0);
+        summaries.iter().map(|(_, due)| *due).min()
+    }
+
+    fn emit_due(&self) {
+        let now = Instant::now();
+        for (summary, due) in self.0.lock().iter_mut() {
+            if *due > now {
+                continue;
+            }
+            if let Some(summary) = summary.upgrade() {
+                summary.emit();
+                *due = now + summary.interval;
+            }
+        }
+    }
+}
+
+/// A log file appender rotating by time and size.
+///
+/// Log files are only opened with `O_APPEND`, and each event, or batch of
+/// [buffered](RollingFileAppenderBuilder::write_buffer) events, is written with
+/// a single `write`, so:
+///
+/// - lines from other handles on the same file, e.g. a sidecar writing to it,
+///   are never overwritten nor interleaved within a line;
+/// - after an external truncation, lines land at the new end of file instead
+///   of the old offset, leaving no hole;
+/// - a deleted file is recreated under a free name on a write within a
+///   second, never reusing an existing file;
+/// - a rotated file is compressed to a new archive, and only deleted once the
+///   archive is synced to disk, so a crash leaves the source, the archive, or
+///   both.
+pub struct RollingFileAppender {
+    config: Config,
+    disk: DiskCheck,
+    fallback: Option<Box<RollingFileAppender>>,
+    on_fallback: AtomicBool,
+    read_only: Arc<ReadOnly>,
+    event_tx: flume::Sender<MaintenanceEvent>,
+    /// dropped to stop the threads of the appender
+    stop: Mutex<Option<flume::Sender<()>>>,
+    /// keeps the disk monitor of a [`SharedMaintenance`] running
+    _shared_monitor: Option<flume::Sender<()>>,
+    /// the maintenance thread stops with the appender, neither shared nor
+    /// the one of the primary appender of a fallback
+    owns_maintenance: bool,
+    state: RwLock<State>,
+    /// next rotation by time, changed under the state lock
+    next_date: AtomicI64,
+    /// bytes written to the current file, for the rotation by size
+    written: AtomicU64,
+    /// next check of the current file on disk, changed under the state lock
+    next_check: AtomicI64,
+    writer: Arc<CurrentFile>,
+    buffer: Option<Arc<WriteBuffer>>,
+    durability: Arc<Durability>,
+    flush_scheduler: Option<FlushScheduler>,
+    disk_pressure: Arc<DiskPressure>,
+    #[cfg(feature = "hash-chain")]
+    chain: Option<HashChain>,
+    /// logged by the disk monitor while kept
+    _disk_summary: Option<Arc<DiskSummary>>,
+    _instance_lock: Option<InstanceLock>,
+}
+
+impl RollingFileAppender {
+    pub fn builder<'a>(
+        log_dir: impl AsRef<Path>,
+        component: &str,
+        instance_id: u8,
+    ) -> RollingFileAppenderBuilder<'a> {
+        RollingFileAppenderBuilder {
+            log_dir: log_dir.as_ref().to_path_buf(),
+            rotation_count: 30,
+            rotation_size: SizeArg::Text("1GB"),
+            max_total_size: None,
+            max_age_days: None,
+            compress: false,
+            compression: Compression::default(),
+            reserved_disk_size: SizeArg::Text("2GB"),
+            maintenance_nice: None,
+            maintenance_io_priority: None,
+            heartbeat: None,
+            disk_summary_interval: Duration::from_secs(60),
+            single_file_compression: SingleFileCompression::default(),
+            cleanup_interval: None,
+            verify_compression: false,
+            archive: false,
+            rotation_interval: RotationInterval::default(),
+            rotate_at: NaiveTime::MIN,
+            flush_scheduler: None,
+            position_hints: false,
+            instance_lock: None,
+            component_name: component.to_string(),
+            instance_id,
+            extension: "log",
+            write_buffer: None,
+            batch_events: None,
+            buffer_flush_interval: DEFAULT_BUFFER_FLUSH_INTERVAL,
+            language: Language::default(),
+            disk_policy: Arc::new(DefaultDiskPolicy),
+            sync_policy: SyncPolicy::default(),
+            clock: WallClock::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: FaultInjector::default(),
+            on_rotate: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            #[cfg(feature = "hash-chain")]
+            hash_chain: false,
+            shared_maintenance: None,
+            maintenance: None,
+            fallback_dir: None,
+        }
+    }
+
+    /// Like [`builder`](Self::builder), with the lowest instance id not used by
+    /// a running process.
+    ///
+    /// The id is locked through `<component>_<instance_id>.pid` in `log_dir`,
+    /// holding the process id, until the appender is dropped or the process
+    /// exits.
+    pub fn builder_auto_instance<'a>(
+        log_dir: impl AsRef<Path>,
+        component: &str,
+    ) -> Result<RollingFileAppenderBuilder<'a>> {
+        let lock = InstanceLock::acquire(log_dir.as_ref(), component)?;
+        let instance_id = lock.instance_id();
+        Ok(RollingFileAppenderBuilder {
+            instance_lock: Some(lock),
+            ..Self::builder(log_dir, component, instance_id)
+        })
+    }
+
+    pub fn component(&self) -> &str {
+        &self.config.component_name
+    }
+
+    pub fn instance_id(&self) -> u8 {
+        self.config.instance_id
+    }
+
+    /// Wrap `inner` to apply the disk pressure policy of this appender to it.
+    ///
+    /// The guard shares the disk monitor and drop counters of the appender.
+    pub fn disk_guard<M>(&self, inner: M) -> DiskGuard<M> {
+        DiskGuard::with_check(inner, self.disk.share())
+    }
+
+    pub fn stats(&self) -> AppenderStats {
+        AppenderStats {
+            disk: self.disk.stats(),
+            read_only: self.read_only.get(),
+            usage: self.config.usage.stats(Settings {
+                rotation_size: self.config.rotation.file_size,
+                rotation_count: self.config.rotate_count,
+            }),
+            syncs: self.durability.syncs.load(atomic::Ordering::Relaxed),
+        }
+    }
+
+    /// Write a marker line in the language of the banners, whatever the level
+    /// of the layer.
+    #[cfg(unix)]
+    pub(crate) fn write_marker(&self, marker: impl FnOnce(Language) -> String) {
+        let mut writer = tracing_subscriber::fmt::MakeWriter::make_writer(self);
+        writer
+            .write_all(marker(self.disk.language()).as_bytes())
+            .ok();
+        writer.flush().ok();
+    }
+
+    /// Switch to a new file now, e.g. on request of an operator or of a
+    /// logrotate-style tool, the old file being compressed and cleaned up as
+    /// on any rotation.
+    pub fn rotate_now(&self, reason: RotationReason) -> Result<()> {
+        let mut state = self.state.write();
+        let now = self.config.clock.now();
+        self.next_seq_file(&mut state, now, reason)
+    }
+
+    /// Path of the file being written.
+    pub fn current_file_path(&self) -> PathBuf {
+        self.state.read().file_path.clone()
+    }
+
+    /// Size of the file being written, without the buffered events.
+    pub fn current_file_size(&self) -> u64 {
+        self.writer
+            .load()
+            .metadata()
+            .map_or(0, |metadata| metadata.len())
+    }
+
+    /// Available space of the disk holding the log dir, as last refreshed by
+    /// the disk monitor.
+    pub fn available_disk_space(&self) -> u64 {
+        self.disk.available_space.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Events are dropped for lack of disk space, see [`DiskStage`].
+    pub fn is_downgraded(&self) -> bool {
+        self.disk.stage() != DiskStage::Normal
+    }
+
+    /// Rotated files of this appender, archived ones included, oldest first.
+    pub fn list_rotated_files(&self) -> Result<Vec<PathBuf>> {
+        let current = self.current_file_path();
+        let mut files: Vec<_> = log_files(&self.config.log_dir)?
+            .into_iter()
+            .filter(|(path, _)| *path != current)
+            .filter_map(|(path, _)| {
+                let res = parse_filename(
+                    &self.config.component_name,
+                    self.config.instance_id,
+                    self.config.extension,
+                    path.file_name()?.to_str()?,
+                )?;
+                Some((path, res))
+            })
+            .collect();
+        files.sort_by(|(_, a), (_, b)| filename_cmp(a, b));
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// A handle on the write, drop, rotation and compression counters.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            component: self.config.component_name.clone(),
+            instance_id: self.config.instance_id,
+            counters: self.config.metrics.clone(),
+            pressure: self.disk_pressure.clone(),
+        }
+    }
+
+    fn create_file(&self, path: &Path) -> Result<Option<File>> {
+        #[cfg(feature = "fault-injection")]
+        self.config
+            .faults
+            .check(FaultPoint::Rotate)
+            .context(OpenLogFileSnafu { path })?;
+        create_file(path)
+    }
+
+    /// Flush and sync the current file, stop the threads of the appender and
+    /// wait for the rotated files queued for compression and cleanup.
+    ///
+    /// For processes exiting right after, as the appender of the global
+    /// subscriber is never dropped. Later events are still written, without
+    /// rotated files being compressed or cleaned up. Done on drop too.
+    pub fn shutdown(&self) -> std::io::Result<()> {
+        if let Some(fallback) = &self.fallback {
+            fallback.shutdown()?;
+        }
+        self.flush_buffer();
+        let synced = self.writer.load().sync_all();
+        if self.stop.lock().take().is_some() && !internal::in_maintenance() {
+            let (ack, done) = flume::bounded(1);
+            let stop = self.owns_maintenance;
+            if self
+                .event_tx
+                .send(MaintenanceEvent::Barrier { ack, stop })
+                .is_ok()
+            {
+                done.recv().ok();
+            }
+        }
+        synced
+    }
+
+    /// The appender of the [`fallback_dir`](RollingFileAppenderBuilder::fallback_dir).
+    pub fn fallback(&self) -> Option<&RollingFileAppender> {
+        self.fallback.as_deref()
+    }
+
+    /// The fallback appender while the log dir is short of space or
+    /// read-only, and the fallback dir is not.
+    fn active_fallback(&self) -> Option<&RollingFileAppender> {
+        let fallback = self.fallback.as_deref()?;
+        let active = (self.disk.stage() != DiskStage::Normal || self.read_only.get())
+            && fallback.disk.stage() == DiskStage::Normal
+            && !fallback.read_only.get();
+        if self.on_fallback.swap(active, atomic::Ordering::Relaxed) != active {
+            let (from, to) = (&self.config.log_dir, &fallback.config.log_dir);
+            if active {
+                eprintln!(
+                    "[RollingFileAppender] {} short of space, writing to {}",
+                    from.display(),
+                    to.display()
+                );
+            } else {
+                eprintln!(
+                    "[RollingFileAppender] {} recovered, writing back from {}",
+                    from.display(),
+                    to.display()
+                );
+            }
+        }
+        active.then_some(fallback)
+    }
+
+    /// Write the buffered events to the current file.
+    fn flush_buffer(&self) {
+        if let Some(buffer) = &self.buffer {
+            buffer.drain(&self.writer.load(), &self.read_only).ok();
+        }
+    }
+
+    /// Switch the writers to `file` holding `len` bytes, under the state lock
+    /// not to race with another rotation.
+    fn swap_file(&self, _state: &mut State, file: File, len: u64) -> Released {
+        // lines are sealed and written under the chain lock, so the new file
+        // starts a new chain
+        #[cfg(feature = "hash-chain")]
+        let _last = self.chain.as_ref().map(|chain| chain.reset());
+        let released = self.writer.swap(file);
+        self.written.store(len, atomic::Ordering::Relaxed);
+        released
+    }
+
+    /// Switch to the next sequence number of the period.
+    fn next_seq_file(
+        &self,
+        state: &mut State,
+        now: DateTime<Local>,
+        reason: RotationReason,
+    ) -> Result<()> {
+        // 创建新文件
+        let mut max_seq_id = state.max_seq_id + 1;
+        let (filename, file) = loop {
+            let filename = format!(
+                "{}_{}_{}.{}.{}",
+                self.config.component_name,
+                self.config.instance_id,
+                self.config.rotation.format(now),
+                self.config.extension,
+                max_seq_id
+            );
+            let filename = self.config.log_dir.join(filename);
+            match self.create_file(&filename)? {
+                Some(file) => break (filename, file),
+                None => max_seq_id += 1,
+            }
+        };
+        state.max_seq_id = max_seq_id;
+        self.config.usage.rotated(reason == RotationReason::Size);
+        self.config.metrics.rotated(reason);
+        self.rotated(state, filename, file, reason);
+        Ok(())
+    }
+
+    /// Switch to the new file `filename` of a rotation, the old one being
+    /// compressed once released by the writers.
+    fn rotated(&self, state: &mut State, filename: PathBuf, file: File, reason: RotationReason) {
+        if self.config.compress {
+            self.config.metrics.compression_queued();
+        }
+        if let Some(hints) = &self.config.position_hints {
+            hints.rotated(&state.file_path, &filename).ok();
+        }
+        // the old file gets the buffered events
+        self.flush_buffer();
+        let released = self.swap_file(state, file, 0);
+        // 处理旧文件
+        self.event_tx
+            .send(MaintenanceEvent::OldFiles(Box::new(HandleOldFileEvent {
+                config: self.config.clone(),
+                compress_file: Some(state.file_path.clone()),
+                rotated: Some(RotationEvent {
+                    old_path: state.file_path.clone(),
+                    new_path: filename.clone(),
+                    reason,
+                }),
+                released: Some(released),
+            })))
+            .ok();
+        state.file_path = filename;
+        state.file_size = 0;
+    }
+
+    /// Switch to `file`, at `filename`, in place of a deleted or replaced
+    /// current file.
+    fn reopened(&self, state: &mut State, filename: PathBuf, file: File, len: u64) {
+        if let Some(hints) = &self.config.position_hints {
+            hints.active(&filename).ok();
+        }
+        self.config.metrics.rotated(RotationReason::MissingFile);
+        let released = self.swap_file(state, file, len);
+        if self.config.on_rotate.is_some() {
+            self.event_tx
+                .send(MaintenanceEvent::OldFiles(Box::new(HandleOldFileEvent {
+                    config: self.config.clone(),
+                    compress_file: None,
+                    rotated: Some(RotationEvent {
+                        old_path: state.file_path.clone(),
+                        new_path: filename.clone(),
+                        reason: RotationReason::MissingFile,
+                    }),
+                    released: Some(released),
+                })))
+                .ok();
+        }
+        state.file_path = filename;
+        state.file_size = len;
+    }
+
+    /// Rotate if due. Writes only check the atomics, the state lock is taken
+    /// when a rotation is due and the file on disk checked once a second.
+    fn rotate(&self) -> Result<()> {
+        let now = self.config.clock.now();
+        let due = now.timestamp() >= self.next_date.load(atomic::Ordering::Relaxed)
+            || self.written.load(atomic::Ordering::Relaxed) >= self.config.rotation.file_size
+            || now.timestamp() >= self.next_check.load(atomic::Ordering::Relaxed);
+        if !due {
+            return Ok(());
+        }
+        let mut state = self.state.write();
+
+        // rotate by time, unless another writer just did
+        if now.timestamp() >= self.next_date.load(atomic::Ordering::Relaxed) {
+            state.max_seq_id = 0;
+            let (filename, file) = loop {
+                // 创建新文件
+                let filename = if state.max_seq_id == 0 {
+                    format!(
+                        "{}_{}_{}.{}",
+                        self.config.component_name,
+                        self.config.instance_id,
+                        self.config.rotation.format(now),
+                        self.config.extension
+                    )
+                } else {
+                    format!(
+                        "{}_{}_{}.{}.{}",
+                        self.config.component_name,
+                        self.config.instance_id,
+                        self.config.rotation.format(now),
+                        self.config.extension,
+                        state.max_seq_id
+                    )
+                };
+                let filename = self.config.log_dir.join(filename);
+                match self.create_file(&filename)? {
+                    Some(file) => break (filename, file),
+                    None => state.max_seq_id += 1,
+                }
+            };
+
+            self.next_date.store(
+                self.config.rotation.next_timestamp(now),
+                atomic::Ordering::Relaxed,
+            );
+            self.config.usage.rotated(false);
+            self.config.metrics.rotated(RotationReason::Time);
+            self.rotated(&mut state, filename, file, RotationReason::Time);
+            return Ok(());
+        }
+
+        // rotate by size, never with size rotation disabled by u64::MAX
+        if self.written.load(atomic::Ordering::Relaxed) >= self.config.rotation.file_size {
+            return self.next_seq_file(&mut state, now, RotationReason::Size);
+        }
+        if now.timestamp() < self.next_check.load(atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.next_check
+            .store(now.timestamp() + 1, atomic::Ordering::Relaxed);
+
+        let open = self.writer.load().metadata().context(GetFileSizeSnafu {
+            path: &state.file_path,
+        })?;
+        let current = fs::metadata(&state.file_path)
+            .ok()
+            .filter(|metadata| metadata.is_file());
+        // replaced by another file, e.g. created by logrotate after moving the
+        // open one away, which would keep receiving the writes
+        if let Some(current) = current
+            .as_ref()
+            .filter(|current| !same_file(current, &open))
+        {
+            let file = fs::OpenOptions::new()
+                .append(true)
+                .open(&state.file_path)
+                .context(OpenLogFileSnafu {
+                    path: &state.file_path,
+                })?;
+            let filename = state.file_path.clone();
+            self.reopened(&mut state, filename, file, current.len());
+            return Ok(());
+        }
+        // truncated, e.g. by logrotate copytruncate: the file is opened in
+        // append mode, writes go on at its new end
+        if current.is_some() && open.len() < state.file_size {
+            eprintln!(
+                "[RollingFileAppender] {} truncated by someone else from {} to {} bytes",
+                state.file_path.display(),
+                state.file_size,
+                open.len()
+            );
+            self.written.store(open.len(), atomic::Ordering::Relaxed);
+        }
+        state.file_size = open.len();
+
+        // 当前文件被误删除的情况
+        if current.is_none() {
+            let mut max_seq_id = max_seq_id(
+                &self.config.component_name,
+                self.config.instance_id,
+                self.config.extension,
+                &self.config.log_dir,
+                self.config.rotation.period(now),
+            )?;
+            loop {
+                let filename = if max_seq_id == 0 {
+                    format!(
+                        "{}_{}_{}.{}",
+                        self.config.component_name,
+                        self.config.instance_id,
+                        self.config.rotation.format(now),
+                        self.config.extension
+                    )
+                } else {
+                    format!(
+                        "{}_{}_{}.{}.{}",
+                        self.config.component_name,
+                        self.config.instance_id,
+                        self.config.rotation.format(now),
+                        self.config.extension,
+                        max_seq_id
+                    )
+                };
+                let filename = self.config.log_dir.join(filename);
+                match self.create_file(&filename)? {
+                    Some(file) => {
+                        state.max_seq_id = max_seq_id;
+                        self.reopened(&mut state, filename, file, 0);
+                        return Ok(());
+                    }
+                    None => max_seq_id += 1,
+                }
+            }
+        }
+
+        // written by others too, e.g. by a previous run to a reused file
+        if open.len() >= self.config.rotation.file_size {
+            return self.next_seq_file(&mut state, now, RotationReason::Size);
+        }
+        Ok(())
+    }
+}
+
+fn max_seq_id(
+    component_name: &str,
+    instance_id: u8,
+    extension: &str,
+    log_dir: impl AsRef<Path>,
+    period: Option<DateTime<Local>>,
+) -> Result<usize> {
+    Ok(log_files(log_dir.as_ref())?
+        .into_iter()
+        .filter_map(|(path, _)| {
+            let filename = path.file_name()?.to_str()?;
+            let res = parse_filename(component_name, instance_id, extension, filename)?;
+
+            (Some(res.0) == period).then_some(res.1)
+        })
+        .max()
+        .unwrap_or_default())
+}
+
+/// Subdirectory of the archived files, see
+/// [`archive`](RollingFileAppenderBuilder::archive).
+const ARCHIVE_DIR: &str = "archive";
+
+/// Files of `log_dir` and of its `archive/YYYYMM` subdirectories.
+fn log_files(log_dir: &Path) -> Result<Vec<(PathBuf, fs::Metadata)>> {
+    let files = |dir: &Path| {
+        fs::read_dir(dir).map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let metadata = entry.metadata().ok()?;
+                    Some((entry.path(), metadata))
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+    let mut all = Vec::new();
+    for (path, metadata) in files(log_dir).context(ReadDirSnafu { path: log_dir })? {
+        if metadata.is_file() {
+            all.push((path, metadata));
+        }
+    }
+    let months = files(&log_dir.join(ARCHIVE_DIR)).unwrap_or_default();
+    for (month, _) in months.into_iter().filter(|(_, m)| m.is_dir()) {
+        let archived = files(&month).unwrap_or_default();
+        all.extend(archived.into_iter().filter(|(_, m)| m.is_file()));
+    }
+    Ok(all)
+}
+
+/// Move `path`, dated `date`, to the archive dir of its month.
+fn archive_old_file(config: &Config, path: &Path, date: DateTime<Local>) -> Result<()> {
+    let dir = config
+        .log_dir
+        .join(ARCHIVE_DIR)
+        .join(date.format("%Y%m").to_string());
+    fs::create_dir_all(&dir).context(CreateLogDirSnafu { path: &dir })?;
+    let archived = dir.join(path.file_name().unwrap_or_default());
+    fs::rename(path, &archived).context(ArchiveSnafu { path })?;
+    #[cfg(feature = "hash-chain")]
+    if config.hash_chain {
+        fs::rename(chain::digest_path(path), chain::digest_path(&archived)).ok();
+    }
+    tracing::info!(
+        target: internal::TARGET,
+        "archived {} to {}",
+        path.display(),
+        archived.display()
+    );
+    if let Some(hints) = &config.position_hints {
+        hints.archived(path, &archived, &config.log_dir).ok();
+    }
+    Ok(())
+}
+
+struct HandleOldFileEvent {
+    config: Config,
+    compress_file: Option<PathBuf>,
+    rotated: Option<RotationEvent>,
+    /// of the old file, handled once it is
+    released: Option<Released>,
+}
+
+enum MaintenanceEvent {
+    OldFiles(Box<HandleOldFileEvent>),
+    /// Acknowledged once the events queued before are handled, then the
+    /// thread exits if `stop`.
+    Barrier {
+        ack: flume::Sender<()>,
+        stop: bool,
+    },
+}
+
+fn handle_old_files(config: Config, compress_filename: Option<PathBuf>) -> Result<()> {
+    // 压缩上一个文件
+    if let Some(filename) = compress_filename {
+        #[cfg(feature = "hash-chain")]
+        if config.hash_chain {
+            if let Err(e) = chain::write_digest(&filename) {
+                tracing::warn!(target: internal::TARGET, "digest {} error: {e}", filename.display());
+            }
+        }
+        #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+        let rotated = if config.compress {
+            let archive = compress_old_file(&config, &filename);
+            config.metrics.compression_done();
+            archive.unwrap_or_else(|e| {
+                tracing::warn!(target: internal::TARGET, "compress {} error: {e}", filename.display());
+                filename
+            })
+        } else {
+            filename
+        };
+        #[cfg(feature = "encryption")]
+        let rotated = match &config.encryption {
+            Some(provider) => match encrypt_old_file(&config, provider.as_ref(), &rotated) {
+                Ok(encrypted) => encrypted,
+                Err(e) => {
+                    tracing::warn!(target: internal::TARGET, "encrypt {} error: {e}", rotated.display());
+                    rotated
+                }
+            },
+            None => rotated,
+        };
+        let date = rotated
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| {
+                parse_filename(
+                    &config.component_name,
+                    config.instance_id,
+                    config.extension,
+                    name,
+                )
+            });
+        if let Some((date, _)) = date.filter(|_| config.archive) {
+            if let Err(e) = archive_old_file(&config, &rotated, date) {
+                tracing::warn!(target: internal::TARGET, "archive {} error: {e}", rotated.display());
+            }
+        }
+    }
+
+    if config.rotate_count == 0 && config.max_total_size.is_none() && config.max_age_days.is_none()
+    {
+        return Ok(());
+    }
+
+    // 删除多余的旧文件
+    let mut files = log_files(&config.log_dir)?
+        .into_iter()
+        .filter_map(|(path, metadata)| {
+            let res = parse_filename(
+                &config.component_name,
+                config.instance_id,
+                config.extension,
+                path.file_name()?.to_str()?,
+            )?;
+
+            let modified = metadata.modified().ok();
+            Some((path, res, metadata.len(), modified))
+        })
+        .collect::<Vec<(PathBuf, (DateTime<Local>, usize), u64, Option<SystemTime>)>>();
+    files.sort_by(|(_, a, _, _), (_, b, _, _)| filename_cmp(a, b));
+    // dbg!(&files);
+    let mut total_size: u64 = files.iter().map(|(_, _, size, _)| size).sum();
+    let expired_before = config
+        .max_age_days
+        .map(|days| config.clock.now() - TimeDelta::days(days as i64));
+    let mut files = files.into_iter().peekable();
+    let mut delete_files = Vec::new();
+    // the newest file is the one being written, never delete it
+    while files.len() > 1 {
+        let over_count = config.rotate_count > 0 && files.len() > config.rotate_count;
+        let over_size = config.max_total_size.is_some_and(|max| total_size > max);
+        // the date of a file is the start of its period, it ends a period later
+        let expired =
+            files
+                .peek()
+                .zip(expired_before)
+                .is_some_and(|((_, (date, _), _, _), before)| {
+                    config.rotation.period_end(*date) <= before.timestamp()
+                });
+        if !over_count && !over_size && !expired {
+            break;
+        }
+        let Some((path, _, size, modified)) = files.next() else {
+            break;
+        };
+        total_size -= size;
+        delete_files.push((path, modified));
+    }
+    for (file, modified) in delete_files {
+        #[cfg(feature = "fault-injection")]
+        let removed = config
+            .faults
+            .check(FaultPoint::Delete)
+            .and_then(|_| fs::remove_file(&file));
+        #[cfg(not(feature = "fault-injection"))]
+        let removed = fs::remove_file(&file);
+        if let Err(e) = removed {
+            tracing::warn!(target: internal::TARGET, "delete {} error: {e}", file.display());
+            continue;
+        }
+        tracing::info!(target: internal::TARGET, "deleted {}", file.display());
+        #[cfg(feature = "hash-chain")]
+        if config.hash_chain {
+            fs::remove_file(chain::digest_path(&file)).ok();
+        }
+        if let Some(age) = modified.and_then(|modified| modified.elapsed().ok()) {
+            config.usage.deleted(age);
+        }
+        if let Some(hints) = &config.position_hints {
+            hints.deleted(&file).ok();
+        }
+    }
+
+    Ok(())
+}
+
+impl Drop for RollingFileAppender {
+    fn drop(&mut self) {
+        self.shutdown().ok();
+    }
+}
+
+pub struct RollingWriter<'a> {
+    current: &'a CurrentFile,
+    written: &'a AtomicU64,
+    read_only: &'a ReadOnly,
+    buffer: Option<&'a WriteBuffer>,
+    durability: &'a Durability,
+    metrics: &'a Counters,
+    #[cfg(feature = "hash-chain")]
+    chain: Option<&'a HashChain>,
+    /// written through the buffer right away
+    urgent: bool,
+}
+
+impl RollingWriter<'_> {
+    fn write_event(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        #[cfg(feature = "hash-chain")]
+        if let Some(chain) = self.chain {
+            let mut last = chain.lock();
+            // loaded under the chain lock, the chain restarts with the file
+            let file = self.current.load();
+            let sealed = chain::seal(&mut last, buf);
+            self.write_sealed(&file, &sealed)?;
+            return Ok(buf.len());
+        }
+        self.write_sealed(&self.current.load(), buf)
+    }
+
+    /// Write `buf`, with its chain value if any.
+    fn write_sealed(&self, file: &File, buf: &[u8]) -> std::io::Result<usize> {
+        match self.buffer {
+            Some(buffer) => buffer.write(file, self.read_only, buf, self.urgent)?,
+            None => write_file(file, self.read_only, buf)?,
+        }
+        self.written
+            .fetch_add(buf.len() as u64, atomic::Ordering::Relaxed);
+        if self.durability.due(buf.len(), self.urgent) {
+            if let Some(buffer) = self.buffer {
+                buffer.drain(file, self.read_only)?;
+            }
+            self.durability.sync(file)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl std::io::Write for RollingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let res = self.write_event(buf);
+        match &res {
+            Ok(len) => self.metrics.written(*len),
+            Err(e) => self.metrics.write_failed(e),
+        }
+        res
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let file = self.current.load();
+        if let Some(buffer) = self.buffer {
+            buffer.drain(&file, self.read_only)?;
+        }
+        (&*file).flush()
+    }
+}
+
+pub enum TaosLogWriter<'a> {
+    Rolling(RollingWriter<'a>),
+    Stderr(std::io::Stderr),
+    Null(std::io::Empty),
+}
+
+impl<'a> std::io::Write for TaosLogWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            TaosLogWriter::Rolling(w) => w.write(buf),
+            TaosLogWriter::Stderr(w) => w.write(buf),
+            TaosLogWriter::Null(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            TaosLogWriter::Rolling(w) => w.flush(),
+            TaosLogWriter::Stderr(w) => w.flush(),
+            TaosLogWriter::Null(w) => w.flush(),
+        }
+    }
+}
+
+impl RollingFileAppender {
+    fn writer(&self, urgent: bool) -> TaosLogWriter<'_> {
+        if self.read_only.get() {
+            return TaosLogWriter::Stderr(std::io::stderr());
+        }
+        // rotating would wait on the maintenance queue served by this thread
+        if !internal::in_maintenance() {
+            self.rotate().ok();
+        }
+        TaosLogWriter::Rolling(RollingWriter {
+            current: &self.writer,
+            written: &self.written,
+            read_only: &self.read_only,
+            buffer: self.buffer.as_deref(),
+            durability: &self.durability,
+            metrics: &self.config.metrics,
+            #[cfg(feature = "hash-chain")]
+            chain: self.chain.as_ref(),
+            urgent,
+        })
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for RollingFileAppender {
+    type Writer = TaosLogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        if let Some(fallback) = self.active_fallback() {
+            return fallback.make_writer();
+        }
+        self.writer(false)
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        if let Some(fallback) = self.active_fallback() {
+            return fallback.make_writer_for(meta);
+        }
+        let action = self.disk.admit(meta.level(), |banner| {
+            let mut writer = self.make_writer();
+            writer.write_all(banner).ok();
+            writer.flush().ok();
+        });
+        match action {
+            DiskAction::Write => self.writer(false),
+            DiskAction::ForceFlush => self.writer(true),
+            DiskAction::Drop => TaosLogWriter::Null(std::io::empty()),
+        }
+    }
+}
+
+/// A shared [`RollingFileAppender`], e.g. written by a
+/// [`TaosLayer`](crate::layer::TaosLayer), flushed by an admin API and kept in
+/// a static for the panic hook.
+///
+/// `MakeWriter` is not implemented for `Arc<RollingFileAppender>`, as
+/// tracing-subscriber implements it for any `Arc<W>` where `&W` is a writer.
+///
+/// ```ignore
+/// static APPENDER: OnceLock<Handle> = OnceLock::new();
+///
+/// let handle = APPENDER.get_or_init(|| Handle::new(appender)).clone();
+/// tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(handle));
+/// APPENDER.get().unwrap().shutdown()?;
+/// ```
+#[derive(Clone)]
+pub struct Handle(Arc<RollingFileAppender>);
+
+impl Handle {
+    pub fn new(appender: RollingFileAppender) -> Self {
+        Self(Arc::new(appender))
+    }
+
+    pub fn appender(&self) -> &Arc<RollingFileAppender> {
+        &self.0
+    }
+}
+
+impl From<RollingFileAppender> for Handle {
+    fn from(appender: RollingFileAppender) -> Self {
+        Self::new(appender)
+    }
+}
+
+impl From<Arc<RollingFileAppender>> for Handle {
+    fn from(appender: Arc<RollingFileAppender>) -> Self {
+        Self(appender)
+    }
+}
+
+impl Deref for Handle {
+    type Target = RollingFileAppender;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Handle {
+    type Writer = TaosLogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.0.make_writer()
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        self.0.make_writer_for(meta)
+    }
+}
+
+/// Available space of the disk holding the log dir.
+pub(crate) trait DiskSpace: Send + 'static {
+    fn available_space(&self) -> u64;
+
+    fn refresh(&mut self);
+}
+
+#[cfg(feature = "sysinfo")]
+impl DiskSpace for sysinfo::Disk {
+    fn available_space(&self) -> u64 {
+        sysinfo::Disk::available_space(self)
+    }
+
+    fn refresh(&mut self) {
+        sysinfo::Disk::refresh(self);
+    }
+}
+
+/// The disk holding `path`, refreshed.
+#[cfg(feature = "sysinfo")]
+pub(crate) fn find_disk(path: &Path) -> Result<impl DiskSpace> {
+    let mut disks = sysinfo::Disks::new();
+    disks.refresh_list();
+    let mut disks = Vec::from(disks);
+    disks.sort_by_key(|a| cmp::Reverse(a.mount_point().to_str().map(|s| s.len())));
+    let mut disk = disks
+        .into_iter()
+        .find(|d| on_mount_point(path, d.mount_point()))
+        .context(DiskMountPointNotFoundSnafu)?;
+    disk.refresh();
+    Ok(disk)
+}
+
+#[cfg(all(feature = "sysinfo", not(windows)))]
+fn on_mount_point(path: &Path, mount_point: &Path) -> bool {
+    path.starts_with(mount_point)
+}
+
+/// Windows mount points are drive roots like `C:\`, or folders of a drive,
+/// compared ignoring case and verbatim prefixes.
+#[cfg(all(feature = "sysinfo", windows))]
+fn on_mount_point(path: &Path, mount_point: &Path) -> bool {
+    windows_starts_with(path, mount_point)
+}
+
+#[cfg(any(all(feature = "sysinfo", windows), test))]
+fn windows_starts_with(path: &Path, prefix: &Path) -> bool {
+    let normalize = |path: &Path| {
+        strip_verbatim(path)
+            .to_string_lossy()
+            .replace('/', "\\")
+            .trim_end_matches('\\')
+            .to_lowercase()
+    };
+    let (path, prefix) = (normalize(path), normalize(prefix));
+    path == prefix || path.starts_with(&format!("{prefix}\\"))
+}
+
+/// `path` without the `\\?\` prefix of `canonicalize` on Windows, e.g.
+/// `\\?\C:\logs` to `C:\logs` and `\\?\UNC\host\share` to `\\host\share`,
+/// as written by users and reported for disks.
+#[cfg(any(windows, test))]
+fn strip_verbatim(path: &Path) -> PathBuf {
+    let Some(s) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    if let Some(unc) = s.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{unc}"))
+    } else if let Some(local) = s.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// The disk holding `path`, refreshed.
+#[cfg(not(feature = "sysinfo"))]
+pub(crate) fn find_disk(path: &Path) -> Result<impl DiskSpace> {
+    sys::StatvfsDisk::new(path).context(DiskMountPointNotFoundSnafu)
+}
+
+fn time_format<'a>(datetime: DateTime<Local>) -> DelayedFormat<StrftimeItems<'a>> {
+    datetime.date_naive().format(DATE_FORMAT)
+}
+
+/// `path` is still the `open` file, compared by device and inode on Unix.
+#[cfg(unix)]
+fn same_file(path: &fs::Metadata, open: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (path.dev(), path.ino()) == (open.dev(), open.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_path: &fs::Metadata, _open: &fs::Metadata) -> bool {
+    true
+}
+
+fn create_file(name: impl AsRef<Path>) -> Result<Option<File>> {
+    let path = name.as_ref();
+    match fs::OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+        e @ Err(_) => Ok(Some(e.context(OpenLogFileSnafu { path })?)),
+    }
+}
+
+/// A compressed log file with the size and CRC32 of its source.
+pub(crate) struct Archive {
+    path: PathBuf,
+    compression: Compression,
+    size: u64,
+    crc: u32,
+}
+
+/// Compress `path` and delete it, returning the path of the archive, or
+/// `path` if an archive already exists.
+fn compress_old_file(config: &Config, path: &Path) -> Result<PathBuf> {
+    #[cfg(feature = "fault-injection")]
+    config
+        .faults
+        .check(FaultPoint::Compress)
+        .context(CompressSnafu { path })?;
+    let Some(archive) = compress(path, config.compression)? else {
+        return Ok(path.to_path_buf());
+    };
+
+    if config.verify_compression {
+        let verified = verify_archive(&archive);
+        let mut entry = serde_json::json!({
+            "file": archive.path.file_name().map(|s| s.to_string_lossy()),
+            "source": path.file_name().map(|s| s.to_string_lossy()),
+            "size": archive.size,
+            "crc32": format!("{:08x}", archive.crc),
+            "verified": verified.is_ok(),
+        });
+        if let Err(e) = &verified {
+            entry["error"] = e.to_string().into();
+        }
+        manifest::append(
+            &config.log_dir,
+            &config.component_name,
+            config.instance_id,
+            entry,
+        )
+        .ok();
+        if verified.is_err() {
+            // keep the source, drop the bad archive
+            fs::remove_file(&archive.path).ok();
+            return verified.map(|_| path.to_path_buf());
+        }
+    }
+
+    fs::remove_file(path).context(CompressSnafu { path })?;
+    if let Ok(metadata) = fs::metadata(&archive.path) {
+        config.usage.compressed(archive.size, metadata.len());
+    }
+    tracing::info!(
+        target: internal::TARGET,
+        bytes_source = archive.size,
+        "compressed {} to {}",
+        path.display(),
+        archive.path.display()
+    );
+    if let Some(hints) = &config.position_hints {
+        hints.compressed(path, &archive.path).ok();
+    }
+    Ok(archive.path)
+}
+
+/// Encrypt `path` and delete it.
+#[cfg(feature = "encryption")]
+fn encrypt_old_file(config: &Config, provider: &dyn KeyProvider, path: &Path) -> Result<PathBuf> {
+    let encrypted = crate::encrypt::encrypt_file(provider, path)?;
+    fs::remove_file(path).context(EncryptSnafu { path })?;
+    tracing::info!(
+        target: internal::TARGET,
+        "encrypted {} to {}",
+        path.display(),
+        encrypted.display()
+    );
+    if let Some(hints) = &config.position_hints {
+        hints.compressed(path, &encrypted).ok();
+    }
+    Ok(encrypted)
+}
+
+/// Compress `path` into `<path>.gz` or `<path>.zst`, the source file is kept.
+///
+/// Returns `None` if the archive already exists.
+pub(crate) fn compress(
+    path: impl AsRef<Path>,
+    compression: Compression,
+) -> Result<Option<Archive>> {
+    let path = path.as_ref();
+    let dest_path = PathBuf::from(format!("{}.{}", path.display(), compression.extension()));
+
+    let src_file = File::open(path).context(CompressSnafu { path })?;
+    let dest_file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&dest_path)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(None),
+        e @ Err(_) => e.context(OpenLogFileSnafu { path })?,
+    };
+
+    let mut src = CrcReader::new(src_file);
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(dest_file, flate2::Compression::default());
+            std::io::copy(&mut src, &mut encoder).context(CompressSnafu { path })?;
+            // durable before the source is deleted
+            let file = encoder.finish().context(CompressSnafu { path })?;
+            file.sync_all().context(CompressSnafu { path })?;
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { level } => {
+            let mut encoder =
+                zstd::stream::Encoder::new(dest_file, level).context(CompressSnafu { path })?;
+            std::io::copy(&mut src, &mut encoder).context(CompressSnafu { path })?;
+            // durable before the source is deleted
+            let file = encoder.finish().context(CompressSnafu { path })?;
+            file.sync_all().context(CompressSnafu { path })?;
+        }
+    }
+
+    Ok(Some(Archive {
+        path: dest_path,
+        compression,
+        size: src.crc().amount() as u64,
+        crc: src.crc().sum(),
+    }))
+}
+
+/// Decompress `archive` and compare it with the size and CRC32 of its source.
+fn verify_archive(archive: &Archive) -> Result<()> {
+    let path = &archive.path;
+    let file = File::open(path).context(CompressSnafu { path })?;
+    let decoder: Box<dyn std::io::Read> = match archive.compression {
+        Compression::Gzip => Box::new(GzDecoder::new(file)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { .. } => {
+            Box::new(zstd::stream::Decoder::new(file).context(CompressSnafu { path })?)
+        }
+    };
+    let mut decoded = CrcReader::new(decoder);
+    if let Err(e) = std::io::copy(&mut decoded, &mut std::io::sink()) {
+        return VerifyArchiveSnafu {
+            path,
+            reason: e.to_string(),
+        }
+        .fail();
+    }
+    let (size, crc) = (decoded.crc().amount() as u64, decoded.crc().sum());
+    ensure!(
+        size == archive.size && crc == archive.crc,
+        VerifyArchiveSnafu {
+            path,
+            reason: format!(
+                "size {size} crc32 {crc:08x}, expected size {} crc32 {:08x}",
+                archive.size, archive.crc
+            ),
+        }
+    );
+    Ok(())
+}
+
+/// Parses `<component>_<instance_id>_<date>.log[.<index>][.gz|.zst]` into the
+/// date and index of a log file.
+trait FileNameParser {
+    fn parse(
+        component: &str,
+        instance_id: u8,
+        extension: &str,
+        name: &str,
+    ) -> Option<(DateTime<Local>, usize)>;
+}
+
+#[cfg(feature = "regex")]
+struct RegexParser;
+
+#[cfg(feature = "regex")]
+impl FileNameParser for RegexParser {
+    fn parse(
+        component: &str,
+        instance_id: u8,
+        extension: &str,
+        name: &str,
+    ) -> Option<(DateTime<Local>, usize)> {
+        static LOG_FILE_NAME_RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+        let re = LOG_FILE_NAME_RE.get_or_init(|| {
+            Regex::new(
+                r"^(?<date>\d{8}(\d{2})?)\.(?<ext>log|errlog|access)(\.(?<index1>\d+)|\.(gz|zst)|\.(?<index2>\d+)\.(gz|zst))?(\.enc)?$",
+            )
+            .unwrap()
+        });
+        let name = name.strip_prefix(&format!("{component}_{instance_id}_"))?;
+        let caps = re.captures(name)?;
+        if &caps["ext"] != extension {
+            return None;
+        }
+        let date = caps.name("date").and_then(|m| parse_date_str(m.as_str()))?;
+        let index = caps
+            .name("index1")
+            .or(caps.name("index2"))
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or_default();
+        Some((date, index))
+    }
+}
+
+/// The same grammar as [`RegexParser`], without the regex dependency.
+#[cfg(any(not(feature = "regex"), test))]
+struct PlainParser;
+
+#[cfg(any(not(feature = "regex"), test))]
+impl FileNameParser for PlainParser {
+    fn parse(
+        component: &str,
+        instance_id: u8,
+        extension: &str,
+        name: &str,
+    ) -> Option<(DateTime<Local>, usize)> {
+        let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let name = name.strip_prefix(&format!("{component}_{instance_id}_"))?;
+        let (date, rest) = name.split_once(&format!(".{extension}"))?;
+        if !is_digits(date) || !(date.len() == 8 || date.len() == 10) {
+            return None;
+        }
+        let rest = rest.strip_suffix(".enc").unwrap_or(rest);
+        let rest = rest
+            .strip_suffix(".gz")
+            .or_else(|| rest.strip_suffix(".zst"))
+            .unwrap_or(rest);
+        let index = match rest {
+            "" => 0,
+            _ => {
+                let index = rest.strip_prefix('.').filter(|i| is_digits(i))?;
+                index.parse().unwrap_or_default()
+            }
+        };
+        Some((parse_date_str(date)?, index))
+    }
+}
+
+#[cfg(feature = "regex")]
+type DefaultParser = RegexParser;
+#[cfg(not(feature = "regex"))]
+type DefaultParser = PlainParser;
+
+fn parse_filename(
+    component: &str,
+    instance_id: u8,
+    extension: &str,
+    name: &str,
+) -> Option<(DateTime<Local>, usize)> {
+    DefaultParser::parse(component, instance_id, extension, name)
+}
+
+fn parse_date_str(date: &str) -> Option<DateTime<Local>> {
+    let (date, hour) = date.split_at_checked(8)?;
+    let hour = if hour.is_empty() { "00" } else { hour };
+    let dt = NaiveDateTime::parse_from_str(&format!("{date} {hour}0000"), DATE_TIME_FORMAT).ok()?;
+    Local.from_local_datetime(&dt).single()
+}
+
+pub(crate) fn parse_unit_size(size: &str) -> Result<u64> {
+    ensure!(size.len() >= 3, InvalidRotationSizeSnafu { size });
+    ensure!(size.is_ascii(), InvalidRotationSizeSnafu { size });
+    let (count, unit) = size.split_at(size.len() - 2);
+    let count = count
+        .parse::<u64>()
+        .ok()
+        .context(InvalidRotationSizeSnafu { size })?;
+    match unit.to_ascii_uppercase().as_str() {
+        "KB" => Ok(count * 1024),
+        "MB" => Ok(count * 1024 * 1024),
+        "GB" => Ok(count * 1024 * 1024 * 1024),
+        _ => InvalidRotationSizeSnafu { size }.fail(),
+    }
+}
+
+pub(crate) fn filename_cmp(
+    a: &(DateTime<Local>, usize),
+    b: &(DateTime<Local>, usize),
+) -> cmp::Ordering {
+    match a.0.cmp(&b.0) {
+        cmp::Ordering::Equal => a.1.cmp(&b.1),
+        p => p,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Metadata of an INFO or ERROR event, for the writer checks by level.
+    fn event_meta(level: tracing::Level) -> &'static tracing::Metadata<'static> {
+        use tracing::{
+            callsite::{DefaultCallsite, Identifier},
+            field::FieldSet,
+            metadata::Kind,
+            Level, Metadata,
+        };
+
+        static INFO_CALLSITE: DefaultCallsite = DefaultCallsite::new(&INFO);
+        static INFO: Metadata<'static> = Metadata::new(
+            "info",
+            "taosx",
+            Level::INFO,
+            None,
+            None,
+            None,
+            FieldSet::new(&[], Identifier(&INFO_CALLSITE)),
+            Kind::EVENT,
+        );
+        static ERROR_CALLSITE: DefaultCallsite = DefaultCallsite::new(&ERROR);
+        static ERROR: Metadata<'static> = Metadata::new(
+            "error",
+            "taosx",
+            Level::ERROR,
+            None,
+            None,
+            None,
+            FieldSet::new(&[], Identifier(&ERROR_CALLSITE)),
+            Kind::EVENT,
+        );
+        assert!(level == Level::INFO || level == Level::ERROR);
+        if level == Level::ERROR {
+            &ERROR
+        } else {
+            &INFO
+        }
+    }
+
+    #[test]
+    fn parse_filename_test() {
+        let component = "taosx";
+
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log"),
+            Some((parse_date_str("20240909").unwrap(), 0))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.1"),
+            Some((parse_date_str("20240909").unwrap(), 1))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.gz"),
+            Some((parse_date_str("20240909").unwrap(), 0))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.1.gz"),
+            Some((parse_date_str("20240909").unwrap(), 1))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_agent_1_20240909.log"),
+            None
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_agent_1_20240909.log"),
+            None
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.2.zst"),
+            Some((parse_date_str("20240909").unwrap(), 2))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.zst"),
+            Some((parse_date_str("20240909").unwrap(), 0))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.3.gz.enc"),
+            Some((parse_date_str("20240909").unwrap(), 3))
+        );
+        assert_eq!(
+            parse_filename(component, 1, "log", "taosx_1_20240909.log.enc"),
+            Some((parse_date_str("20240909").unwrap(), 0))
+        );
+        assert_eq!(
+            parse_filename("taosx_agent", 1, "log", "taosx_agent_1_20240909.log"),
+            Some((parse_date_str("20240909").unwrap(), 0))
+        );
+    }
+
+    /// The files of the other instances in the same log dir are neither
+    /// counted for the sequence ids nor cleaned up.
+    #[test]
+    fn parse_filename_instance_test() {
+        assert_eq!(
+            parse_filename("taosx", 2, "log", "taosx_2_20240909.log.1"),
+            Some((parse_date_str("20240909").unwrap(), 1))
+        );
+        assert_eq!(
+            parse_filename("taosx", 2, "log", "taosx_1_20240909.log"),
+            None
+        );
+        assert_eq!(
+            parse_filename("taosx", 1, "log", "taosx_2_20240909.log.1.gz"),
+            None
+        );
+        assert_eq!(
+            parse_filename("taosx", 1, "log", "taosx_12_20240909.log"),
+            None
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn plain_parser_test() {
+        let names = [
+            "taosx_1_20240909.log",
+            "taosx_1_2024090910.log",
+            "taosx_1_20240909.log.1",
+            "taosx_1_20240909.log.12.gz",
+            "taosx_1_20240909.log.3.zst",
+            "taosx_1_20240909.log.gz",
+            "taosx_1_20240909.log.zst",
+            "taosx_1_20240909.log.99999999999999999999999",
+            "taosx_1_202409091.log",
+            "taosx_1_2024090.log",
+            "taosx_1_20240909.log.",
+            "taosx_1_20240909.log.1.",
+            "taosx_1_20240909.log.a",
+            "taosx_1_20240909.log.1.gz.1",
+            "taosx_1_20240909.log.gz.gz",
+            "taosx_1_20240909.logx",
+            "taosx_1_20240909.log.1.bz2",
+            "taosx_1_+20240909.log",
+            "taosx_2_20240909.log",
+            "taosx_agent_1_20240909.log",
+            "taosx_1_20241309.log",
+            "taosx_1_.log",
+        ];
+        for name in names {
+            assert_eq!(
+                PlainParser::parse("taosx", 1, "log", name),
+                RegexParser::parse("taosx", 1, "log", name),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn time_format_test() {
+        let dt_str = "20250626";
+        assert_eq!(
+            time_format(parse_date_str(dt_str).unwrap()).to_string(),
+            "20250626"
+        );
+    }
+
+    #[test]
+    fn size_bytes_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size_bytes(512 * crate::size::MB)
+            .reserved_disk_size_bytes(ByteSize::kb(1))
+            .max_total_size_bytes(ByteSize::gb(10));
+        assert_eq!(builder.rotation_size.bytes().unwrap(), 512 * 1024 * 1024);
+        let appender = builder.build().unwrap();
+        assert_eq!(appender.config.rotation.file_size, 512 * 1024 * 1024);
+        assert_eq!(
+            appender.config.max_total_size,
+            Some(10 * 1024 * 1024 * 1024)
+        );
+
+        // the string form fails on build
+        let built = RollingFileAppender::builder(dir.path(), "taosx", 2)
+            .rotation_size("512TB")
+            .build();
+        assert!(matches!(
+            built,
+            Err(crate::Error::InvalidRotationSize { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_unit_size_test() {
+        assert_eq!(parse_unit_size("5KB").unwrap(), 5 * 1024);
+        assert_eq!(parse_unit_size("5MB").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_unit_size("5GB").unwrap(), 5 * 1024 * 1024 * 1024);
+
+        assert!(parse_unit_size("5GBK").is_err());
+        assert!(parse_unit_size("GB").is_err());
+    }
+
+    #[test]
+    fn next_timestamp_test() {
+        let rotatoin = Rotation::default();
+        assert_eq!(
+            rotatoin.next_timestamp(Local.with_ymd_and_hms(2024, 8, 23, 10, 2, 27).unwrap()),
+            Local
+                .with_ymd_and_hms(2024, 8, 24, 0, 0, 0)
+                .unwrap()
+                .timestamp()
+        );
+
+        assert_eq!(
+            rotatoin.next_timestamp(Local.with_ymd_and_hms(2024, 8, 24, 0, 0, 0).unwrap()),
+            Local
+                .with_ymd_and_hms(2024, 8, 25, 0, 0, 0)
+                .unwrap()
+                .timestamp()
+        );
+    }
+
+    #[test]
+    fn filename_cmp_test() {
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 1),
+                &(parse_date_str("20240909").unwrap(), 1)
+            ),
+            cmp::Ordering::Equal
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 2),
+                &(parse_date_str("20240909").unwrap(), 1)
+            ),
+            cmp::Ordering::Greater
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 1),
+                &(parse_date_str("20240909").unwrap(), 2)
+            ),
+            cmp::Ordering::Less
+        );
+
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240910").unwrap(), 1),
+                &(parse_date_str("20240909").unwrap(), 1)
+            ),
+            cmp::Ordering::Greater
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240910").unwrap(), 2),
+                &(parse_date_str("20240909").unwrap(), 1)
+            ),
+            cmp::Ordering::Greater
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240910").unwrap(), 0),
+                &(parse_date_str("20240909").unwrap(), 1)
+            ),
+            cmp::Ordering::Greater
+        );
+
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 1),
+                &(parse_date_str("20240910").unwrap(), 1)
+            ),
+            cmp::Ordering::Less
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 2),
+                &(parse_date_str("20240910").unwrap(), 1)
+            ),
+            cmp::Ordering::Less
+        );
+        assert_eq!(
+            filename_cmp(
+                &(parse_date_str("20240909").unwrap(), 0),
+                &(parse_date_str("20240910").unwrap(), 1)
+            ),
+            cmp::Ordering::Less
+        );
+    }
+
+    fn test_config(log_dir: &Path) -> Config {
+        Config {
+            log_dir: log_dir.to_path_buf(),
+            component_name: "taosx".to_string(),
+            instance_id: 1,
+            extension: "log",
+            rotation: Rotation::default(),
+            compress: true,
+            compression: Compression::default(),
+            rotate_count: 3,
+            verify_compression: false,
+            archive: false,
+            max_total_size: None,
+            max_age_days: None,
+            position_hints: None,
+            usage: Arc::new(Usage::new()),
+            metrics: Arc::default(),
+            clock: WallClock::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: FaultInjector::default(),
+            on_rotate: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            #[cfg(feature = "hash-chain")]
+            hash_chain: false,
+        }
+    }
+
+    fn list_files(log_dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(log_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn rotate_fault_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let faults = FaultInjector::new();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("1KB")
+            .reserved_disk_size("1KB")
+            .build()
+            .map(|mut appender| {
+                appender.config.faults = faults.clone();
+                appender
+            })
+            .unwrap();
+
+        appender.make_writer().write_all(&[b'x'; 2048]).unwrap();
+
+        // rotation fails, keep writing to the current file
+        faults.fail(FaultPoint::Rotate, crate::fault::Fault::NoSpace);
+        appender.make_writer().write_all(b"kept\n").unwrap();
+        assert_eq!(list_files(dir.path()).len(), 1);
+        let current = appender.state.read().file_path.clone();
+        assert!(fs::read_to_string(current).unwrap().ends_with("kept\n"));
+
+        // rotation recovers once the fault is gone
+        faults.clear_all();
+        appender.make_writer().write_all(b"rotated\n").unwrap();
+        assert_eq!(list_files(dir.path()).len(), 2);
+        let current = appender.state.read().file_path.clone();
+        assert!(current.to_string_lossy().ends_with(".log.1"));
+        assert_eq!(fs::read_to_string(current).unwrap(), "rotated\n");
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn compress_fault_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path());
+        let file = dir.path().join("taosx_1_20240901.log");
+        fs::write(&file, "content").unwrap();
+
+        // compression fails, the source file is kept as is
+        config
+            .faults
+            .fail(FaultPoint::Compress, crate::fault::Fault::Io);
+        handle_old_files(config.clone(), Some(file.clone())).unwrap();
+        assert_eq!(list_files(dir.path()), vec!["taosx_1_20240901.log"]);
+
+        config.faults.clear_all();
+        handle_old_files(config, Some(file)).unwrap();
+        assert_eq!(list_files(dir.path()), vec!["taosx_1_20240901.log.gz"]);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn delete_fault_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            compress: false,
+            rotate_count: 1,
+            ..test_config(dir.path())
+        };
+        for name in [
+            "taosx_1_20240901.log",
+            "taosx_1_20240902.log",
+            "taosx_1_20240903.log",
+        ] {
+            fs::write(dir.path().join(name), "content").unwrap();
+        }
+
+        // deletion fails, cleanup is skipped without error
+        config
+            .faults
+            .fail(FaultPoint::Delete, crate::fault::Fault::PermissionDenied);
+        handle_old_files(config.clone(), None).unwrap();
+        assert_eq!(list_files(dir.path()).len(), 3);
+
+        config.faults.clear_all();
+        handle_old_files(config, None).unwrap();
+        assert_eq!(list_files(dir.path()), vec!["taosx_1_20240903.log"]);
+    }
+
+    #[test]
+    fn heartbeat_test() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = crate::fake::Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(
+                crate::layer::TaosLayer::<crate::fake::Qid, _, _>::new(capture.clone()),
+            );
+        let heartbeat = Heartbeat {
+            started: Instant::now(),
+            disk_available_space: Arc::new(AtomicU64::new(1073741824)),
+            disk_pressure: Arc::new(DiskPressure::new()),
+            reserved_disk_size: 1024,
+        };
+
+        tracing::subscriber::with_default(subscriber, || heartbeat.emit());
+
+        let content = capture.content();
+        assert!(content.contains("duration_uptime:"));
+        assert!(content.contains(
+            "bytes_disk_available:1.0GiB, disk_stage:Normal, dropped_downgrade:0, dropped_stopped:0 heartbeat"
+        ));
+    }
+
+    #[test]
+    fn disk_summary_test() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = crate::fake::Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(
+                crate::layer::TaosLayer::<crate::fake::Qid, _, _>::new(capture.clone()),
+            );
+        let summary = Arc::new(DiskSummary {
+            disk_available_space: Arc::new(AtomicU64::new(512)),
+            disk_pressure: Arc::new(DiskPressure::new()),
+            reserved_disk_size: 1024,
+            interval: Duration::ZERO,
+        });
+        let summaries = DiskSummaries::default();
+        summaries.add(&summary);
+        assert!(summaries.next_due().is_some());
+
+        tracing::subscriber::with_default(subscriber, || {
+            summaries.emit_due();
+            summary.disk_pressure.enter(DiskStage::Downgrade);
+            summary.disk_pressure.drop_event(DiskStage::Downgrade);
+            summaries.emit_due();
+        });
+        // not logged anymore once the appender is dropped
+        drop(summary);
+        assert_eq!(summaries.next_due(), None);
+
+        let content = capture.content();
+        assert_eq!(content.lines().count(), 1, "{content}");
+        assert!(content.contains(
+            "bytes_disk_available:512B, bytes_downgrade_threshold:1.0KiB, bytes_stop_threshold:204B, dropped_downgrade:1, dropped_stopped:0 disk pressure"
+        ), "{content}");
+        assert!(content.contains("disk_stage:Downgrade, since:"));
+    }
+
+    #[test]
+    fn single_file_compression_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = |policy| {
+            RollingFileAppender::builder(dir.path(), "taosx", 1)
+                .reserved_disk_size("1KB")
+                .compress(true)
+                .rotation_count(1)
+                .single_file_compression(policy)
+                .build()
+                .unwrap()
+        };
+
+        let appender = build(SingleFileCompression::Skip);
+        assert!(!appender.config.compress);
+        assert_eq!(appender.config.rotate_count, 1);
+
+        let appender = build(SingleFileCompression::CompressThenDeleteOlder);
+        assert!(appender.config.compress);
+        assert_eq!(appender.config.rotate_count, 2);
+    }
+
+    #[test]
+    fn cleanup_interval_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "cleanup", 1)
+            .reserved_disk_size("1KB")
+            .rotation_count(2)
+            .cleanup_interval(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        for name in ["cleanup_1_20240901.log", "cleanup_1_20240902.log"] {
+            fs::write(dir.path().join(name), "content").unwrap();
+        }
+
+        let mut files = list_files(dir.path());
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(20));
+            files = list_files(dir.path());
+            if files.len() == 2 {
+                break;
+            }
+        }
+        assert_eq!(files[0], "cleanup_1_20240902.log");
+        assert_eq!(
+            files[1],
+            appender
+                .state
+                .read()
+                .file_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn verify_compression_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            verify_compression: true,
+            ..test_config(dir.path())
+        };
+        let file = dir.path().join("taosx_1_20240901.log");
+        fs::write(&file, "content\n".repeat(100)).unwrap();
+
+        handle_old_files(config, Some(file)).unwrap();
+        assert_eq!(
+            list_files(dir.path()),
+            vec!["taosx_1.manifest", "taosx_1_20240901.log.gz"]
+        );
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("taosx_1.manifest")).unwrap())
+                .unwrap();
+        assert_eq!(manifest["file"], "taosx_1_20240901.log.gz");
+        assert_eq!(manifest["size"], 800);
+        assert_eq!(manifest["verified"], true);
+    }
+
+    #[test]
+    fn verify_truncated_archive_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("taosx_1_20240901.log");
+        fs::write(&file, "content\n".repeat(100)).unwrap();
+
+        let archive = compress(&file, Compression::Gzip).unwrap().unwrap();
+        verify_archive(&archive).unwrap();
+
+        let len = fs::metadata(&archive.path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&archive.path)
+            .unwrap()
+            .set_len(len / 2)
+            .unwrap();
+        assert!(verify_archive(&archive).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [3; 32];
+        let config = Config {
+            compress: false,
+            encryption: Some(Arc::new(key)),
+            ..test_config(dir.path())
+        };
+        let plain = dir.path().join("taosx_1_20240901.log");
+        fs::write(&plain, "secret\n").unwrap();
+        handle_old_files(config.clone(), Some(plain)).unwrap();
+        let compressed = dir.path().join("taosx_1_20240902.log");
+        fs::write(&compressed, "secret\n".repeat(100)).unwrap();
+        handle_old_files(
+            Config {
+                compress: true,
+                ..config
+            },
+            Some(compressed),
+        )
+        .unwrap();
+
+        assert_eq!(
+            list_files(dir.path()),
+            vec!["taosx_1_20240901.log.enc", "taosx_1_20240902.log.gz.enc"]
+        );
+        let decrypt = |name: &str| {
+            let mut plain = Vec::new();
+            let file = File::open(dir.path().join(name)).unwrap();
+            crate::encrypt::decrypt(&key, file, &mut plain).unwrap();
+            plain
+        };
+        assert_eq!(decrypt("taosx_1_20240901.log.enc"), b"secret\n");
+        let mut content = String::new();
+        std::io::Read::read_to_string(
+            &mut GzDecoder::new(decrypt("taosx_1_20240902.log.gz.enc").as_slice()),
+            &mut content,
+        )
+        .unwrap();
+        assert_eq!(content, "secret\n".repeat(100));
+    }
+
+    #[cfg(feature = "hash-chain")]
+    #[test]
+    fn hash_chain_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("1KB")
+            .reserved_disk_size("1KB")
+            .hash_chain(true)
+            .build()
+            .unwrap();
+        let first = appender.state.read().file_path.clone();
+        for i in 0..40 {
+            appender
+                .make_writer()
+                .write_all(format!("line {i:026}\n").as_bytes())
+                .unwrap();
+        }
+        assert_ne!(first, appender.state.read().file_path.clone());
+
+        // each file is a chain of its own
+        let lines: u64 = list_files(dir.path())
+            .iter()
+            .filter(|name| !name.ends_with(".sha256"))
+            .map(|name| chain::verify(fs::read(dir.path().join(name)).unwrap().as_slice()).unwrap())
+            .sum();
+        assert_eq!(lines, 40);
+        let digest = chain::digest_path(&first);
+        for _ in 0..1000 {
+            if digest.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(fs::read_to_string(digest).unwrap().ends_with(&format!(
+            "  {}\n",
+            first.file_name().unwrap().to_string_lossy()
+        )));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_compression_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            verify_compression: true,
+            compression: Compression::Zstd { level: 3 },
+            ..test_config(dir.path())
+        };
+        for day in 1..=4 {
+            let file = dir.path().join(format!("taosx_1_2024090{day}.log"));
+            fs::write(&file, "content\n".repeat(100)).unwrap();
+            handle_old_files(config.clone(), Some(file)).unwrap();
+        }
+
+        // zstd archives count for retention
+        assert_eq!(
+            list_files(dir.path()),
+            vec![
+                "taosx_1.manifest",
+                "taosx_1_20240902.log.zst",
+                "taosx_1_20240903.log.zst",
+                "taosx_1_20240904.log.zst"
+            ]
+        );
+        let file = dir.path().join("taosx_1_20240904.log.zst");
+        let content = zstd::decode_all(File::open(file).unwrap()).unwrap();
+        assert_eq!(content, "content\n".repeat(100).as_bytes());
+    }
+
+    #[test]
+    fn disk_stage_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "stage", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let info = event_meta(tracing::Level::INFO);
+
+        let stats = appender.stats();
+        assert_eq!(stats.disk.stage, DiskStage::Normal);
+        assert_eq!(stats.disk.downgrade_threshold, 1024);
+        assert_eq!(stats.disk.stop_threshold, 204);
+
+        appender
+            .disk
+            .available_space
+            .store(512, atomic::Ordering::SeqCst);
+        appender.make_writer_for(info).write_all(b"x").unwrap();
+        appender.make_writer_for(info).write_all(b"x").unwrap();
+        let stats = appender.stats();
+        assert_eq!(stats.disk.stage, DiskStage::Downgrade);
+        assert_eq!(stats.disk.dropped_downgrade, 2);
+
+        appender
+            .disk
+            .available_space
+            .store(100, atomic::Ordering::SeqCst);
+        appender.make_writer_for(info).write_all(b"x").unwrap();
+        let stats = appender.stats();
+        assert_eq!(stats.disk.stage, DiskStage::Stopped);
+        assert_eq!(stats.disk.dropped_stopped, 1);
+        assert!(stats.disk.since <= Local::now());
+    }
+
+    #[test]
+    fn rotation_interval_test() {
+        let at = |y, m, d, h| Local.with_ymd_and_hms(y, m, d, h, 30, 0).unwrap();
+        let ts = |y, m, d, h| {
+            Local
+                .with_ymd_and_hms(y, m, d, h, 0, 0)
+                .unwrap()
+                .timestamp()
+        };
+        let rotation = |interval| Rotation {
+            interval,
+            ..Rotation::default()
+        };
+
+        // 2024-08-23 is a Friday
+        let hourly = rotation(RotationInterval::Hourly);
+        assert_eq!(
+            hourly.next_timestamp(at(2024, 8, 23, 10)),
+            ts(2024, 8, 23, 11)
+        );
+        assert_eq!(
+            hourly.next_timestamp(at(2024, 8, 23, 23)),
+            ts(2024, 8, 24, 0)
+        );
+        let six_hours = rotation(RotationInterval::Hours(6));
+        assert_eq!(
+            six_hours.next_timestamp(at(2024, 8, 23, 10)),
+            ts(2024, 8, 23, 12)
+        );
+        assert_eq!(
+            six_hours.next_timestamp(at(2024, 8, 23, 18)),
+            ts(2024, 8, 24, 0)
+        );
+        let weekly = rotation(RotationInterval::Weekly);
+        assert_eq!(
+            weekly.next_timestamp(at(2024, 8, 23, 10)),
+            ts(2024, 8, 26, 0)
+        );
+        assert_eq!(weekly.next_timestamp(at(2024, 8, 26, 0)), ts(2024, 9, 2, 0));
+        let monthly = rotation(RotationInterval::Monthly);
+        assert_eq!(
+            monthly.next_timestamp(at(2024, 8, 23, 10)),
+            ts(2024, 9, 1, 0)
+        );
+        assert_eq!(
+            monthly.next_timestamp(at(2024, 12, 31, 23)),
+            ts(2025, 1, 1, 0)
+        );
+
+        assert_eq!(hourly.format(at(2024, 8, 23, 9)).to_string(), "2024082309");
+        assert_eq!(weekly.format(at(2024, 8, 23, 9)).to_string(), "20240823");
+        assert_eq!(
+            hourly.period(at(2024, 8, 23, 9)),
+            Some(at(2024, 8, 23, 9) - TimeDelta::minutes(30))
+        );
+        assert_eq!(
+            parse_filename("taosx", 1, "log", "taosx_1_2024082309.log.2.gz"),
+            Some((Local.with_ymd_and_hms(2024, 8, 23, 9, 0, 0).unwrap(), 2))
+        );
+    }
+
+    #[test]
+    fn rotate_at_test() {
+        let at = |d, h, m| Local.with_ymd_and_hms(2024, 8, d, h, m, 0).unwrap();
+        let rotation = |interval, h, m| Rotation {
+            interval,
+            at: NaiveTime::from_hms_opt(h, m, 0).unwrap(),
+            ..Rotation::default()
+        };
+
+        let daily = rotation(RotationInterval::Daily, 4, 0);
+        assert_eq!(
+            daily.next_timestamp(at(23, 10, 0)),
+            at(24, 4, 0).timestamp()
+        );
+        assert_eq!(daily.next_timestamp(at(24, 2, 0)), at(24, 4, 0).timestamp());
+        // named by the day the period started
+        assert_eq!(daily.format(at(24, 2, 0)).to_string(), "20240823");
+        assert_eq!(daily.format(at(24, 4, 0)).to_string(), "20240824");
+        assert_eq!(daily.period_end(at(23, 0, 0)), at(24, 4, 0).timestamp());
+
+        let hourly = rotation(RotationInterval::Hourly, 4, 30);
+        assert_eq!(
+            hourly.next_timestamp(at(23, 10, 0)),
+            at(23, 10, 30).timestamp()
+        );
+        assert_eq!(
+            hourly.next_timestamp(at(23, 10, 45)),
+            at(23, 11, 30).timestamp()
+        );
+        assert_eq!(hourly.format(at(23, 10, 15)).to_string(), "2024082309");
+        assert_eq!(hourly.period_end(at(23, 9, 0)), at(23, 10, 30).timestamp());
+
+        let six_hours = rotation(RotationInterval::Hours(6), 4, 0);
+        assert_eq!(
+            six_hours.next_timestamp(at(23, 3, 0)),
+            at(23, 4, 0).timestamp()
+        );
+        assert_eq!(
+            six_hours.next_timestamp(at(23, 5, 0)),
+            at(23, 10, 0).timestamp()
+        );
+        assert_eq!(
+            six_hours.next_timestamp(at(23, 23, 0)),
+            at(24, 4, 0).timestamp()
+        );
+    }
+
+    #[test]
+    fn hourly_rotate_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "hourly", 1)
+            .reserved_disk_size("1KB")
+            .rotation_interval(RotationInterval::Hourly)
+            .build()
+            .unwrap();
+        let hour = Local::now().format("%Y%m%d%H").to_string();
+        assert_eq!(list_files(dir.path()), vec![format!("hourly_1_{hour}.log")]);
+
+        // the hour boundary is reached, the file of the same hour gets a sequence id
+        appender.next_date.store(0, atomic::Ordering::Relaxed);
+        appender.make_writer().write_all(b"x").unwrap();
+        let files = list_files(dir.path());
+        assert_eq!(files.len(), 2, "{files:?}");
+        assert!(files
+            .iter()
+            .all(|f| parse_filename("hourly", 1, "log", f).is_some()));
+        assert!(appender.next_date.load(atomic::Ordering::Relaxed) > Local::now().timestamp());
+    }
+
+    #[test]
+    fn fallback_dir_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let (dir, fallback_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .position_hints(true)
+            .compress(true)
+            .fallback_dir(fallback_dir.path())
+            .build()
+            .unwrap();
+        let meta = event_meta(tracing::Level::INFO);
+        let today = Local::now().format("%Y%m%d");
+        let read = |dir: &tempfile::TempDir| {
+            fs::read_to_string(dir.path().join(format!("taosx_1_{today}.log"))).unwrap()
+        };
+
+        appender
+            .make_writer_for(meta)
+            .write_all(b"primary\n")
+            .unwrap();
+        appender
+            .disk
+            .available_space
+            .store(512, atomic::Ordering::SeqCst);
+        appender
+            .make_writer_for(meta)
+            .write_all(b"fallback\n")
+            .unwrap();
+        appender
+            .disk
+            .available_space
+            .store(1 << 30, atomic::Ordering::SeqCst);
+        appender.make_writer_for(meta).write_all(b"back\n").unwrap();
+
+        assert_eq!(read(&dir), "primary\nback\n");
+        assert_eq!(read(&fallback_dir), "fallback\n");
+        assert_eq!(appender.fallback().unwrap().instance_id(), 1);
+        // no downgrade while the fallback has space
+        assert_eq!(appender.stats().disk.dropped_downgrade, 0);
+
+        // rotated on the maintenance thread of the primary, reported to readers
+        let fallback = appender.fallback().unwrap();
+        assert!(fallback.event_tx.same_channel(&appender.event_tx));
+        assert!(!fallback.owns_maintenance);
+        fallback.rotate_now(RotationReason::Manual).unwrap();
+        appender.shutdown().unwrap();
+        let hints =
+            fs::read_to_string(fallback_dir.path().join("taosx_1.positions-hints.json")).unwrap();
+        assert!(hints.contains(".log.gz"), "{hints}");
+    }
+
+    #[test]
+    fn archive_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .rotation_size("1KB")
+            .rotation_count(2)
+            .compress(true)
+            .archive(true)
+            .build()
+            .unwrap();
+        for _ in 0..5 {
+            appender.make_writer().write_all(&[b'x'; 600]).unwrap();
+        }
+        appender.shutdown().unwrap();
+
+        let now = Local::now();
+        let today = now.format("%Y%m%d");
+        let month = dir
+            .path()
+            .join(ARCHIVE_DIR)
+            .join(now.format("%Y%m").to_string());
+        assert_eq!(
+            list_files(dir.path()),
+            [ARCHIVE_DIR.to_string(), format!("taosx_1_{today}.log.2")]
+        );
+        // the oldest archived file was deleted by the rotation count
+        assert_eq!(list_files(&month), [format!("taosx_1_{today}.log.1.gz")]);
+
+        fs::remove_file(dir.path().join(format!("taosx_1_{today}.log.2"))).unwrap();
+        let period = Rotation::default().period(now);
+        assert_eq!(
+            max_seq_id("taosx", 1, "log", dir.path(), period).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn never_rotate_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let today = Local::now().format("%Y%m%d").to_string();
+        let size_only = RollingFileAppender::builder(dir.path(), "size", 1)
+            .reserved_disk_size("1KB")
+            .rotation_size("1KB")
+            .rotation_interval(RotationInterval::Never)
+            .build()
+            .unwrap();
+        assert_eq!(
+            size_only.next_date.load(atomic::Ordering::Relaxed),
+            i64::MAX
+        );
+        for _ in 0..3 {
+            size_only.make_writer().write_all(&[b'x'; 600]).unwrap();
+        }
+        let files = list_files(dir.path());
+        assert_eq!(
+            files,
+            [
+                format!("size_1_{today}.log"),
+                format!("size_1_{today}.log.1")
+            ]
+        );
+
+        let time_only = RollingFileAppender::builder(dir.path(), "time", 1)
+            .reserved_disk_size("1KB")
+            .no_size_rotation()
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            time_only.make_writer().write_all(&[b'x'; 600]).unwrap();
+        }
+        let files = list_files(dir.path());
+        assert_eq!(files.len(), 3, "{files:?}");
+        time_only.next_date.store(0, atomic::Ordering::Relaxed);
+        time_only.make_writer().write_all(b"x").unwrap();
+        assert_eq!(list_files(dir.path()).len(), 4);
+
+        let rotation = Rotation {
+            interval: RotationInterval::Never,
+            ..Rotation::default()
+        };
+        let date = Local.with_ymd_and_hms(2024, 8, 23, 0, 0, 0).unwrap();
+        assert_eq!(
+            rotation.period_end(date),
+            (date + TimeDelta::days(1)).timestamp()
+        );
+    }
+
+    #[test]
+    fn read_only_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "ro", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        assert!(!appender.stats().read_only);
+        assert!(matches!(appender.make_writer(), TaosLogWriter::Rolling(_)));
+
+        appender.read_only.set(true);
+        assert!(appender.stats().read_only);
+        assert!(matches!(appender.make_writer(), TaosLogWriter::Stderr(_)));
+
+        appender.read_only.set(false);
+        assert!(matches!(appender.make_writer(), TaosLogWriter::Rolling(_)));
+    }
+
+    #[test]
+    fn position_hints_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "hints", 1)
+            .reserved_disk_size("1KB")
+            .rotation_size("1KB")
+            .rotation_count(1)
+            .position_hints(true)
+            .build()
+            .unwrap();
+        let read_hints = || -> serde_json::Value {
+            let path = dir.path().join("hints_1.positions-hints.json");
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let first = read_hints()["active"]["path"].clone();
+
+        appender.make_writer().write_all(&[b'x'; 1024]).unwrap();
+        appender.make_writer().write_all(b"x").unwrap();
+        // wait for the cleaner to delete the rotated file
+        for _ in 0..1000 {
+            if read_hints()["events"].as_array().unwrap().len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let hints = read_hints();
+        assert_ne!(hints["active"]["path"], first);
+        assert_eq!(hints["events"][0]["event"], "rotated");
+        assert_eq!(hints["events"][0]["path"], first);
+        assert_eq!(hints["events"][0]["size"], 1024);
+        assert_eq!(hints["events"][1]["event"], "deleted");
+        assert_eq!(hints["events"][1]["path"], first);
+    }
+
+    #[test]
+    fn max_total_size_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            compress: false,
+            rotate_count: 0,
+            max_total_size: Some(250),
+            ..test_config(dir.path())
+        };
+        for (name, size) in [
+            ("taosx_1_20240901.log.gz", 100),
+            ("taosx_1_20240902.log", 100),
+            ("taosx_1_20240903.log.1", 100),
+            ("taosx_1_20240903.log", 100),
+            ("taosx_2_20240901.log", 1000),
+        ] {
+            fs::write(dir.path().join(name), vec![b'x'; size]).unwrap();
+        }
+
+        handle_old_files(config.clone(), None).unwrap();
+        assert_eq!(
+            list_files(dir.path()),
+            vec![
+                "taosx_1_20240903.log",
+                "taosx_1_20240903.log.1",
+                "taosx_2_20240901.log"
+            ]
+        );
+        // deleted right after their last write
+        let usage = config.usage.stats(Settings::default());
+        assert_eq!(usage.deleted_files, 2);
+        assert!(usage.advisor()[0].starts_with("files deleted"));
+
+        // the file being written is kept even above the cap
+        let config = Config {
+            max_total_size: Some(10),
+            ..config
+        };
+        handle_old_files(config, None).unwrap();
+        assert_eq!(
+            list_files(dir.path()),
+            vec!["taosx_1_20240903.log.1", "taosx_2_20240901.log"]
+        );
+    }
+
+    #[test]
+    fn max_age_days_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            compress: false,
+            rotate_count: 0,
+            max_age_days: Some(7),
+            ..test_config(dir.path())
+        };
+        let day = |days: i64| (Local::now() - TimeDelta::days(days)).format(DATE_FORMAT);
+        let names = [
+            format!("taosx_1_{}.log.gz", day(30)),
+            format!("taosx_1_{}.log", day(8)),
+            format!("taosx_1_{}.log", day(6)),
+            format!("taosx_1_{}.log", day(0)),
+        ];
+        for name in &names {
+            fs::write(dir.path().join(name), "x").unwrap();
+        }
+
+        handle_old_files(config, None).unwrap();
+        assert_eq!(list_files(dir.path()), names[2..]);
+    }
+
+    #[test]
+    fn builder_auto_instance_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = || {
+            RollingFileAppender::builder_auto_instance(dir.path(), "agent")
+                .unwrap()
+                .reserved_disk_size("1KB")
+                .build()
+                .unwrap()
+        };
+        let a = build();
+        let b = build();
+        assert_eq!((a.instance_id(), b.instance_id()), (0, 1));
+
+        drop(a);
+        assert_eq!(build().instance_id(), 0);
+    }
+
+    #[test]
+    fn maintenance_log_test() {
+        use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt};
+
+        use crate::{
+            fake::{Capture, Qid},
+            layer::TaosLayer,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+        let config = Config {
+            rotate_count: 1,
+            ..test_config(dir.path())
+        };
+        let file = dir.path().join("taosx_1_20240901.log");
+        fs::write(&file, "content\n").unwrap();
+        fs::write(dir.path().join("taosx_1_20240902.log"), "").unwrap();
+
+        let appender = RollingFileAppender::builder(dir.path(), "maintenance", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                enter_maintenance();
+                tracing::subscriber::with_default(subscriber, || {
+                    handle_old_files(config, Some(file)).unwrap();
+                });
+                // no rotation from a maintenance thread
+                appender.next_date.store(0, atomic::Ordering::Relaxed);
+                appender.make_writer().write_all(b"x").unwrap();
+                assert_eq!(appender.next_date.load(atomic::Ordering::Relaxed), 0);
+            });
+        });
+
+        let content = capture.content();
+        assert!(content.contains("bytes_source:8B compressed"), "{content}");
+        assert!(content.contains("deleted"));
+        assert_eq!(list_files(dir.path()).len(), 2);
+    }
+
+    #[test]
+    fn append_only_test() {
+        use std::io::Read;
+
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+
+        // another handle appending to the same file concurrently
+        let other = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let lines = 1000;
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..lines {
+                    (&other)
+                        .write_all(format!("other {i:04} {}\n", "y".repeat(64)).as_bytes())
+                        .unwrap();
+                }
+            });
+            for i in 0..lines {
+                appender
+                    .make_writer()
+                    .write_all(format!("appender {i:04} {}\n", "x".repeat(64)).as_bytes())
+                    .unwrap();
+            }
+        });
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), lines * 2);
+        for line in content.lines() {
+            assert!(
+                line.starts_with("appender ") && line.ends_with(&"x".repeat(64))
+                    || line.starts_with("other ") && line.ends_with(&"y".repeat(64)),
+                "{line}"
+            );
+        }
+
+        // writes after an external truncation start at the new end, no hole
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        appender.make_writer().write_all(b"truncated\n").unwrap();
+        let mut content = Vec::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"truncated\n");
+    }
+
+    #[test]
+    fn deleted_file_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+        // a rotated file of the same period is kept, not appended to
+        fs::write(format!("{}.1", path.display()), "old\n").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        appender.make_writer().write_all(b"recreated\n").unwrap();
+        let current = appender.state.read().file_path.clone();
+        assert_eq!(current, PathBuf::from(format!("{}.2", path.display())));
+        assert_eq!(fs::read_to_string(&current).unwrap(), "recreated\n");
+        assert_eq!(
+            fs::read_to_string(format!("{}.1", path.display())).unwrap(),
+            "old\n"
+        );
+
+        // the next write stays in the recreated file
+        appender.make_writer().write_all(b"again\n").unwrap();
+        assert_eq!(fs::read_to_string(&current).unwrap(), "recreated\nagain\n");
+    }
+
+    #[test]
+    fn shared_maintenance_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = SharedMaintenance::new(dir.path()).unwrap();
+        let appenders: Vec<_> = ["taosx", "taosx-agent"]
+            .into_iter()
+            .map(|component| {
+                RollingFileAppender::builder(dir.path(), component, 1)
+                    .rotation_size("1KB")
+                    .reserved_disk_size("1KB")
+                    .compress(true)
+                    .shared_maintenance(&shared)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        for appender in &appenders {
+            appender.make_writer().write_all(&[b'x'; 1024]).unwrap();
+            appender.make_writer().write_all(b"x").unwrap();
+        }
+
+        // one maintenance thread compresses the files of both
+        let archives = || {
+            list_files(dir.path())
+                .into_iter()
+                .filter(|name| name.ends_with(".gz"))
+                .count()
+        };
+        for _ in 0..1000 {
+            if archives() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(archives(), 2, "{:?}", list_files(dir.path()));
+
+        let other = tempfile::tempdir().unwrap();
+        let res = RollingFileAppender::builder(other.path(), "taosx", 1)
+            .shared_maintenance(&shared)
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn shutdown_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = SharedMaintenance::new(dir.path()).unwrap();
+        let build = |component: &str, shared: Option<&SharedMaintenance>| {
+            let builder = RollingFileAppender::builder(dir.path(), component, 1)
+                .rotation_size("1KB")
+                .reserved_disk_size("1KB")
+                .with_heartbeat(Duration::from_secs(3600))
+                .compress(true);
+            match shared {
+                Some(shared) => builder.shared_maintenance(shared),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+        let archives = |component: &str| {
+            list_files(dir.path())
+                .into_iter()
+                .filter(|name| name.starts_with(&format!("{component}_")) && name.ends_with(".gz"))
+                .count()
+        };
+
+        // the rotated file is compressed on return
+        for (component, shared) in [("own", None), ("shared", Some(&shared))] {
+            let appender = build(component, shared);
+            appender.make_writer().write_all(&[b'x'; 1024]).unwrap();
+            appender.make_writer().write_all(b"last\n").unwrap();
+            appender.shutdown().unwrap();
+            assert_eq!(archives(component), 1, "{:?}", list_files(dir.path()));
+            let last = list_files(dir.path())
+                .into_iter()
+                .find(|name| name.starts_with(&format!("{component}_")) && name.ends_with(".1"))
+                .unwrap();
+            assert_eq!(fs::read_to_string(dir.path().join(last)).unwrap(), "last\n");
+            // later events are still written
+            appender.make_writer().write_all(b"after\n").unwrap();
+            appender.shutdown().unwrap();
+        }
+
+        // the shared maintenance thread still serves other appenders
+        let other = build("other", Some(&shared));
+        other.make_writer().write_all(&[b'x'; 1024]).unwrap();
+        other.make_writer().write_all(b"x").unwrap();
+        drop(other);
+        assert_eq!(archives("other"), 1, "{:?}", list_files(dir.path()));
+    }
+
+    #[test]
+    fn on_rotate_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, rx) = flume::unbounded();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("1KB")
+            .reserved_disk_size("1KB")
+            .compress(true)
+            .on_rotate(move |event| {
+                // not compressed yet
+                let exists = event.old_path.is_file();
+                tx.send((event, exists)).unwrap();
+            })
+            .build()
+            .unwrap();
+        let first = appender.state.read().file_path.clone();
+
+        appender.make_writer().write_all(&[b'x'; 1024]).unwrap();
+        appender.make_writer().write_all(b"x").unwrap();
+        let second = appender.state.read().file_path.clone();
+        let (event, exists) = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            event,
+            RotationEvent {
+                old_path: first,
+                new_path: second.clone(),
+                reason: RotationReason::Size,
+            }
+        );
+        assert!(exists);
+
+        fs::remove_file(&second).unwrap();
+        // checked once a second
+        appender.next_check.store(0, atomic::Ordering::Relaxed);
+        appender.make_writer().write_all(b"x").unwrap();
+        let (event, _) = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(event.old_path, second);
+        assert_eq!(event.new_path, appender.state.read().file_path.clone());
+        assert_eq!(event.reason, RotationReason::MissingFile);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaced_file_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let path = appender.current_file_path();
+        appender.make_writer().write_all(b"before\n").unwrap();
+
+        // logrotate moves the file away and creates a new one
+        let moved = dir.path().join("moved");
+        fs::rename(&path, &moved).unwrap();
+        fs::write(&path, "").unwrap();
+        // checked once a second
+        appender.next_check.store(0, atomic::Ordering::Relaxed);
+        appender.make_writer().write_all(b"after\n").unwrap();
+        assert_eq!(appender.current_file_path(), path);
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "before\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+
+        // copytruncate
+        appender.make_writer().write_all(b"more\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        appender.make_writer().write_all(b"truncated\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "truncated\n");
+        assert_eq!(appender.metrics().snapshot().missing_file_rotations, 1);
+    }
+
+    #[test]
+    fn write_buffer_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .write_buffer("1KB")
+            .buffer_flush_interval(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+        let content = || fs::read_to_string(&path).unwrap();
+
+        appender.make_writer().write_all(b"buffered\n").unwrap();
+        assert_eq!(content(), "");
+
+        // an ERROR event writes the buffer
+        appender
+            .make_writer_for(event_meta(tracing::Level::ERROR))
+            .write_all(b"failed\n")
+            .unwrap();
+        assert_eq!(content(), "buffered\nfailed\n");
+
+        // a full buffer is written
+        for _ in 0..17 {
+            appender.make_writer().write_all(&[b'x'; 63]).unwrap();
+        }
+        assert_eq!(content().len(), 16 + 63 * 17);
+
+        appender.make_writer().write_all(b"dropped\n").unwrap();
+        drop(appender);
+        assert!(content().ends_with("dropped\n"));
+
+        // periodic flush
+        let appender = RollingFileAppender::builder(dir.path(), "periodic", 1)
+            .reserved_disk_size("1KB")
+            .write_buffer("1KB")
+            .buffer_flush_interval(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+        appender.make_writer().write_all(b"later\n").unwrap();
+        for _ in 0..100 {
+            if !fs::read_to_string(&path).unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "later\n");
+    }
+
+    #[test]
+    fn rotate_now_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, rx) = flume::unbounded();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .compress(true)
+            .on_rotate(move |event| tx.send(event).unwrap())
+            .build()
+            .unwrap();
+        let first = appender.current_file_path();
+        appender.make_writer().write_all(b"before\n").unwrap();
+        appender.rotate_now(RotationReason::Manual).unwrap();
+        appender.make_writer().write_all(b"after\n").unwrap();
+
+        let event = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(event.old_path, first);
+        assert_eq!(event.new_path, appender.current_file_path());
+        assert_eq!(event.reason, RotationReason::Manual);
+        assert_eq!(
+            fs::read_to_string(appender.current_file_path()).unwrap(),
+            "after\n"
+        );
+        appender.shutdown().unwrap();
+        let rotated = appender.list_rotated_files().unwrap();
+        assert_eq!(rotated, [first.with_extension("log.gz")], "{rotated:?}");
+        assert_eq!(appender.metrics().snapshot().manual_rotations, 1);
+    }
+
+    #[test]
+    fn concurrent_size_rotation_test() {
+        use std::io::Read;
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("16KB")
+            .rotation_count(100)
+            .reserved_disk_size("1KB")
+            .compress(true)
+            .build()
+            .unwrap();
+        thread::scope(|s| {
+            for t in 0..8 {
+                let appender = &appender;
+                s.spawn(move || {
+                    for i in 0..500 {
+                        let line = format!("{t:02} {i:04} {}\n", "x".repeat(55));
+                        appender.make_writer().write_all(line.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        appender.shutdown().unwrap();
+
+        let current = appender.current_file_path();
+        let mut lines = Vec::new();
+        let mut rotated = 0;
+        for name in list_files(dir.path()) {
+            let path = dir.path().join(&name);
+            let mut content = String::new();
+            if name.ends_with(".gz") {
+                GzDecoder::new(File::open(&path).unwrap())
+                    .read_to_string(&mut content)
+                    .unwrap();
+            } else {
+                assert_eq!(path, current, "not compressed: {name}");
+                content = fs::read_to_string(&path).unwrap();
+            }
+            if path != current {
+                rotated += 1;
+                // rotated once full, not again by a racing writer
+                assert!(content.len() >= 16 * 1024 - 8 * 64, "{name}");
+            }
+            lines.extend(content.lines().map(str::to_string));
+        }
+        // no line lost in a rotated file, nor torn
+        assert_eq!(lines.len(), 8 * 500);
+        assert!(lines.iter().all(|line| line.len() == 63));
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 8 * 500);
+        assert_eq!(appender.metrics().snapshot().size_rotations, rotated);
+    }
+
+    #[test]
+    fn accessors_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .rotation_size("1KB")
+            .reserved_disk_size("1KB")
+            .build()
+            .unwrap();
+        let first = appender.current_file_path();
+        assert!(appender.list_rotated_files().unwrap().is_empty());
+        appender.make_writer().write_all(&[b'x'; 600]).unwrap();
+        assert_eq!(appender.current_file_size(), 600);
+
+        for _ in 0..2 {
+            appender.make_writer().write_all(&[b'x'; 600]).unwrap();
+        }
+        appender.shutdown().unwrap();
+        assert_ne!(appender.current_file_path(), first);
+        assert!(appender.current_file_path().exists());
+        let rotated = appender.list_rotated_files().unwrap();
+        assert_eq!(rotated.len(), 1, "{rotated:?}");
+        assert!(rotated[0].starts_with(dir.path()));
+        assert!(appender.available_disk_space() > 0);
+        assert!(!appender.is_downgraded());
+    }
+
+    #[test]
+    fn handle_test() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        use crate::{fake::Qid, layer::TaosLayer};
+
+        static HANDLE: std::sync::OnceLock<Handle> = std::sync::OnceLock::new();
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .write_buffer("1KB")
+            .buffer_flush_interval(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let handle = HANDLE.get_or_init(|| appender.into()).clone();
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(handle));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| tracing::info!("shared"));
+        });
+
+        // the same appender, flushed through the static
+        let shared = HANDLE.get().unwrap();
+        let path = shared.state.read().file_path.clone();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        shared.shutdown().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.ends_with(" shared\n"), "{content:?}");
+        assert_eq!(Arc::strong_count(shared.appender()), 1);
+    }
+
+    #[test]
+    fn batch_writes_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let appender = RollingFileAppender::builder(dir.path(), "taosx", 1)
+            .reserved_disk_size("1KB")
+            .batch_writes(3, Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+        let content = || fs::read_to_string(&path).unwrap();
+
+        appender.make_writer().write_all(b"a\n").unwrap();
+        appender.make_writer().write_all(b"b\n").unwrap();
+        assert_eq!(content(), "");
+        appender.make_writer().write_all(b"c\n").unwrap();
+        assert_eq!(content(), "a\nb\nc\n");
+
+        // reused event buffers
+        for _ in 0..3 {
+            appender.make_writer().write_all(&[b'x'; 100]).unwrap();
+        }
+        assert_eq!(content().len(), 6 + 300);
+        appender.make_writer().write_all(b"d\n").unwrap();
+        drop(appender);
+        assert!(content().ends_with("d\n"));
+
+        // written at the end of the tick
+        let appender = RollingFileAppender::builder(dir.path(), "tick", 1)
+            .reserved_disk_size("1KB")
+            .batch_writes(100, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let path = appender.state.read().file_path.clone();
+        appender.make_writer().write_all(b"later\n").unwrap();
+        for _ in 0..100 {
+            if !fs::read_to_string(&path).unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "later\n");
+    }
+
+    #[test]
+    fn sync_policy_test() {
+        use tracing_subscriber::fmt::MakeWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let build = |instance, policy| {
+            RollingFileAppender::builder(dir.path(), "sync", instance)
+                .reserved_disk_size("1KB")
+                .sync_policy(policy)
+                .build()
+                .unwrap()
+        };
+
+        let appender = build(1, SyncPolicy::Never);
+        appender.make_writer().write_all(&[b'x'; 100]).unwrap();
+        assert_eq!(appender.stats().syncs, 0);
+
+        let appender = build(2, SyncPolicy::EveryBytes(250));
+        for _ in 0..5 {
+            appender.make_writer().write_all(&[b'x'; 100]).unwrap();
+        }
+        assert_eq!(appender.stats().syncs, 1);
+
+        let appender = build(3, SyncPolicy::Every(Duration::from_millis(10)));
+        for _ in 0..100 {
+            if appender.stats().syncs > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(appender.stats().syncs > 0);
+    }
+
+    #[test]
+    fn windows_path_test() {
+        assert_eq!(
+            strip_verbatim(Path::new(r"\\?\C:\ProgramData\taos\log")),
+            Path::new(r"C:\ProgramData\taos\log")
+        );
+        assert_eq!(
+            strip_verbatim(Path::new(r"\\?\UNC\nas\share\log")),
+            Path::new(r"\\nas\share\log")
+        );
+        assert_eq!(
+            strip_verbatim(Path::new("/var/log/taos")),
+            Path::new("/var/log/taos")
+        );
+
+        assert!(windows_starts_with(
+            Path::new(r"\\?\C:\taos\log"),
+            Path::new(r"C:\")
+        ));
+        assert!(windows_starts_with(
+            Path::new(r"c:\taos\log"),
+            Path::new(r"C:\")
+        ));
+        assert!(windows_starts_with(
+            Path::new(r"D:\mnt\vol\log"),
+            Path::new(r"D:\mnt\vol\")
+        ));
+        assert!(!windows_starts_with(
+            Path::new(r"D:\mnt\volume\log"),
+            Path::new(r"D:\mnt\vol\")
+        ));
+        assert!(!windows_starts_with(
+            Path::new(r"D:\taos\log"),
+            Path::new(r"C:\")
+        ));
+    }
+}
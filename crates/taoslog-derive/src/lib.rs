@@ -0,0 +1,193 @@
+//! `#[derive(LogSafe)]`, a `Debug` implementation masking secrets.
+//!
+//! Named fields matching the patterns of `taoslog::redact` are masked, as
+//! well as fields marked `#[log_safe(redact)]`. `#[log_safe(show)]` keeps a
+//! field matching a pattern. The generated code refers to `::taoslog`, use
+//! `#[log_safe(crate = "taoslog_core")]` on the type to change it.
+//!
+//! ```ignore
+//! #[derive(LogSafe)]
+//! struct Dsn {
+//!     host: String,
+//!     password: String,          // masked by pattern
+//!     #[log_safe(redact)]
+//!     cloud_key: String,         // masked by attribute
+//!     #[log_safe(show)]
+//!     token_ttl: u64,            // shown
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, LitStr, Path};
+
+#[proc_macro_derive(LogSafe, attributes(log_safe))]
+pub fn derive_log_safe(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// masked if the name matches a pattern
+    Auto,
+    Redact,
+    Show,
+}
+
+fn field_mode(attrs: &[syn::Attribute]) -> syn::Result<Mode> {
+    let mut mode = Mode::Auto;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("log_safe")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("redact") {
+                mode = Mode::Redact;
+                Ok(())
+            } else if meta.path.is_ident("show") {
+                mode = Mode::Show;
+                Ok(())
+            } else {
+                Err(meta.error("expected `redact` or `show`"))
+            }
+        })?;
+    }
+    Ok(mode)
+}
+
+fn crate_path(attrs: &[syn::Attribute]) -> syn::Result<Path> {
+    let mut path = parse_quote!(::taoslog);
+    for attr in attrs.iter().filter(|a| a.path().is_ident("log_safe")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                path = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `crate = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(path)
+}
+
+/// Statements adding `fields` to the builder `d`, bound by `bindings`.
+fn debug_fields(
+    krate: &Path,
+    name: &str,
+    fields: &Fields,
+    bindings: &[syn::Ident],
+) -> syn::Result<TokenStream2> {
+    let redact = quote!(#krate::redact);
+    let mut body = Vec::new();
+    for (field, binding) in fields.iter().zip(bindings) {
+        let mode = field_mode(&field.attrs)?;
+        let value = match (mode, &field.ident) {
+            (Mode::Show, _) | (Mode::Auto, None) => quote!(#binding),
+            (Mode::Redact, _) => quote!(&#redact::Mask),
+            (Mode::Auto, Some(ident)) => {
+                let field_name = ident.to_string();
+                quote! {
+                    if #redact::is_sensitive(#field_name) {
+                        &#redact::Mask as &dyn ::core::fmt::Debug
+                    } else {
+                        #binding as &dyn ::core::fmt::Debug
+                    }
+                }
+            }
+        };
+        body.push(match &field.ident {
+            Some(ident) => {
+                let field_name = ident.to_string();
+                quote!(d.field(#field_name, #value);)
+            }
+            None => quote!(d.field(#value);),
+        });
+    }
+    Ok(match fields {
+        Fields::Named(_) => quote! {
+            let mut d = f.debug_struct(#name);
+            #(#body)*
+            d.finish()
+        },
+        Fields::Unnamed(_) => quote! {
+            let mut d = f.debug_tuple(#name);
+            #(#body)*
+            d.finish()
+        },
+        Fields::Unit => quote!(f.write_str(#name)),
+    })
+}
+
+/// The pattern destructuring `fields`, and its bindings.
+fn pattern(fields: &Fields) -> (TokenStream2, Vec<syn::Ident>) {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#idents: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(( #(#bindings),* )),
+        Fields::Unit => quote!(),
+    };
+    (pattern, bindings)
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = crate_path(&input.attrs)?;
+    let ident = &input.ident;
+    let arms = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, bindings) = pattern(&data.fields);
+            let body = debug_fields(&krate, &ident.to_string(), &data.fields, &bindings)?;
+            vec![quote!(#ident #pattern => { #body })]
+        }
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let (pattern, bindings) = pattern(&variant.fields);
+                let name = &variant.ident;
+                let body = debug_fields(&krate, &name.to_string(), &variant.fields, &bindings)?;
+                Ok(quote!(#ident::#name #pattern => { #body }))
+            })
+            .collect::<syn::Result<_>>()?,
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "LogSafe cannot be derived for unions",
+            ))
+        }
+    };
+
+    let params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::core::fmt::Debug));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    // an empty enum has no value to match on through a reference
+    let scrutinee = if arms.is_empty() {
+        quote!(*self)
+    } else {
+        quote!(self)
+    };
+    Ok(quote! {
+        impl #impl_generics ::core::fmt::Debug for #ident #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                #[allow(unused_variables)]
+                match #scrutinee {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
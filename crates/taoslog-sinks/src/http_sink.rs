@@ -0,0 +1,371 @@
+//! Ship logs to a custom collector as NDJSON over HTTP.
+//!
+//! [`HttpSink`] is a `MakeWriter` queueing each line written by
+//! [`TaosLayer`](taoslog_core::layer::TaosLayer), in its [JSON](taoslog_core::layer::TaosLayer::json)
+//! output, and POSTing them in gzipped batches of newline delimited JSON.
+//!
+//! Failed batches are retried with a backoff, then spilled to files of the
+//! [`spill_dir`](HttpSinkBuilder::spill_dir) if set, and sent again oldest
+//! first once the endpoint answers. Without spill dir, or with it full, they
+//! are dropped. Only `http://` endpoints are supported.
+//!
+//! ```ignore
+//! let sink = HttpSink::builder("http://collector:8080/logs")
+//!     .header("Authorization", "Bearer ...")
+//!     .spill_dir("/var/lib/taosx/log-spill")
+//!     .build()?;
+//! tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(sink).json());
+//! ```
+
+use std::{
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Local;
+use flate2::{write::GzEncoder, Compression};
+use snafu::ResultExt;
+use tracing_subscriber::fmt::MakeWriter;
+
+use taoslog_core::size::ByteSize;
+
+use crate::{
+    post::{self, Batching, Deliver, Queue, Url},
+    CreateSpillDirSnafu, Result,
+};
+
+/// Extension of spilled batches.
+const SPILL_EXTENSION: &str = "ndjson";
+
+/// Builder of a [`HttpSink`].
+pub struct HttpSinkBuilder {
+    url: String,
+    headers: Vec<(String, String)>,
+    gzip: bool,
+    batching: Batching,
+    timeout: Duration,
+    spill_dir: Option<PathBuf>,
+    spill_max_size: ByteSize,
+}
+
+impl HttpSinkBuilder {
+    /// Add a header to each request, e.g. for authentication.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Gzip the request bodies, on by default.
+    pub fn gzip(self, gzip: bool) -> Self {
+        Self { gzip, ..self }
+    }
+
+    /// Lines per request, 500 by default.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batching.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Longest wait before sending a partial batch, 1s by default.
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.batching.flush_interval = flush_interval;
+        self
+    }
+
+    /// Lines waiting to be sent before new ones are dropped, 10000 by default.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.batching.queue_capacity = queue_capacity.max(1);
+        self
+    }
+
+    /// Retries of a failed batch before it is spilled or dropped, 3 by
+    /// default, waiting 500ms then twice as long each time.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.batching.max_retries = max_retries;
+        self
+    }
+
+    /// Timeout of each request, 10s by default.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Keep failed batches in `dir` until the endpoint is back.
+    pub fn spill_dir(self, dir: impl AsRef<Path>) -> Self {
+        Self {
+            spill_dir: Some(dir.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    /// Size of the spilled batches beyond which failed ones are dropped,
+    /// 100MB by default.
+    pub fn spill_max_size(self, size: impl Into<ByteSize>) -> Self {
+        Self {
+            spill_max_size: size.into(),
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<HttpSink> {
+        let url = Url::parse(&self.url)?;
+        if let Some(dir) = &self.spill_dir {
+            fs::create_dir_all(dir).context(CreateSpillDirSnafu { path: dir })?;
+        }
+        let client = Client {
+            url,
+            headers: self.headers,
+            gzip: self.gzip,
+            timeout: self.timeout,
+            spill: self.spill_dir.map(|dir| Spill {
+                dir,
+                max_size: self.spill_max_size.bytes(),
+                seq: 0,
+            }),
+        };
+        let queue = post::spawn("taoslog-http-sink", self.batching, client);
+        Ok(HttpSink {
+            queue: Arc::new(queue),
+        })
+    }
+}
+
+struct Client {
+    url: Url,
+    headers: Vec<(String, String)>,
+    gzip: bool,
+    timeout: Duration,
+    spill: Option<Spill>,
+}
+
+impl Client {
+    fn post(&self, batch: &[u8]) -> io::Result<()> {
+        let mut headers = vec![("Content-Type", "application/x-ndjson")];
+        let body = if self.gzip {
+            headers.push(("Content-Encoding", "gzip"));
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(batch)?;
+            encoder.finish()?
+        } else {
+            batch.to_vec()
+        };
+        headers.extend(self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        post::post(&self.url, &self.url.path, &headers, &body, self.timeout)
+    }
+}
+
+impl Deliver for Client {
+    fn send(&mut self, batch: &[u8]) -> io::Result<()> {
+        self.post(batch)?;
+        // the endpoint is back, resend what failed before
+        if let Some(spill) = &self.spill {
+            for path in spill.files() {
+                match fs::read(&path).and_then(|batch| self.post(&batch)) {
+                    Ok(()) => fs::remove_file(&path).unwrap_or_default(),
+                    Err(_) => break,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn failed(&mut self, batch: Vec<u8>, error: io::Error) {
+        let lines = batch.iter().filter(|&&b| b == b'\n').count();
+        let spilled = match &mut self.spill {
+            Some(spill) => spill.save(&batch),
+            None => Ok(false),
+        };
+        match spilled {
+            Ok(true) => {}
+            Ok(false) => eprintln!("[HttpSink] Dropped {lines} lines after retries: {error}"),
+            Err(e) => eprintln!("[HttpSink] Dropped {lines} lines, unable to spill them: {e}"),
+        }
+    }
+}
+
+/// Failed batches, one file each.
+struct Spill {
+    dir: PathBuf,
+    max_size: u64,
+    seq: u32,
+}
+
+impl Spill {
+    /// Spilled files, oldest first.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == SPILL_EXTENSION))
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Save `batch`, `false` if it would exceed the max size.
+    fn save(&mut self, batch: &[u8]) -> io::Result<bool> {
+        let size: u64 = self
+            .files()
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if size + batch.len() as u64 > self.max_size {
+            return Ok(false);
+        }
+        self.seq = self.seq.wrapping_add(1);
+        let name = format!(
+            "{}-{:010}.{SPILL_EXTENSION}",
+            Local::now().format("%Y%m%d%H%M%S%6f"),
+            self.seq
+        );
+        // renamed once written, so a partial file is never sent
+        let path = self.dir.join(name);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, batch)?;
+        fs::rename(tmp, path)?;
+        Ok(true)
+    }
+}
+
+/// Sends NDJSON lines to a collector, see the [module](self) documentation.
+#[derive(Clone)]
+pub struct HttpSink {
+    queue: Arc<Queue>,
+}
+
+impl HttpSink {
+    /// A sink POSTing to `url`, e.g. `http://collector:8080/logs`.
+    pub fn builder(url: &str) -> HttpSinkBuilder {
+        HttpSinkBuilder {
+            url: url.to_string(),
+            headers: Vec::new(),
+            gzip: true,
+            batching: Batching::default(),
+            timeout: Duration::from_secs(10),
+            spill_dir: None,
+            spill_max_size: ByteSize::mb(100),
+        }
+    }
+
+    /// Lines dropped with the queue full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+/// Buffers one line, queued when dropped.
+pub struct HttpLine<'a> {
+    sink: &'a HttpSink,
+    buf: Vec<u8>,
+}
+
+impl io::Write for HttpLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for HttpLine<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.sink.queue.push(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for HttpSink {
+    type Writer = HttpLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        HttpLine {
+            sink: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::tests::serve;
+
+    fn wait(until: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if until() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn http_sink_test() {
+        let (url, requests) = serve(200);
+        let sink = HttpSink::builder(&format!("{url}/logs"))
+            .header("Authorization", "Bearer t")
+            .flush_interval(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        sink.make_writer().write_all(b"{\"a\":1}\n").unwrap();
+        sink.make_writer().write_all(b"{\"b\":2}\n").unwrap();
+
+        wait(|| !requests.lock().unwrap().is_empty());
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /logs HTTP/1.1\r\n"), "{request}");
+        assert!(request.contains("Content-Type: application/x-ndjson\r\n"));
+        assert!(request.contains("Content-Encoding: gzip\r\n"));
+        assert!(request.contains("Authorization: Bearer t\r\n"));
+    }
+
+    #[test]
+    fn spill_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, requests) = serve(503);
+        let sink = HttpSink::builder(&url)
+            .gzip(false)
+            .max_retries(0)
+            .flush_interval(Duration::from_millis(50))
+            .spill_dir(dir.path())
+            .build()
+            .unwrap();
+        sink.make_writer().write_all(b"{\"a\":1}\n").unwrap();
+        let spilled = || {
+            Spill {
+                dir: dir.path().to_path_buf(),
+                max_size: 0,
+                seq: 0,
+            }
+            .files()
+        };
+        wait(|| spilled().len() == 1);
+        assert_eq!(fs::read_to_string(&spilled()[0]).unwrap(), "{\"a\":1}\n");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // the endpoint is back
+        let (url, requests) = serve(200);
+        let sink = HttpSink::builder(&url)
+            .gzip(false)
+            .flush_interval(Duration::from_millis(50))
+            .spill_dir(dir.path())
+            .build()
+            .unwrap();
+        sink.make_writer().write_all(b"{\"b\":2}\n").unwrap();
+        wait(|| requests.lock().unwrap().len() == 2);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].ends_with("{\"b\":2}\n"));
+        assert!(requests[1].ends_with("{\"a\":1}\n"));
+        wait(|| spilled().is_empty());
+    }
+}
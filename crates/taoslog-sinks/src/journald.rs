@@ -0,0 +1,226 @@
+//! systemd-journald backend, sending events as structured journal entries so
+//! `journalctl QID=0x... COMPONENT=taosx` filters them.
+//!
+//! Each event has `MESSAGE`, `PRIORITY` from its level, `QID`, `TARGET`,
+//! `CODE_FILE`, `CODE_LINE`, its fields in upper case, and `COMPONENT`,
+//! `INSTANCE_ID` and `SYSLOG_IDENTIFIER` when set with
+//! [`JournaldLayer::with_identity`].
+//!
+//! ```ignore
+//! tracing_subscriber::registry()
+//!     .with(taoslog::journald::JournaldLayer::<Qid>::new()?.with_identity("taosx", 1))
+//!     .try_init()?;
+//! ```
+
+use std::{fmt, marker::PhantomData, os::unix::net::UnixDatagram, path::Path};
+
+use snafu::ResultExt;
+use taoslog_core::{utils::qid_to_hex, QidManager};
+use tracing::{
+    field::{Field, Visit},
+    span, Event,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{JournaldConnectSnafu, Result};
+
+/// Socket of the native journal protocol.
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// A layer sending events to journald, see the [module](self) documentation.
+pub struct JournaldLayer<Q> {
+    socket: UnixDatagram,
+    identity: Option<(String, u8)>,
+    _q: PhantomData<fn(Q)>,
+}
+
+impl<Q> JournaldLayer<Q> {
+    /// Connect to the journal at [`JOURNALD_SOCKET`].
+    pub fn new() -> Result<Self> {
+        Self::with_socket(JOURNALD_SOCKET)
+    }
+
+    /// Connect to the journal listening on `path`.
+    pub fn with_socket(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path).map(|_| socket))
+            .context(JournaldConnectSnafu { path })?;
+        Ok(Self {
+            socket,
+            identity: None,
+            _q: PhantomData,
+        })
+    }
+
+    /// Add `COMPONENT`, `INSTANCE_ID` and `SYSLOG_IDENTIFIER` to each entry.
+    pub fn with_identity(self, component: &str, instance: u8) -> Self {
+        Self {
+            identity: Some((component.to_string(), instance)),
+            ..self
+        }
+    }
+}
+
+/// Append `name=value`, in the binary form if `value` has a new line.
+fn put_field(buf: &mut Vec<u8>, name: &str, value: &[u8]) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
+
+/// Journal field names only have `A-Z`, `0-9` and `_`, and cannot start with
+/// `_` which is reserved for trusted fields.
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    name.trim_start_matches('_').to_string()
+}
+
+fn priority(level: &tracing::Level) -> &'static [u8] {
+    match *level {
+        tracing::Level::ERROR => b"3",
+        tracing::Level::WARN => b"4",
+        tracing::Level::INFO => b"6",
+        _ => b"7",
+    }
+}
+
+struct JournalVisit<'a>(&'a mut Vec<u8>);
+
+impl Visit for JournalVisit<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let name = match field.name() {
+            "message" => "MESSAGE".to_string(),
+            name => field_name(name),
+        };
+        if !name.is_empty() {
+            put_field(self.0, &name, value.as_bytes());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+impl<S, Q> Layer<S> for JournaldLayer<Q>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    Q: QidManager,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if span.extensions().get::<Q>().is_some() {
+            return;
+        }
+        let qid = match span
+            .parent()
+            .as_ref()
+            .and_then(|p| p.extensions().get::<Q>().cloned())
+        {
+            Some(qid) => qid,
+            None => taoslog_core::scope::current().map_or_else(Q::init, Q::from),
+        };
+        span.extensions_mut().insert(qid);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut buf = Vec::with_capacity(256);
+        put_field(&mut buf, "PRIORITY", priority(metadata.level()));
+        let qid = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<Q>().map(Q::get))
+            .or_else(taoslog_core::scope::current);
+        if let Some(qid) = qid {
+            put_field(&mut buf, "QID", qid_to_hex(qid).as_bytes());
+        }
+        if let Some((component, instance)) = &self.identity {
+            put_field(&mut buf, "COMPONENT", component.as_bytes());
+            put_field(&mut buf, "INSTANCE_ID", instance.to_string().as_bytes());
+            put_field(&mut buf, "SYSLOG_IDENTIFIER", component.as_bytes());
+        }
+        put_field(&mut buf, "TARGET", metadata.target().as_bytes());
+        if let Some(file) = metadata.file() {
+            put_field(&mut buf, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = metadata.line() {
+            put_field(&mut buf, "CODE_LINE", line.to_string().as_bytes());
+        }
+        event.record(&mut JournalVisit(&mut buf));
+        if let Err(e) = self.socket.send(&buf) {
+            eprintln!("[JournaldLayer] Unable to send an event to journald: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use taoslog_core::utils::{QidMetadataSetter, Span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::fake::Qid;
+
+    #[test]
+    fn journald_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let layer = JournaldLayer::<Qid>::with_socket(&path)
+            .unwrap()
+            .with_identity("taosx", 2);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("sync").in_scope(|| {
+                Span.set_qid(&Qid::from(0x1234));
+                tracing::warn!(rows = 10, sql = "select 1;\nselect 2;", "slow query");
+            })
+        });
+
+        let mut buf = [0; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let entry = &buf[..n];
+        let text = String::from_utf8_lossy(entry);
+        for field in [
+            "PRIORITY=4\n",
+            "QID=0x0000000000001234\n",
+            "COMPONENT=taosx\n",
+            "INSTANCE_ID=2\n",
+            "SYSLOG_IDENTIFIER=taosx\n",
+            "MESSAGE=slow query\n",
+            "ROWS=10\n",
+        ] {
+            assert!(text.contains(field), "{field} not in {text}");
+        }
+        // multi-line values use the binary form
+        let sql = b"select 1;\nselect 2;";
+        let mut binary = b"SQL\n".to_vec();
+        binary.extend_from_slice(&(sql.len() as u64).to_le_bytes());
+        binary.extend_from_slice(sql);
+        assert!(entry.windows(binary.len()).any(|w| w == binary));
+    }
+
+    #[test]
+    fn field_name_test() {
+        assert_eq!(field_name("bytes_total"), "BYTES_TOTAL");
+        assert_eq!(field_name("_private"), "PRIVATE");
+        assert_eq!(field_name("http.method"), "HTTP_METHOD");
+    }
+}
@@ -0,0 +1,69 @@
+//! Destinations of the taoslog lines other than the log files, each behind a
+//! feature of its own:
+//!
+//! - `syslog`: [`syslog`] the local syslog daemon or a remote RFC 5424 endpoint
+//! - `http-sink`: [`http_sink`] NDJSON batches POSTed to a collector
+//! - `journald`: [`journald`] layer sending structured entries to systemd-journald
+//! - `tdengine`: [`tdengine`] rows written to TDengine through taosadapter
+
+use std::path::PathBuf;
+
+#[cfg(feature = "http-sink")]
+pub mod http_sink;
+#[cfg(all(feature = "journald", unix))]
+pub mod journald;
+#[cfg(any(feature = "http-sink", feature = "tdengine"))]
+mod post;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+#[cfg(feature = "tdengine")]
+pub mod tdengine;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid config {key}: {reason}"))]
+    InvalidConfig { key: String, reason: String },
+    #[snafu(display("Create spill dir {} error: {source}", path.display()))]
+    CreateSpillDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Connect to syslog {addr} error: {source}"))]
+    SyslogConnect {
+        addr: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Connect to journald {} error: {source}", path.display()))]
+    JournaldConnect {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Resolve syslog address error"))]
+    SyslogResolve,
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use taoslog_core::QidManager;
+
+    #[derive(Clone)]
+    pub(crate) struct Qid(u64);
+
+    impl QidManager for Qid {
+        fn init() -> Self {
+            Self(9223372036854775807)
+        }
+
+        fn get(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<u64> for Qid {
+        fn from(value: u64) -> Self {
+            Self(value)
+        }
+    }
+}
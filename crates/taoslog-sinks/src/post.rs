@@ -0,0 +1,288 @@
+//! Batching and HTTP POST shared by the network sinks.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{InvalidConfigSnafu, Result};
+
+/// An `http://host[:port][/path]` endpoint, TLS is not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    host: String,
+    port: u16,
+    pub(crate) path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            InvalidConfigSnafu {
+                key: "url",
+                reason: format!("{url}: {reason}"),
+            }
+            .fail()
+        };
+        let Some(rest) = url.strip_prefix("http://") else {
+            return invalid("expected http://host[:port][/path]");
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        // the port follows the brackets of an IPv6 address
+        let host_end = authority.find(']').map_or(0, |i| i + 1);
+        let (host, port) = match authority[host_end..].rfind(':') {
+            Some(i) => match authority[host_end + i + 1..].parse() {
+                Ok(port) => (&authority[..host_end + i], port),
+                Err(_) => return invalid("invalid port"),
+            },
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return invalid("missing host");
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: if path.is_empty() { "/" } else { path }.to_string(),
+        })
+    }
+}
+
+/// POST `body` to `path` of `url`, failing on a status other than 2xx.
+pub(crate) fn post(
+    url: &Url,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> io::Result<()> {
+    let addr = (url.host.trim_matches(|c| c == '[' || c == ']'), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {}:{}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.host,
+        url.port,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "unexpected response: {}",
+            status.trim_end()
+        ))),
+    }
+}
+
+/// Where the worker of [`spawn`] sends batches.
+pub(crate) trait Deliver: Send + 'static {
+    fn send(&mut self, batch: &[u8]) -> io::Result<()>;
+
+    /// `batch` failed after all retries.
+    fn failed(&mut self, batch: Vec<u8>, error: io::Error);
+}
+
+/// Batching and retries of a sink.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Batching {
+    pub(crate) batch_size: usize,
+    pub(crate) flush_interval: Duration,
+    pub(crate) queue_capacity: usize,
+    pub(crate) max_retries: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            flush_interval: Duration::from_secs(1),
+            queue_capacity: 10000,
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Bounded queue of lines in front of the worker thread.
+pub(crate) struct Queue {
+    tx: flume::Sender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Queue {
+    /// Queue `line`, dropped if the queue is full.
+    pub(crate) fn push(&self, line: Vec<u8>) {
+        if self.tx.try_send(line).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Lines dropped with the queue full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Spawn the thread sending lines of the returned queue in batches of
+/// newline separated lines, until the queue is dropped.
+pub(crate) fn spawn(name: &str, batching: Batching, mut deliver: impl Deliver) -> Queue {
+    let (tx, rx) = flume::bounded::<Vec<u8>>(batching.queue_capacity);
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let mut batch = Vec::new();
+            let mut lines = 0;
+            let mut deadline = Instant::now() + batching.flush_interval;
+            loop {
+                let disconnected = match rx.recv_deadline(deadline) {
+                    Ok(line) => {
+                        batch.extend_from_slice(&line);
+                        if !line.ends_with(b"\n") {
+                            batch.push(b'\n');
+                        }
+                        lines += 1;
+                        false
+                    }
+                    Err(flume::RecvTimeoutError::Timeout) => false,
+                    Err(flume::RecvTimeoutError::Disconnected) => true,
+                };
+                let due = Instant::now() >= deadline;
+                if lines > 0 && (lines >= batching.batch_size || due || disconnected) {
+                    deliver_with_retry(&mut deliver, &batching, std::mem::take(&mut batch));
+                    lines = 0;
+                }
+                if due {
+                    deadline = Instant::now() + batching.flush_interval;
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        })
+        .expect("spawn sink thread");
+    Queue {
+        tx,
+        dropped: Arc::default(),
+    }
+}
+
+fn deliver_with_retry(deliver: &mut impl Deliver, batching: &Batching, batch: Vec<u8>) {
+    let mut backoff = batching.backoff;
+    let mut attempt = 0;
+    loop {
+        match deliver.send(&batch) {
+            Ok(()) => return,
+            Err(e) if attempt >= batching.max_retries => return deliver.failed(batch, e),
+            Err(_) => {
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::Read,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// A server answering `status` to each request, returning the requests.
+    pub(crate) fn serve(status: u16) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                    head.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(format!("{head}{}", String::from_utf8_lossy(&body)));
+                write!(stream, "HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn url_test() {
+        let url = Url::parse("http://localhost:6041/influxdb").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                host: "localhost".to_string(),
+                port: 6041,
+                path: "/influxdb".to_string()
+            }
+        );
+        assert_eq!(Url::parse("http://[::1]").unwrap().port, 80);
+        assert_eq!(Url::parse("http://[::1]:8080").unwrap().host, "[::1]");
+        assert!(Url::parse("https://localhost").is_err());
+        assert!(Url::parse("http://localhost:port").is_err());
+    }
+
+    #[test]
+    fn post_test() {
+        let (url, requests) = serve(204);
+        let url = Url::parse(&url).unwrap();
+        post(
+            &url,
+            "/write",
+            &[("X-Test", "1")],
+            b"body",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /write HTTP/1.1\r\n"), "{request}");
+        assert!(request.contains("X-Test: 1\r\n") && request.ends_with("\r\n\r\nbody"));
+
+        let (url, _) = serve(500);
+        let url = Url::parse(&url).unwrap();
+        assert!(post(&url, "/", &[], b"", Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Syslog backend, for deployments where log files on local disk are not
+//! allowed.
+//!
+//! [`SyslogWriter`] is a `MakeWriter` sending each line written by
+//! [`TaosLayer`](taoslog_core::layer::TaosLayer) as one syslog message, to the local
+//! daemon (RFC 3164 over `/dev/log`) or a remote endpoint (RFC 5424 over UDP).
+//! Use it in place of the appender, or next to it with
+//! [`TeeWriter`](taoslog_core::tee::TeeWriter).
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+};
+
+use chrono::{Local, SecondsFormat};
+use snafu::{OptionExt, ResultExt};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{Result, SyslogConnectSnafu, SyslogResolveSnafu};
+
+/// Socket of the local syslog daemon.
+pub const LOCAL_SOCKET: &str = "/dev/log";
+
+/// Syslog facility of the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facility {
+    User = 1,
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+enum Transport {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl Transport {
+    fn send(&self, msg: &[u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(msg),
+            Transport::Udp(socket) => socket.send(msg),
+        }
+    }
+}
+
+/// Sends log lines to syslog, see the [module](self) documentation.
+#[derive(Clone)]
+pub struct SyslogWriter {
+    transport: Arc<Transport>,
+    app_name: String,
+    hostname: String,
+    facility: Facility,
+}
+
+impl SyslogWriter {
+    /// Send to the local syslog daemon at [`LOCAL_SOCKET`].
+    #[cfg(unix)]
+    pub fn local(app_name: &str) -> Result<Self> {
+        Self::unix(LOCAL_SOCKET, app_name)
+    }
+
+    /// Send to the syslog daemon listening on the unix datagram socket `path`.
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<std::path::Path>, app_name: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let socket = std::os::unix::net::UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path).map(|_| socket))
+            .context(SyslogConnectSnafu {
+                addr: path.display().to_string(),
+            })?;
+        Ok(Self::with_transport(Transport::Unix(socket), app_name))
+    }
+
+    /// Send RFC 5424 messages to the syslog endpoint `addr` over UDP.
+    pub fn remote(addr: impl ToSocketAddrs, app_name: &str) -> Result<Self> {
+        let addr: SocketAddr = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .context(SyslogResolveSnafu)?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)
+            .and_then(|socket| socket.connect(addr).map(|_| socket))
+            .context(SyslogConnectSnafu {
+                addr: addr.to_string(),
+            })?;
+        Ok(Self::with_transport(Transport::Udp(socket), app_name))
+    }
+
+    fn with_transport(transport: Transport, app_name: &str) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            app_name: app_name.to_string(),
+            hostname: hostname(),
+            facility: Facility::User,
+        }
+    }
+
+    /// Facility of the messages, `User` by default.
+    pub fn facility(self, facility: Facility) -> Self {
+        Self { facility, ..self }
+    }
+
+    fn format(&self, severity: u8, line: &[u8]) -> Vec<u8> {
+        let pri = self.facility as u8 * 8 + severity;
+        let pid = std::process::id();
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\n');
+        match &*self.transport {
+            // the local daemons parse RFC 3164
+            #[cfg(unix)]
+            Transport::Unix(_) => format!(
+                "<{pri}>{} {}[{pid}]: {line}",
+                Local::now().format("%b %e %H:%M:%S"),
+                self.app_name
+            ),
+            Transport::Udp(_) => format!(
+                "<{pri}>1 {} {} {} {pid} - - {line}",
+                Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+                self.hostname,
+                self.app_name
+            ),
+        }
+        .into_bytes()
+    }
+}
+
+fn severity(level: &tracing::Level) -> u8 {
+    match *level {
+        tracing::Level::ERROR => 3,
+        tracing::Level::WARN => 4,
+        tracing::Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hostname() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string())
+}
+
+/// Buffers one line, sent as a message when dropped.
+pub struct SyslogLine<'a> {
+    writer: &'a SyslogWriter,
+    severity: u8,
+    buf: Vec<u8>,
+}
+
+impl io::Write for SyslogLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogLine<'_> {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let msg = self.writer.format(self.severity, &self.buf);
+        if let Err(e) = self.writer.transport.send(&msg) {
+            eprintln!("[SyslogWriter] Unable to send a log line: {e}");
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = SyslogLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogLine {
+            writer: self,
+            severity: 6,
+            buf: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        SyslogLine {
+            writer: self,
+            severity: severity(meta.level()),
+            buf: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use taoslog_core::layer::TaosLayer;
+
+    use super::*;
+    use crate::fake::Qid;
+
+    #[cfg(unix)]
+    #[test]
+    fn local_syslog_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let writer = SyslogWriter::unix(&path, "taosx")
+            .unwrap()
+            .facility(Facility::Local0);
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+        tracing::subscriber::with_default(subscriber, || tracing::warn!("disk slow"));
+
+        let mut buf = [0; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..n]);
+        // local0.warning
+        assert!(msg.starts_with("<132>"), "{msg}");
+        assert!(msg.contains(&format!(" taosx[{}]: ", std::process::id())));
+        assert!(msg.ends_with("WARN  disk slow"));
+    }
+
+    #[test]
+    fn remote_syslog_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        let writer = SyslogWriter::remote(server.local_addr().unwrap(), "taosx").unwrap();
+        let subscriber = tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(writer));
+        tracing::subscriber::with_default(subscriber, || tracing::error!("write failed"));
+
+        let mut buf = [0; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..n]);
+        // user.err, version 1
+        assert!(msg.starts_with("<11>1 "), "{msg}");
+        assert!(msg.contains(&format!(" taosx {} - - ", std::process::id())));
+        assert!(msg.ends_with("ERROR write failed"));
+    }
+}
@@ -0,0 +1,337 @@
+//! Ship logs to TDengine itself, through the InfluxDB line protocol endpoint
+//! of taosadapter.
+//!
+//! [`TDengineSink`] is a `MakeWriter` queueing each line written by
+//! [`TaosLayer`](taoslog_core::layer::TaosLayer) as a row of the `taoslog` super
+//! table, tagged with the component, instance id and level:
+//!
+//! ```text
+//! taoslog,component=taosx,instance=1,level=ERROR message="... ERROR write failed" 1725000000000000000
+//! ```
+//!
+//! Rows are sent in batches by a background thread, retried with a backoff
+//! while taosadapter is unreachable. With the queue full, new lines are
+//! dropped and counted by [`TDengineSink::dropped`]. Only `http://` endpoints
+//! are supported.
+//!
+//! ```ignore
+//! let sink = TDengineSink::builder("http://localhost:6041", "log")
+//!     .credentials("root", "taosdata")
+//!     .identity("taosx", 1)
+//!     .build()?;
+//! tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(TeeWriter::new(appender, sink)));
+//! ```
+
+use std::{io, sync::Arc, time::Duration};
+
+use chrono::Local;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    post::{self, Batching, Deliver, Queue, Url},
+    Result,
+};
+
+/// Default super table of the rows.
+pub const DEFAULT_STABLE: &str = "taoslog";
+
+/// Builder of a [`TDengineSink`].
+pub struct TDengineSinkBuilder {
+    url: String,
+    database: String,
+    user: String,
+    password: String,
+    stable: String,
+    component: String,
+    instance_id: u8,
+    batching: Batching,
+    timeout: Duration,
+}
+
+impl TDengineSinkBuilder {
+    /// User and password of taosadapter, `root`/`taosdata` by default.
+    pub fn credentials(self, user: &str, password: &str) -> Self {
+        Self {
+            user: user.to_string(),
+            password: password.to_string(),
+            ..self
+        }
+    }
+
+    /// Super table of the rows, [`DEFAULT_STABLE`] by default.
+    pub fn stable(self, stable: &str) -> Self {
+        Self {
+            stable: stable.to_string(),
+            ..self
+        }
+    }
+
+    /// Component and instance id tags of the rows.
+    pub fn identity(self, component: &str, instance_id: u8) -> Self {
+        Self {
+            component: component.to_string(),
+            instance_id,
+            ..self
+        }
+    }
+
+    /// Rows per request, 500 by default.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batching.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Longest wait before sending a partial batch, 1s by default.
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.batching.flush_interval = flush_interval;
+        self
+    }
+
+    /// Rows waiting to be sent before new ones are dropped, 10000 by default.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.batching.queue_capacity = queue_capacity.max(1);
+        self
+    }
+
+    /// Retries of a failed batch before it is dropped, 3 by default, waiting
+    /// 500ms then twice as long each time.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.batching.max_retries = max_retries;
+        self
+    }
+
+    /// Timeout of each request, 10s by default.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn build(self) -> Result<TDengineSink> {
+        let url = Url::parse(&self.url)?;
+        let path = format!(
+            "{}/influxdb/v1/write?db={}&precision=ns&u={}&p={}",
+            url.path.trim_end_matches('/'),
+            query_escape(&self.database),
+            query_escape(&self.user),
+            query_escape(&self.password),
+        );
+        let client = Client {
+            url,
+            path,
+            timeout: self.timeout,
+        };
+        let queue = post::spawn("taoslog-tdengine", self.batching, client);
+        Ok(TDengineSink {
+            inner: Arc::new(Inner {
+                queue,
+                measurement: escape(&self.stable, ", "),
+                tags: format!(
+                    "component={},instance={}",
+                    escape(&self.component, ",= "),
+                    self.instance_id
+                ),
+            }),
+        })
+    }
+}
+
+struct Client {
+    url: Url,
+    path: String,
+    timeout: Duration,
+}
+
+impl Deliver for Client {
+    fn send(&mut self, batch: &[u8]) -> io::Result<()> {
+        post::post(
+            &self.url,
+            &self.path,
+            &[("Content-Type", "text/plain; charset=utf-8")],
+            batch,
+            self.timeout,
+        )
+    }
+
+    fn failed(&mut self, batch: Vec<u8>, error: io::Error) {
+        let rows = batch.iter().filter(|&&b| b == b'\n').count();
+        eprintln!("[TDengineSink] Dropped {rows} rows after retries: {error}");
+    }
+}
+
+struct Inner {
+    queue: Queue,
+    measurement: String,
+    tags: String,
+}
+
+/// Sends log lines to TDengine, see the [module](self) documentation.
+#[derive(Clone)]
+pub struct TDengineSink {
+    inner: Arc<Inner>,
+}
+
+impl TDengineSink {
+    /// A sink writing to the database `database` of the taosadapter at `url`,
+    /// e.g. `http://localhost:6041`.
+    pub fn builder(url: &str, database: &str) -> TDengineSinkBuilder {
+        TDengineSinkBuilder {
+            url: url.to_string(),
+            database: database.to_string(),
+            user: "root".to_string(),
+            password: "taosdata".to_string(),
+            stable: DEFAULT_STABLE.to_string(),
+            component: "taoslog".to_string(),
+            instance_id: 0,
+            batching: Batching::default(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Rows dropped with the queue full.
+    pub fn dropped(&self) -> u64 {
+        self.inner.queue.dropped()
+    }
+
+    fn row(&self, level: &str, line: &[u8]) -> Vec<u8> {
+        let line = String::from_utf8_lossy(line);
+        let message = line
+            .trim_end_matches('\n')
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let ts = Local::now().timestamp_nanos_opt().unwrap_or_default();
+        format!(
+            "{},{},level={level} message=\"{message}\" {ts}",
+            self.inner.measurement, self.inner.tags
+        )
+        .into_bytes()
+    }
+}
+
+/// `value` escaped for a URL query.
+fn query_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// `value` with the `special` characters escaped for the line protocol.
+fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Buffers one line, queued as a row when dropped.
+pub struct TDengineLine<'a> {
+    sink: &'a TDengineSink,
+    level: &'static str,
+    buf: Vec<u8>,
+}
+
+impl io::Write for TDengineLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TDengineLine<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let row = self.sink.row(self.level, &self.buf);
+            self.sink.inner.queue.push(row);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for TDengineSink {
+    type Writer = TDengineLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        TDengineLine {
+            sink: self,
+            level: "INFO",
+            buf: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        TDengineLine {
+            sink: self,
+            level: meta.level().as_str(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use taoslog_core::layer::TaosLayer;
+
+    use super::*;
+    use crate::{fake::Qid, post::tests::serve};
+
+    #[test]
+    fn tdengine_sink_test() {
+        let (url, requests) = serve(204);
+        let sink = TDengineSink::builder(&url, "log")
+            .credentials("root", "p@ss")
+            .identity("taosx", 1)
+            .flush_interval(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(sink.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("started");
+            tracing::error!(path = "a \"b\"", "write failed");
+        });
+
+        for _ in 0..500 {
+            if !requests.lock().unwrap().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let request = requests.lock().unwrap().concat();
+        assert!(
+            request.starts_with(
+                "POST /influxdb/v1/write?db=log&precision=ns&u=root&p=p%40ss HTTP/1.1\r\n"
+            ),
+            "{request}"
+        );
+        let rows: Vec<_> = request.split("\r\n\r\n").nth(1).unwrap().lines().collect();
+        assert_eq!(rows.len(), 2, "{request}");
+        assert!(rows[0].starts_with("taoslog,component=taosx,instance=1,level=INFO message=\""));
+        assert!(rows[1].contains("level=ERROR "));
+        assert!(
+            rows[1].contains(r#"path:\"a \\\"b\\\"\" write failed" "#),
+            "{}",
+            rows[1]
+        );
+        assert_eq!(sink.dropped(), 0);
+    }
+
+    #[test]
+    fn escape_test() {
+        assert_eq!(escape("my app,x=1", ",= "), r"my\ app\,x\=1");
+        assert_eq!(query_escape("a b&c"), "a%20b%26c");
+    }
+}
@@ -0,0 +1,262 @@
+//! tower integration of taoslog, for axum and tonic services.
+//!
+//! [`TaosTraceLayer`] opens a root span per request with the QID of the
+//! upstream `x-qid` header, or a new one, logs the request and response
+//! summary, and returns the QID in the `x-qid` response header.
+//!
+//! ```ignore
+//! let app = axum::Router::new()
+//!     .route("/", get(handler))
+//!     .layer(TaosTraceLayer::<Qid>::new());
+//! ```
+
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use http::{Request, Response};
+use tracing::Instrument;
+
+use taoslog_core::{
+    utils::{QidMetadataGetter, QidMetadataSetter},
+    QidManager,
+};
+
+pub struct TaosTraceLayer<Q>(PhantomData<fn(Q)>);
+
+impl<Q> TaosTraceLayer<Q> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Q> Default for TaosTraceLayer<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> Clone for TaosTraceLayer<Q> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<S, Q> tower_layer::Layer<S> for TaosTraceLayer<Q> {
+    type Service = TaosTraceService<S, Q>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TaosTraceService {
+            inner,
+            _q: PhantomData,
+        }
+    }
+}
+
+pub struct TaosTraceService<S, Q> {
+    inner: S,
+    _q: PhantomData<fn(Q)>,
+}
+
+impl<S: Clone, Q> Clone for TaosTraceService<S, Q> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _q: PhantomData,
+        }
+    }
+}
+
+impl<S, Q, ReqBody, ResBody> tower_service::Service<Request<ReqBody>> for TaosTraceService<S, Q>
+where
+    S: tower_service::Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: std::fmt::Display,
+    Q: QidManager,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let method = request.method().as_str().to_string();
+        let target = request
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("")
+            .to_string();
+        let flavor = http_flavor(request.version());
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("")
+        };
+        let user_agent = header("user-agent");
+        let client_ip = header("x-forwarded-for")
+            .split(',')
+            .next()
+            .filter(|ip| !ip.is_empty())
+            .unwrap_or_else(|| header("x-real-ip"))
+            .trim();
+
+        let mut span = tracing::info_span!(
+            "HTTP request",
+            http.method = %method,
+            http.target = %target,
+            http.flavor = %flavor,
+        );
+        // get qid from upstream header
+        if let Some(qid) = request.headers().get_qid::<Q>() {
+            span.set_qid(&qid);
+        }
+        let qid = span.get_qid::<Q>();
+        span.in_scope(|| {
+            tracing::info!("{client_ip} \"{method} {target} HTTP/{flavor}\" {user_agent}");
+        });
+
+        let start = Instant::now();
+        let future = self.inner.call(request).instrument(span.clone());
+        Box::pin(async move {
+            let outcome = future.await;
+            let elapsed = start.elapsed().as_millis() as u64;
+            span.in_scope(|| match &outcome {
+                Ok(response) => {
+                    let code = response.status().as_u16();
+                    tracing::info!(
+                        duration_elapsed = elapsed,
+                        "\"{method} {target}\" status code: {code}"
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        duration_elapsed = elapsed,
+                        "\"{method} {target}\" error: {e}"
+                    );
+                }
+            });
+            outcome.map(|mut response| {
+                if let Some(qid) = qid {
+                    response.headers_mut().set_qid(&qid);
+                }
+                response
+            })
+        })
+    }
+}
+
+pub fn http_flavor(version: http::Version) -> &'static str {
+    match version {
+        http::Version::HTTP_09 => "0.9",
+        http::Version::HTTP_10 => "1.0",
+        http::Version::HTTP_11 => "1.1",
+        http::Version::HTTP_2 => "2.0",
+        http::Version::HTTP_3 => "3.0",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use taoslog_core::{layer::TaosLayer, utils::QID_HEADER_KEY};
+    use tower::{ServiceBuilder, ServiceExt};
+    use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Qid(u64);
+
+    impl QidManager for Qid {
+        fn init() -> Self {
+            Self(0x1234)
+        }
+
+        fn get(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<u64> for Qid {
+        fn from(value: u64) -> Self {
+            Self(value)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn trace_layer_test() {
+        let capture = Capture::default();
+        let subscriber =
+            tracing_subscriber::registry().with(TaosLayer::<Qid, _, _>::new(capture.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = ServiceBuilder::new()
+            .layer(TaosTraceLayer::<Qid>::new())
+            .service_fn(|_: Request<()>| async {
+                tracing::info!("handled");
+                Ok::<_, Infallible>(Response::new(()))
+            });
+
+        // the upstream qid is kept
+        let request = Request::get("/sql?db=test")
+            .header(QID_HEADER_KEY, "0x0000000000000abc")
+            .body(())
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[QID_HEADER_KEY], "0x0000000000000abc");
+
+        // a new qid is returned
+        let response = service
+            .oneshot(Request::get("/").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[QID_HEADER_KEY], "0x0000000000001234");
+
+        let content = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 6, "{content}");
+        assert!(lines[0].contains("qid:0x0000000000000abc"));
+        assert!(lines[0].contains("\"GET /sql?db=test HTTP/1.1\""));
+        assert!(lines[1].contains("qid:0x0000000000000abc") && lines[1].ends_with("handled"));
+        assert!(lines[2].contains("status code: 200"));
+        assert!(lines[3].contains("qid:0x0000000000001234"));
+    }
+}
@@ -1,75 +1,62 @@
-use std::path::PathBuf;
-
-pub mod layer;
-pub mod middleware;
-pub mod utils;
-pub mod writer;
-
-pub(crate) type Result<T> = std::result::Result<T, Error>;
-
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {
-    #[snafu(display("Create log dir {} error: {source}", path.display()))]
-    CreateLogDir {
-        path: PathBuf,
-        source: std::io::Error,
-    },
-    #[snafu(display("Open log file {} error: {source}", path.display()))]
-    OpenLogFile {
-        path: PathBuf,
-        source: std::io::Error,
-    },
-    #[snafu(display("Get file {} size error: {source}", path.display()))]
-    GetFileSize {
-        path: PathBuf,
-        source: std::io::Error,
-    },
-    #[snafu(display("Compress file {} error: {source}", path.display()))]
-    Compress {
-        path: PathBuf,
-        source: std::io::Error,
-    },
-    #[snafu(display("List dir {} error: {source}", path.display()))]
-    ReadDir {
-        path: PathBuf,
-        source: std::io::Error,
-    },
-    #[snafu(display("Parse date error: {source}"))]
-    ParseDate { source: chrono::ParseError },
-    #[snafu(display("Invalid rotation size: {size}"))]
-    InvalidRotationSize { size: String },
-    #[snafu(display("Get disk space error"))]
-    DiskMountPointNotFound,
-    #[snafu(display("Get log absolute path error: {source}"))]
-    GetLogAbsolutePath { source: std::io::Error },
-}
-
-pub trait QidManager: Send + Sync + 'static + Clone + From<u64> {
-    fn init() -> Self;
-
-    fn get(&self) -> u64;
-}
-
-#[cfg(test)]
-pub(crate) mod fake {
-    use crate::QidManager;
-
-    #[derive(Clone)]
-    pub(crate) struct Qid(u64);
-
-    impl QidManager for Qid {
-        fn init() -> Self {
-            Self(9223372036854775807)
-        }
-
-        fn get(&self) -> u64 {
-            self.0
-        }
-    }
-
-    impl From<u64> for Qid {
-        fn from(value: u64) -> Self {
-            Self(value)
-        }
-    }
-}
+//! Logging toolbox used by taosdata services.
+//!
+//! This facade re-exports [`taoslog_core`], and the framework integrations
+//! and sinks enabled by cargo features:
+//!
+//! - `actix`: [`middleware`] and [`admin`] endpoints for actix-web, QID carriers
+//!   for its `HeaderMap`
+//! - `config`: [`config`] appender and layer settings from a config file
+//! - `derive`: `#[derive(LogSafe)]` in [`redact`], masking secrets in `Debug`
+//! - `encryption`: [`encrypt`] AES-256-GCM encryption of rotated files
+//! - `flight`: [`flight`] QID stamping of Arrow Flight streams
+//! - `hash-chain`: [`chain`] tamper-evident hash chain of log lines
+//! - `http-sink`: [`http_sink`] NDJSON batches POSTed to a collector
+//! - `http`: QID carriers for `http::HeaderMap`
+//! - `arrow`: QID carriers for `arrow_schema::Schema`
+//! - `journald`: [`journald`] layer sending structured entries to systemd-journald
+//! - `syslog`: [`syslog`] lines sent to the local syslog daemon or a remote
+//!   RFC 5424 endpoint
+//! - `reqwest`: [`client`] QID propagation for outgoing reqwest calls, with
+//!   [`utils::propagate_qid`] and a `reqwest-middleware` middleware
+//! - `regex`, `sysinfo`: file name parsing and disk space with these crates,
+//!   replaced by a hand-rolled parser and `statvfs` when disabled
+//! - `log`: [`log_bridge`] records of the `log` crate written by the layer
+//! - `otel`: [`otel`] layer exporting spans and events to OpenTelemetry
+//! - `tdengine`: [`tdengine`] sink shipping logs to TDengine through taosadapter
+//! - `testing`: [`testing`], also at [`test_util`], temp log dirs with a
+//!   manual clock and in-memory capture of events, for tests of a logging
+//!   configuration
+//! - `tokio`: task-local [`context`] fields
+//! - `tonic`: QID carriers for tonic `MetadataMap` and gRPC interceptors
+//! - `tower`: [`tower`] middleware for axum and tonic services
+//! - `zstd`: zstd compression of rotated files
+//!
+//! `actix`, `http`, `arrow`, `regex` and `sysinfo` are enabled by default,
+//! use `default-features = false` to embed only the appender and layer with
+//! the smallest dependency tree.
+
+pub use taoslog_core::*;
+
+#[cfg(feature = "actix")]
+pub use taoslog_actix as middleware;
+
+#[cfg(feature = "actix")]
+pub use taoslog_actix::admin;
+
+#[cfg(feature = "tower")]
+pub use taoslog_tower as tower;
+
+#[cfg(feature = "flight")]
+pub use taoslog_arrow::flight;
+
+#[cfg(feature = "syslog")]
+pub use taoslog_sinks::syslog;
+
+#[cfg(feature = "http-sink")]
+pub use taoslog_sinks::http_sink;
+
+#[cfg(all(feature = "journald", unix))]
+pub use taoslog_sinks::journald;
+
+#[cfg(feature = "tdengine")]
+pub use taoslog_sinks::tdengine;